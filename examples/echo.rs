@@ -0,0 +1,51 @@
+//! An echo server built on `Net::run`, which shuts down cleanly once it's echoed a megabyte.
+
+use std::time::Duration;
+
+use failure::Error;
+use nofio::prelude::*;
+use nofio::Handler;
+use nofio::ListenerToken;
+
+const LIMIT: usize = 1024 * 1024;
+
+#[derive(Default)]
+struct Echo {
+    echoed: usize,
+}
+
+impl Handler for Echo {
+    fn on_accept(&mut self, io: &mut Io<'_>, _listener: ListenerToken) {
+        println!("{} connected", io.peer_addr());
+    }
+
+    fn on_data(&mut self, io: &mut Io<'_>) {
+        let data = match io.take_buf() {
+            Ok(data) => data,
+            // they've finished sending; nothing more to echo
+            Err(_) => return,
+        };
+        self.echoed += data.len();
+        // fails if they've stopped listening; they'll be closed soon enough
+        let _ = io.write_vec(data);
+    }
+
+    fn on_event(&mut self, net: &mut Net, event: Event) {
+        if let Event::Closed(_) = event {
+            if self.echoed >= LIMIT {
+                let _ = net.shutdown_gracefully(Duration::from_secs(5));
+            }
+        }
+    }
+}
+
+fn main() -> Result<(), Error> {
+    pretty_env_logger::init();
+
+    let mut net = Net::empty()?;
+    net.tcp_listen(&"127.0.0.1:6062".parse()?)?;
+    let mut echo = Echo::default();
+    net.run(&mut echo)?;
+    println!("echoed {} bytes", echo.echoed);
+    Ok(())
+}
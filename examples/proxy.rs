@@ -0,0 +1,32 @@
+//! A TCP proxy: everything accepted on 127.0.0.1:6061 is passed on to the address given on the
+//! command line, and back.
+
+use std::env;
+use std::net::SocketAddr;
+
+use failure::format_err;
+use failure::Error;
+
+fn main() -> Result<(), Error> {
+    pretty_env_logger::init();
+
+    let upstream: SocketAddr = env::args()
+        .nth(1)
+        .ok_or_else(|| format_err!("usage: proxy UPSTREAM_ADDR"))?
+        .parse()?;
+
+    let mut net = nofio::Net::empty()?;
+    net.tcp_listen(&"127.0.0.1:6061".parse()?)?;
+    loop {
+        let ev = net.next()?;
+        match ev {
+            nofio::Event::NewConnection { token, .. } => {
+                let server = net.tcp_connect(&upstream)?;
+                net.link(token, server)?;
+            }
+            nofio::Event::ConnectFailed(token, e) => println!("{:?}: {}", token, e),
+            nofio::Event::Error(token, e) => println!("{:?}: {}", token, e),
+            _ => println!("{:?}", ev),
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! Churn a single `Net` with many clients connecting, transferring, closing, aborting and
+//! stalling, checking everything adds up at the end.
+//!
+//! `cargo run --release --features debug-invariants --example soak -- [seconds] [clients] [seed]`
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::net::Shutdown;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::process;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use failure::Error;
+use nofio::Event;
+use nofio::Net;
+
+/// xorshift64*; plenty for picking what to do next, and reproducible from the seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+#[derive(Default)]
+struct Checksum(u64, usize);
+
+impl Checksum {
+    fn update(&mut self, data: &[u8]) {
+        if 0 == self.1 {
+            self.0 = 0xcbf2_9ce4_8422_2325;
+        }
+        for b in data {
+            self.0 = (self.0 ^ u64::from(*b)).wrapping_mul(0x100_0000_01b3);
+        }
+        self.1 += data.len();
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    finished: AtomicUsize,
+    /// Every successful connect, so we know when they've all been accepted.
+    connected: AtomicU64,
+    failed: AtomicBool,
+    verified: AtomicU64,
+    aborted: AtomicU64,
+    abandoned: AtomicU64,
+}
+
+fn main() -> Result<(), Error> {
+    pretty_env_logger::init();
+
+    let args: Vec<String> = env::args().collect();
+    let seconds: u64 = args.get(1).map(|s| s.parse()).transpose()?.unwrap_or(180);
+    let clients: usize = args.get(2).map(|s| s.parse()).transpose()?.unwrap_or(16);
+    let seed: u64 = match args.get(3) {
+        Some(s) => s.parse()?,
+        None => SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64,
+    };
+    println!(
+        "soak: {}s, {} clients, seed {} (pass it as the third argument to reproduce)",
+        seconds, clients, seed
+    );
+
+    let mut net = Net::empty()?;
+    net.tcp_listen(&"127.0.0.1:0".parse()?)?;
+    let addr = match net.next()? {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    let baseline_fds = open_fds()?;
+
+    let stats = Arc::new(Stats::default());
+    let deadline = Instant::now() + Duration::from_secs(seconds);
+
+    let mut threads = Vec::with_capacity(clients);
+    for id in 0..clients {
+        let stats = stats.clone();
+        let seed = seed ^ (id as u64 + 1);
+        threads.push(thread::spawn(move || {
+            let result = client(addr, Rng::new(seed), deadline, &stats);
+            if let Err(e) = &result {
+                eprintln!("client {} failed: {}", id, e);
+                stats.failed.store(true, Ordering::SeqCst);
+            }
+            // one last connection, closed after we're counted, so the event loop notices
+            let last = TcpStream::connect(addr);
+            if last.is_ok() {
+                stats.connected.fetch_add(1, Ordering::SeqCst);
+            }
+            stats.finished.fetch_add(1, Ordering::SeqCst);
+            drop(last);
+        }));
+    }
+
+    let watchdog = deadline + Duration::from_secs(30);
+    {
+        let stats = stats.clone();
+        thread::spawn(move || {
+            while Instant::now() < watchdog {
+                thread::sleep(Duration::from_millis(100));
+            }
+            eprintln!(
+                "watchdog: still running; {} of {} clients finished",
+                stats.finished.load(Ordering::SeqCst),
+                clients
+            );
+            process::exit(2);
+        });
+    }
+
+    let mut open = HashSet::new();
+    let mut accepted = 0u64;
+    loop {
+        let ev = net.next()?;
+        log::debug!("{:?}", ev);
+        match ev {
+            Event::NewConnection { token, .. } => {
+                accepted += 1;
+                assert!(open.insert(token), "{:?} accepted twice", token);
+            }
+            Event::Data(token) => {
+                let mut io = net.io(token)?;
+                let data = io.take_buf()?;
+                // fails once the client has stopped listening, which some of them do
+                let _ = io.write_vec(data);
+            }
+            Event::Done(token, _) => net.io(token)?.close(),
+            Event::Closed(token) => {
+                assert!(open.remove(&token), "{:?} closed but never opened", token);
+                // a finished client's last connection may not even have been accepted yet
+                if open.is_empty()
+                    && clients == stats.finished.load(Ordering::SeqCst)
+                    && accepted == stats.connected.load(Ordering::SeqCst)
+                {
+                    break;
+                }
+            }
+            Event::Error(token, e) => log::info!("{:?}: {}", token, e),
+            other => log::info!("{:?}", other),
+        }
+    }
+
+    // their last connections may not have been closed yet, which would upset the fd count
+    for thread in threads {
+        thread.join().expect("client panicked");
+    }
+
+    let metrics = net.metrics();
+    let dump = net.debug_dump();
+    let tracked = dump
+        .lines()
+        .filter(|l| {
+            l.split(':')
+                .next()
+                .is_some_and(|t| t.parse::<usize>().is_ok())
+        })
+        .count();
+    let fds = open_fds()?;
+
+    println!(
+        "accepted {}, verified {}, aborted {}, abandoned {}",
+        accepted,
+        stats.verified.load(Ordering::SeqCst),
+        stats.aborted.load(Ordering::SeqCst),
+        stats.abandoned.load(Ordering::SeqCst),
+    );
+    println!("{:?}", metrics);
+
+    let mut ok = !stats.failed.load(Ordering::SeqCst);
+    if 1 != tracked {
+        eprintln!("expected only the listener to remain:\n{}", dump);
+        ok = false;
+    }
+    if 0 != metrics.buffered_bytes {
+        eprintln!("{} bytes still buffered", metrics.buffered_bytes);
+        ok = false;
+    }
+    if fds != baseline_fds {
+        eprintln!("{} fds open, expected {}", fds, baseline_fds);
+        ok = false;
+    }
+
+    if !ok {
+        eprintln!("soak failed; seed {}", seed);
+        process::exit(1);
+    }
+    println!("ok");
+    Ok(())
+}
+
+fn client(addr: SocketAddr, mut rng: Rng, deadline: Instant, stats: &Stats) -> Result<(), Error> {
+    while Instant::now() < deadline {
+        let mut sock = TcpStream::connect(addr)?;
+        stats.connected.fetch_add(1, Ordering::SeqCst);
+        let len = rng.below(256 * 1024) as usize;
+        let style = rng.below(10);
+
+        // readers run alongside, as the server starts echoing before we've finished sending
+        let reader = {
+            let mut sock = sock.try_clone()?;
+            thread::spawn(move || -> Result<Checksum, std::io::Error> {
+                let mut got = Checksum::default();
+                let mut buf = [0u8; 16 * 1024];
+                loop {
+                    let r = sock.read(&mut buf)?;
+                    if 0 == r {
+                        return Ok(got);
+                    }
+                    got.update(&buf[..r]);
+                }
+            })
+        };
+
+        let cut = match style {
+            0 | 1 => rng.below(len as u64 + 1) as usize,
+            _ => len,
+        };
+
+        let mut sent = Checksum::default();
+        while sent.1 < cut {
+            let chunk = (rng.below(16 * 1024) as usize + 1).min(cut - sent.1);
+            let data: Vec<u8> = (0..chunk).map(|_| rng.next() as u8).collect();
+            sock.write_all(&data)?;
+            sent.update(&data);
+            if 0 == rng.below(50) {
+                thread::sleep(Duration::from_millis(rng.below(20)));
+            }
+        }
+
+        match style {
+            0 => {
+                // reset, with whatever's in flight; the reader's clone must go before the close
+                set_linger_zero(&sock);
+                let _ = sock.shutdown(Shutdown::Read);
+                let _ = reader.join();
+                drop(sock);
+                stats.aborted.fetch_add(1, Ordering::Relaxed);
+            }
+            1 => {
+                // stop listening part way through
+                let _ = sock.shutdown(Shutdown::Both);
+                drop(sock);
+                let _ = reader.join();
+                stats.abandoned.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {
+                sock.shutdown(Shutdown::Write)?;
+                let got = reader.join().expect("reader panicked")?;
+                if (got.0, got.1) != (sent.0, sent.1) {
+                    failure::bail!(
+                        "checksum mismatch: sent {} bytes ({:x}), got {} bytes ({:x})",
+                        sent.1,
+                        sent.0,
+                        got.1,
+                        got.0
+                    );
+                }
+                stats.verified.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_linger_zero(sock: &TcpStream) {
+    let linger = libc::linger {
+        l_onoff: 1,
+        l_linger: 0,
+    };
+    unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_LINGER,
+            &linger as *const _ as *const libc::c_void,
+            std::mem::size_of::<libc::linger>() as libc::socklen_t,
+        );
+    }
+}
+
+#[cfg(not(unix))]
+fn set_linger_zero(sock: &TcpStream) {
+    use net2::TcpStreamExt;
+    let _ = TcpStreamExt::set_linger(sock, Some(Duration::from_secs(0)));
+}
+
+fn open_fds() -> Result<usize, Error> {
+    Ok(fs::read_dir("/proc/self/fd")?.count())
+}
@@ -0,0 +1,49 @@
+//! An echo server which reports each connection's round trip time every few seconds.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use failure::Error;
+use nofio::prelude::*;
+
+const INTERVAL: Duration = Duration::from_secs(2);
+
+fn main() -> Result<(), Error> {
+    pretty_env_logger::init();
+
+    let mut net = Net::empty()?;
+    net.tcp_listen(&"127.0.0.1:6061".parse()?)?;
+    net.timer(INTERVAL)?;
+
+    let mut conns = HashSet::new();
+    loop {
+        match net.next()? {
+            Event::NewConnection { token, .. } => {
+                conns.insert(token);
+            }
+            Event::Data(token) => {
+                let mut io = net.io(token)?;
+                let data = io.buf()?.to_vec();
+                io.consume_all()?;
+                io.write(&data)?;
+            }
+            Event::Timer(_) => {
+                for &token in &conns {
+                    match net.io(token)?.tcp_info() {
+                        Ok(info) => println!(
+                            "{:?}: rtt {:?} ± {:?}, cwnd {}, {} retransmits",
+                            token, info.rtt, info.rttvar, info.snd_cwnd, info.total_retrans
+                        ),
+                        Err(e) => println!("{:?}: {}", token, e),
+                    }
+                }
+                net.timer(INTERVAL)?;
+            }
+            Event::Done(token, _) => net.io(token)?.close(),
+            Event::Closed(token) => {
+                conns.remove(&token);
+            }
+            ev => println!("{:?}", ev),
+        }
+    }
+}
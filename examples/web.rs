@@ -1,4 +1,7 @@
+use std::time::Duration;
+
 use failure::Error;
+use nofio::http1;
 
 fn main() -> Result<(), Error> {
     pretty_env_logger::init();
@@ -8,31 +11,38 @@ fn main() -> Result<(), Error> {
     loop {
         let ev = net.next()?;
         match ev {
-            nofio::Event::Data(token) => {
-                let mut io = net.io(token);
-                let buf = io.buf();
-                if let Some(header_end) = find_subsequence(buf, b"\r\n\r\n") {
-                    println!(
-                        "headers: {:?}",
-                        String::from_utf8(buf[..header_end].to_vec())
-                    );
-                    io.consume(header_end);
+            nofio::Event::NewConnection { token, .. } => {
+                let mut io = net.io(token)?;
+                // don't wait forever for clients who never finish a request
+                io.set_idle_timeout(Duration::from_secs(30))?;
+                http1::serve(&mut io, 64 * 1024)?;
+            }
+            nofio::Event::HttpRequest(token) => {
+                let mut io = net.io(token)?;
+                // clients may pipeline; we won't hear about requests that are already buffered
+                while let Some(req) = http1::next_request(&mut io)? {
+                    println!("{} {} {}", io.peer_addr(), req.method, req.target);
+                    let keep_alive = req.keep_alive();
+                    http1::write_response(
+                        &mut io,
+                        200,
+                        &[("Content-Type", "text/plain")],
+                        b"hello\n",
+                        keep_alive,
+                    )?;
 
-                    io.write(b"HTTP/1.0 200 OK\r\n\r\n");
-                    io.close();
+                    if !keep_alive {
+                        break;
+                    }
                 }
             }
-            nofio::Event::Done(token, _) => net.io(token).close(),
+            nofio::Event::Error(token, e) => println!("{:?}: {}", token, e),
+            nofio::Event::Done(token, _) => net.io(token)?.close(),
+            nofio::Event::Closed(token) => {
+                let remains = net.take_final_buffers(token).unwrap_or_default();
+                println!("{:?} closed, {} bytes unread", token, remains.unread.len());
+            }
             _ => println!("{:?}", ev),
         }
     }
-    Ok(())
-}
-
-// https://stackoverflow.com/questions/35901547/how-can-i-find-a-subsequence-in-a-u8-slice
-fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
-    haystack
-        .windows(needle.len())
-        .position(|window| window == needle)
-        .map(|pos| pos + needle.len())
 }
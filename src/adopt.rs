@@ -0,0 +1,75 @@
+//! Sockets made somewhere else, like inherited from a parent process, or bound to a
+//! privileged port before dropping privileges, joining a `Net`.
+
+use std::net;
+
+use log::info;
+use mio::net::TcpListener;
+use mio::net::TcpStream;
+
+use crate::memory::Transport;
+use crate::reactor::Interest;
+use crate::reactor::Source;
+use crate::ConnToken;
+use crate::Error;
+use crate::ListenOptions;
+use crate::ListenerToken;
+use crate::Net;
+use crate::Operation;
+use crate::Owned;
+use crate::OwnedMode;
+
+impl<T> Net<T> {
+    /// Accept connections on a listener which is already bound and listening, as if from
+    /// `tcp_listen_all`; `Event::Listening` follows. It's made non-blocking. What `options`
+    /// says about binding, like `backlog` or `reuse_port`, is too late to matter, so ignored.
+    pub fn adopt_listener(
+        &mut self,
+        listener: net::TcpListener,
+        options: &ListenOptions,
+    ) -> Result<ListenerToken, Error> {
+        options.check()?;
+        let addr = listener
+            .local_addr()
+            .map_err(|e| Error::new(Operation::Adopt, e))?;
+        let inner = TcpListener::from_std(listener)
+            .map_err(|e| Error::new(Operation::Adopt, e).with_addr(addr))?;
+        let token = self
+            .add_listener(inner, options)
+            .map_err(|e| e.with_addr(addr))?;
+        self.announce_listening(&[token]);
+        Ok(ListenerToken(token))
+    }
+
+    /// Take on a connection which is already established, as if it had just been accepted,
+    /// but without an `Event::NewConnection`; its token is only known from here. It's made
+    /// non-blocking. Anything it has already been sent arrives as `Event::Data`, as usual.
+    pub fn adopt_stream(&mut self, stream: net::TcpStream) -> Result<ConnToken, Error> {
+        let peer = stream
+            .peer_addr()
+            .map_err(|e| Error::new(Operation::Adopt, e))?;
+        let sock = TcpStream::from_stream(stream)
+            .map_err(|e| Error::new(Operation::Adopt, e).with_peer(Some(peer)))?;
+        let token = self.tokens.reserve()?;
+        if let Err(e) = self
+            .reactor
+            .register(Source::Stream(&sock), token, Interest::READABLE)
+        {
+            self.release(token);
+            return Err(Error::new(Operation::Register, e)
+                .with_token(token)
+                .with_peer(Some(peer)));
+        }
+        info!("{} adopted {}", token.0, peer);
+        let mut conn = self.new_conn(Transport::Tcp(sock), peer);
+        conn.registered = Some(Interest::READABLE);
+        self.tokens.insert(
+            token,
+            Owned {
+                token,
+                mode: OwnedMode::Conn(conn),
+            },
+        );
+        Ok(ConnToken(token))
+    }
+}
@@ -0,0 +1,390 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::mem;
+
+use bytes::Buf;
+use bytes::Bytes;
+use bytes::BytesMut;
+use iovec::IoVec;
+
+use crate::memory::Transport;
+use crate::sys;
+
+/// Writes smaller than this are appended to the last queued chunk, instead of starting a new
+/// one, so a stream of tiny writes doesn't turn into a stream of tiny iovecs.
+const COALESCE: usize = 16 * 1024;
+
+/// The most chunks offered to the kernel in one `writev`.
+const MAX_IOVECS: usize = 64;
+
+/// The most copied for a reactor to write in one go, as it's held until it's been written.
+const MAX_COPY: usize = 256 * 1024;
+
+/// Buffers which grew bigger than this are freed, not pooled; few connections need one.
+const MAX_POOLED: usize = 64 * 1024;
+
+/// What a `Stream` keeps its bytes in.
+pub(crate) trait Buffer: Default {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        0 == self.len()
+    }
+
+    /// Everything still held, in order, as one allocation.
+    fn into_vec(self) -> Vec<u8>;
+}
+
+/// The read side. Consuming from the front never moves what's left; the space is reclaimed
+/// when more is read, if the front has caught up far enough to make that cheap.
+///
+/// `bytes` hands out what's unconsumed as `Bytes`, sharing the storage, which is then kept in
+/// `shared` until more arrives. It's only written to in place again once they've all gone, and
+/// is otherwise replaced with a copy of what's unconsumed.
+#[derive(Default)]
+pub(crate) struct ReadBuf {
+    buf: BytesMut,
+    /// Everything unconsumed, instead of `buf`, since `bytes` handed it out.
+    shared: Option<Bytes>,
+    /// How much of `buf`'s spare capacity, from its end, an earlier `read_with` has already
+    /// zeroed, so needn't be again.
+    initialised: usize,
+}
+
+impl ReadBuf {
+    /// Start out with `buf`'s capacity, e.g. from a `BufferPool`.
+    pub(crate) fn with_storage(mut buf: Vec<u8>) -> ReadBuf {
+        buf.clear();
+        ReadBuf {
+            buf: BytesMut::from(Bytes::from(buf)),
+            shared: None,
+            initialised: 0,
+        }
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        match &self.shared {
+            Some(shared) => shared,
+            None => &self.buf,
+        }
+    }
+
+    pub(crate) fn bytes(&mut self) -> Bytes {
+        let buf = &mut self.buf;
+        self.shared
+            .get_or_insert_with(|| buf.split().freeze())
+            .clone()
+    }
+
+    pub(crate) fn consume(&mut self, n: usize) {
+        assert!(n <= self.len(), "consumed more than was buffered");
+        match &mut self.shared {
+            Some(shared) if n == shared.len() => self.shared = None,
+            Some(shared) => shared.advance(n),
+            None => self.buf.advance(n),
+        }
+    }
+
+    /// Remove and return up to `n` bytes from the front. Taking everything moves the
+    /// storage out, rather than copying it.
+    pub(crate) fn take(&mut self, n: usize) -> Vec<u8> {
+        let n = n.min(self.len());
+        if let Some(mut shared) = self.shared.take() {
+            let taken = shared.split_to(n);
+            if !shared.is_empty() {
+                self.shared = Some(shared);
+            }
+            return Vec::from(taken);
+        }
+        if n == self.buf.len() {
+            self.initialised = 0;
+            return Vec::from(mem::take(&mut self.buf));
+        }
+        self.buf.split_to(n).to_vec()
+    }
+
+    /// Let `read` put up to `limit` bytes straight onto the end.
+    pub(crate) fn read_with(
+        &mut self,
+        limit: usize,
+        read: impl FnOnce(&mut [u8]) -> io::Result<usize>,
+    ) -> io::Result<usize> {
+        self.unshare();
+        let end = self.buf.len();
+        let before = self.buf.as_ptr();
+        self.buf.reserve(limit);
+        if self.buf.as_ptr() != before {
+            // moved, so what's past the end is new
+            self.initialised = 0;
+        }
+        let zeroed = self.initialised.min(limit);
+        // SAFETY: the spare capacity up to `initialised` was zeroed by an earlier `resize`, and
+        // only ever written to since; consuming from the front, or `reserve` without moving,
+        // leaves it where it was
+        unsafe { self.buf.set_len(end + zeroed) };
+        // zeroed, as `read` is free to look at what it's given, whatever it's reading from; but
+        // only what never has been, as the limit can be large
+        self.buf.resize(end + limit, 0);
+        let read = read(&mut self.buf[end..]);
+        let got = match &read {
+            Ok(n) => (*n).min(limit),
+            Err(_) => 0,
+        };
+        self.buf.truncate(end + got);
+        self.initialised = self.initialised.max(limit) - got;
+        read.map(|_| got)
+    }
+
+    /// Move what's unconsumed back into `buf`, where it can be added to.
+    fn unshare(&mut self) {
+        if let Some(shared) = self.shared.take() {
+            // the empty remainder would stop `shared` being the only one left using the storage
+            self.buf = BytesMut::new();
+            self.buf = BytesMut::from(shared);
+            self.initialised = 0;
+        }
+    }
+}
+
+impl Buffer for ReadBuf {
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn into_vec(mut self) -> Vec<u8> {
+        self.take(usize::MAX)
+    }
+}
+
+/// Capacity from buffers nobody needs any more, for new connections to start with.
+#[derive(Default)]
+pub(crate) struct BufferPool {
+    spare: Vec<Vec<u8>>,
+    /// Total capacity of everything in `spare`.
+    pub(crate) bytes: usize,
+    pub(crate) limit: usize,
+    pub(crate) recycled: u64,
+}
+
+impl BufferPool {
+    pub(crate) fn new(limit: usize) -> BufferPool {
+        BufferPool {
+            limit,
+            ..BufferPool::default()
+        }
+    }
+
+    /// A spare buffer if there is one, otherwise a new (unallocated) one.
+    pub(crate) fn get(&mut self) -> Vec<u8> {
+        match self.spare.pop() {
+            Some(buf) => {
+                self.bytes -= buf.capacity();
+                self.recycled += 1;
+                buf
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Keep `buf`'s capacity, if it's worth keeping and there's room.
+    pub(crate) fn put(&mut self, mut buf: Vec<u8>) {
+        let capacity = buf.capacity();
+        if 0 == capacity || capacity > MAX_POOLED || self.bytes + capacity > self.limit {
+            return;
+        }
+        buf.clear();
+        self.bytes += capacity;
+        self.spare.push(buf);
+    }
+}
+
+/// The write side: a queue of owned chunks, so queueing never moves what's already queued,
+/// and writing never moves what's left. Parts of files can be queued between them, which are
+/// sent straight from the file, and aren't counted in `len`, as they're not in memory.
+#[derive(Default)]
+pub(crate) struct Chunks {
+    chunks: VecDeque<Chunk>,
+    /// How much of the front chunk has already been written, if it's `Data`.
+    offset: usize,
+    len: usize,
+}
+
+enum Chunk {
+    Data(Vec<u8>),
+    File(FileRegion),
+}
+
+/// What's left to send of a file, from `Io::send_file`.
+pub(crate) struct FileRegion {
+    pub(crate) file: File,
+    pub(crate) offset: u64,
+    pub(crate) len: usize,
+}
+
+impl Chunks {
+    pub(crate) fn extend_from_slice(&mut self, data: &[u8]) {
+        match self.chunks.back_mut() {
+            Some(Chunk::Data(back)) if back.len() + data.len() <= COALESCE => {
+                back.extend_from_slice(data)
+            }
+            _ if data.is_empty() => return,
+            _ => self.chunks.push_back(Chunk::Data(data.to_vec())),
+        }
+        self.len += data.len();
+    }
+
+    /// Queue `data` without copying it, unless it's small enough to coalesce.
+    pub(crate) fn push(&mut self, data: Vec<u8>) {
+        if data.len() < COALESCE {
+            return self.extend_from_slice(&data);
+        }
+        self.len += data.len();
+        self.chunks.push_back(Chunk::Data(data));
+    }
+
+    /// Queue part of a file, to be sent once everything before it has been.
+    pub(crate) fn push_file(&mut self, region: FileRegion) {
+        if 0 != region.len {
+            self.chunks.push_back(Chunk::File(region));
+        }
+    }
+
+    /// The bytes which will be written next; none if it's a file's turn.
+    pub(crate) fn front(&self) -> &[u8] {
+        match self.chunks.front() {
+            Some(Chunk::Data(front)) => &front[self.offset..],
+            Some(Chunk::File(_)) | None => &[],
+        }
+    }
+
+    /// The first `n` bytes, a chunk at a time; none, if it's a file's turn.
+    pub(crate) fn front_chunks(&self, mut n: usize, mut f: impl FnMut(&[u8])) {
+        let mut offset = self.offset;
+        for chunk in &self.chunks {
+            let data = match chunk {
+                Chunk::Data(data) => &data[offset..],
+                Chunk::File(_) => return,
+            };
+            offset = 0;
+            if 0 == n {
+                return;
+            }
+            let data = &data[..n.min(data.len())];
+            f(data);
+            n -= data.len();
+        }
+    }
+
+    /// Forget the first `n` bytes, as they've been written. They're all from a file, if it's
+    /// a file's turn, otherwise none of them are.
+    pub(crate) fn advance(&mut self, mut n: usize) {
+        if let Some(Chunk::File(region)) = self.chunks.front_mut() {
+            assert!(n <= region.len, "advancing past the end of a file");
+            region.offset += n as u64;
+            region.len -= n;
+            if 0 == region.len {
+                self.chunks.pop_front();
+            }
+            return;
+        }
+        assert!(n <= self.len, "advancing past the end");
+        self.len -= n;
+        while 0 != n {
+            let front = match self.chunks.front() {
+                Some(Chunk::Data(front)) => front,
+                _ => unreachable!("len says there's more data before any file"),
+            };
+            let available = front.len() - self.offset;
+            if n < available {
+                self.offset += n;
+                return;
+            }
+            n -= available;
+            self.chunks.pop_front();
+            self.offset = 0;
+        }
+    }
+
+    /// Write as many chunks as the kernel will take in one go, up to the next file; or, if
+    /// it's a file's turn, as much of that as it will take. Never more than `limit` bytes.
+    pub(crate) fn write_to(&self, sock: &Transport, limit: usize) -> io::Result<usize> {
+        if let Some(Chunk::File(region)) = self.chunks.front() {
+            return sock.send_file(&region.file, region.offset, region.len.min(limit));
+        }
+        let front = self.front();
+        if let None | Some(Chunk::File(_)) = self.chunks.get(1) {
+            return io::Write::write(&mut &*sock, &front[..front.len().min(limit)]);
+        }
+        let mut left = limit;
+        let bufs: Vec<&IoVec> = self
+            .chunks
+            .iter()
+            .take(MAX_IOVECS)
+            .map_while(|chunk| match chunk {
+                Chunk::Data(data) => Some(data),
+                Chunk::File(_) => None,
+            })
+            .enumerate()
+            .map(|(i, chunk)| match i {
+                0 => &chunk[self.offset..],
+                _ => &chunk[..],
+            })
+            .map(|data| {
+                let data = &data[..data.len().min(left)];
+                left -= data.len();
+                data
+            })
+            .filter_map(IoVec::from_bytes)
+            .collect();
+        sock.write_bufs(&bufs)
+    }
+
+    /// Add to `out` what `write_to` would write, for a reactor which writes it later, from a
+    /// copy; a file's turn is read from the page cache, a piece at a time.
+    pub(crate) fn copy_to(&self, out: &mut Vec<u8>, limit: usize) -> io::Result<()> {
+        let limit = limit.min(MAX_COPY);
+        if let Some(Chunk::File(region)) = self.chunks.front() {
+            sys::copy_file(out, &region.file, region.offset, region.len.min(limit))?;
+            return Ok(());
+        }
+        let mut offset = self.offset;
+        let mut left = limit;
+        for chunk in self.chunks.iter().take(MAX_IOVECS) {
+            let data = match chunk {
+                Chunk::Data(data) => &data[offset..],
+                Chunk::File(_) => break,
+            };
+            offset = 0;
+            let data = &data[..data.len().min(left)];
+            out.extend_from_slice(data);
+            left -= data.len();
+            if 0 == left {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Buffer for Chunks {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Files may still be waiting, even with nothing in memory.
+    fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Only what's in memory; parts of files still to be sent are dropped.
+    fn into_vec(self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.len);
+        for (i, chunk) in self.chunks.into_iter().enumerate() {
+            if let Chunk::Data(data) = chunk {
+                out.extend_from_slice(&data[if 0 == i { self.offset } else { 0 }..]);
+            }
+        }
+        out
+    }
+}
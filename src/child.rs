@@ -0,0 +1,152 @@
+//! Child processes, talked to over pipes on the loop, instead of with a thread each; see
+//! `Net::spawn`.
+
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::IntoRawFd;
+use std::process;
+use std::process::Command;
+use std::process::Stdio;
+use std::time::Duration;
+
+use log::info;
+use mio::Token;
+
+use crate::pipes::Pipes;
+use crate::reactor::Interest;
+use crate::reactor::Source;
+use crate::sys;
+use crate::timer::Expiry;
+use crate::ChildToken;
+use crate::ConnToken;
+use crate::Error;
+use crate::Event;
+use crate::Net;
+use crate::Operation;
+
+/// Where there's no pidfd to wait on, how often children are checked on.
+const REAP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What `Net::spawn` started.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Spawned {
+    /// For its `Event::ChildExited`.
+    pub child: ChildToken,
+    /// The process ID, e.g. for sending it a signal.
+    pub id: u32,
+    /// Writing sends to its stdin, and its stdout is read; `Io::close_write` closes its stdin.
+    pub stdio: ConnToken,
+    /// Its stderr, which can only be read.
+    pub stderr: ConnToken,
+}
+
+/// A running child, and, if the OS has them, a descriptor which is ready once it exits.
+pub(crate) struct Running {
+    process: process::Child,
+    exited: Option<File>,
+}
+
+impl<T> Net<T> {
+    /// Start `command`, with its stdin, stdout and stderr piped to two new connections; its
+    /// other settings, like arguments and environment, are kept. `Event::ChildExited` follows
+    /// once it exits, which may be before or after everything it wrote has been read.
+    /// Dropping the `Net` leaves the child running.
+    pub fn spawn(&mut self, command: &mut Command) -> Result<Spawned, Error> {
+        let mut process = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::new(Operation::Spawn, e))?;
+        let id = process.id();
+        let stdin = process.stdin.take().map(|p| (p.into_raw_fd(), true));
+        let stdout = process.stdout.take().map(|p| (p.into_raw_fd(), true));
+        let stderr = process.stderr.take().map(|p| (p.into_raw_fd(), true));
+
+        // its pipes are ours either way, so it's left to exit on its own if they fail
+        let stdio = Pipes::new(stdout, stdin).map_err(|e| Error::new(Operation::Spawn, e))?;
+        let stderr = Pipes::new(stderr, None).map_err(|e| Error::new(Operation::Spawn, e))?;
+        let stdio = self.add_pipes(stdio)?;
+        let stderr = match self.add_pipes(stderr) {
+            Ok(stderr) => stderr,
+            Err(e) => {
+                self.io(stdio)?.close();
+                return Err(e);
+            }
+        };
+
+        let token = match self.tokens.reserve() {
+            Ok(token) => token,
+            Err(e) => {
+                self.io(stdio)?.close();
+                self.io(stderr)?.close();
+                return Err(e);
+            }
+        };
+        let exited = match sys::pidfd_open(id) {
+            Ok(fd) => {
+                let raw = fd.as_raw_fd();
+                match self
+                    .reactor
+                    .register(Source::Fd(raw), token, Interest::READABLE)
+                {
+                    Ok(()) => Some(fd),
+                    Err(e) => {
+                        info!("{} pidfd-register-err {:?}", token.0, e);
+                        None
+                    }
+                }
+            }
+            Err(e) => {
+                info!("{} pidfd-err {:?}", token.0, e);
+                None
+            }
+        };
+        if exited.is_none() {
+            let at = self.clock.now() + REAP_INTERVAL;
+            self.deadlines.push(at, token, Expiry::Reap);
+        }
+        info!("{} spawned {}", token.0, id);
+        self.children.insert(token, Running { process, exited });
+        Ok(Spawned {
+            child: ChildToken(token),
+            id,
+            stdio,
+            stderr,
+        })
+    }
+
+    /// Check on a child which may have exited; one which hasn't is checked again later, if
+    /// nothing will say when it does.
+    pub(crate) fn reap(&mut self, token: Token) {
+        let running = match self.children.get_mut(&token) {
+            Some(running) => running,
+            None => return,
+        };
+        let status = match running.process.try_wait() {
+            Ok(Some(status)) => status,
+            Ok(None) => {
+                if running.exited.is_none() {
+                    let at = self.clock.now() + REAP_INTERVAL;
+                    self.deadlines.push(at, token, Expiry::Reap);
+                }
+                return;
+            }
+            Err(e) => {
+                // nothing more will be known about it
+                info!("{} wait-err {:?}", token.0, e);
+                return;
+            }
+        };
+        info!("{} exited {:?}", token.0, status);
+        let running = self.children.remove(&token).expect("just looked");
+        if let Some(fd) = &running.exited {
+            let raw = fd.as_raw_fd();
+            if let Err(e) = self.reactor.deregister(Source::Fd(raw), token) {
+                info!("{} deregister-err {:?}", token.0, e);
+            }
+        }
+        self.events
+            .push(Event::ChildExited(ChildToken(token), status));
+    }
+}
@@ -0,0 +1,168 @@
+//! Framing, so a connection's events are about whole messages instead of however many bytes
+//! happened to arrive; see `Io::set_codec`.
+
+use std::convert::TryFrom;
+use std::io;
+use std::ops::Range;
+
+use crate::config;
+use crate::Error;
+use crate::MAX_BUFFER_LIMIT;
+
+/// Bytes in a `LengthDelimited` header.
+const HEADER: usize = 4;
+
+/// How a connection's bytes are split into frames, and how frames are turned back into bytes.
+pub trait Codec: Send {
+    /// Where the first frame in `buf` is, once all of it has arrived. The same bytes may be
+    /// offered more than once, until the frame is taken. An error resets the connection.
+    fn decode(&mut self, buf: &[u8]) -> io::Result<Option<Frame>>;
+
+    /// Append `payload`, as one frame, to `out`.
+    fn encode(&mut self, payload: &[u8], out: &mut Vec<u8>) -> Result<(), Error>;
+
+    /// How much must be buffered for the biggest acceptable frame to fit.
+    fn max_buffered(&self) -> usize;
+}
+
+/// Where a frame is, at the start of a buffer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// Everything taken from the buffer, including any header or delimiter.
+    pub len: usize,
+    /// The part the application gets; within `..len`.
+    pub payload: Range<usize>,
+}
+
+/// Each frame is its length, as a big-endian `u32`, then that many bytes.
+#[derive(Copy, Clone, Debug)]
+pub struct LengthDelimited {
+    max_frame: usize,
+}
+
+impl LengthDelimited {
+    /// Frames longer than `max_frame`, not counting the header, are refused: a peer which
+    /// sends one is reset. Must be between one and `MAX_BUFFER_LIMIT`.
+    pub fn new(max_frame: usize) -> Result<LengthDelimited, Error> {
+        let max_frame = config::check_limit("max_frame", max_frame, MAX_BUFFER_LIMIT)?;
+        Ok(LengthDelimited { max_frame })
+    }
+
+    pub fn max_frame(&self) -> usize {
+        self.max_frame
+    }
+}
+
+impl Codec for LengthDelimited {
+    fn decode(&mut self, buf: &[u8]) -> io::Result<Option<Frame>> {
+        let header = match buf.get(..HEADER) {
+            Some(header) => <[u8; HEADER]>::try_from(header).expect("sliced to length"),
+            None => return Ok(None),
+        };
+        let len = u32::from_be_bytes(header) as usize;
+        if len > self.max_frame {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} byte frame, max_frame is {}", len, self.max_frame),
+            ));
+        }
+        if buf.len() < HEADER + len {
+            return Ok(None);
+        }
+        Ok(Some(Frame {
+            len: HEADER + len,
+            payload: HEADER..HEADER + len,
+        }))
+    }
+
+    fn encode(&mut self, payload: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        if payload.len() > self.max_frame {
+            return Err(Error::invalid(
+                "frame",
+                payload.len(),
+                "longer than max_frame",
+            ));
+        }
+        out.reserve(HEADER + payload.len());
+        out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        out.extend_from_slice(payload);
+        Ok(())
+    }
+
+    fn max_buffered(&self) -> usize {
+        HEADER + self.max_frame
+    }
+}
+
+/// Each frame is a line, ending in `\n` or `\r\n`, which isn't part of the payload. Frames are
+/// written with just `\n`.
+#[derive(Copy, Clone, Debug)]
+pub struct Lines {
+    max_line: usize,
+}
+
+impl Lines {
+    /// Lines longer than `max_line`, not counting the ending, are refused: a peer which sends
+    /// one is reset. Must be between one and `MAX_BUFFER_LIMIT`.
+    pub fn new(max_line: usize) -> Result<Lines, Error> {
+        let max_line = config::check_limit("max_line", max_line, MAX_BUFFER_LIMIT)?;
+        Ok(Lines { max_line })
+    }
+
+    pub fn max_line(&self) -> usize {
+        self.max_line
+    }
+}
+
+impl Codec for Lines {
+    fn decode(&mut self, buf: &[u8]) -> io::Result<Option<Frame>> {
+        let searched = &buf[..buf.len().min(self.max_buffered())];
+        let too_long = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no line ending within max_line, {}", self.max_line),
+            )
+        };
+        let end = match searched.iter().position(|&b| b == b'\n') {
+            Some(end) => end,
+            None if searched.len() < self.max_buffered() => return Ok(None),
+            None => return Err(too_long()),
+        };
+        let payload = match end.checked_sub(1) {
+            Some(cr) if b'\r' == buf[cr] => 0..cr,
+            _ => 0..end,
+        };
+        if payload.len() > self.max_line {
+            return Err(too_long());
+        }
+        Ok(Some(Frame {
+            len: end + 1,
+            payload,
+        }))
+    }
+
+    fn encode(&mut self, payload: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        if payload.len() > self.max_line {
+            return Err(Error::invalid(
+                "line",
+                payload.len(),
+                "longer than max_line",
+            ));
+        }
+        if payload.contains(&b'\n') {
+            return Err(Error::invalid(
+                "line",
+                payload.len(),
+                "contains a line ending",
+            ));
+        }
+        out.reserve(payload.len() + 1);
+        out.extend_from_slice(payload);
+        out.push(b'\n');
+        Ok(())
+    }
+
+    fn max_buffered(&self) -> usize {
+        self.max_line + "\r\n".len()
+    }
+}
@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use crate::Error;
+
+/// Bytes requested from the kernel by each `read` call.
+pub const DEFAULT_READ_CHUNK: usize = 8 * 1024;
+
+/// A connection stops reading once it has this many unconsumed bytes buffered.
+pub const DEFAULT_WANTED: usize = 8 * 1024;
+
+/// The largest `wanted` (or similar per-connection buffer size) a setter will accept.
+pub const MAX_BUFFER_LIMIT: usize = 1 << 30;
+
+/// Spare buffer capacity a `Net` keeps from closed connections, for new ones to reuse.
+pub const DEFAULT_BUFFER_POOL_BYTES: usize = 1024 * 1024;
+
+/// How many readiness notifications each poll can collect from the kernel.
+pub const DEFAULT_EVENTS_CAPACITY: usize = 32;
+
+/// More than this is probably a mistake, and would be allocated on every poll.
+const MAX_EVENTS_CAPACITY: usize = 64 * 1024;
+
+/// How long `Io::respond_and_close` waits for the peer to take the response.
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many arrived connections a listener asks the OS to queue until they're accepted.
+pub const DEFAULT_BACKLOG: usize = 1024;
+
+/// The most `ListenOptions::backlog` will accept; the OS caps it much lower anyway.
+pub const MAX_BACKLOG: usize = i32::MAX as usize;
+
+/// The longest timeout a setter will accept; anything bigger risks overflowing `Instant`.
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// How many sockets, sources and children a `Net` can hold at once; timers don't count. Half of each token's bits say which
+/// slot it's for, and the other half which use of the slot, so a token isn't reused as soon as
+/// its slot is.
+pub const MAX_TOKENS: usize = crate::tokens::MAX_SLOTS;
+
+/// The settings a `Net` is actually running with.
+#[derive(Clone, Debug)]
+pub struct NetConfig {
+    pub read_chunk: usize,
+    pub wanted: usize,
+    /// Given to every new connection as if by `Io::set_max_read_buffer`.
+    pub max_read_buffer: Option<usize>,
+    pub max_buffered_bytes: Option<usize>,
+    /// Bytes a second, written across every connection.
+    pub max_egress_bps: Option<u64>,
+    /// The most live connections before listeners stop accepting.
+    pub max_connections: Option<usize>,
+    /// The most open connections accepted from any one address.
+    pub max_connections_per_ip: Option<usize>,
+    pub drain_timeout: Duration,
+    /// How often to record `Io::last_tcp_info`, if at all.
+    pub tcp_info_interval: Option<Duration>,
+    /// Whether an `Anomaly` fails `next()` (and panics, in debug builds).
+    pub strict: bool,
+    /// The most spare buffer capacity to keep for reuse; zero keeps none.
+    pub buffer_pool_bytes: usize,
+    pub events_capacity: usize,
+    /// `(low, high)`, given to every new connection as if by `Io::set_write_watermarks`.
+    pub write_watermarks: Option<(usize, usize)>,
+}
+
+impl Default for NetConfig {
+    fn default() -> NetConfig {
+        NetConfig {
+            read_chunk: DEFAULT_READ_CHUNK,
+            wanted: DEFAULT_WANTED,
+            max_read_buffer: None,
+            max_buffered_bytes: None,
+            max_egress_bps: None,
+            max_connections: None,
+            max_connections_per_ip: None,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            tcp_info_interval: None,
+            strict: false,
+            buffer_pool_bytes: DEFAULT_BUFFER_POOL_BYTES,
+            events_capacity: DEFAULT_EVENTS_CAPACITY,
+            write_watermarks: None,
+        }
+    }
+}
+
+/// What this build of the crate supports.
+#[derive(Clone, Debug)]
+pub struct Capabilities {
+    pub defaults: NetConfig,
+    pub max_tokens: usize,
+    /// Whether every pass of the event loop checks its own bookkeeping, and panics if it's off.
+    pub debug_invariants: bool,
+    pub fault_injection: bool,
+    pub kernel_send_queue: bool,
+    pub tcp_info: bool,
+    /// Whether `Net::link_spliced` can actually splice.
+    pub splice: bool,
+    /// Whether there's a `reactor::Uring`; whether the kernel has io_uring is up to `Uring::new`.
+    pub io_uring: bool,
+}
+
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        defaults: NetConfig::default(),
+        max_tokens: MAX_TOKENS,
+        debug_invariants: cfg!(feature = "debug-invariants"),
+        fault_injection: cfg!(feature = "fault-injection"),
+        kernel_send_queue: cfg!(any(
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "ios"
+        )),
+        tcp_info: cfg!(target_os = "linux"),
+        splice: cfg!(target_os = "linux"),
+        io_uring: cfg!(all(target_os = "linux", feature = "io-uring")),
+    }
+}
+
+pub(crate) fn check_limit(setting: &'static str, value: usize, max: usize) -> Result<usize, Error> {
+    if 0 == value {
+        return Err(Error::invalid(setting, value, "must be non-zero"));
+    }
+    if value > max {
+        return Err(Error::invalid(setting, value, "too large"));
+    }
+    Ok(value)
+}
+
+pub(crate) fn check_events_capacity(value: usize) -> Result<usize, Error> {
+    check_limit("events_capacity", value, MAX_EVENTS_CAPACITY)
+}
+
+pub(crate) fn check_watermarks(low: usize, high: usize) -> Result<(usize, usize), Error> {
+    let high = check_limit("high watermark", high, usize::MAX)?;
+    if low >= high {
+        return Err(Error::invalid(
+            "low watermark",
+            low,
+            "must be below the high watermark",
+        ));
+    }
+    Ok((low, high))
+}
+
+pub(crate) fn check_timeout(setting: &'static str, value: Duration) -> Result<Duration, Error> {
+    if Duration::from_secs(0) == value {
+        return Err(Error::invalid(setting, value, "must be non-zero"));
+    }
+    if value > MAX_TIMEOUT {
+        return Err(Error::invalid(setting, value, "longer than MAX_TIMEOUT"));
+    }
+    Ok(value)
+}
+
+/// For `Net::timer` and `Ctx::timer`, where zero means as soon as possible.
+pub(crate) fn check_timer(after: Duration) -> Result<Duration, Error> {
+    if after > MAX_TIMEOUT {
+        return Err(Error::invalid("timer", after, "longer than MAX_TIMEOUT"));
+    }
+    Ok(after)
+}
@@ -0,0 +1,302 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+use mio::Token;
+
+use crate::Direction;
+
+/// What was being attempted when the operating system refused, e.g. `Register` when a new
+/// socket couldn't be added to the poll.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Operation {
+    Poll,
+    Register,
+    Bind,
+    Accept,
+    Connect,
+    Read,
+    Write,
+    SocketQuery,
+    /// Taking on a socket or pipe made elsewhere, like with `Net::adopt_stream`, or `Net::stdio`.
+    Adopt,
+    /// Starting a child process, with `Net::spawn`.
+    Spawn,
+    /// Catching signals, with `Net::signals`.
+    Signals,
+    /// Handing a connection's socket back, with `Net::detach`.
+    Detach,
+    /// Setting something like `Io::set_nodelay`.
+    SocketOption,
+    /// Writing a `Net::record`ing.
+    Record,
+    /// Reading a recording, for `NetBuilder::build_replay`.
+    Replay,
+}
+
+/// Everything which can go wrong, in a form which can be matched on; `operation`, `token`
+/// and friends pick out the details which are present in more than one variant.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The operating system refused something.
+    Os {
+        operation: Operation,
+        context: Context,
+        cause: io::Error,
+    },
+    /// A setter was given a value it can't sensibly use; nothing was changed.
+    InvalidConfig {
+        setting: &'static str,
+        value: String,
+        reason: &'static str,
+    },
+    /// Something odd happened inside the event loop, and `NetConfig::strict` is set.
+    Anomaly(Anomaly),
+    /// The token doesn't name anything the `Net` has: it was never handed out, or what it
+    /// named has since been removed, e.g. it's a connection whose `Event::Closed` was delivered.
+    UnknownToken(Token),
+    /// That side of the connection has been closed, by us or by a failure, so there's nothing
+    /// more to read from it, or nothing more can be written to it.
+    Closed(Token, Direction),
+    /// Every token is in use, so nothing more can be added until something is removed.
+    NoTokens,
+}
+
+/// Things the event loop shrugs off, unless asked to be strict. Every one is counted in
+/// `Metrics::anomalies` either way.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Anomaly {
+    /// `poll` reported readiness for a token we don't have.
+    UnknownToken(Token),
+    /// A `Ctx` or channel command named a connection which has already gone.
+    DeadCommand(Token),
+    /// The peer kept sending after we closed our read side; the bytes were thrown away.
+    ReadAfterClose { token: Token, bytes: usize },
+}
+
+impl Anomaly {
+    pub fn token(&self) -> Token {
+        match *self {
+            Anomaly::UnknownToken(token)
+            | Anomaly::DeadCommand(token)
+            | Anomaly::ReadAfterClose { token, .. } => token,
+        }
+    }
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anomaly::UnknownToken(token) => write!(f, "readiness for unknown token {}", token.0),
+            Anomaly::DeadCommand(token) => write!(f, "command for dead connection {}", token.0),
+            Anomaly::ReadAfterClose { token, bytes } => {
+                write!(f, "{} bytes arrived on {} after close", bytes, token.0)
+            }
+        }
+    }
+}
+
+/// Where an `Error::Os` happened, as far as we know.
+#[derive(Clone, Debug, Default)]
+pub struct Context {
+    token: Option<Token>,
+    peer: Option<SocketAddr>,
+    listener: Option<Token>,
+    addr: Option<SocketAddr>,
+}
+
+impl Error {
+    pub(crate) fn new(operation: Operation, cause: io::Error) -> Error {
+        Error::Os {
+            operation,
+            context: Context::default(),
+            cause,
+        }
+    }
+
+    pub(crate) fn invalid<V: fmt::Debug>(
+        setting: &'static str,
+        value: V,
+        reason: &'static str,
+    ) -> Error {
+        Error::InvalidConfig {
+            setting,
+            value: format!("{:?}", value),
+            reason,
+        }
+    }
+
+    fn context_mut(&mut self) -> Option<&mut Context> {
+        match self {
+            Error::Os { context, .. } => Some(context),
+            Error::InvalidConfig { .. }
+            | Error::Anomaly(_)
+            | Error::UnknownToken(_)
+            | Error::Closed(..)
+            | Error::NoTokens => None,
+        }
+    }
+
+    fn context(&self) -> Option<&Context> {
+        match self {
+            Error::Os { context, .. } => Some(context),
+            Error::InvalidConfig { .. }
+            | Error::Anomaly(_)
+            | Error::UnknownToken(_)
+            | Error::Closed(..)
+            | Error::NoTokens => None,
+        }
+    }
+
+    pub(crate) fn with_token(mut self, token: Token) -> Error {
+        if let Some(context) = self.context_mut() {
+            context.token = Some(token);
+        }
+        self
+    }
+
+    pub(crate) fn with_peer(mut self, peer: Option<SocketAddr>) -> Error {
+        if let Some(context) = self.context_mut() {
+            context.peer = peer;
+        }
+        self
+    }
+
+    pub(crate) fn with_listener(mut self, listener: Token) -> Error {
+        if let Some(context) = self.context_mut() {
+            context.listener = Some(listener);
+        }
+        self
+    }
+
+    pub(crate) fn with_addr(mut self, addr: SocketAddr) -> Error {
+        if let Some(context) = self.context_mut() {
+            context.addr = Some(addr);
+        }
+        self
+    }
+
+    pub fn operation(&self) -> Option<Operation> {
+        match self {
+            Error::Os { operation, .. } => Some(*operation),
+            Error::InvalidConfig { .. }
+            | Error::Anomaly(_)
+            | Error::UnknownToken(_)
+            | Error::Closed(..)
+            | Error::NoTokens => None,
+        }
+    }
+
+    pub fn token(&self) -> Option<Token> {
+        match self {
+            Error::Anomaly(anomaly) => Some(anomaly.token()),
+            Error::UnknownToken(token) | Error::Closed(token, _) => Some(*token),
+            _ => self.context().and_then(|c| c.token),
+        }
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.context().and_then(|c| c.peer)
+    }
+
+    pub fn listener(&self) -> Option<Token> {
+        self.context().and_then(|c| c.listener)
+    }
+
+    /// The local address being bound, for failures while setting up a listener.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.context().and_then(|c| c.addr)
+    }
+
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            Error::Os { cause, .. } => Some(cause),
+            Error::InvalidConfig { .. }
+            | Error::Anomaly(_)
+            | Error::UnknownToken(_)
+            | Error::Closed(..)
+            | Error::NoTokens => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Os {
+                operation,
+                context,
+                cause,
+            } => {
+                write!(f, "{:?} failed", operation)?;
+                if let Some(token) = context.token {
+                    write!(f, " on {}", token.0)?;
+                }
+                if let Some(peer) = context.peer {
+                    write!(f, " (peer {})", peer)?;
+                }
+                if let Some(listener) = context.listener {
+                    write!(f, " (listener {})", listener.0)?;
+                }
+                if let Some(addr) = context.addr {
+                    write!(f, " (address {})", addr)?;
+                }
+                write!(f, ": {}", cause)
+            }
+            Error::InvalidConfig {
+                setting,
+                value,
+                reason,
+            } => write!(f, "invalid {}: {}: {}", setting, value, reason),
+            Error::Anomaly(anomaly) => write!(f, "anomaly: {}", anomaly),
+            Error::UnknownToken(token) => write!(f, "unknown token {}", token.0),
+            Error::Closed(token, direction) => {
+                write!(f, "{:?} side of {} closed", direction, token.0)
+            }
+            Error::NoTokens => write!(f, "out of tokens"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Os { cause, .. } => Some(cause),
+            Error::InvalidConfig { .. }
+            | Error::Anomaly(_)
+            | Error::UnknownToken(_)
+            | Error::Closed(..)
+            | Error::NoTokens => None,
+        }
+    }
+}
+
+/// For `std::io` interfaces, like `Io`'s `Write`. Operating system errors keep their kind.
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        let kind = match &e {
+            Error::Os { cause, .. } => cause.kind(),
+            Error::InvalidConfig { .. } => io::ErrorKind::InvalidInput,
+            Error::Anomaly(_) => io::ErrorKind::Other,
+            Error::UnknownToken(_) => io::ErrorKind::NotFound,
+            Error::Closed(_, Direction::Read) => io::ErrorKind::NotConnected,
+            Error::Closed(_, Direction::Write) => io::ErrorKind::BrokenPipe,
+            Error::NoTokens => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+pub(crate) trait ResultExt<T> {
+    fn during(self, operation: Operation) -> Result<T, Error>;
+}
+
+impl<T> ResultExt<T> for io::Result<T> {
+    fn during(self, operation: Operation) -> Result<T, Error> {
+        self.map_err(|e| Error::new(operation, e))
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum Fault {
+    /// The read fails, e.g. with `ConnectionReset`.
+    ReadError(io::ErrorKind),
+    WriteError(io::ErrorKind),
+    /// The write takes at most this many bytes.
+    ShortWrite(usize),
+    /// The read sees the end of the stream.
+    Eof,
+    /// The read returns at most this many bytes.
+    ShortRead(usize),
+    /// The read finds nothing, as if the readiness had been spurious.
+    ReadWouldBlock,
+    /// The write is refused, as if the kernel's buffer were full.
+    WriteWouldBlock,
+}
+
+/// The faults still to come, for each direction, in order.
+#[derive(Default)]
+pub(crate) struct Pending {
+    read: VecDeque<Scripted>,
+    write: VecDeque<Scripted>,
+}
+
+struct Scripted {
+    /// How many more bytes get through untouched before the fault.
+    after: usize,
+    fault: Fault,
+}
+
+impl Fault {
+    fn is_read(&self) -> bool {
+        match self {
+            Fault::ReadError(_) | Fault::Eof | Fault::ShortRead(_) | Fault::ReadWouldBlock => true,
+            Fault::WriteError(_) | Fault::ShortWrite(_) | Fault::WriteWouldBlock => false,
+        }
+    }
+}
+
+impl Pending {
+    pub(crate) fn inject(&mut self, after: usize, fault: Fault) {
+        let scripted = Scripted { after, fault };
+        if scripted.fault.is_read() {
+            self.read.push_back(scripted);
+        } else {
+            self.write.push_back(scripted);
+        }
+    }
+
+    /// Whether reads are still scripted.
+    pub(crate) fn reading(&self) -> bool {
+        !self.read.is_empty()
+    }
+
+    /// Whether writes are still scripted.
+    pub(crate) fn writing(&self) -> bool {
+        !self.write.is_empty()
+    }
+
+    pub(crate) fn read<R: Read>(&mut self, inner: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+        let next = match self.read.front_mut() {
+            Some(next) => next,
+            None => return inner.read(buf),
+        };
+        // stop short of the fault, so it lands exactly where it was asked for
+        if 0 != next.after {
+            let len = buf.len().min(next.after);
+            let n = inner.read(&mut buf[..len])?;
+            next.after -= n;
+            return Ok(n);
+        }
+        match self.read.pop_front().expect("just looked").fault {
+            Fault::ReadError(kind) => Err(io::Error::new(kind, "injected read fault")),
+            Fault::Eof => Ok(0),
+            Fault::ShortRead(len) => {
+                let len = len.min(buf.len());
+                inner.read(&mut buf[..len])
+            }
+            Fault::ReadWouldBlock => Err(io::ErrorKind::WouldBlock.into()),
+            other => unreachable!("write fault in read slot: {:?}", other),
+        }
+    }
+
+    pub(crate) fn write<W: Write>(&mut self, inner: &mut W, buf: &[u8]) -> io::Result<usize> {
+        let next = match self.write.front_mut() {
+            Some(next) => next,
+            None => return inner.write(buf),
+        };
+        if 0 != next.after {
+            let n = inner.write(&buf[..buf.len().min(next.after)])?;
+            next.after -= n;
+            return Ok(n);
+        }
+        match self.write.pop_front().expect("just looked").fault {
+            Fault::WriteError(kind) => Err(io::Error::new(kind, "injected write fault")),
+            Fault::ShortWrite(len) => inner.write(&buf[..len.min(buf.len())]),
+            Fault::WriteWouldBlock => Err(io::ErrorKind::WouldBlock.into()),
+            other => unreachable!("read fault in write slot: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::io;
+
+use log::debug;
+
+use crate::Direction;
+use crate::Error;
+use crate::Event;
+use crate::Io;
+use crate::ListenerToken;
+use crate::Net;
+
+/// What to do about each event, for `Net::run`. Everything but `on_event` is about one
+/// connection, and is handed it. Unless overridden, a connection is closed once either
+/// direction is done, and everything else is ignored.
+pub trait Handler<T = ()> {
+    /// `listener` accepted a new connection; `Io::peer_addr` says who from.
+    fn on_accept(&mut self, _io: &mut Io<'_, T>, _listener: ListenerToken) {}
+
+    fn on_data(&mut self, _io: &mut Io<'_, T>) {}
+
+    /// Instead of `on_data`, for connections with a codec; see `Io::next_frame`.
+    fn on_frame(&mut self, _io: &mut Io<'_, T>) {}
+
+    /// Instead of `on_data`, once a connection is a WebSocket; see `ws::recv`.
+    fn on_ws_message(&mut self, _io: &mut Io<'_, T>) {}
+
+    /// Instead of `on_data`, once a connection is being served; see `http1::next_request`.
+    fn on_http_request(&mut self, _io: &mut Io<'_, T>) {}
+
+    /// Instead of `on_data`, for `http1::client` connections; see `http1::next_response`.
+    fn on_http_response(&mut self, _io: &mut Io<'_, T>) {}
+
+    fn on_done(&mut self, io: &mut Io<'_, T>, _direction: Direction) {
+        io.close();
+    }
+
+    fn on_error(&mut self, _io: &mut Io<'_, T>, _error: io::Error) {}
+
+    /// Everything else, e.g. `Event::Timer` or `Event::Closed`, with the whole `Net`, so it can
+    /// e.g. set more timers, or `shutdown_gracefully`.
+    fn on_event(&mut self, _net: &mut Net<T>, _event: Event) {}
+}
+
+impl<T> Net<T> {
+    /// Feed every event to `handler`, until `Event::Shutdown`, or an error.
+    pub fn run(&mut self, handler: &mut impl Handler<T>) -> Result<(), Error> {
+        loop {
+            let ev = self.next()?;
+            if let Event::Shutdown = ev {
+                handler.on_event(self, ev);
+                return Ok(());
+            }
+            self.dispatch(handler, ev);
+        }
+    }
+
+    pub(crate) fn dispatch(&mut self, handler: &mut impl Handler<T>, ev: Event) {
+        let token = match &ev {
+            Event::NewConnection { token, .. }
+            | Event::Data(token)
+            | Event::Frame(token)
+            | Event::WsMessage(token)
+            | Event::HttpRequest(token)
+            | Event::HttpResponse(token)
+            | Event::Done(token, _)
+            | Event::Error(token, _) => *token,
+            _ => return handler.on_event(self, ev),
+        };
+        let mut io = match self.io(token) {
+            Ok(io) => io,
+            Err(e) => {
+                debug!("{} not dispatched: {}", token.token().0, e);
+                return handler.on_event(self, ev);
+            }
+        };
+        match ev {
+            Event::NewConnection { listener, .. } => handler.on_accept(&mut io, listener),
+            Event::Data(_) => handler.on_data(&mut io),
+            Event::Frame(_) => handler.on_frame(&mut io),
+            Event::WsMessage(_) => handler.on_ws_message(&mut io),
+            Event::HttpRequest(_) => handler.on_http_request(&mut io),
+            Event::HttpResponse(_) => handler.on_http_response(&mut io),
+            Event::Done(_, direction) => handler.on_done(&mut io, direction),
+            Event::Error(_, error) => handler.on_error(&mut io, error),
+            _ => unreachable!("only connection events get this far"),
+        }
+    }
+}
@@ -0,0 +1,1005 @@
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::str;
+
+use crate::codec::Codec;
+use crate::codec::Frame;
+use crate::config;
+use crate::Bytes;
+use crate::Conn;
+use crate::ConnToken;
+use crate::Error;
+use crate::Io;
+use crate::Net;
+use crate::Operation;
+use crate::PoolOptions;
+use crate::MAX_BUFFER_LIMIT;
+
+/// A request head which hasn't ended after this many bytes is rejected.
+pub const MAX_HEAD: usize = 64 * 1024;
+
+/// The longest response body `Net::http_get` will accept.
+pub const DEFAULT_MAX_BODY: usize = 16 * 1024 * 1024;
+
+/// A chunk's size line, extensions and all, which hasn't ended after this many bytes is rejected.
+const MAX_CHUNK_LINE: usize = 1024;
+
+#[derive(Clone, Debug)]
+pub struct Request<'b> {
+    pub method: &'b str,
+    pub target: &'b str,
+    /// The `x` in `HTTP/1.x`.
+    pub minor_version: u8,
+    pub headers: Vec<(&'b str, &'b [u8])>,
+}
+
+/// A whole request, body and all, from `next_request`; it doesn't borrow the `Net`.
+#[derive(Clone, Debug)]
+pub struct OwnedRequest {
+    pub method: String,
+    pub target: String,
+    /// The `x` in `HTTP/1.x`.
+    pub minor_version: u8,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub body: Bytes,
+}
+
+#[derive(Clone, Debug)]
+pub struct Response<'b> {
+    /// The `x` in `HTTP/1.x`.
+    pub minor_version: u8,
+    pub status: u16,
+    pub reason: &'b str,
+    pub headers: Vec<(&'b str, &'b [u8])>,
+}
+
+/// A whole response, body and all, from `next_response`; it doesn't borrow the `Net`.
+#[derive(Clone, Debug)]
+pub struct OwnedResponse {
+    /// The `x` in `HTTP/1.x`.
+    pub minor_version: u8,
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub body: Bytes,
+    keep_alive: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HttpError {
+    RequestLine,
+    StatusLine,
+    Version,
+    Header,
+    ContentLength,
+    TooLarge,
+    /// The body is longer than `serve`'s `max_body`.
+    BodyTooLarge,
+    /// Bodies sent with a `Transfer-Encoding` other than `chunked` aren't understood.
+    TransferEncoding,
+    /// A chunk's size line is malformed, or its data isn't followed by a line ending.
+    Chunk,
+}
+
+/// Chunked transfer-coding, for a body of unknown length: each frame is the data from one
+/// chunk, and `Io::write_frame` sends each payload as one. An empty frame is the last chunk,
+/// which ends the body (any trailers are dropped); `Io::remove_codec` before reading on.
+#[derive(Copy, Clone, Debug)]
+pub struct Chunked {
+    max_chunk: usize,
+}
+
+/// Where a body is, after the head.
+struct Body {
+    /// How much of the buffer it takes, including any chunk framing.
+    len: usize,
+    /// For a chunked body, where the data in each chunk is, relative to the end of the head.
+    chunks: Option<Vec<Range<usize>>>,
+}
+
+/// Per-connection state, for `serve`.
+pub(crate) struct Server {
+    max_body: usize,
+}
+
+/// Per-connection state, for `client`.
+pub(crate) struct Client {
+    max_body: usize,
+    /// For each request written and not yet answered, oldest first, whether it was a `HEAD`;
+    /// the response to one has no body, whatever its headers say.
+    heads: VecDeque<bool>,
+}
+
+impl<'b> Request<'b> {
+    /// The first header with this name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&'b [u8]> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| *v)
+    }
+
+    /// Whether the client expects the connection to stay open after the response.
+    pub fn keep_alive(&self) -> bool {
+        keep_alive(self.minor_version, list(&self.headers, "connection"))
+    }
+
+    /// The length of the body which follows the head; not included in `parse_request`'s count.
+    pub fn content_length(&self) -> Result<usize, HttpError> {
+        Ok(content_length(&self.headers)?.unwrap_or(0))
+    }
+}
+
+impl<'b> Response<'b> {
+    /// The first header with this name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&'b [u8]> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| *v)
+    }
+
+    /// The `Content-Length`, if there is one. Without one, a response's body (if it's allowed
+    /// one) lasts until the server closes the connection.
+    pub fn content_length(&self) -> Result<Option<usize>, HttpError> {
+        content_length(&self.headers)
+    }
+}
+
+impl OwnedRequest {
+    /// The first header with this name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_slice())
+    }
+
+    /// Whether the client expects the connection to stay open after the response.
+    pub fn keep_alive(&self) -> bool {
+        keep_alive(self.minor_version, list(&self.headers, "connection"))
+    }
+}
+
+impl OwnedResponse {
+    /// The first header with this name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_slice())
+    }
+
+    /// Whether the connection can be used for another request, e.g. by `Io::release_to_pool`.
+    pub fn keep_alive(&self) -> bool {
+        self.keep_alive
+    }
+}
+
+impl Server {
+    /// The length of the first request in `buf`, head and body, once it's all arrived.
+    pub(crate) fn complete(&self, buf: &[u8]) -> Result<Option<usize>, HttpError> {
+        Ok(self
+            .parse(buf)?
+            .map(|(_, head, body)| head + body.len)
+            .filter(|&len| len <= buf.len()))
+    }
+
+    /// The first request's head, how long that is, and where its body will be.
+    fn parse<'b>(&self, buf: &'b [u8]) -> Result<Option<(Request<'b>, usize, Body)>, HttpError> {
+        let (request, head) = match parse_request(buf)? {
+            Some(parsed) => parsed,
+            None => return Ok(None),
+        };
+        if is_chunked(&request.headers)? {
+            let max_len = (MAX_HEAD + self.max_body).saturating_sub(head);
+            let body = dechunk(&buf[head..], self.max_body, max_len)?;
+            return Ok(body.map(|body| (request, head, body)));
+        }
+        match request.content_length()? {
+            body if body > self.max_body => Err(HttpError::BodyTooLarge),
+            body => Ok(Some((request, head, Body::plain(body)))),
+        }
+    }
+}
+
+/// Handle requests for the connection from now on: `Event::HttpRequest` replaces `Event::Data`
+/// once a whole request, body and all, has arrived; see `next_request`. Anything malformed, or
+/// with a body longer than `max_body`, is answered with an error status, and the connection
+/// closed, with just an `Event::Error` to say so. `max_body` must be at most `MAX_BUFFER_LIMIT`.
+pub fn serve<T>(io: &mut Io<'_, T>, max_body: usize) -> Result<(), Error> {
+    if max_body > MAX_BUFFER_LIMIT {
+        return Err(Error::invalid("max_body", max_body, "too large"));
+    }
+    let conn = io.as_conn_mut();
+    conn.read_buffer.raise_wanted(MAX_HEAD + max_body);
+    conn.http = Some(Server { max_body });
+    Ok(())
+}
+
+/// The next whole request, consumed, if there is one. Fails if `serve` hasn't been called.
+pub fn next_request<T>(io: &mut Io<'_, T>) -> Result<Option<OwnedRequest>, Error> {
+    let max_body = match &io.as_conn().http {
+        Some(server) => server.max_body,
+        None => return Err(Error::invalid("http", io.token, "not serving")),
+    };
+    let buf = io.buf()?;
+    let (request, head, body) = match (Server { max_body }).parse(buf) {
+        Ok(Some((request, head, body))) if head + body.len <= buf.len() => (request, head, body),
+        Ok(_) => return Ok(None),
+        Err(e) => {
+            return Err(Error::new(Operation::Read, e.into())
+                .with_token(io.token)
+                .with_peer(Some(io.peer_addr())))
+        }
+    };
+    let mut request = OwnedRequest {
+        method: request.method.to_string(),
+        target: request.target.to_string(),
+        minor_version: request.minor_version,
+        headers: request
+            .headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_vec()))
+            .collect(),
+        body: Bytes::default(),
+    };
+    request.body = body.data(&Io::bytes(io)?, head);
+    io.consume(head + body.len)?;
+    Ok(Some(request))
+}
+
+impl Client {
+    /// The length of the first response in `buf`, head and body, once it's all arrived.
+    pub(crate) fn complete(&self, buf: &[u8], eof: bool) -> Result<Option<usize>, HttpError> {
+        Ok(self.parse(buf, eof)?.map(|(_, head, body)| head + body.len))
+    }
+
+    /// The first whole response's head, how long that is, and where its body is. `eof`
+    /// says the server has finished, which is all that ends a body without a length.
+    fn parse<'b>(
+        &self,
+        buf: &'b [u8],
+        eof: bool,
+    ) -> Result<Option<(Response<'b>, usize, Body)>, HttpError> {
+        let (response, head) = match parse_response(buf)? {
+            Some(parsed) => parsed,
+            None => return Ok(None),
+        };
+        let body = if !self.has_body(&response) {
+            Body::plain(0)
+        } else if is_chunked(&response.headers)? {
+            let max_len = (MAX_HEAD + self.max_body).saturating_sub(head);
+            match dechunk(&buf[head..], self.max_body, max_len)? {
+                Some(body) => body,
+                None => return Ok(None),
+            }
+        } else {
+            let len = if let Some(len) = response.content_length()? {
+                len
+            } else if eof {
+                buf.len() - head
+            } else {
+                // more than has arrived, so it's incomplete, unless it's already too long
+                buf.len() - head + 1
+            };
+            if len > self.max_body {
+                return Err(HttpError::BodyTooLarge);
+            }
+            Body::plain(len)
+        };
+        if head + body.len > buf.len() {
+            return Ok(None);
+        }
+        Ok(Some((response, head, body)))
+    }
+
+    fn has_body(&self, response: &Response<'_>) -> bool {
+        let status = response.status;
+        Some(&true) != self.heads.front() && status >= 200 && 204 != status && 304 != status
+    }
+}
+
+/// Handle responses for the connection from now on: `Event::HttpResponse` replaces
+/// `Event::Data` once a whole response, body and all, has arrived; see `next_response`. A
+/// malformed response, or a body longer than `max_body`, gets the connection reset, with just
+/// an `Event::Error` to say so. `max_body` must be at most `MAX_BUFFER_LIMIT`.
+pub fn client<T>(io: &mut Io<'_, T>, max_body: usize) -> Result<(), Error> {
+    if max_body > MAX_BUFFER_LIMIT {
+        return Err(Error::invalid("max_body", max_body, "too large"));
+    }
+    let conn = io.as_conn_mut();
+    conn.read_buffer.raise_wanted(MAX_HEAD + max_body);
+    conn.http_client = Some(Client {
+        max_body,
+        heads: VecDeque::new(),
+    });
+    Ok(())
+}
+
+/// Write a complete request, with a `Content-Length` if there's a body. `headers` should
+/// include `Host`. A method, target or header which would change the request's meaning, e.g.
+/// with a line break, is refused, and nothing is written.
+pub fn write_request<T>(
+    io: &mut Io<'_, T>,
+    method: &str,
+    target: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<(), Error> {
+    if !is_token(method.as_bytes()) {
+        return Err(Error::invalid("method", method, "not a token"));
+    }
+    if target.is_empty() || !target.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(Error::invalid("target", target, "not a request target"));
+    }
+    check_headers(headers)?;
+    let mut head = format!("{} {} HTTP/1.1\r\n", method, target);
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !body.is_empty() {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("\r\n");
+
+    io.write(head.as_bytes())?;
+    io.write(body)?;
+    if let Some(client) = &mut io.as_conn_mut().http_client {
+        client.heads.push_back("HEAD" == method);
+    }
+    Ok(())
+}
+
+/// The next whole response, consumed, if there is one. Fails if `client` hasn't been called.
+pub fn next_response<T>(io: &mut Io<'_, T>) -> Result<Option<OwnedResponse>, Error> {
+    let conn = io.as_conn();
+    let eof = conn.read_buffer.eof;
+    let client = match &conn.http_client {
+        Some(client) => client,
+        None => return Err(Error::invalid("http", io.token, "not a client")),
+    };
+    let buf = io.buf()?;
+    let (response, head, body) = match client.parse(buf, eof) {
+        Ok(Some(parsed)) => parsed,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            return Err(Error::new(Operation::Read, e.into())
+                .with_token(io.token)
+                .with_peer(Some(io.peer_addr())))
+        }
+    };
+    let delimited = !client.has_body(&response)
+        || response.header("content-length").is_some()
+        || body.chunks.is_some();
+    let interim = response.status < 200;
+    let mut response = OwnedResponse {
+        minor_version: response.minor_version,
+        status: response.status,
+        reason: response.reason.to_string(),
+        headers: response
+            .headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_vec()))
+            .collect(),
+        body: Bytes::default(),
+        keep_alive: delimited
+            && keep_alive(
+                response.minor_version,
+                list(&response.headers, "connection"),
+            ),
+    };
+    response.body = body.data(&Io::bytes(io)?, head);
+    io.consume(head + body.len)?;
+    if !interim {
+        if let Some(client) = &mut io.as_conn_mut().http_client {
+            client.heads.pop_front();
+        }
+    }
+    Ok(Some(response))
+}
+
+impl<T> Net<T> {
+    /// Start a `GET` of `target` from `addr`, on a pooled connection if there's one to spare.
+    /// `Event::HttpResponse` follows, for `next_response`; then `Io::release_to_pool` if the
+    /// response was `keep_alive`, otherwise `Io::close`.
+    pub fn http_get(
+        &mut self,
+        addr: &SocketAddr,
+        host: &str,
+        target: &str,
+    ) -> Result<ConnToken, Error> {
+        let token = self.pooled_connect(addr, &PoolOptions::default())?;
+        let mut io = Io {
+            inner: self,
+            token: token.0,
+        };
+        client(&mut io, DEFAULT_MAX_BODY)?;
+        write_request(&mut io, "GET", target, &[("Host", host)], b"")?;
+        Ok(token)
+    }
+
+    /// Connect to `authority`, a `host:port`, through the HTTP proxy at `proxy`, with a
+    /// `CONNECT`. `Event::Connected` follows only once the proxy has connected onwards, or
+    /// `Event::ConnectFailed` if it won't; nothing can be written before then. `headers` are
+    /// added to the request, e.g. `Proxy-Authorization`. The connection's `peer_addr` is the
+    /// proxy's.
+    pub fn connect_via_http_proxy(
+        &mut self,
+        proxy: &SocketAddr,
+        authority: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<ConnToken, Error> {
+        if authority.is_empty() || !authority.bytes().all(|b| b.is_ascii_graphic()) {
+            return Err(Error::invalid("authority", authority, "not a host:port"));
+        }
+        check_headers(headers)?;
+        let mut request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", authority, authority);
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+
+        let token = self.tcp_connect(proxy)?;
+        let conn = self.conn_mut(token.0).expect("just connected");
+        let before = conn.buffered();
+        conn.enqueue(token.0, |buf| buf.push(request.into_bytes()))?;
+        conn.http_tunnel = true;
+        let after = conn.buffered();
+        self.buffered = self.buffered - before + after;
+        Ok(token)
+    }
+}
+
+/// Answer a `CONNECT` request from `client`, a `serve`d connection, by connecting to
+/// `upstream`, the request's `target` resolved. Once that's connected, the client is told, and
+/// from then on whatever either end sends, the other is sent; if it fails, the client gets a
+/// `502` instead. Either way, the client is no longer served, so stop calling `next_request`.
+///
+/// Neither connection reports anything more but errors, and the upstream's `Event::Connected`
+/// or `Event::ConnectFailed`; when one closes, so does the other. Returns the upstream's token.
+pub fn accept_connect<T>(
+    net: &mut Net<T>,
+    client: ConnToken,
+    upstream: &SocketAddr,
+) -> Result<ConnToken, Error> {
+    match net.io(client)?.as_conn().http {
+        Some(_) => (),
+        None => return Err(Error::invalid("http", client.0, "not serving")),
+    }
+    let upstream = net.tcp_connect(upstream)?;
+    net.conn_mut(client.0).expect("checked above").http = None;
+    net.link_conns(client.0, upstream.0, true);
+    Ok(upstream)
+}
+
+impl Conn {
+    /// Take the proxy's answer to `connect_via_http_proxy` from the read buffer. Returns
+    /// whether the tunnel is now up, at which point the connection is an ordinary one.
+    pub(crate) fn http_tunnel_process(&mut self) -> io::Result<bool> {
+        let buf = match self.read_buffer.buf() {
+            Some(buf) => buf.as_slice(),
+            None => return Ok(false),
+        };
+        let (response, head) = match parse_response(buf)? {
+            Some(parsed) => parsed,
+            None if self.read_buffer.eof => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "HTTP proxy closed before answering CONNECT",
+                ));
+            }
+            None => return Ok(false),
+        };
+        if !(200..300).contains(&response.status) {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!(
+                    "HTTP proxy refused CONNECT: {} {}",
+                    response.status, response.reason
+                ),
+            ));
+        }
+        self.read_buffer
+            .buf_mut()
+            .expect("just parsed from it")
+            .consume(head);
+        self.http_tunnel = false;
+        Ok(true)
+    }
+}
+
+/// Parse a response head from the start of `buf`, as for `parse_request`.
+pub fn parse_response(buf: &[u8]) -> Result<Option<(Response<'_>, usize)>, HttpError> {
+    let end = match head_end(buf)? {
+        Some(end) => end,
+        None => return Ok(None),
+    };
+    let mut lines = head_lines(&buf[..end]);
+
+    let status_line = lines.next().ok_or(HttpError::StatusLine)?;
+    let mut parts = status_line.splitn(3, |&b| b == b' ');
+    let minor_version = match parts.next() {
+        Some(b"HTTP/1.0") => 0,
+        Some(b"HTTP/1.1") => 1,
+        Some(_) => return Err(HttpError::Version),
+        None => return Err(HttpError::StatusLine),
+    };
+    let status = parts
+        .next()
+        .filter(|s| 3 == s.len() && s.iter().all(|b| b.is_ascii_digit()))
+        .map(|s| ascii(s).parse().expect("three digits"))
+        .ok_or(HttpError::StatusLine)?;
+    let reason = parts.next().unwrap_or_default();
+    if !reason
+        .iter()
+        .all(|&b| b == b' ' || b == b'\t' || b.is_ascii_graphic())
+    {
+        return Err(HttpError::StatusLine);
+    }
+
+    Ok(Some((
+        Response {
+            minor_version,
+            status,
+            reason: ascii(reason),
+            headers: parse_headers(lines)?,
+        },
+        end + 4,
+    )))
+}
+
+/// Parse a request head from the start of `buf`. `Ok(None)` means it's incomplete; read more.
+/// On success, also returns how many bytes the head occupied, i.e. how much to `consume`.
+pub fn parse_request(buf: &[u8]) -> Result<Option<(Request<'_>, usize)>, HttpError> {
+    let end = match head_end(buf)? {
+        Some(end) => end,
+        None => return Ok(None),
+    };
+    let mut lines = head_lines(&buf[..end]);
+
+    let request_line = lines.next().ok_or(HttpError::RequestLine)?;
+    let mut parts = request_line.split(|&b| b == b' ');
+    let method = parts
+        .next()
+        .filter(|m| is_token(m))
+        .ok_or(HttpError::RequestLine)?;
+    let target = parts
+        .next()
+        .filter(|t| !t.is_empty() && t.iter().all(|b| b.is_ascii_graphic()))
+        .ok_or(HttpError::RequestLine)?;
+    let minor_version = match parts.next() {
+        Some(b"HTTP/1.0") => 0,
+        Some(b"HTTP/1.1") => 1,
+        Some(_) => return Err(HttpError::Version),
+        None => return Err(HttpError::RequestLine),
+    };
+    if parts.next().is_some() {
+        return Err(HttpError::RequestLine);
+    }
+
+    Ok(Some((
+        Request {
+            method: ascii(method),
+            target: ascii(target),
+            minor_version,
+            headers: parse_headers(lines)?,
+        },
+        end + 4,
+    )))
+}
+
+/// Where the head at the start of `buf` ends, not counting the blank line, if it has.
+fn head_end(buf: &[u8]) -> Result<Option<usize>, HttpError> {
+    match find(buf, b"\r\n\r\n") {
+        Some(end) if end > MAX_HEAD => Err(HttpError::TooLarge),
+        Some(end) => Ok(Some(end)),
+        None if buf.len() > MAX_HEAD => Err(HttpError::TooLarge),
+        None => Ok(None),
+    }
+}
+
+fn head_lines(head: &[u8]) -> impl Iterator<Item = &[u8]> {
+    head.split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+}
+
+fn parse_headers<'b>(
+    lines: impl Iterator<Item = &'b [u8]>,
+) -> Result<Vec<(&'b str, &'b [u8])>, HttpError> {
+    let mut headers = Vec::new();
+    for line in lines {
+        let colon = line
+            .iter()
+            .position(|&b| b == b':')
+            .ok_or(HttpError::Header)?;
+        let name = &line[..colon];
+        if !is_token(name) {
+            return Err(HttpError::Header);
+        }
+        headers.push((ascii(name), trim(&line[colon + 1..])));
+    }
+    Ok(headers)
+}
+
+/// Write a complete response with a `Content-Length`. Unless `keep_alive`, close afterwards.
+/// As with `write_request`, headers which would change the response's meaning are refused.
+pub fn write_response<T>(
+    io: &mut Io<'_, T>,
+    status: u16,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    keep_alive: bool,
+) -> Result<(), Error> {
+    check_headers(headers)?;
+    io.write(response_head(status, headers, Some(body.len()), keep_alive).as_bytes())?;
+    io.write(body)?;
+    if !keep_alive {
+        io.close();
+    }
+    Ok(())
+}
+
+/// Write a response head for a body of unknown length, which follows as `write_chunk`s. The
+/// client must be HTTP/1.1. Unlike `write_response`, this can't close the connection after the
+/// body; do that after the last chunk, unless `keep_alive`.
+pub fn write_response_head<T>(
+    io: &mut Io<'_, T>,
+    status: u16,
+    headers: &[(&str, &str)],
+    keep_alive: bool,
+) -> Result<(), Error> {
+    check_headers(headers)?;
+    io.write(response_head(status, headers, None, keep_alive).as_bytes())
+}
+
+/// Write `data` as one chunk of a body; an empty one is the last chunk. For a request body,
+/// pass `("Transfer-Encoding", "chunked")` to `write_request`, and no body.
+pub fn write_chunk<T>(io: &mut Io<'_, T>, data: &[u8]) -> Result<(), Error> {
+    let mut chunk = Vec::new();
+    encode_chunk(data, &mut chunk);
+    io.write_vec(chunk)
+}
+
+/// What to send back instead of a response, for an error from a `serve`d connection.
+pub(crate) fn refusal(e: &io::Error) -> Option<Vec<u8>> {
+    let status = match e.get_ref()?.downcast_ref::<HttpError>()? {
+        HttpError::TooLarge => 431,
+        HttpError::BodyTooLarge => 413,
+        HttpError::TransferEncoding => 501,
+        _ => 400,
+    };
+    Some(response_head(status, &[], Some(0), false).into_bytes())
+}
+
+/// What `accept_connect` tells the client, once it knows whether the tunnel is up.
+pub(crate) fn tunnel_answer(connected: bool) -> Vec<u8> {
+    if connected {
+        b"HTTP/1.1 200 Connection Established\r\n\r\n".to_vec()
+    } else {
+        response_head(502, &[], Some(0), false).into_bytes()
+    }
+}
+
+/// Refuse a header whose name isn't a token, or whose value could end the line, so nothing
+/// can smuggle in headers, or a whole message, of its own.
+fn check_headers(headers: &[(&str, &str)]) -> Result<(), Error> {
+    for (name, value) in headers {
+        if !is_token(name.as_bytes()) {
+            return Err(Error::invalid("header name", name, "not a token"));
+        }
+        if value.bytes().any(|b| matches!(b, b'\r' | b'\n' | b'\0')) {
+            return Err(Error::invalid(
+                "header value",
+                value,
+                "contains CR, LF or NUL",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// A response head; without a `len`, the body is chunked. `headers` must have been checked.
+fn response_head(
+    status: u16,
+    headers: &[(&str, &str)],
+    len: Option<usize>,
+    keep_alive: bool,
+) -> String {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", status, reason(status));
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    match len {
+        Some(len) => head.push_str(&format!("Content-Length: {}\r\n", len)),
+        None => head.push_str("Transfer-Encoding: chunked\r\n"),
+    }
+    head.push_str(if keep_alive {
+        "Connection: keep-alive\r\n\r\n"
+    } else {
+        "Connection: close\r\n\r\n"
+    });
+    head
+}
+
+/// The length every `Content-Length` gives. Saying the same thing twice is fine, but
+/// anything else is refused: something in between might believe a different one.
+fn content_length<N: AsRef<str>, V: AsRef<[u8]>>(
+    headers: &[(N, V)],
+) -> Result<Option<usize>, HttpError> {
+    let mut len = None;
+    for value in list(headers, "content-length") {
+        let value = str::from_utf8(value)
+            .ok()
+            .filter(|v| !v.is_empty() && v.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|v| v.parse().ok())
+            .ok_or(HttpError::ContentLength)?;
+        if len.is_some_and(|len| len != value) {
+            return Err(HttpError::ContentLength);
+        }
+        len = Some(value);
+    }
+    Ok(len)
+}
+
+/// Whether a body is chunked, the only transfer-coding understood. A length as well is refused,
+/// as for `content_length`.
+fn is_chunked<N: AsRef<str>, V: AsRef<[u8]>>(headers: &[(N, V)]) -> Result<bool, HttpError> {
+    if list(headers, "transfer-encoding").next().is_none() {
+        return Ok(false);
+    }
+    // empty elements are allowed, and mean nothing
+    let mut codings = list(headers, "transfer-encoding").filter(|coding| !coding.is_empty());
+    let chunked = match (codings.next(), codings.next()) {
+        (Some(coding), None) => coding.eq_ignore_ascii_case(b"chunked"),
+        _ => false,
+    };
+    if list(headers, "content-length").next().is_some() {
+        return Err(HttpError::ContentLength);
+    }
+    match chunked {
+        true => Ok(true),
+        false => Err(HttpError::TransferEncoding),
+    }
+}
+
+/// The chunked body at the start of `buf`, once it's all arrived: no more than `max_body` of
+/// data, in no more than `max_len` bytes.
+fn dechunk(buf: &[u8], max_body: usize, max_len: usize) -> Result<Option<Body>, HttpError> {
+    let mut chunks = Vec::new();
+    let (mut len, mut data) = (0, 0);
+    loop {
+        let frame = match chunk(&buf[len..], max_body - data)? {
+            Some(frame) => frame,
+            None if buf.len() > max_len => return Err(HttpError::BodyTooLarge),
+            None => return Ok(None),
+        };
+        let payload = len + frame.payload.start..len + frame.payload.end;
+        len += frame.len;
+        if len > max_len {
+            return Err(HttpError::BodyTooLarge);
+        }
+        if payload.is_empty() {
+            let chunks = Some(chunks);
+            return Ok(Some(Body { len, chunks }));
+        }
+        data += payload.len();
+        chunks.push(payload);
+    }
+}
+
+/// The chunk at the start of `buf`, once it's all arrived. The last chunk, with no data,
+/// includes any trailers.
+fn chunk(buf: &[u8], max_chunk: usize) -> Result<Option<Frame>, HttpError> {
+    let line = match find(&buf[..buf.len().min(MAX_CHUNK_LINE)], b"\r\n") {
+        Some(line) => line,
+        None if buf.len() >= MAX_CHUNK_LINE => return Err(HttpError::Chunk),
+        None => return Ok(None),
+    };
+    // anything after a `;` is an extension, which nobody needs
+    let size = trim(buf[..line].split(|&b| b == b';').next().unwrap_or_default());
+    let size = Some(size)
+        .filter(|s| !s.is_empty() && s.len() <= 16 && s.iter().all(u8::is_ascii_hexdigit))
+        .and_then(|s| usize::from_str_radix(ascii(s), 16).ok())
+        .ok_or(HttpError::Chunk)?;
+    let start = line + 2;
+
+    if 0 == size {
+        let trailers = &buf[start..];
+        let len = if trailers.starts_with(b"\r\n") {
+            start + 2
+        } else {
+            match head_end(trailers)? {
+                Some(end) => start + end + 4,
+                None => return Ok(None),
+            }
+        };
+        let payload = start..start;
+        return Ok(Some(Frame { len, payload }));
+    }
+
+    if size > max_chunk {
+        return Err(HttpError::BodyTooLarge);
+    }
+    let end = start + size;
+    match buf.get(end..end + 2) {
+        None => Ok(None),
+        Some(b"\r\n") => Ok(Some(Frame {
+            len: end + 2,
+            payload: start..end,
+        })),
+        Some(_) => Err(HttpError::Chunk),
+    }
+}
+
+/// One chunk; for the last, empty, one, that's the size line and the blank line which ends
+/// the (absent) trailers.
+fn encode_chunk(data: &[u8], out: &mut Vec<u8>) {
+    let size = format!("{:x}\r\n", data.len());
+    out.reserve(size.len() + data.len() + 2);
+    out.extend_from_slice(size.as_bytes());
+    out.extend_from_slice(data);
+    out.extend_from_slice(b"\r\n");
+}
+
+/// `connection` is every option in the `Connection` headers; `close` wins over anything else.
+fn keep_alive<'h>(minor_version: u8, connection: impl Iterator<Item = &'h [u8]>) -> bool {
+    let mut keep_alive = minor_version >= 1;
+    for option in connection {
+        if option.eq_ignore_ascii_case(b"close") {
+            return false;
+        }
+        if option.eq_ignore_ascii_case(b"keep-alive") {
+            keep_alive = true;
+        }
+    }
+    keep_alive
+}
+
+/// Every element of the comma separated lists in the headers with this name, ignoring case.
+fn list<'h, N: AsRef<str>, V: AsRef<[u8]>>(
+    headers: &'h [(N, V)],
+    name: &'h str,
+) -> impl Iterator<Item = &'h [u8]> {
+    headers
+        .iter()
+        .filter(move |(n, _)| n.as_ref().eq_ignore_ascii_case(name))
+        .flat_map(|(_, v)| v.as_ref().split(|&b| b == b','))
+        .map(trim)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+fn is_token(s: &[u8]) -> bool {
+    !s.is_empty()
+        && s.iter()
+            .all(|&b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn ascii(s: &[u8]) -> &str {
+    str::from_utf8(s).expect("validated as ascii")
+}
+
+fn trim(mut s: &[u8]) -> &[u8] {
+    while let [b' ' | b'\t', rest @ ..] = s {
+        s = rest;
+    }
+    while let [rest @ .., b' ' | b'\t'] = s {
+        s = rest;
+    }
+    s
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HttpError::RequestLine => "malformed request line",
+            HttpError::StatusLine => "malformed status line",
+            HttpError::Version => "unsupported http version",
+            HttpError::Header => "malformed header",
+            HttpError::ContentLength => "invalid content-length",
+            HttpError::TooLarge => "request head too large",
+            HttpError::BodyTooLarge => "request body too large",
+            HttpError::TransferEncoding => "unsupported transfer-encoding",
+            HttpError::Chunk => "malformed chunk",
+        })
+    }
+}
+
+impl error::Error for HttpError {}
+
+impl From<HttpError> for io::Error {
+    fn from(e: HttpError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+impl Body {
+    fn plain(len: usize) -> Body {
+        Body { len, chunks: None }
+    }
+
+    /// The data, from `all`, the buffer the body is in. Chunks are copied together, unless
+    /// there's only one.
+    fn data(&self, all: &Bytes, head: usize) -> Bytes {
+        match self.chunks.as_deref() {
+            None => all.slice(head..head + self.len),
+            Some([]) => Bytes::default(),
+            Some([chunk]) => all.slice(head + chunk.start..head + chunk.end),
+            Some(chunks) => {
+                let mut data = Vec::with_capacity(chunks.iter().map(|c| c.len()).sum());
+                for chunk in chunks {
+                    data.extend_from_slice(&all[head + chunk.start..head + chunk.end]);
+                }
+                Bytes::from(data)
+            }
+        }
+    }
+}
+
+impl Chunked {
+    /// Chunks with more than `max_chunk` of data are refused: a peer which sends one is reset.
+    /// Must be between one and `MAX_BUFFER_LIMIT`.
+    pub fn new(max_chunk: usize) -> Result<Chunked, Error> {
+        let max_chunk = config::check_limit("max_chunk", max_chunk, MAX_BUFFER_LIMIT)?;
+        Ok(Chunked { max_chunk })
+    }
+
+    pub fn max_chunk(&self) -> usize {
+        self.max_chunk
+    }
+}
+
+impl Codec for Chunked {
+    fn decode(&mut self, buf: &[u8]) -> io::Result<Option<Frame>> {
+        Ok(chunk(buf, self.max_chunk)?)
+    }
+
+    fn encode(&mut self, payload: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        if payload.len() > self.max_chunk {
+            return Err(Error::invalid(
+                "chunk",
+                payload.len(),
+                "longer than max_chunk",
+            ));
+        }
+        encode_chunk(payload, out);
+        Ok(())
+    }
+
+    fn max_buffered(&self) -> usize {
+        // the last chunk's trailers can be as long as a head
+        MAX_CHUNK_LINE + self.max_chunk.max(MAX_HEAD + 2) + 2
+    }
+}
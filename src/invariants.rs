@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use crate::ConnToken;
+use crate::Event;
+use crate::Net;
+use crate::OwnedMode;
+use crate::StreamState;
+use crate::UdpToken;
+
+impl<T> Net<T> {
+    /// Panics, with a dump of everything, if the bookkeeping has gone wrong.
+    pub(crate) fn check_invariants(&self) {
+        if let Err(problem) = self.find_violation() {
+            panic!("invariant violated: {}\n{}", problem, self.debug_dump());
+        }
+    }
+
+    fn find_violation(&self) -> Result<(), String> {
+        let mut buffered = 0;
+        let mut conns = 0;
+        let mut per_ip = HashMap::new();
+        for (token, owned) in self.tokens.iter() {
+            if token != owned.token {
+                return Err(format!("{} stored under {}", owned.token.0, token.0));
+            }
+
+            let conn = match &owned.mode {
+                OwnedMode::Conn(conn) => conn,
+                OwnedMode::Udp(udp) => {
+                    buffered += udp.buffered();
+                    continue;
+                }
+                OwnedMode::Server(_) => continue,
+            };
+
+            buffered += conn.buffered();
+            conns += 1;
+            if let Some(ip) = conn.per_ip {
+                *per_ip.entry(ip).or_insert(0) += 1;
+            }
+
+            if let StreamState::AwaitingConfirmation = conn.write_buffer.state {
+                return Err(format!("{} write side awaiting confirmation", token.0));
+            }
+
+            if conn.read_buffer.read_interest() {
+                match conn.read_buffer.state {
+                    StreamState::Normal { .. } | StreamState::AwaitingConfirmation => (),
+                    _ => return Err(format!("{} read interest while closing", token.0)),
+                }
+            }
+        }
+
+        if buffered != self.buffered {
+            return Err(format!(
+                "buffered bytes drifted: counted {}, recorded {}",
+                buffered, self.buffered
+            ));
+        }
+
+        if conns != self.tokens.conns() {
+            return Err(format!(
+                "connections drifted: counted {}, recorded {}",
+                conns,
+                self.tokens.conns()
+            ));
+        }
+
+        if per_ip != self.per_ip {
+            return Err(format!(
+                "per-address counts drifted: counted {:?}, recorded {:?}",
+                per_ip, self.per_ip
+            ));
+        }
+
+        if self.memory_pressure && self.config.max_buffered_bytes.is_none() {
+            return Err("memory pressure without a limit".to_string());
+        }
+
+        if self.at_capacity && self.config.max_connections.is_none() && !self.tokens.exhausted() {
+            return Err("at capacity without a limit".to_string());
+        }
+
+        let mut closed = HashSet::new();
+        for ev in &self.events {
+            if let Event::Closed(ConnToken(token)) = ev {
+                if self.tokens.get(token).is_some() {
+                    return Err(format!("{} closed but still present", token.0));
+                }
+                if !self.final_buffers.contains_key(token) {
+                    return Err(format!("{} closed without final buffers", token.0));
+                }
+                closed.insert(*token);
+                continue;
+            }
+            if let Event::UdpClosed(UdpToken(token)) = ev {
+                if self.tokens.get(token).is_some() {
+                    return Err(format!("{} closed but still present", token.0));
+                }
+                closed.insert(*token);
+                continue;
+            }
+
+            // timers, sources and children never had a slot filled, and listeners can be stopped before
+            // they're announced
+            if let Event::Timer(_)
+            | Event::Ready(_)
+            | Event::ChildExited(..)
+            | Event::Listening { .. } = ev
+            {
+                continue;
+            }
+
+            if let Some(token) = ev.token() {
+                if self.tokens.get(&token).is_none() && !closed.contains(&token) {
+                    return Err(format!("event for unknown token: {:?}", ev));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,29 +1,237 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
+use std::fs::File;
 use std::io;
+#[cfg(not(feature = "fault-injection"))]
 use std::io::Read;
-use std::io::Write;
 use std::mem;
+use std::net::IpAddr;
+use std::net::Shutdown;
 use std::net::SocketAddr;
+use std::process::ExitStatus;
+use std::time::Duration;
+use std::time::Instant;
 
-use failure::Error;
 use log::debug;
 use log::info;
+use log::warn;
 use mio::net::TcpListener;
 use mio::net::TcpStream;
-use mio::Events;
-use mio::PollOpt;
-use mio::Ready;
-use mio::Token;
-use mio_extras::channel as mio_chanel;
-
-const BUF_SIZE: usize = 8 * 1024;
-
-pub struct Net {
-    last_token: usize,
-    tokens: HashMap<Token, Owned>,
-    poll: mio::Poll,
+
+mod adopt;
+mod buffer;
+#[cfg(unix)]
+mod child;
+pub mod codec;
+mod config;
+mod error;
+#[cfg(feature = "fault-injection")]
+mod fault;
+mod handler;
+pub mod http1;
+#[cfg(feature = "debug-invariants")]
+mod invariants;
+mod limits;
+mod link;
+mod memory;
+mod metrics;
+mod mode;
+pub mod os;
+mod pair;
+#[cfg(unix)]
+mod pipes;
+mod pool;
+pub mod prelude;
+mod proxy;
+mod queue;
+pub mod reactor;
+mod record;
+mod remote;
+mod signals;
+mod sim;
+mod sockopt;
+pub mod socks5;
+mod sources;
+mod std_io;
+mod sys;
+mod tap;
+mod teardown;
+mod throttle;
+mod timer;
+mod tokens;
+mod udp;
+pub mod ws;
+
+#[cfg(unix)]
+pub use crate::child::Spawned;
+pub use crate::config::capabilities;
+pub use crate::config::Capabilities;
+pub use crate::config::NetConfig;
+pub use crate::config::DEFAULT_BACKLOG;
+pub use crate::config::DEFAULT_BUFFER_POOL_BYTES;
+pub use crate::config::DEFAULT_DRAIN_TIMEOUT;
+pub use crate::config::DEFAULT_EVENTS_CAPACITY;
+pub use crate::config::DEFAULT_READ_CHUNK;
+pub use crate::config::DEFAULT_WANTED;
+pub use crate::config::MAX_BACKLOG;
+pub use crate::config::MAX_BUFFER_LIMIT;
+pub use crate::config::MAX_TIMEOUT;
+pub use crate::config::MAX_TOKENS;
+pub use crate::error::Anomaly;
+pub use crate::error::Context;
+pub use crate::error::Error;
+pub use crate::error::Operation;
+#[cfg(feature = "fault-injection")]
+pub use crate::fault::Fault;
+pub use crate::handler::Handler;
+pub use crate::limits::IpNet;
+pub use crate::memory::MEMORY_PIPE_CAPACITY;
+pub use crate::metrics::ConnInfo;
+pub use crate::metrics::ConnState;
+pub use crate::metrics::ConnStats;
+pub use crate::metrics::EventAges;
+pub use crate::metrics::Metrics;
+pub use crate::metrics::MetricsSink;
+pub use crate::metrics::NetStats;
+pub use crate::metrics::Wakeups;
+pub use crate::mode::Mode;
+pub use crate::mode::ModeMetrics;
+pub use crate::pair::IoPair;
+pub use crate::pair::PairedIo;
+pub use crate::pool::PoolMetrics;
+pub use crate::pool::PoolOptions;
+pub use crate::record::Replay;
+pub use crate::remote::Remote;
+pub use crate::signals::Signal;
+pub use crate::sim::SimClock;
+pub use crate::sockopt::SocketOptions;
+pub use crate::sys::TcpInfo;
+pub use crate::tap::Tap;
+pub use crate::teardown::Parts;
+pub use crate::tokens::ChildToken;
+pub use crate::tokens::ConnToken;
+pub use crate::tokens::ListenerToken;
+pub use crate::tokens::SourceToken;
+pub use crate::tokens::TimerToken;
+pub use crate::tokens::UdpToken;
+pub use crate::udp::UdpIo;
+pub use bytes::Bytes;
+pub use mio::Token;
+
+use crate::buffer::Buffer;
+use crate::buffer::BufferPool;
+use crate::buffer::Chunks;
+use crate::buffer::FileRegion;
+use crate::buffer::ReadBuf;
+use crate::codec::Codec;
+use crate::codec::Frame;
+use crate::error::ResultExt;
+use crate::memory::Through;
+use crate::memory::Transport;
+use crate::metrics::AcceptRate;
+use crate::pool::Pool;
+use crate::pool::Pooled;
+use crate::queue::EventQueue;
+use crate::reactor::Interest;
+use crate::reactor::MioReactor;
+use crate::reactor::Reactor;
+use crate::reactor::Source;
+use crate::record::Recorder;
+use crate::remote::CommandChannel;
+use crate::sim::Clock;
+use crate::sources::Registered;
+use crate::tap::SharedTap;
+use crate::throttle::Bucket;
+use crate::timer::Deadlines;
+use crate::timer::Expiry;
+use crate::tokens::TimerTokens;
+use crate::tokens::Tokens;
+use crate::udp::Udp;
+
+const SPIN_THRESHOLD: u32 = 1000;
+
+/// The event loop. `T` is whatever the application wants to keep with each connection; see
+/// `Io::set_data`.
+pub struct Net<T = ()> {
+    tokens: Tokens,
+    reactor: Box<dyn Reactor>,
+    clock: Clock,
+    /// In-memory connections to handle as if the reactor had said they were ready.
+    woken: Vec<Token>,
     channel: CommandChannel,
-    events: VecDeque<Event>,
+    events: EventQueue,
+    last_event_created_at: Option<Instant>,
+    event_ages: EventAges,
+    config: NetConfig,
+    buffered: usize,
+    memory_pressure: bool,
+    /// Listeners have stopped accepting, as there are `max_connections`.
+    at_capacity: bool,
+    final_buffers: HashMap<Token, FinalBuffers>,
+    /// The `Closed`s and `ChildExited`s returned by the latest `next()` or `poll_events()`; their
+    /// tokens are freed on the next call, so they can't be reused while they're being handled.
+    delivered: Vec<Token>,
+    in_handler: bool,
+    wakeups: Wakeups,
+    deadlines: Deadlines,
+    first_byte_timeouts: u64,
+    establish_timeouts: u64,
+    pool: Pool,
+    mode_stats: ModeMetrics,
+    anomalies: u64,
+    /// `Net::timer`s which haven't fired or been cancelled, and when they're due.
+    timers: HashMap<Token, Instant>,
+    timer_tokens: TimerTokens,
+    /// `Net::register_source`s; their slots are never filled.
+    sources: HashMap<Token, Registered>,
+    /// `Net::spawn`ed children which haven't been seen to exit; their slots aren't filled either.
+    #[cfg(unix)]
+    children: HashMap<Token, child::Running>,
+    /// Where `Net::signals` are read from, once they've been asked for.
+    #[cfg(unix)]
+    signals: Option<signals::Signals>,
+    /// Connections waiting on `close_after`.
+    deferred_closes: Vec<Token>,
+    /// An error from somewhere which couldn't return it, for the next call to `next()`.
+    deferred_error: Option<Error>,
+    recorder: Option<Recorder>,
+    stats: NetStats,
+    accept_rate: AcceptRate,
+    metrics_sink: Option<Box<dyn MetricsSink>>,
+    /// What `metrics_sink` was last told.
+    reported: NetStats,
+    /// For new connections, from `Net::set_tap`.
+    tap: Option<SharedTap>,
+    /// Open connections accepted from each address, for `NetBuilder::max_connections_per_ip`.
+    per_ip: HashMap<IpAddr, usize>,
+    buffers: BufferPool,
+    /// `shutdown_gracefully` has been called, and `Event::Shutdown` hasn't been queued yet.
+    shutting_down: bool,
+    /// For `NetBuilder::max_egress_bps`.
+    egress: Option<Bucket>,
+    /// `egress` has run dry, so nothing is written until it's topped up.
+    egress_blocked: bool,
+    /// Connections with something to write, as of the latest `reregister`.
+    writers: usize,
+    /// From `Io::set_data`; dropped when the token is released.
+    data: HashMap<Token, T>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ListenOptions {
+    first_byte_timeout: Option<Duration>,
+    establish_timeout: Option<Duration>,
+    mode: Mode,
+    proxy_protocol: bool,
+    max_accepts_per_sec: Option<u32>,
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+    socket_options: SocketOptions,
+    bind: sys::BindOptions,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct NetBuilder {
+    config: NetConfig,
 }
 
 struct Owned {
@@ -31,68 +239,307 @@ struct Owned {
     mode: OwnedMode,
 }
 
+#[allow(clippy::large_enum_variant)]
 enum OwnedMode {
     Server(Server),
     Conn(Conn),
+    Udp(Udp),
 }
 
 struct Server {
     inner: TcpListener,
+    options: ListenOptions,
+    /// Accepts, for `ListenOptions::max_accepts_per_sec`.
+    rate: AcceptRate,
+    /// Stopped until the next second, by `max_accepts_per_sec`.
+    deferred: bool,
 }
 
 struct Conn {
-    inner: TcpStream,
-    read_buffer: Stream,
-    write_buffer: Stream,
+    inner: Transport,
+    /// As accepted, or connected to; still known after the connection breaks.
+    peer: SocketAddr,
+    read_buffer: Stream<ReadBuf>,
+    write_buffer: Stream<Chunks>,
+    #[cfg(feature = "fault-injection")]
+    faults: fault::Pending,
+    first_byte_deadline: Option<Instant>,
+    /// Until `Io::establish` is called.
+    establish_deadline: Option<Instant>,
+    /// For topping up an `Io::set_rate_limit` bucket which has run dry.
+    throttle_deadline: Option<Instant>,
+    /// Set by `respond_and_close`: nobody is listening for events any more.
+    unattended: bool,
+    drain_deadline: Option<Instant>,
+    idle_timeout: Option<Duration>,
+    idle_deadline: Option<Instant>,
+    /// When anything was last read or written.
+    last_active: Instant,
+    created: Instant,
+    bytes_read: u64,
+    bytes_written: u64,
+    /// Read, but not yet written to the `Net::record`ing.
+    recorded: Option<Vec<u8>>,
+    tap: Option<SharedTap>,
+    /// Counted against `NetBuilder::max_connections_per_ip`, as accepted from this address.
+    per_ip: Option<IpAddr>,
+    /// Set by `close_deferred`: close once the events queued before this mark are delivered.
+    close_after: Option<u64>,
+    /// The most recent periodic sample, if `NetConfig::tcp_info_interval` is set.
+    tcp_info: Option<TcpInfo>,
+    peer_closed_policy: PeerClosedPolicy,
+    connecting: bool,
+    /// The interest `reregister` last asked for; `None` if it has to ask again regardless.
+    registered: Option<Interest>,
+    /// The most each read asks the kernel for; see `Io::set_read_limit`.
+    read_chunk: usize,
+    watermarks: Option<Watermarks>,
+    pooled: Option<Pooled>,
+    mode: Mode,
+    /// Bytes generated so far by `Mode::Source`.
+    sourced: u64,
+    /// Set by `Io::set_codec`: report whole frames, not bytes.
+    codec: Option<Box<dyn Codec>>,
+    /// Set by `ws::accept`.
+    ws: Option<ws::Session>,
+    /// Set by `http1::serve`.
+    http: Option<http1::Server>,
+    /// Set by `http1::client`.
+    http_client: Option<http1::Client>,
+    /// From `ListenOptions::proxy_protocol`, until the header has been taken.
+    proxy_pending: bool,
+    /// The client's address, according to the PROXY header, if it gave one.
+    proxied_peer: Option<SocketAddr>,
+    /// Set by `Net::connect_via_socks5`, until the tunnel is up.
+    socks: Option<socks5::Handshake>,
+    /// Set by `Net::connect_via_http_proxy`, until the tunnel is up.
+    http_tunnel: bool,
+    /// Joined to another connection, which gets everything this one reads.
+    link: Option<link::Link>,
+}
+
+/// See `Io::set_write_watermarks`.
+struct Watermarks {
+    high: usize,
+    low: usize,
+    /// Reached `high`, and hasn't yet dropped back to `low`.
+    above: bool,
+}
+
+impl Watermarks {
+    fn new(low: usize, high: usize) -> Watermarks {
+        Watermarks {
+            high,
+            low,
+            above: false,
+        }
+    }
+}
+
+/// What to do with writes once the peer has closed its side of the connection.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PeerClosedPolicy {
+    /// Keep writing, as the peer may still be reading; if it isn't, the failure is reported
+    /// as an `Event::Error`.
+    #[default]
+    ContinueWriting,
+    /// `Io::write` fails immediately.
+    FailFast,
+    /// Accept the write, flush it as best we can, then close our side.
+    DrainAndClose,
 }
 
-struct Stream {
-    state: StreamState,
+struct Stream<B> {
+    state: StreamState<B>,
+    abandoned: Vec<u8>,
+    peer_done: bool,
+    eof: bool,
+    error: Option<io::Error>,
+    credit: Option<usize>,
+    /// Set by `Io::pause_reads`: leave everything with the kernel, however little is buffered.
+    paused: bool,
+    /// A write emptied the buffer, not yet reported as `Event::Flushed`.
+    flushed: bool,
+    /// Bytes read while there was nowhere to put them, not yet reported as an anomaly.
+    discarded: usize,
+    /// Never read so much that more than this is buffered, whatever's `wanted`.
+    max: Option<usize>,
+    /// A read has reached `max`, not yet reported as `Event::ReadBufferFull`.
+    filled: bool,
+    /// From `Io::set_rate_limit`.
+    bucket: Option<Bucket>,
+    /// How much more may be written in this pass, as its part of `NetBuilder::max_egress_bps`.
+    share: Option<usize>,
 }
 
-enum StreamState {
-    Normal { buf: Vec<u8>, wanted: usize },
-    Draining { buf: Vec<u8> },
+enum StreamState<B> {
+    Normal { buf: B, wanted: usize },
+    Draining { buf: B },
     AwaitingConfirmation,
     Done,
 }
 
-enum Command {}
-
-struct CommandChannel {
-    recv: mio_chanel::Receiver<Command>,
-    send: mio_chanel::Sender<Command>,
+enum Command {
+    Write(Token, Vec<u8>),
+    Close(Token),
+    Timer(Token, Duration),
+    CancelTimer(Token),
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Event {
-    NewConnection(Token),
-    Data(Token),
-    Done(Token, Direction),
+    /// A listener is bound; `addr` is what the kernel picked, e.g. the real port for port zero.
+    Listening {
+        token: ListenerToken,
+        addr: SocketAddr,
+    },
+    /// `listener` accepted a connection from `peer`, which is now known as `token`.
+    NewConnection {
+        token: ConnToken,
+        listener: ListenerToken,
+        peer: SocketAddr,
+    },
+    Connected(ConnToken),
+    /// A `tcp_connect` didn't work out; `Closed` follows.
+    ConnectFailed(ConnToken, io::Error),
+    /// A UDP socket has datagrams waiting for `UdpIo::recv_from`.
+    Datagram(UdpToken),
+    /// Sending or receiving on a UDP socket failed, e.g. with an ICMP unreachable for something
+    /// sent earlier; the socket carries on.
+    UdpError(UdpToken, io::Error),
+    /// A UDP socket has been removed, after `UdpIo::close`; the token names nothing from now on.
+    UdpClosed(UdpToken),
+    /// A `Net::timer` is due.
+    Timer(TimerToken),
+    /// A `Net::spawn`ed child has exited; it's been reaped, so there's no zombie.
+    ChildExited(ChildToken, ExitStatus),
+    /// A `Net::register_source` may have become ready for what it was registered for. It's
+    /// not reported again until it's been used up, to `WouldBlock`.
+    Ready(SourceToken),
+    /// Nothing was read or written within the connection's idle timeout, so it's being closed.
+    TimedOut(ConnToken),
+    /// The write buffer has reached the connection's high watermark; consider holding off.
+    WriteBufferHigh(ConnToken),
+    /// After a `WriteBufferHigh`, the write buffer has drained down to the low watermark.
+    WriteBufferLow(ConnToken),
+    /// Everything written so far has been handed to the kernel.
+    Flushed(ConnToken),
+    /// The read buffer has reached `Io::set_max_read_buffer`, so reading has stopped until
+    /// some of it is consumed.
+    ReadBufferFull(ConnToken),
+    Data(ConnToken),
+    /// A connection with a codec has a whole frame waiting for `Io::next_frame`.
+    Frame(ConnToken),
+    /// A WebSocket connection has a whole message waiting for `ws::recv`.
+    WsMessage(ConnToken),
+    /// A connection being `http1::serve`d has a whole request waiting for `next_request`.
+    HttpRequest(ConnToken),
+    /// An `http1::client` connection has a whole response waiting for `next_response`.
+    HttpResponse(ConnToken),
+    Done(ConnToken, Direction),
+    MemoryPressure {
+        buffered: usize,
+    },
+    /// `NetBuilder::max_connections` has been reached, or every token is in use, so listeners
+    /// have stopped accepting; new connections wait in the backlog.
+    AtCapacity {
+        connections: usize,
+    },
+    /// After an `AtCapacity`, connections have closed, and listeners are accepting again.
+    BelowCapacity {
+        connections: usize,
+    },
+    /// The connection has been removed; this is the last event for it, so any state kept for
+    /// the token can be dropped. `Net::io` rejects the token from now on.
+    Closed(ConnToken),
+    Spinning {
+        passes: u32,
+    },
+    /// Why something went wrong with a connection, e.g. `ConnectionReset` from a read, or
+    /// `BrokenPipe` from a write. The `Done` for that direction follows.
+    Error(ConnToken, io::Error),
+    /// Everything open at `Net::shutdown_gracefully` has now been closed.
+    Shutdown,
+    /// A signal asked for with `Net::signals` has arrived. The same signal arriving again
+    /// before it's been read may be reported only once.
+    Signal(Signal),
 }
 
-#[derive(Debug)]
+impl Event {
+    /// The listener or connection the event is about, if any.
+    pub fn token(&self) -> Option<Token> {
+        match self {
+            Event::Listening { token, .. } => Some(token.0),
+            Event::Timer(token) => Some(token.0),
+            Event::Ready(token) => Some(token.0),
+            Event::ChildExited(token, _) => Some(token.0),
+            Event::Datagram(token) | Event::UdpError(token, _) | Event::UdpClosed(token) => {
+                Some(token.0)
+            }
+            Event::NewConnection { token, .. }
+            | Event::Connected(token)
+            | Event::Data(token)
+            | Event::Frame(token)
+            | Event::WsMessage(token)
+            | Event::HttpRequest(token)
+            | Event::HttpResponse(token)
+            | Event::TimedOut(token)
+            | Event::WriteBufferHigh(token)
+            | Event::WriteBufferLow(token)
+            | Event::Flushed(token)
+            | Event::ReadBufferFull(token)
+            | Event::Done(token, _)
+            | Event::Closed(token)
+            | Event::ConnectFailed(token, _)
+            | Event::Error(token, _) => Some(token.0),
+            Event::MemoryPressure { .. }
+            | Event::AtCapacity { .. }
+            | Event::BelowCapacity { .. }
+            | Event::Spinning { .. }
+            | Event::Shutdown
+            | Event::Signal(_) => None,
+        }
+    }
+}
+
+/// What was left in a connection's buffers when it was removed; see `Net::take_final_buffers`.
+#[derive(Debug, Default)]
+pub struct FinalBuffers {
+    pub unread: Vec<u8>,
+    pub unwritten: Vec<u8>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Direction {
     Read,
     Write,
 }
 
-pub struct Io<'n> {
-    inner: &'n mut Net,
+pub struct Io<'n, T = ()> {
+    inner: &'n mut Net<T>,
     token: Token,
 }
 
-impl Default for CommandChannel {
-    fn default() -> CommandChannel {
-        let (send, recv) = mio_chanel::channel();
-        CommandChannel { send, recv }
-    }
+/// What a `with_io` closure may do to the rest of the `Net`. Requests are queued, and
+/// carried out once the closure returns.
+pub struct Ctx {
+    commands: Vec<Command>,
+    metrics: Metrics,
+    timer_tokens: TimerTokens,
 }
 
-impl Stream {
+impl<B: Buffer> Stream<B> {
     fn read_interest(&self) -> bool {
         match &self.state {
-            StreamState::Normal { buf, wanted } => buf.len() < *wanted,
+            StreamState::Normal { .. } if self.paused || self.is_full() || self.is_throttled() => {
+                false
+            }
+            StreamState::Normal { buf, wanted } => match self.credit {
+                Some(credit) => credit > 0,
+                None => buf.len() < *wanted,
+            },
             StreamState::AwaitingConfirmation => true,
             StreamState::Draining { .. } | StreamState::Done => false,
         }
@@ -100,11 +547,72 @@ impl Stream {
 
     fn do_read(&self) -> bool {
         match self.state {
-            StreamState::Normal { .. } | StreamState::AwaitingConfirmation => true,
+            StreamState::Normal { .. } => {
+                !self.paused && Some(0) != self.credit && !self.is_full() && !self.is_throttled()
+            }
+            StreamState::AwaitingConfirmation => true,
             StreamState::Draining { .. } | StreamState::Done => false,
         }
     }
 
+    /// Whether `max` is buffered already.
+    fn is_full(&self) -> bool {
+        match (&self.state, self.max) {
+            (StreamState::Normal { buf, .. }, Some(max)) => buf.len() >= max,
+            _ => false,
+        }
+    }
+
+    /// Whether `Io::set_rate_limit` is holding it back for now.
+    fn is_throttled(&self) -> bool {
+        0 == self.io_limit()
+    }
+
+    /// How much the next read, or write, may take, at most.
+    fn io_limit(&self) -> usize {
+        let bucket = self
+            .bucket
+            .as_ref()
+            .map_or(usize::MAX, |bucket| bucket.available());
+        bucket.min(self.share.unwrap_or(usize::MAX))
+    }
+
+    /// How much the next read may take. Waiting for the peer's end isn't held back by
+    /// anything: what's read is thrown away, and a read of nothing would look like the end.
+    fn read_limit(&self, chunk: usize) -> usize {
+        let chunk = match (&self.state, self.credit) {
+            (StreamState::Normal { .. }, Some(credit)) => credit.min(chunk).min(self.io_limit()),
+            (StreamState::Normal { .. }, None) => chunk.min(self.io_limit()),
+            _ => chunk,
+        };
+        match (&self.state, self.max) {
+            (StreamState::Normal { buf, .. }, Some(max)) => {
+                chunk.min(max.saturating_sub(buf.len()))
+            }
+            _ => chunk,
+        }
+    }
+
+    /// Has no effect once the stream has started closing.
+    fn set_wanted(&mut self, wanted: usize) {
+        if let StreamState::Normal {
+            wanted: current, ..
+        } = &mut self.state
+        {
+            *current = wanted;
+        }
+    }
+
+    /// As `set_wanted`, but never lowers it.
+    fn raise_wanted(&mut self, wanted: usize) {
+        if let StreamState::Normal {
+            wanted: current, ..
+        } = &mut self.state
+        {
+            *current = (*current).max(wanted);
+        }
+    }
+
     fn could_read(&self) -> bool {
         match &self.state {
             StreamState::Normal { buf, .. } | StreamState::Draining { buf } => !buf.is_empty(),
@@ -114,7 +622,9 @@ impl Stream {
 
     fn write_interest(&self) -> bool {
         match &self.state {
-            StreamState::Normal { buf, .. } | StreamState::Draining { buf } => !buf.is_empty(),
+            StreamState::Normal { buf, .. } | StreamState::Draining { buf } => {
+                !buf.is_empty() && !self.is_throttled()
+            }
             StreamState::AwaitingConfirmation => true,
             StreamState::Done => false,
         }
@@ -122,40 +632,57 @@ impl Stream {
 
     fn do_write(&self) -> bool {
         match &self.state {
-            StreamState::Normal { buf, .. } | StreamState::Draining { buf } => !buf.is_empty(),
+            StreamState::Normal { buf, .. } | StreamState::Draining { buf } => {
+                !buf.is_empty() && !self.is_throttled()
+            }
             StreamState::AwaitingConfirmation => true,
             StreamState::Done => false,
         }
     }
 
+    fn len(&self) -> usize {
+        self.buf().map(|buf| buf.len()).unwrap_or(0) + self.abandoned.len()
+    }
+
     fn is_done(&self) -> bool {
-        match self.state {
-            StreamState::Done => true,
-            _ => false,
-        }
+        matches!(self.state, StreamState::Done)
     }
 
-    fn buf(&self) -> Option<&[u8]> {
+    fn buf(&self) -> Option<&B> {
         match &self.state {
             StreamState::Normal { buf, .. } | StreamState::Draining { buf } => Some(buf),
             StreamState::AwaitingConfirmation | StreamState::Done => None,
         }
     }
 
-    fn buf_mut(&mut self) -> Option<&mut Vec<u8>> {
+    fn buf_mut(&mut self) -> Option<&mut B> {
         match &mut self.state {
             StreamState::Normal { buf, .. } | StreamState::Draining { buf } => Some(buf),
             StreamState::AwaitingConfirmation | StreamState::Done => None,
         }
     }
 
+    fn abandon(&mut self, buf: B) {
+        if self.abandoned.is_empty() {
+            self.abandoned = buf.into_vec();
+        } else {
+            self.abandoned.extend_from_slice(&buf.into_vec());
+        }
+    }
+
     fn become_at_least_truncating_close(&mut self) {
         debug!("become-truncating-close");
-        self.state = match self.state {
-            StreamState::Normal { .. }
-            | StreamState::Draining { .. }
-            | StreamState::AwaitingConfirmation => StreamState::AwaitingConfirmation,
-            StreamState::Done => StreamState::Done,
+        let state = mem::replace(&mut self.state, StreamState::Done);
+        self.state = match state {
+            StreamState::Normal { buf, .. } => {
+                self.abandon(buf);
+                StreamState::AwaitingConfirmation
+            }
+            StreamState::Draining { buf } => {
+                self.abandon(buf);
+                StreamState::Done
+            }
+            other => other,
         };
     }
 
@@ -169,22 +696,297 @@ impl Stream {
 
     fn totes_done(&mut self) {
         debug!("totes-done");
-        self.state = StreamState::Done;
+        match mem::replace(&mut self.state, StreamState::Done) {
+            StreamState::Normal { buf, .. } | StreamState::Draining { buf } => self.abandon(buf),
+            StreamState::AwaitingConfirmation | StreamState::Done => (),
+        }
+    }
+
+    /// The peer will send no more: keep what we have if we still care, otherwise we're finished.
+    fn peer_eof(&mut self) {
+        self.eof = true;
+        match self.state {
+            StreamState::Normal { .. } => {
+                self.become_at_least_draining_close();
+                self.peer_done = true;
+            }
+            StreamState::AwaitingConfirmation => self.totes_done(),
+            StreamState::Draining { .. } | StreamState::Done => (),
+        }
+    }
+
+    /// A draining stream which has run dry is done.
+    fn settle(&mut self) -> bool {
+        match &self.state {
+            StreamState::Draining { buf } if buf.is_empty() => {
+                self.totes_done();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn describe(&self) -> String {
+        let state = match self.state {
+            StreamState::Normal { .. } => "normal",
+            StreamState::Draining { .. } => "draining",
+            StreamState::AwaitingConfirmation => "awaiting-confirmation",
+            StreamState::Done => "done",
+        };
+        format!("{} ({} bytes)", state, self.len())
+    }
+
+    fn into_remains(mut self) -> Vec<u8> {
+        self.totes_done();
+        self.abandoned
     }
 }
 
-impl Default for Stream {
-    fn default() -> Stream {
+impl<B: Buffer> Default for Stream<B> {
+    fn default() -> Stream<B> {
         Stream {
             state: StreamState::Normal {
-                buf: Vec::new(),
-                wanted: 8 * 1024,
+                buf: B::default(),
+                wanted: DEFAULT_WANTED,
             },
+            abandoned: Vec::new(),
+            peer_done: false,
+            eof: false,
+            error: None,
+            credit: None,
+            paused: false,
+            flushed: false,
+            discarded: 0,
+            max: None,
+            filled: false,
+            bucket: None,
+            share: None,
+        }
+    }
+}
+
+impl Conn {
+    fn new(inner: Transport, peer: SocketAddr, now: Instant) -> Conn {
+        Conn {
+            inner,
+            peer,
+            read_buffer: Stream::default(),
+            write_buffer: Stream::default(),
+            #[cfg(feature = "fault-injection")]
+            faults: fault::Pending::default(),
+            first_byte_deadline: None,
+            establish_deadline: None,
+            throttle_deadline: None,
+            unattended: false,
+            drain_deadline: None,
+            idle_timeout: None,
+            idle_deadline: None,
+            last_active: now,
+            created: now,
+            bytes_read: 0,
+            bytes_written: 0,
+            recorded: None,
+            tap: None,
+            per_ip: None,
+            close_after: None,
+            tcp_info: None,
+            peer_closed_policy: PeerClosedPolicy::default(),
+            connecting: false,
+            registered: None,
+            read_chunk: DEFAULT_READ_CHUNK,
+            watermarks: None,
+            pooled: None,
+            mode: Mode::Events,
+            sourced: 0,
+            codec: None,
+            ws: None,
+            http: None,
+            http_client: None,
+            proxy_pending: false,
+            proxied_peer: None,
+            socks: None,
+            http_tunnel: false,
+            link: None,
+        }
+    }
+
+    /// Connected to a proxy, which hasn't yet connected onwards.
+    fn tunnelling(&self) -> bool {
+        self.socks.is_some() || self.http_tunnel
+    }
+
+    fn is_pooled_idle(&self) -> bool {
+        self.pooled.as_ref().is_some_and(|p| p.is_idle())
+    }
+
+    /// `None` while an outbound connection is still in progress.
+    fn connect_result(&self) -> Option<io::Result<()>> {
+        match self.inner.take_error() {
+            Ok(Some(e)) | Err(e) => return Some(Err(e)),
+            Ok(None) => (),
+        }
+        match self.inner.peer_addr() {
+            Ok(_) => Some(Ok(())),
+            Err(ref e) if io::ErrorKind::NotConnected == e.kind() => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    fn buffered(&self) -> usize {
+        self.read_buffer.len() + self.write_buffer.len()
+    }
+
+    /// `Some(true)` on reaching the high watermark, `Some(false)` on dropping back to the low.
+    fn cross_watermarks(&mut self) -> Option<bool> {
+        let len = self.write_buffer.len();
+        let marks = self.watermarks.as_mut()?;
+        if !marks.above && len >= marks.high {
+            marks.above = true;
+            return Some(true);
+        }
+        if marks.above && len <= marks.low {
+            marks.above = false;
+            return Some(false);
+        }
+        None
+    }
+
+    fn enqueue(&mut self, token: Token, add: impl FnOnce(&mut Chunks)) -> Result<(), Error> {
+        if self.tunnelling() {
+            let msg = "proxy handshake in progress";
+            return Err(self.write_error(token, io::ErrorKind::NotConnected, msg));
+        }
+        if self.read_buffer.eof && PeerClosedPolicy::FailFast == self.peer_closed_policy {
+            return Err(self.write_error(token, io::ErrorKind::BrokenPipe, "peer has closed"));
+        }
+
+        match self.write_buffer.buf_mut() {
+            Some(buf) => add(buf),
+            None => return Err(Error::Closed(token, Direction::Write)),
+        }
+
+        if self.read_buffer.eof && PeerClosedPolicy::DrainAndClose == self.peer_closed_policy {
+            self.write_buffer.become_at_least_draining_close();
+        }
+
+        Ok(())
+    }
+
+    /// `Data` for anything unconsumed or, with a codec, `Frame` once a whole one has arrived,
+    /// or for a WebSocket, `WsMessage`, or for HTTP, `HttpRequest` or `HttpResponse`. Fails if
+    /// what's arrived makes no sense.
+    fn read_event(&mut self, token: Token) -> io::Result<Option<Event>> {
+        let token = ConnToken(token);
+        if self.proxy_pending {
+            let header = match self.read_buffer.buf() {
+                Some(buf) => proxy::parse(buf.as_slice())?,
+                None => None,
+            };
+            let (source, len) = match header {
+                Some(header) => header,
+                None => return Ok(None),
+            };
+            self.read_buffer
+                .buf_mut()
+                .expect("just parsed from it")
+                .consume(len);
+            self.proxied_peer = source;
+            self.proxy_pending = false;
+        }
+        if self.socks.is_some() {
+            let connected = self.socks_process()?;
+            return Ok(Some(Event::Connected(token)).filter(|_| connected));
+        }
+        if self.http_tunnel {
+            let connected = self.http_tunnel_process()?;
+            return Ok(Some(Event::Connected(token)).filter(|_| connected));
+        }
+        if self.ws.is_some() {
+            let waiting = self.ws_process()?;
+            return Ok(Some(Event::WsMessage(token)).filter(|_| waiting));
+        }
+        if let (Some(server), Some(buf)) = (&self.http, self.read_buffer.buf()) {
+            let complete = server.complete(buf.as_slice())?;
+            return Ok(complete.map(|_| Event::HttpRequest(token)));
+        }
+        if let (Some(client), Some(buf)) = (&self.http_client, self.read_buffer.buf()) {
+            let complete = client.complete(buf.as_slice(), self.read_buffer.eof)?;
+            return Ok(complete.map(|_| Event::HttpResponse(token)));
+        }
+        if !self.read_buffer.could_read() {
+            return Ok(None);
+        }
+        match &self.codec {
+            None => Ok(Some(Event::Data(token))),
+            Some(_) => Ok(self.decode()?.map(|_| Event::Frame(token))),
+        }
+    }
+
+    /// Where the codec says the next frame is; never anything without a codec, or a read buffer.
+    fn decode(&mut self) -> io::Result<Option<Frame>> {
+        let frame = match (&mut self.codec, self.read_buffer.buf()) {
+            (Some(codec), Some(buf)) => codec.decode(buf.as_slice())?,
+            _ => return Ok(None),
+        };
+        if let Some(frame) = &frame {
+            let buffered = self.read_buffer.len();
+            assert!(
+                frame.len <= buffered && frame.payload.end <= frame.len,
+                "codec returned {:?}, with only {} bytes buffered",
+                frame,
+                buffered
+            );
+        }
+        Ok(frame)
+    }
+
+    fn write_error(&self, token: Token, kind: io::ErrorKind, msg: &str) -> Error {
+        Error::new(Operation::Write, io::Error::new(kind, msg))
+            .with_token(token)
+            .with_peer(Some(self.peer))
+    }
+
+    /// Queue `bytes`, close, and stop reporting anything but the final `Event::Closed`.
+    /// Returns the drain deadline, which the caller must arm.
+    fn respond_and_close(
+        &mut self,
+        token: Token,
+        bytes: &[u8],
+        drain_timeout: Duration,
+        now: Instant,
+    ) -> (Result<(), Error>, Instant) {
+        let written = match self.write_buffer.buf_mut() {
+            Some(buf) => {
+                buf.extend_from_slice(bytes);
+                Ok(())
+            }
+            None => Err(Error::Closed(token, Direction::Write)),
+        };
+        self.read_buffer.become_at_least_truncating_close();
+        self.write_buffer.become_at_least_draining_close();
+        self.unattended = true;
+        let at = now + drain_timeout;
+        self.drain_deadline = Some(at);
+        (written, at)
+    }
+
+    fn take_errors(&mut self) -> impl Iterator<Item = io::Error> {
+        let read = self.read_buffer.error.take();
+        let write = self.write_buffer.error.take();
+        read.into_iter().chain(write)
+    }
+
+    /// Give up on the connection, and ask the kernel to reset it instead of closing politely.
+    fn abort(&mut self) {
+        if let Err(e) = self.inner.reset_on_close() {
+            info!("set-linger-err {:?}", e);
         }
+        self.read_buffer.totes_done();
+        self.write_buffer.totes_done();
     }
 }
 
-impl<'n> Io<'n> {
+impl<'n, T> Io<'n, T> {
     fn as_conn(&self) -> &Conn {
         match self
             .inner
@@ -211,269 +1013,2116 @@ impl<'n> Io<'n> {
         }
     }
 
-    pub fn buf(&self) -> &[u8] {
-        self.as_conn()
-            .read_buffer
-            .buf()
-            .expect("TODO: buf: read buffer closed")
+    /// What's been read and not yet consumed. Fails once the read side has been closed, e.g.
+    /// by `close`.
+    pub fn buf(&self) -> Result<&[u8], Error> {
+        match self.as_conn().read_buffer.buf() {
+            Some(buf) => Ok(buf.as_slice()),
+            None => Err(Error::Closed(self.token, Direction::Read)),
+        }
     }
 
-    pub fn consume(&mut self, len: usize) {
-        drop(
-            self.as_conn_mut()
-                .read_buffer
-                .buf_mut()
-                .expect("TODO: consume: read buffer closed")
-                .drain(..len),
-        )
+    /// The same bytes as `buf`, but as a `Bytes` which doesn't borrow the `Net`. Nothing is
+    /// copied now; `consume` still decides what's seen next time. If the `Bytes` (or anything
+    /// sliced from it) is still alive when more arrives, whatever's unconsumed is copied then.
+    pub fn bytes(&mut self) -> Result<Bytes, Error> {
+        match self.as_conn_mut().read_buffer.buf_mut() {
+            Some(buf) => Ok(buf.bytes()),
+            None => Err(Error::Closed(self.token, Direction::Read)),
+        }
     }
 
-    pub fn write(&mut self, data: &[u8]) {
-        self.as_conn_mut()
-            .write_buffer
-            .buf_mut()
-            .expect("TODO: write buffer closed")
-            .extend_from_slice(data)
+    /// Everything buffered, moved out without being copied, as if `consume`d.
+    pub fn take_buf(&mut self) -> Result<Vec<u8>, Error> {
+        self.take_up_to(usize::MAX)
     }
 
-    pub fn close(&mut self) -> () {
-        let conn = self.as_conn_mut();
-        conn.read_buffer.become_at_least_truncating_close();
-        conn.write_buffer.become_at_least_draining_close();
+    /// As `take_buf`, but at most `n` bytes; anything less than everything is copied.
+    pub fn take_up_to(&mut self, n: usize) -> Result<Vec<u8>, Error> {
+        let token = self.token;
+        self.with_conn(|conn| match conn.read_buffer.buf_mut() {
+            Some(buf) => Ok(buf.take(n)),
+            None => Err(Error::Closed(token, Direction::Read)),
+        })
     }
-}
 
-const COMMANDS_TOKEN: Token = Token(0);
+    /// Everything up to and including the first `delim`, consumed, or `None` (consuming
+    /// nothing) if it hasn't arrived yet. Anything left after the peer finishes, without a
+    /// `delim`, is still only available through `buf`.
+    pub fn read_until(&mut self, delim: u8) -> Result<Option<Vec<u8>>, Error> {
+        match self.buf()?.iter().position(|&b| b == delim) {
+            Some(end) => self.take_up_to(end + 1).map(Some),
+            None => Ok(None),
+        }
+    }
 
-impl Net {
-    pub fn empty() -> Result<Net, Error> {
-        let poll = mio::Poll::new()?;
-        let channel = CommandChannel::default();
-        poll.register(
-            &channel.recv,
-            COMMANDS_TOKEN,
-            Ready::readable(),
-            PollOpt::edge(),
-        )?;
-        Ok(Net {
-            last_token: 1,
-            poll,
-            tokens: Default::default(),
-            channel,
-            events: VecDeque::new(),
+    /// As `read_until(b'\n')`, but without the `\n`, or a `\r` before it.
+    pub fn read_line(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.read_until(b'\n')?.map(|mut line| {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            line
+        }))
+    }
+
+    fn with_conn<R>(&mut self, f: impl FnOnce(&mut Conn) -> R) -> R {
+        let before = self.as_conn().buffered();
+        let ret = f(self.as_conn_mut());
+        let after = self.as_conn().buffered();
+        self.inner.buffered = self.inner.buffered - before + after;
+        ret
+    }
+
+    /// Forget the first `len` bytes of `buf`, or all of them if there aren't that many.
+    /// Returns how many were forgotten. Fails once the read side has been closed.
+    pub fn consume(&mut self, len: usize) -> Result<usize, Error> {
+        let token = self.token;
+        self.with_conn(|conn| match conn.read_buffer.buf_mut() {
+            Some(buf) => {
+                let len = len.min(buf.len());
+                buf.consume(len);
+                Ok(len)
+            }
+            None => Err(Error::Closed(token, Direction::Read)),
         })
     }
 
-    fn bump_token(&mut self) -> Token {
-        self.last_token = self.last_token.checked_add(1).expect("out of tokens!");
-        Token(self.last_token)
+    /// Forget everything in `buf`, returning how much that was.
+    pub fn consume_all(&mut self) -> Result<usize, Error> {
+        self.consume(usize::MAX)
     }
 
-    pub fn tcp_listen(&mut self, addr: &SocketAddr) -> Result<(), Error> {
-        let inner = TcpListener::bind(addr)?;
-        let token = self.bump_token();
-        self.poll
-            .register(&inner, token, Ready::readable(), PollOpt::edge())?;
-        self.tokens.insert(
-            token,
-            Owned {
-                token,
-                mode: OwnedMode::Server(Server { inner }),
-            },
-        );
-        Ok(())
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.enqueue(|buf| buf.extend_from_slice(data))
     }
 
-    pub fn next(&mut self) -> Result<Event, Error> {
-        while self.events.is_empty() {
-            self.fill()?;
-        }
+    /// As `write`, but large buffers are queued as they are, instead of being copied.
+    pub fn write_vec(&mut self, data: Vec<u8>) -> Result<(), Error> {
+        self.enqueue(|buf| buf.push(data))
+    }
 
-        Ok(self.events.pop_front().expect("non-empty"))
+    /// Queue `len` bytes of `file`, from `offset`, after anything already queued. They're sent
+    /// straight from the file, with `sendfile(2)` where there is one, so don't count towards
+    /// the write buffer's size, or watermarks. If the file can't be read, or turns out to be
+    /// shorter, the connection is reset, with an `Event::Error` (of kind `UnexpectedEof`, if
+    /// it was short).
+    pub fn send_file(&mut self, file: File, offset: u64, len: usize) -> Result<(), Error> {
+        self.enqueue(|buf| buf.push_file(FileRegion { file, offset, len }))
     }
 
-    pub fn io(&mut self, token: Token) -> Io {
-        Io { inner: self, token }
+    fn enqueue(&mut self, add: impl FnOnce(&mut Chunks)) -> Result<(), Error> {
+        let token = self.token;
+        let queued = self.with_conn(|conn| conn.enqueue(token, add));
+        // now, not on the next pass: a peer which isn't reading may never cause one
+        self.report_watermarks();
+        queued
     }
 
-    fn close_some(&mut self) -> Result<(), Error> {
-        let mut to_close = Vec::new();
-        for (token, owned) in &self.tokens {
-            match &owned.mode {
-                OwnedMode::Server(_) => continue,
-                OwnedMode::Conn(conn) => {
-                    if conn.read_buffer.is_done() && conn.write_buffer.is_done() {
-                        info!("{} closing", token.0);
-                        to_close.push(*token);
-                    }
-                }
-            }
-        }
+    /// Split what's read into frames: `Event::Frame` replaces `Event::Data`, and `next_frame`
+    /// and `write_frame` replace `buf` and `write`. `wanted` is raised so the biggest frame fits;
+    /// lowering it again could leave a frame which never finishes arriving.
+    pub fn set_codec(&mut self, codec: impl Codec + 'static) {
+        let conn = self.as_conn_mut();
+        conn.read_buffer.raise_wanted(codec.max_buffered());
+        conn.codec = Some(Box::new(codec));
+    }
 
-        for close in to_close {
-            drop(self.tokens.remove(&close).expect("it was just there"));
-        }
+    /// Stop splitting what's read into frames, e.g. once a codec's last frame has been taken;
+    /// `Event::Data` is reported again, for anything left. Returns the codec, if there was one.
+    pub fn remove_codec(&mut self) -> Option<Box<dyn Codec>> {
+        self.as_conn_mut().codec.take()
+    }
 
-        Ok(())
+    fn codec_mut(&mut self) -> Result<&mut dyn Codec, Error> {
+        let token = self.token;
+        match &mut self.as_conn_mut().codec {
+            Some(codec) => Ok(codec.as_mut()),
+            None => Err(Error::invalid("codec", token, "not set")),
+        }
     }
 
-    fn reregister(&mut self) -> Result<(), Error> {
-        for (token, owned) in &self.tokens {
-            match &owned.mode {
-                OwnedMode::Server(_) => continue,
-                OwnedMode::Conn(conn) => {
-                    let mut interest = Ready::empty();
+    /// The payload of the next whole frame, consumed, or `None` if it hasn't all arrived yet.
+    /// Nothing is copied; the `Bytes` shares the read buffer, as for `bytes`.
+    pub fn next_frame(&mut self) -> Result<Option<Bytes>, Error> {
+        // the errors for no codec, or no read buffer; `decode` just says there's nothing
+        self.codec_mut()?;
+        self.buf()?;
+        let frame = match self.as_conn_mut().decode() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                return Err(Error::new(Operation::Read, e)
+                    .with_token(self.token)
+                    .with_peer(Some(self.peer_addr())))
+            }
+        };
+        let payload = Io::bytes(self)?.slice(frame.payload);
+        self.consume(frame.len)?;
+        Ok(Some(payload))
+    }
 
-                    if conn.read_buffer.read_interest() {
-                        interest |= Ready::readable();
-                    }
+    /// Queue `payload` as one frame.
+    pub fn write_frame(&mut self, payload: &[u8]) -> Result<(), Error> {
+        let mut frame = Vec::new();
+        self.codec_mut()?.encode(payload, &mut frame)?;
+        self.write_vec(frame)
+    }
 
-                    if !conn.write_buffer.write_interest() {
-                        interest |= Ready::writable();
-                    }
+    /// Emit `Event::WriteBufferHigh` once this many bytes are waiting to be written, then
+    /// `Event::WriteBufferLow` once it's drained to `low`, and so on. `low` must be below `high`.
+    pub fn set_write_watermarks(&mut self, low: usize, high: usize) -> Result<(), Error> {
+        let (low, high) = config::check_watermarks(low, high)?;
+        self.as_conn_mut().watermarks = Some(Watermarks::new(low, high));
+        self.report_watermarks();
+        Ok(())
+    }
 
-                    self.poll
-                        .reregister(&conn.inner, *token, interest, PollOpt::edge())?;
-                }
-            }
+    fn report_watermarks(&mut self) {
+        let token = self.token;
+        if let Some(true) = self.as_conn_mut().cross_watermarks() {
+            self.inner
+                .events
+                .push(Event::WriteBufferHigh(ConnToken(token)));
         }
+    }
 
+    /// Keep reading until this many bytes are buffered. Must be between one and `MAX_BUFFER_LIMIT`.
+    /// Leaves credit mode, if `grant_read_credit` had been used.
+    pub fn want(&mut self, wanted: usize) -> Result<(), Error> {
+        let wanted = config::check_limit("wanted", wanted, MAX_BUFFER_LIMIT)?;
+        let read_buffer = &mut self.as_conn_mut().read_buffer;
+        read_buffer.credit = None;
+        read_buffer.set_wanted(wanted);
         Ok(())
     }
 
-    fn fill(&mut self) -> Result<(), Error> {
-        self.close_some()?;
+    /// Stop reading once this many bytes are buffered, however much is `want`ed, or granted by
+    /// `grant_read_credit`, with an `Event::ReadBufferFull`; reading resumes as it's consumed.
+    /// A frame, or request, which can't fit is never seen. Must be between one and
+    /// `MAX_BUFFER_LIMIT`.
+    pub fn set_max_read_buffer(&mut self, bytes: usize) -> Result<(), Error> {
+        let bytes = config::check_limit("max_read_buffer", bytes, MAX_BUFFER_LIMIT)?;
+        self.as_conn_mut().read_buffer.max = Some(bytes);
+        Ok(())
+    }
 
-        self.reregister()?;
+    /// Ask the kernel for at most this many bytes per read; bigger suits bulk transfers,
+    /// smaller suits small messages. Must be between one and `MAX_BUFFER_LIMIT`.
+    pub fn set_read_limit(&mut self, bytes: usize) -> Result<(), Error> {
+        let bytes = config::check_limit("read_limit", bytes, MAX_BUFFER_LIMIT)?;
+        self.as_conn_mut().read_chunk = bytes;
+        Ok(())
+    }
 
-        let mut events = Events::with_capacity(32);
-        self.poll.poll(&mut events, None)?;
-        for ev in events {
-            if COMMANDS_TOKEN == ev.token() {
-                while let Ok(_) = self.channel.recv.try_recv() {
-                    unimplemented!("commands")
-                }
-                continue;
-            }
+    /// Allow exactly `n` more bytes to be read, on top of any unused credit. Once any credit
+    /// has been granted, reading stops whenever it runs out, however much is buffered; until
+    /// more is granted, the peer closing won't be noticed either.
+    pub fn grant_read_credit(&mut self, n: usize) {
+        let read_buffer = &mut self.as_conn_mut().read_buffer;
+        read_buffer.credit = Some(read_buffer.credit.unwrap_or(0).saturating_add(n));
+    }
 
-            let us: &mut Owned = match self.tokens.get_mut(&ev.token()) {
-                Some(us) => us,
-                None => continue,
-            };
+    /// Stop reading from the socket until `resume_reads`, regardless of `want` or any read
+    /// credit. What's already buffered is still available; the peer closing won't be noticed.
+    pub fn pause_reads(&mut self) {
+        self.as_conn_mut().read_buffer.paused = true;
+    }
 
-            info!("{} woke", ev.token().0);
+    pub fn resume_reads(&mut self) {
+        self.as_conn_mut().read_buffer.paused = false;
+    }
 
-            match us.mode {
-                OwnedMode::Server(ref server) => {
-                    let (sock, addr) = match block_to_none(server.inner.accept())? {
-                        Some(o) => o,
-                        None => continue,
-                    };
-                    let new = self.bump_token();
-                    self.events.push_back(Event::NewConnection(new));
-                    self.poll
-                        .register(&sock, new, Ready::readable(), PollOpt::edge())?;
-                    self.tokens.insert(
-                        new,
-                        Owned {
-                            token: new,
-                            mode: OwnedMode::Conn(Conn {
-                                inner: sock,
-                                read_buffer: Stream::default(),
-                                write_buffer: Stream::default(),
-                            }),
-                        },
-                    );
-                }
-                OwnedMode::Conn(ref mut conn) => shunt_io(conn, ev.token()),
-            }
-        }
+    pub fn reads_paused(&self) -> bool {
+        self.as_conn().read_buffer.paused
+    }
 
-        self.generate_events();
+    /// `None` unless `grant_read_credit` is in use.
+    pub fn read_credit(&self) -> Option<usize> {
+        self.as_conn().read_buffer.credit
+    }
 
+    /// Hand the connection over to one of the built-in behaviours, or back to the caller.
+    /// A `Mode::Source` starts counting from zero again.
+    pub fn set_mode(&mut self, mode: Mode) -> Result<(), Error> {
+        let mode = mode::check(mode)?;
+        self.with_conn(|conn| {
+            conn.mode = mode;
+            conn.sourced = 0;
+        });
         Ok(())
     }
 
-    fn generate_events(&mut self) {
-        for (token, us) in &self.tokens {
-            match &us.mode {
-                OwnedMode::Server(_) => (),
-                OwnedMode::Conn(conn) => {
-                    if conn.read_buffer.could_read() {
-                        self.events.push_back(Event::Data(*token));
-                    }
-                }
-            }
-        }
+    pub fn set_peer_closed_policy(&mut self, policy: PeerClosedPolicy) {
+        self.as_conn_mut().peer_closed_policy = policy;
     }
-}
 
-fn shunt_io(conn: &mut Conn, token: Token) {
-    while conn.read_buffer.do_read() && do_a_read(conn, token) {}
-    while conn.write_buffer.do_write() && do_a_write(conn, token) {}
-}
+    /// The latest sample taken every `NetConfig::tcp_info_interval`.
+    pub fn last_tcp_info(&self) -> Option<&TcpInfo> {
+        self.as_conn().tcp_info.as_ref()
+    }
 
-fn do_a_read(conn: &mut Conn, token: Token) -> bool {
-    let mut buf = [0u8; BUF_SIZE];
-    match conn.inner.read(&mut buf) {
-        Ok(0) => {
-            conn.read_buffer.become_at_least_draining_close();
-            false
-        }
+    /// Make the next read or write (depending on the fault) on this connection fail as described.
+    /// Faults queue up, each direction's in the order they were injected.
+    #[cfg(feature = "fault-injection")]
+    pub fn inject_fault(&mut self, fault: Fault) {
+        self.as_conn_mut().faults.inject(0, fault)
+    }
 
-        Ok(r) => {
-            conn.read_buffer
-                .buf_mut()
-                .expect("TODO: read completed on non-buffer")
-                .extend_from_slice(&buf[..r]);
-            true
-        }
+    /// As `inject_fault`, but only once another `bytes` have been read or written (depending on
+    /// the fault), after any faults already queued that way. Reads and writes are cut short so
+    /// the fault lands exactly there.
+    #[cfg(feature = "fault-injection")]
+    pub fn inject_fault_after(&mut self, bytes: usize, fault: Fault) {
+        self.as_conn_mut().faults.inject(bytes, fault)
+    }
 
-        Err(ref e) if io::ErrorKind::WouldBlock == e.kind() => false,
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.as_conn().peer
+    }
 
-        Err(e) => {
-            info!("{} read-err {:?}", token.0, e);
-            conn.read_buffer.become_at_least_draining_close();
-            false
+    pub fn stats(&self) -> ConnStats {
+        let conn = self.as_conn();
+        ConnStats {
+            bytes_read: conn.bytes_read,
+            bytes_written: conn.bytes_written,
+            read_buffered: conn.read_buffer.len(),
+            write_buffered: conn.write_buffer.len(),
+            age: self
+                .inner
+                .clock
+                .now()
+                .saturating_duration_since(conn.created),
+            last_active: conn.last_active,
         }
     }
-}
 
-fn do_a_write(conn: &mut Conn, token: Token) -> bool {
-    match conn.inner.write(
-        conn.write_buffer
-            .buf()
-            .expect("asked to write, should be able to see data to write"),
-    ) {
-        Ok(0) => {
-            info!("{} write-eof", token.0);
-            conn.write_buffer.totes_done();
-            false
-        }
-        Ok(w) => {
-            drop(
-                conn.write_buffer
-                    .buf_mut()
-                    .expect("wrote data, should be able to discard it")
-                    .drain(..w),
-            );
-            true
-        }
+    /// The client's address according to the PROXY protocol header, for a connection accepted
+    /// with `ListenOptions::proxy_protocol`. `None` until the header has arrived, or if the
+    /// proxy didn't give an address, e.g. for its own health checks.
+    pub fn proxied_peer_addr(&self) -> Option<SocketAddr> {
+        self.as_conn().proxied_peer
+    }
 
-        Err(ref e) if io::ErrorKind::WouldBlock == e.kind() => false,
+    /// The local address of the connection, or of a UDP socket.
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        let local = self.as_conn().inner.local_addr();
+        local.map_err(|e| Error::new(Operation::SocketQuery, e).with_token(self.token))
+    }
 
-        Err(e) => {
-            info!("{} write-err {:?}", token.0, e);
-            conn.write_buffer.totes_done();
+    /// What `set_data` left with this connection, if anything.
+    pub fn data(&self) -> Option<&T> {
+        self.inner.data.get(&self.token)
+    }
+
+    pub fn data_mut(&mut self) -> Option<&mut T> {
+        self.inner.data.get_mut(&self.token)
+    }
+
+    /// Keep `data` with the connection until it's closed, e.g. from `Event::NewConnection`.
+    /// Returns whatever was kept before.
+    pub fn set_data(&mut self, data: T) -> Option<T> {
+        self.inner.data.insert(self.token, data)
+    }
+
+    /// Close the connection, with an `Event::TimedOut`, once nothing has been read or written
+    /// for `timeout`. Replaces any earlier idle timeout, and counts from now. Must be non-zero
+    /// and at most `MAX_TIMEOUT`. If the peer won't take what's left to write, the connection
+    /// is reset after `NetConfig::drain_timeout`.
+    pub fn set_idle_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        let timeout = config::check_timeout("idle_timeout", timeout)?;
+        let token = self.token;
+        let now = self.inner.clock.now();
+        let conn = self.as_conn_mut();
+        conn.idle_timeout = Some(timeout);
+        conn.last_active = now;
+        conn.idle_deadline = Some(now + timeout);
+        self.inner
+            .deadlines
+            .push(now + timeout, token, Expiry::Idle);
+        Ok(())
+    }
+
+    /// The connection has got far enough, e.g. it's sent a whole request head, that its
+    /// `ListenOptions::establish_timeout` no longer applies. Harmless if it has none.
+    pub fn establish(&mut self) {
+        self.as_conn_mut().establish_deadline = None;
+    }
+
+    /// Send `bytes` and close, for answers which need no follow-up. No more events are
+    /// generated for the connection except `Event::Closed`; if the peer won't take the bytes
+    /// within `NetConfig::drain_timeout`, the connection is reset. The connection is closed
+    /// even if the bytes couldn't be queued.
+    pub fn respond_and_close(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let token = self.token;
+        let drain_timeout = self.inner.config.drain_timeout;
+        let now = self.inner.clock.now();
+        let (written, at) =
+            self.with_conn(|conn| conn.respond_and_close(token, bytes, drain_timeout, now));
+        self.inner.deadlines.push(at, token, Expiry::Drain);
+        written
+    }
+
+    /// Close once every event already queued for this connection has been handled, i.e. at
+    /// the first call to `next()` after the last of them was returned. Until then, the
+    /// connection works as normal.
+    pub fn close_deferred(&mut self) {
+        let mark = self.inner.events.mark();
+        let conn = self.as_conn_mut();
+        if conn.close_after.is_none() {
+            conn.close_after = Some(mark);
+            self.inner.deferred_closes.push(self.token);
+        }
+    }
+
+    /// Whether `close_deferred` has been called, but the close hasn't started yet.
+    pub fn is_close_pending(&self) -> bool {
+        self.as_conn().close_after.is_some()
+    }
+
+    pub fn close(&mut self) {
+        self.as_conn_mut().close_after = None;
+        self.with_conn(|conn| {
+            conn.read_buffer.become_at_least_truncating_close();
+            conn.write_buffer.become_at_least_draining_close();
+        })
+    }
+
+    /// Finish writing: once everything queued has been sent, our side is shut down, so the
+    /// peer sees the end, e.g. a `Net::spawn`ed child's stdin; reading carries on.
+    pub fn close_write(&mut self) {
+        self.with_conn(|conn| conn.write_buffer.become_at_least_draining_close())
+    }
+}
+
+impl Ctx {
+    pub fn write(&mut self, token: ConnToken, data: &[u8]) {
+        self.commands.push(Command::Write(token.0, data.to_vec()));
+    }
+
+    pub fn close(&mut self, token: ConnToken) {
+        self.commands.push(Command::Close(token.0));
+    }
+
+    /// As `Net::timer`; it starts once the closure returns.
+    pub fn timer(&mut self, after: Duration) -> Result<TimerToken, Error> {
+        config::check_timer(after)?;
+        let token = self.timer_tokens.next();
+        self.commands.push(Command::Timer(token, after));
+        Ok(TimerToken(token))
+    }
+
+    /// As `Net::cancel_timer`, but without saying whether it was still pending.
+    pub fn cancel_timer(&mut self, token: TimerToken) {
+        self.commands.push(Command::CancelTimer(token.0));
+    }
+
+    /// As of the start of the `with_io` call.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+}
+
+const COMMANDS_TOKEN: Token = Token(0);
+
+impl ListenOptions {
+    /// Abort accepted connections which haven't sent anything within `timeout`, with an
+    /// `Event::Error` of kind `TimedOut`. Unaffected by anything which happens later.
+    /// Must be non-zero and at most `MAX_TIMEOUT`, or the listen call fails.
+    pub fn first_byte_timeout(mut self, timeout: Duration) -> ListenOptions {
+        self.first_byte_timeout = Some(timeout);
+        self
+    }
+
+    /// Abort accepted connections which the application hasn't `Io::establish`ed within
+    /// `timeout`, with an `Event::Error` of kind `TimedOut`; e.g. to stop a client which
+    /// trickles in its request headers from holding on forever. Must be non-zero and at most
+    /// `MAX_TIMEOUT`, or the listen call fails.
+    pub fn establish_timeout(mut self, timeout: Duration) -> ListenOptions {
+        self.establish_timeout = Some(timeout);
+        self
+    }
+
+    /// Start accepted connections in this mode, instead of `Mode::Events`.
+    pub fn mode(mut self, mode: Mode) -> ListenOptions {
+        self.mode = mode;
+        self
+    }
+
+    /// Ask the OS to queue up to this many connections which have arrived, but not yet been
+    /// accepted; it may quietly use less. `DEFAULT_BACKLOG` unless set. Must be non-zero and
+    /// at most `MAX_BACKLOG`, or the listen call fails.
+    pub fn backlog(mut self, backlog: usize) -> ListenOptions {
+        self.bind.backlog = backlog;
+        self
+    }
+
+    /// Set `SO_REUSEADDR`, so the address can be bound again while connections from an earlier
+    /// listener on it are still lingering. On by default on unix, where it's harmless, and off
+    /// elsewhere, where it lets another socket steal the port.
+    pub fn reuse_address(mut self, reuse: bool) -> ListenOptions {
+        self.bind.reuse_address = reuse;
+        self
+    }
+
+    /// Set `SO_REUSEPORT`, so other listeners which also set it, in this process or others,
+    /// can bind the same address, and the kernel shares incoming connections between them.
+    /// Not every platform has it; where it's missing, the listen call fails.
+    pub fn reuse_port(mut self, reuse: bool) -> ListenOptions {
+        self.bind.reuse_port = reuse;
+        self
+    }
+
+    /// Set `IPV6_V6ONLY`: `true` to accept only IPv6 connections, `false` to also accept IPv4
+    /// ones, which show up with mapped addresses like `::ffff:192.0.2.1`. Left as the OS has it
+    /// unless set. Only for IPv6 addresses, or the listen call fails.
+    pub fn only_v6(mut self, only_v6: bool) -> ListenOptions {
+        self.bind.only_v6 = Some(only_v6);
+        self
+    }
+
+    /// Expect every accepted connection to start with a PROXY protocol header, version 1 or 2,
+    /// as sent by a load balancer; it's taken before the first `Event::Data`, and gives
+    /// `Io::proxied_peer_addr`. A connection which starts with anything else is reset, with an
+    /// `Event::Error` of kind `InvalidData`. Only for `Mode::Events`, or the listen call fails.
+    pub fn proxy_protocol(mut self, expected: bool) -> ListenOptions {
+        self.proxy_protocol = expected;
+        self
+    }
+
+    /// Everything but what's only for binding, which an adopted listener has already done.
+    fn check(&self) -> Result<(), Error> {
+        if let Some(timeout) = self.first_byte_timeout {
+            config::check_timeout("first_byte_timeout", timeout)?;
+        }
+        if let Some(timeout) = self.establish_timeout {
+            config::check_timeout("establish_timeout", timeout)?;
+        }
+        mode::check(self.mode)?;
+        limits::check(self)?;
+        if self.proxy_protocol && Mode::Events != self.mode {
+            return Err(Error::invalid(
+                "proxy_protocol",
+                self.mode,
+                "only for Mode::Events",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl NetBuilder {
+    /// How much each read asks the kernel for, unless a connection sets its own with
+    /// `Io::set_read_limit`. Must be between one and `MAX_BUFFER_LIMIT`.
+    pub fn read_chunk(mut self, bytes: usize) -> NetBuilder {
+        self.config.read_chunk = bytes;
+        self
+    }
+
+    /// What new connections `want`: how much they buffer before they stop reading. Must be
+    /// between one and `MAX_BUFFER_LIMIT`.
+    pub fn wanted(mut self, bytes: usize) -> NetBuilder {
+        self.config.wanted = bytes;
+        self
+    }
+
+    /// Give new connections a maximum read buffer, as if by `Io::set_max_read_buffer`. Must be
+    /// between one and `MAX_BUFFER_LIMIT`.
+    pub fn max_read_buffer(mut self, bytes: usize) -> NetBuilder {
+        self.config.max_read_buffer = Some(bytes);
+        self
+    }
+
+    /// Stop reading from every connection once this many bytes are buffered in total, and emit
+    /// `Event::MemoryPressure`. Reading resumes once usage drops below three quarters of it.
+    /// Zero is rejected by `build`.
+    pub fn max_buffered_bytes(mut self, max: usize) -> NetBuilder {
+        self.config.max_buffered_bytes = Some(max);
+        self
+    }
+
+    /// Stop accepting once this many connections are open, however they were made, and emit
+    /// `Event::AtCapacity`; accepting resumes, with an `Event::BelowCapacity`, once one closes.
+    /// Zero is rejected by `build`.
+    pub fn max_connections(mut self, max: usize) -> NetBuilder {
+        self.config.max_connections = Some(max);
+        self
+    }
+
+    /// How long `Io::respond_and_close` gives the peer to take the response before resetting
+    /// the connection. Must be non-zero and at most `MAX_TIMEOUT`.
+    pub fn drain_timeout(mut self, timeout: Duration) -> NetBuilder {
+        self.config.drain_timeout = timeout;
+        self
+    }
+
+    /// Record `TCP_INFO` for every connection this often, for `Io::last_tcp_info`. Must be
+    /// non-zero and at most `MAX_TIMEOUT`.
+    pub fn tcp_info_interval(mut self, interval: Duration) -> NetBuilder {
+        self.config.tcp_info_interval = Some(interval);
+        self
+    }
+
+    /// Fail `next()` with `Error::Anomaly` when the event loop sees something it would
+    /// otherwise shrug off; in debug builds, panic instead.
+    pub fn strict(mut self, strict: bool) -> NetBuilder {
+        self.config.strict = strict;
+        self
+    }
+
+    /// Keep up to this many bytes of buffer capacity from closed connections, so new ones
+    /// don't have to allocate their own. Zero turns this off.
+    pub fn buffer_pool_bytes(mut self, bytes: usize) -> NetBuilder {
+        self.config.buffer_pool_bytes = bytes;
+        self
+    }
+
+    /// How many readiness notifications to collect from the kernel per poll; more suits many
+    /// busy connections. Must be between one and 65536.
+    pub fn events_capacity(mut self, capacity: usize) -> NetBuilder {
+        self.config.events_capacity = capacity;
+        self
+    }
+
+    /// Give every new connection these write watermarks, as if `Io::set_write_watermarks` had
+    /// been called on it straight away.
+    pub fn write_watermarks(mut self, low: usize, high: usize) -> NetBuilder {
+        self.config.write_watermarks = Some((low, high));
+        self
+    }
+
+    pub fn build(self) -> Result<Net, Error> {
+        self.build_with_data()
+    }
+
+    /// As `build`, for a `Net` which keeps a `T` with each connection.
+    pub fn build_with_data<T>(self) -> Result<Net<T>, Error> {
+        let reactor = MioReactor::new().during(Operation::Poll)?;
+        self.build_with_reactor(reactor)
+    }
+
+    /// As `build_with_data`, waiting on `reactor` instead of mio, e.g. a `reactor::Epoll`, or
+    /// a `reactor::TestReactor`.
+    pub fn build_with_reactor<T>(self, reactor: impl Reactor + 'static) -> Result<Net<T>, Error> {
+        self.build_with_clock(reactor, Clock::Real)
+    }
+
+    fn build_with_clock<T>(
+        self,
+        reactor: impl Reactor + 'static,
+        clock: Clock,
+    ) -> Result<Net<T>, Error> {
+        config::check_limit("read_chunk", self.config.read_chunk, MAX_BUFFER_LIMIT)?;
+        config::check_limit("wanted", self.config.wanted, MAX_BUFFER_LIMIT)?;
+        config::check_events_capacity(self.config.events_capacity)?;
+        if let Some((low, high)) = self.config.write_watermarks {
+            config::check_watermarks(low, high)?;
+        }
+        throttle::check("max_egress_bps", self.config.max_egress_bps)?;
+        if let Some(max) = self.config.max_read_buffer {
+            config::check_limit("max_read_buffer", max, MAX_BUFFER_LIMIT)?;
+        }
+        if let Some(max) = self.config.max_buffered_bytes {
+            config::check_limit("max_buffered_bytes", max, usize::MAX)?;
+        }
+        if let Some(max) = self.config.max_connections {
+            config::check_limit("max_connections", max, MAX_TOKENS)?;
+        }
+        if let Some(max) = self.config.max_connections_per_ip {
+            config::check_limit("max_connections_per_ip", max, MAX_TOKENS)?;
+        }
+        config::check_timeout("drain_timeout", self.config.drain_timeout)?;
+        if let Some(interval) = self.config.tcp_info_interval {
+            config::check_timeout("tcp_info_interval", interval)?;
+        }
+
+        let mut reactor: Box<dyn Reactor> = Box::new(reactor);
+        let channel = CommandChannel::new().during(Operation::Register)?;
+        reactor
+            .register(channel.source(), COMMANDS_TOKEN, Interest::READABLE)
+            .during(Operation::Register)?;
+        let buffer_pool_bytes = self.config.buffer_pool_bytes;
+        let mut net = Net {
+            reactor,
+            clock,
+            woken: Vec::new(),
+            tokens: Default::default(),
+            channel,
+            events: EventQueue::default(),
+            last_event_created_at: None,
+            event_ages: EventAges::default(),
+            config: self.config,
+            buffered: 0,
+            memory_pressure: false,
+            at_capacity: false,
+            final_buffers: HashMap::new(),
+            delivered: Vec::new(),
+            in_handler: false,
+            wakeups: Wakeups::default(),
+            deadlines: Deadlines::default(),
+            first_byte_timeouts: 0,
+            establish_timeouts: 0,
+            pool: Pool::default(),
+            mode_stats: ModeMetrics::default(),
+            anomalies: 0,
+            deferred_error: None,
+            recorder: None,
+            stats: NetStats::default(),
+            accept_rate: AcceptRate::default(),
+            metrics_sink: None,
+            reported: NetStats::default(),
+            tap: None,
+            per_ip: HashMap::new(),
+            deferred_closes: Vec::new(),
+            timers: HashMap::new(),
+            timer_tokens: TimerTokens::default(),
+            sources: HashMap::new(),
+            #[cfg(unix)]
+            children: HashMap::new(),
+            #[cfg(unix)]
+            signals: None,
+            buffers: BufferPool::new(buffer_pool_bytes),
+            shutting_down: false,
+            egress: None,
+            egress_blocked: false,
+            writers: 0,
+            data: HashMap::new(),
+        };
+        net.egress = net
+            .config
+            .max_egress_bps
+            .map(|bps| Bucket::new(bps, net.clock.now()));
+        if let Some(interval) = net.config.tcp_info_interval {
+            let at = net.clock.now() + interval;
+            net.deadlines
+                .push(at, COMMANDS_TOKEN, Expiry::TcpInfoSample);
+        }
+        Ok(net)
+    }
+}
+
+impl Net {
+    pub fn builder() -> NetBuilder {
+        NetBuilder::default()
+    }
+
+    pub fn empty() -> Result<Net, Error> {
+        Net::builder().build()
+    }
+}
+
+impl<T> Net<T> {
+    pub fn config(&self) -> &NetConfig {
+        &self.config
+    }
+
+    /// A human-readable description of everything the `Net` is holding, for diagnostics, after
+    /// what this build of the crate can do.
+    pub fn debug_dump(&self) -> String {
+        let mut out = format!(
+            "{:?}\n{:?}\n{:?}\n",
+            capabilities(),
+            self.config,
+            self.metrics()
+        );
+        for (token, owned) in self.tokens.iter() {
+            let line = match &owned.mode {
+                OwnedMode::Server(server) => format!("listener {:?}", server.inner.local_addr()),
+                OwnedMode::Conn(conn) => format!(
+                    "conn read: {}, write: {}",
+                    conn.read_buffer.describe(),
+                    conn.write_buffer.describe()
+                ),
+                OwnedMode::Udp(udp) => udp.describe(),
+            };
+            out.push_str(&format!("{}: {}\n", token.0, line));
+        }
+        for ev in &self.events {
+            out.push_str(&format!("queued: {:?}\n", ev));
+        }
+        out
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            buffered_bytes: self.buffered,
+            wakeups: self.wakeups.clone(),
+            first_byte_timeouts: self.first_byte_timeouts,
+            establish_timeouts: self.establish_timeouts,
+            pool: self.pool.metrics(),
+            modes: self.mode_stats.clone(),
+            anomalies: self.anomalies,
+            event_ages: self.event_ages.clone(),
+            pooled_buffer_bytes: self.buffers.bytes,
+            recycled_buffers: self.buffers.recycled,
+        }
+    }
+
+    /// The returned token identifies the listener in `Event::Listening` and `Event::NewConnection`.
+    pub fn tcp_listen(&mut self, addr: &SocketAddr) -> Result<ListenerToken, Error> {
+        let token = self.listen(addr, &ListenOptions::default())?;
+        self.announce_listening(&[token]);
+        Ok(ListenerToken(token))
+    }
+
+    /// Bind every address, or none of them: if any fails, those already bound are closed again.
+    pub fn tcp_listen_all(
+        &mut self,
+        addrs: &[SocketAddr],
+        options: &ListenOptions,
+    ) -> Result<Vec<ListenerToken>, Error> {
+        let mut bound = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            match self.listen(addr, options) {
+                Ok(token) => bound.push(token),
+                Err(e) => {
+                    for token in bound {
+                        self.unlisten(token);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        self.announce_listening(&bound);
+        Ok(bound.into_iter().map(ListenerToken).collect())
+    }
+
+    /// Close a listener. Connections it accepted are unaffected; any still in its backlog are
+    /// refused by the kernel.
+    pub fn stop_listening(&mut self, listener: ListenerToken) -> Result<(), Error> {
+        match self.tokens.get(&listener.0).map(|o| &o.mode) {
+            Some(OwnedMode::Server(_)) => self.unlisten(listener.0),
+            _ => return Err(Error::UnknownToken(listener.0)),
+        }
+        Ok(())
+    }
+
+    fn announce_listening(&mut self, tokens: &[Token]) {
+        for &token in tokens {
+            let addr = match self.tokens.get(&token).map(|o| &o.mode) {
+                Some(OwnedMode::Server(server)) => server.inner.local_addr(),
+                _ => unreachable!("just bound a listener"),
+            };
+            match addr {
+                Ok(addr) => {
+                    info!("{} listening on {}", token.0, addr);
+                    self.events.push(Event::Listening {
+                        token: ListenerToken(token),
+                        addr,
+                    });
+                }
+                Err(e) => info!("{} local-addr-err {:?}", token.0, e),
+            }
+        }
+    }
+
+    fn listen(&mut self, addr: &SocketAddr, options: &ListenOptions) -> Result<Token, Error> {
+        options.check()?;
+        config::check_limit("backlog", options.bind.backlog, MAX_BACKLOG)?;
+        if options.bind.only_v6.is_some() && addr.is_ipv4() {
+            return Err(Error::invalid("only_v6", addr, "only for IPv6 addresses"));
+        }
+
+        let inner = sys::bind_listener(addr, &options.bind)
+            .map_err(|e| Error::new(Operation::Bind, e).with_addr(*addr))?;
+        self.add_listener(inner, options)
+            .map_err(|e| e.with_addr(*addr))
+    }
+
+    /// Watch a bound listener, and take it on.
+    fn add_listener(
+        &mut self,
+        inner: TcpListener,
+        options: &ListenOptions,
+    ) -> Result<Token, Error> {
+        let token = self.tokens.reserve()?;
+        if let Err(e) = self
+            .reactor
+            .register(Source::Listener(&inner), token, Interest::READABLE)
+        {
+            self.release(token);
+            return Err(Error::new(Operation::Register, e).with_listener(token));
+        }
+        self.tokens.insert(
+            token,
+            Owned {
+                token,
+                mode: OwnedMode::Server(Server {
+                    inner,
+                    options: options.clone(),
+                    rate: AcceptRate::default(),
+                    deferred: false,
+                }),
+            },
+        );
+        Ok(token)
+    }
+
+    fn unlisten(&mut self, token: Token) {
+        if let Some(owned) = self.tokens.remove(&token) {
+            if let OwnedMode::Server(server) = owned.mode {
+                if let Err(e) = self
+                    .reactor
+                    .deregister(Source::Listener(&server.inner), token)
+                {
+                    info!("{} deregister-err {:?}", token.0, e);
+                }
+            }
+        }
+        self.release(token);
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Event, Error> {
+        let ev = self.next_before(None)?;
+        Ok(ev.expect("waits forever"))
+    }
+
+    /// The next event, if there's one already, or one arrives within `timeout`.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Result<Option<Event>, Error> {
+        // too far away to represent is as good as forever
+        self.next_before(self.clock.now().checked_add(timeout))
+    }
+
+    /// The next event, if one can be had without waiting. The network is still checked first.
+    pub fn try_next(&mut self) -> Result<Option<Event>, Error> {
+        self.next_timeout(Duration::from_secs(0))
+    }
+
+    /// Wait, as `next_timeout` (or `next`, for `None`), for an event, then append it and every
+    /// other event already waiting to `events`. Returns how many were added. Anything which
+    /// would only be available while handling an event, like `take_final_buffers`, is
+    /// available for all of them until the next call.
+    pub fn poll_events(
+        &mut self,
+        events: &mut Vec<Event>,
+        timeout: Option<Duration>,
+    ) -> Result<usize, Error> {
+        let deadline = timeout.and_then(|timeout| self.clock.now().checked_add(timeout));
+        let first = match self.next_before(deadline)? {
+            Some(ev) => ev,
+            None => return Ok(0),
+        };
+        let before = events.len();
+        events.push(first);
+        while let Some(ev) = self.deliver() {
+            events.push(ev);
+        }
+        Ok(events.len() - before)
+    }
+
+    fn next_before(&mut self, deadline: Option<Instant>) -> Result<Option<Event>, Error> {
+        debug_assert!(!self.in_handler, "Net::next called from inside with_io");
+
+        for token in mem::take(&mut self.delivered) {
+            if let Some(remains) = self.final_buffers.remove(&token) {
+                self.buffers.put(remains.unread);
+                self.buffers.put(remains.unwritten);
+            }
+            self.release(token);
+        }
+
+        if let Some(e) = self.deferred_error.take() {
+            return Err(e);
+        }
+
+        self.start_deferred_closes();
+
+        while self.events.is_empty() {
+            let wait = deadline.map(|at| at.saturating_duration_since(self.clock.now()));
+            self.fill(wait)?;
+            if self.events.is_empty() && Some(Duration::from_secs(0)) == wait {
+                return Ok(None);
+            }
+        }
+
+        Ok(self.deliver())
+    }
+
+    /// Pop an event, for the caller to handle.
+    fn deliver(&mut self) -> Option<Event> {
+        let now = self.clock.now();
+        let (created, ev) = self.events.pop(now)?;
+        self.stats.events += 1;
+        self.record_event(&ev);
+        self.last_event_created_at = Some(created);
+        self.event_ages
+            .record(now.saturating_duration_since(created));
+        match ev {
+            Event::Closed(ConnToken(token))
+            | Event::UdpClosed(UdpToken(token))
+            | Event::ChildExited(ChildToken(token), _) => {
+                self.delivered.push(token);
+            }
+            _ => (),
+        }
+        Some(ev)
+    }
+
+    fn start_deferred_closes(&mut self) {
+        let mut deferred = mem::take(&mut self.deferred_closes);
+        deferred.retain(|&token| {
+            let mark = match self.conn_mut(token).and_then(|conn| conn.close_after) {
+                Some(mark) => mark,
+                None => return false,
+            };
+            if self.events.any_before(mark, token) {
+                return true;
+            }
+            Io { inner: self, token }.close();
+            false
+        });
+        self.deferred_closes = deferred;
+    }
+
+    /// When the event most recently returned by `next()` was generated; usually, just after
+    /// the kernel reported whatever caused it.
+    pub fn last_event_created_at(&self) -> Option<Instant> {
+        self.last_event_created_at
+    }
+
+    /// `Event::Timer` will be delivered with the returned token once `after` has passed.
+    /// Zero means as soon as possible; more than `MAX_TIMEOUT` is rejected.
+    pub fn timer(&mut self, after: Duration) -> Result<TimerToken, Error> {
+        config::check_timer(after)?;
+        let token = self.timer_tokens.next();
+        self.start_timer(token, after);
+        Ok(TimerToken(token))
+    }
+
+    fn start_timer(&mut self, token: Token, after: Duration) {
+        let at = self.clock.now() + after;
+        self.deadlines.push(at, token, Expiry::User);
+        self.timers.insert(token, at);
+    }
+
+    /// Stop a timer from firing. Returns whether it was still pending.
+    pub fn cancel_timer(&mut self, token: TimerToken) -> bool {
+        self.timers.remove(&token.0).is_some()
+    }
+
+    /// The bytes a connection was holding when it was removed. Only available while handling
+    /// its `Event::Closed`; they're dropped on the next call to `next()`.
+    pub fn take_final_buffers(&mut self, token: ConnToken) -> Option<FinalBuffers> {
+        self.final_buffers.remove(&token.0)
+    }
+
+    /// Take back what `Io::set_data` left with a connection. While handling its `Event::Closed`,
+    /// this is the only way to get at it; it's dropped on the next call to `next()`.
+    pub fn take_data(&mut self, token: ConnToken) -> Option<T> {
+        self.data.remove(&token.0)
+    }
+
+    /// Let the token's slot be reused, dropping anything left with it.
+    fn release(&mut self, token: Token) {
+        self.tokens.release(token);
+        self.data.remove(&token);
+    }
+
+    /// Work with a connection; UDP sockets have `Net::udp` instead. Tokens which no longer
+    /// name anything are rejected, even if their slot has been reused by something else.
+    pub fn io(&mut self, token: ConnToken) -> Result<Io<'_, T>, Error> {
+        let token = token.0;
+        debug_assert!(!self.in_handler, "Net::io called from inside with_io");
+        self.check_token(token)?;
+        Ok(Io { inner: self, token })
+    }
+
+    /// `token` is a connection, which an `Io` can work with.
+    fn check_token(&self, token: Token) -> Result<(), Error> {
+        match self.tokens.get(&token).map(|o| &o.mode) {
+            Some(OwnedMode::Conn(_)) => Ok(()),
+            _ => Err(Error::UnknownToken(token)),
+        }
+    }
+
+    /// Run `f` against one connection. Anything it wants to do to other connections goes
+    /// through the `Ctx`, and happens after `f` returns.
+    pub fn with_io<R>(
+        &mut self,
+        token: ConnToken,
+        f: impl FnOnce(&mut Io<'_, T>, &mut Ctx) -> R,
+    ) -> Result<R, Error> {
+        let token = token.0;
+        debug_assert!(!self.in_handler, "with_io called re-entrantly");
+        self.check_token(token)?;
+        let mut ctx = Ctx {
+            commands: Vec::new(),
+            metrics: self.metrics(),
+            timer_tokens: self.timer_tokens,
+        };
+        self.in_handler = true;
+        let ret = f(&mut Io { inner: self, token }, &mut ctx);
+        self.in_handler = false;
+        self.timer_tokens = ctx.timer_tokens;
+        for command in ctx.commands {
+            if let Err(e) = self.apply(command) {
+                self.deferred_error.get_or_insert(e);
+            }
+        }
+        Ok(ret)
+    }
+
+    fn apply(&mut self, command: Command) -> Result<(), Error> {
+        match command {
+            Command::Write(token, data) => match self.tokens.get(&token).map(|o| &o.mode) {
+                Some(OwnedMode::Conn(_)) => {
+                    if let Err(e) = (Io { inner: self, token }).write_vec(data) {
+                        info!("{} dropping write: {}", token.0, e);
+                    }
+                }
+                _ => {
+                    info!("{} dropping write for closed connection", token.0);
+                    self.anomaly(Anomaly::DeadCommand(token))?;
+                }
+            },
+            Command::Close(token) => match self.tokens.get(&token).map(|o| &o.mode) {
+                Some(OwnedMode::Conn(_)) => Io { inner: self, token }.close(),
+                _ => {
+                    info!("{} dropping close for closed connection", token.0);
+                    self.anomaly(Anomaly::DeadCommand(token))?;
+                }
+            },
+            Command::Timer(token, after) => self.start_timer(token, after),
+            Command::CancelTimer(token) => {
+                self.timers.remove(&token);
+            }
+        }
+        Ok(())
+    }
+
+    /// Every oddity the event loop would otherwise ignore comes through here.
+    fn anomaly(&mut self, what: Anomaly) -> Result<(), Error> {
+        self.anomalies += 1;
+        if !self.config.strict {
+            debug!("ignoring anomaly: {}", what);
+            return Ok(());
+        }
+        if cfg!(debug_assertions) {
+            panic!("anomaly in strict mode: {}", what);
+        }
+        Err(Error::Anomaly(what))
+    }
+
+    fn conn_mut(&mut self, token: Token) -> Option<&mut Conn> {
+        match self.tokens.get_mut(&token).map(|o| &mut o.mode) {
+            Some(OwnedMode::Conn(conn)) => Some(conn),
+            _ => None,
+        }
+    }
+
+    /// Start connecting to `addr`. `Event::Connected` follows once the handshake completes, or
+    /// `Event::ConnectFailed` if it doesn't; writes made before then are sent on connection.
+    pub fn tcp_connect(&mut self, addr: &SocketAddr) -> Result<ConnToken, Error> {
+        let sock = TcpStream::connect(addr)
+            .map_err(|e| Error::new(Operation::Connect, e).with_peer(Some(*addr)))?;
+        let token = self.tokens.reserve()?;
+        if let Err(e) = self.reactor.register(
+            Source::Stream(&sock),
+            token,
+            Interest::READABLE | Interest::WRITABLE,
+        ) {
+            self.release(token);
+            return Err(Error::new(Operation::Register, e)
+                .with_token(token)
+                .with_peer(Some(*addr)));
+        }
+        let mut conn = self.new_conn(Transport::Tcp(sock), *addr);
+        conn.connecting = true;
+        conn.registered = Some(Interest::READABLE | Interest::WRITABLE);
+        self.tokens.insert(
+            token,
+            Owned {
+                token,
+                mode: OwnedMode::Conn(conn),
+            },
+        );
+        Ok(ConnToken(token))
+    }
+
+    fn new_conn(&mut self, inner: Transport, peer: SocketAddr) -> Conn {
+        let mut conn = Conn::new(inner, peer, self.clock.now());
+        if let Some(buf) = conn.read_buffer.buf_mut() {
+            *buf = ReadBuf::with_storage(self.buffers.get());
+        }
+        conn.read_buffer.set_wanted(self.config.wanted);
+        conn.read_buffer.max = self.config.max_read_buffer;
+        conn.read_chunk = self.config.read_chunk;
+        if self.recorder.is_some() {
+            conn.recorded = Some(Vec::new());
+        }
+        conn.tap = self.tap.clone();
+        conn.watermarks = self
+            .config
+            .write_watermarks
+            .map(|(low, high)| Watermarks::new(low, high));
+        conn
+    }
+
+    fn close_some(&mut self) -> Result<(), Error> {
+        let mut to_close = Vec::new();
+        for (token, owned) in self.tokens.iter_mut() {
+            match &mut owned.mode {
+                OwnedMode::Server(_) => continue,
+                OwnedMode::Conn(conn) => {
+                    if conn.read_buffer.eof
+                        && PeerClosedPolicy::DrainAndClose == conn.peer_closed_policy
+                    {
+                        conn.write_buffer.become_at_least_draining_close();
+                    }
+                    conn.read_buffer.settle();
+                    if conn.write_buffer.settle() {
+                        if let Err(e) = conn.inner.shutdown(Shutdown::Write) {
+                            info!("{} shutdown-err {:?}", token.0, e);
+                        }
+                    }
+
+                    if conn.read_buffer.is_done() && conn.write_buffer.is_done() {
+                        info!("{} closing", token.0);
+                        to_close.push(token);
+                    }
+                }
+                OwnedMode::Udp(udp) => {
+                    if udp.finished() {
+                        info!("{} closing", token.0);
+                        to_close.push(token);
+                    }
+                }
+            }
+        }
+
+        for close in to_close {
+            let owned = self.tokens.remove(&close).expect("it was just there");
+            match owned.mode {
+                OwnedMode::Conn(conn) => {
+                    self.buffered -= conn.buffered();
+                    if let Some(ip) = conn.per_ip {
+                        self.forget_peer(ip);
+                    }
+                    // unlike a socket's, what's someone else's is still watched after it's closed
+                    #[cfg(unix)]
+                    if let Transport::Pipes(pipes) = &conn.inner {
+                        if let Err(e) = self.reactor.deregister(pipes.source(), close) {
+                            info!("{} deregister-err {:?}", close.0, e);
+                        }
+                    }
+                    if let Transport::Tcp(sock) = &conn.inner {
+                        if let Err(e) = self.reactor.closing(Source::Stream(sock), close) {
+                            info!("{} closing-err {:?}", close.0, e);
+                        }
+                    }
+                    if let Some(link) = &conn.link {
+                        self.unlink(link.peer);
+                    }
+                    if conn.is_pooled_idle() {
+                        // nobody is told about it, so nobody can still be holding the token
+                        self.release(close);
+                        self.buffers.put(conn.read_buffer.into_remains());
+                        continue;
+                    }
+                    self.final_buffers.insert(
+                        close,
+                        FinalBuffers {
+                            unread: conn.read_buffer.into_remains(),
+                            unwritten: conn.write_buffer.into_remains(),
+                        },
+                    );
+                }
+                OwnedMode::Udp(udp) => {
+                    self.buffered -= udp.buffered();
+                    if let Err(e) = self.reactor.closing(Source::Udp(&udp.inner), close) {
+                        info!("{} closing-err {:?}", close.0, e);
+                    }
+                    self.events.push(Event::UdpClosed(UdpToken(close)));
+                    continue;
+                }
+                OwnedMode::Server(_) => unreachable!("listeners aren't closed here"),
+            }
+            self.events.push(Event::Closed(ConnToken(close)));
+        }
+
+        self.check_shutdown();
+        Ok(())
+    }
+
+    fn expire_deadlines(&mut self) {
+        let now = self.clock.now();
+        while let Some((at, token, expiry)) = self.deadlines.pop_due(now) {
+            if Expiry::PoolIdle == expiry {
+                self.expire_pooled(token, at);
+                continue;
+            }
+
+            if Expiry::TcpInfoSample == expiry {
+                self.sample_tcp_info(now);
+                continue;
+            }
+
+            if Expiry::Egress == expiry {
+                self.resume_egress(now);
+                continue;
+            }
+
+            #[cfg(unix)]
+            if Expiry::Reap == expiry {
+                self.reap(token);
+                continue;
+            }
+
+            if Expiry::AcceptRate == expiry {
+                if let Err(e) = self.resume_accepts(token) {
+                    self.deferred_error.get_or_insert(e);
+                }
+                continue;
+            }
+
+            if Expiry::User == expiry {
+                // the token may have been cancelled, freed, and handed to another timer
+                if self.timers.get(&token) == Some(&at) {
+                    self.timers.remove(&token);
+                    self.events.push(Event::Timer(TimerToken(token)));
+                }
+                continue;
+            }
+
+            let conn = match self.tokens.get_mut(&token).map(|o| &mut o.mode) {
+                Some(OwnedMode::Conn(conn)) => conn,
+                _ => continue,
+            };
+
+            match expiry {
+                Expiry::PoolIdle
+                | Expiry::TcpInfoSample
+                | Expiry::User
+                | Expiry::AcceptRate
+                | Expiry::Egress
+                | Expiry::Reap => {
+                    unreachable!("handled above")
+                }
+                Expiry::Idle => {
+                    if conn.idle_deadline != Some(at) {
+                        continue;
+                    }
+                    let timeout = match conn.idle_timeout {
+                        Some(timeout) => timeout,
+                        None => continue,
+                    };
+                    let due = conn.last_active + timeout;
+                    if due > now {
+                        conn.idle_deadline = Some(due);
+                        self.deadlines.push(due, token, Expiry::Idle);
+                        continue;
+                    }
+                    info!("{} idle timeout", token.0);
+                    conn.idle_timeout = None;
+                    conn.idle_deadline = None;
+                    let before = conn.buffered();
+                    conn.read_buffer.become_at_least_truncating_close();
+                    conn.write_buffer.become_at_least_draining_close();
+                    self.buffered = self.buffered - before + conn.buffered();
+                    // the peer may not be reading either
+                    let drain = now + self.config.drain_timeout;
+                    conn.drain_deadline = Some(drain);
+                    self.deadlines.push(drain, token, Expiry::Drain);
+                    self.events.push(Event::TimedOut(ConnToken(token)));
+                }
+                Expiry::Drain => {
+                    if conn.drain_deadline != Some(at) {
+                        continue;
+                    }
+                    info!("{} drain timeout", token.0);
+                    let before = conn.buffered();
+                    conn.abort();
+                    self.buffered = self.buffered - before + conn.buffered();
+                }
+                Expiry::Throttle => {
+                    if conn.throttle_deadline != Some(at) {
+                        continue;
+                    }
+                    // reading, and writing, are re-armed now there's room in the buckets
+                    conn.throttle_deadline = None;
+                    conn.refill_buckets(now);
+                }
+                Expiry::FirstByte => {
+                    if conn.first_byte_deadline != Some(at) {
+                        continue;
+                    }
+                    info!("{} first-byte timeout", token.0);
+                    self.first_byte_timeouts += 1;
+                    let before = conn.buffered();
+                    conn.abort();
+                    self.buffered = self.buffered - before + conn.buffered();
+                    self.events.push(Event::Error(
+                        ConnToken(token),
+                        io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "no data before first byte timeout",
+                        ),
+                    ));
+                }
+                Expiry::Establish => {
+                    if conn.establish_deadline != Some(at) {
+                        continue;
+                    }
+                    info!("{} establish timeout", token.0);
+                    self.establish_timeouts += 1;
+                    let before = conn.buffered();
+                    conn.abort();
+                    self.buffered = self.buffered - before + conn.buffered();
+                    self.events.push(Event::Error(
+                        ConnToken(token),
+                        io::Error::new(io::ErrorKind::TimedOut, "not established before timeout"),
+                    ));
+                }
+            }
+        }
+    }
+
+    fn sample_tcp_info(&mut self, now: Instant) {
+        for us in self.tokens.values_mut() {
+            if let OwnedMode::Conn(conn) = &mut us.mode {
+                conn.tcp_info = conn.inner.tcp().and_then(sys::tcp_info).ok();
+            }
+        }
+        if let Some(interval) = self.config.tcp_info_interval {
+            self.deadlines
+                .push(now + interval, COMMANDS_TOKEN, Expiry::TcpInfoSample);
+        }
+    }
+
+    fn record_wakeups(&mut self, delivered: usize, idle: usize) {
+        let wakeups = &mut self.wakeups;
+        wakeups.poll_events += delivered as u64;
+        wakeups.idle_wakeups += idle as u64;
+        wakeups.last_poll_events = delivered;
+        wakeups.last_idle_wakeups = idle;
+
+        // a poll which timed out isn't spinning, and isn't progress either
+        if 0 == delivered {
+            return;
+        }
+
+        if delivered != idle {
+            wakeups.no_progress_passes = 0;
+            return;
+        }
+
+        wakeups.no_progress_passes += 1;
+        let passes = wakeups.no_progress_passes;
+        if passes % SPIN_THRESHOLD == 0 {
+            warn!("{} consecutive wakeups made no progress", passes);
+            self.events.push(Event::Spinning { passes });
+        }
+    }
+
+    fn update_memory_pressure(&mut self) {
+        let max = match self.config.max_buffered_bytes {
+            Some(max) => max,
+            None => return,
+        };
+
+        if !self.memory_pressure && self.buffered > max {
+            info!("memory pressure: {} bytes buffered", self.buffered);
+            self.memory_pressure = true;
+            self.events.push(Event::MemoryPressure {
+                buffered: self.buffered,
+            });
+        } else if self.memory_pressure && self.buffered < max / 4 * 3 {
+            info!("memory pressure relieved: {} bytes buffered", self.buffered);
+            self.memory_pressure = false;
+        }
+    }
+
+    /// There's no room for another connection: there are `max_connections`, or there are no
+    /// tokens left to give one.
+    fn full(&self) -> bool {
+        self.tokens.exhausted()
+            || self
+                .config
+                .max_connections
+                .is_some_and(|max| self.tokens.conns() >= max)
+    }
+
+    /// Resume accepting, if it was stopped, and connections have since closed.
+    fn update_capacity(&mut self) -> Result<(), Error> {
+        if !self.at_capacity || self.full() {
+            return Ok(());
+        }
+        let connections = self.tokens.conns();
+        info!("below capacity: {} connections", connections);
+        self.at_capacity = false;
+        self.set_accepting(Interest::READABLE)?;
+        self.events.push(Event::BelowCapacity { connections });
+        Ok(())
+    }
+
+    /// Stop accepting, until `update_capacity` finds there's room.
+    fn reach_capacity(&mut self) -> Result<(), Error> {
+        let connections = self.tokens.conns();
+        info!("at capacity: {} connections", connections);
+        self.at_capacity = true;
+        self.set_accepting(Interest::NONE)?;
+        self.events.push(Event::AtCapacity { connections });
+        Ok(())
+    }
+
+    fn set_accepting(&mut self, interest: Interest) -> Result<(), Error> {
+        let mut listeners = Vec::new();
+        for (token, owned) in self.tokens.iter() {
+            if let OwnedMode::Server(server) = &owned.mode {
+                // `resume_accepts` re-arms it when its second is up
+                if server.deferred {
+                    continue;
+                }
+                self.reactor
+                    .reregister(Source::Listener(&server.inner), token, interest)
+                    .map_err(|e| Error::new(Operation::Register, e).with_listener(token))?;
+                listeners.push(token);
+            }
+        }
+        // being edge-triggered, a backlog which built up while paused wouldn't be reported
+        if Interest::NONE != interest {
+            self.woken.extend(listeners);
+        }
+        Ok(())
+    }
+
+    /// Only sockets whose interest has changed since they were last registered are touched.
+    fn reregister(&mut self) -> Result<(), Error> {
+        self.writers = 0;
+        for (token, owned) in self.tokens.iter_mut() {
+            match &mut owned.mode {
+                OwnedMode::Server(_) => continue,
+                OwnedMode::Conn(conn) => {
+                    let mut interest = Interest::NONE;
+
+                    if !self.memory_pressure && conn.read_buffer.read_interest() {
+                        interest |= Interest::READABLE;
+                    }
+
+                    let writing = conn.write_buffer.write_interest();
+                    if writing {
+                        self.writers += 1;
+                    }
+
+                    if conn.connecting || (writing && !self.egress_blocked) || conn.pipe_waiting() {
+                        interest |= Interest::WRITABLE;
+                    }
+
+                    if Some(interest) == conn.registered {
+                        continue;
+                    }
+
+                    match conn.inner.source() {
+                        Some(source) => {
+                            self.reactor
+                                .reregister(source, token, interest)
+                                .map_err(|e| {
+                                    Error::new(Operation::Register, e)
+                                        .with_token(token)
+                                        .with_peer(Some(conn.peer))
+                                })?;
+                        }
+                        // as the kernel would, report whatever's ready now
+                        None => self.woken.push(token),
+                    }
+                    conn.registered = Some(interest);
+                }
+                OwnedMode::Udp(udp) => {
+                    let mut interest = Interest::NONE;
+
+                    if !self.memory_pressure && udp.read_interest() {
+                        interest |= Interest::READABLE;
+                    }
+
+                    if udp.write_interest() {
+                        interest |= Interest::WRITABLE;
+                    }
+
+                    if Some(interest) == udp.registered {
+                        continue;
+                    }
+
+                    self.reactor
+                        .reregister(Source::Udp(&udp.inner), token, interest)
+                        .map_err(|e| Error::new(Operation::Register, e).with_token(token))?;
+                    udp.registered = Some(interest);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// One pass: close what's finished, wait for the network (but no longer than `wait`, if
+    /// given, or the next deadline), and turn what happened into events.
+    fn fill(&mut self, wait: Option<Duration>) -> Result<(), Error> {
+        self.close_some()?;
+
+        self.run_modes();
+
+        self.update_memory_pressure();
+
+        self.update_capacity()?;
+
+        self.reregister()?;
+
+        let mut ready = Vec::with_capacity(self.config.events_capacity);
+        // we may have generated events (e.g. Closed) already, which shouldn't wait for the network
+        let timeout = if self.events.is_empty() && self.woken.is_empty() {
+            match (self.deadlines.timeout(self.clock.now()), wait) {
+                (Some(due), Some(wait)) => Some(due.min(wait)),
+                (due, wait) => due.or(wait),
+            }
+        } else {
+            Some(Duration::from_secs(0))
+        };
+        self.reactor
+            .poll(&mut ready, self.config.events_capacity, timeout)
+            .during(Operation::Poll)?;
+        self.stats.polls += 1;
+        ready.append(&mut self.woken);
+
+        let now = self.clock.now();
+        self.events.stamp(now);
+        if let Some(egress) = &mut self.egress {
+            egress.refill(now);
+        }
+
+        self.expire_deadlines();
+
+        let mut delivered = 0;
+        let mut idle = 0;
+        // the same for everyone, so nobody gets less for being woken later
+        let share = self.egress_share();
+        for token in ready {
+            delivered += 1;
+            if COMMANDS_TOKEN == token {
+                self.channel.clear();
+                while let Some(command) = self.channel.try_recv() {
+                    self.apply(command)?;
+                }
+                continue;
+            }
+
+            if self.sources.contains_key(&token) {
+                self.events.push(Event::Ready(SourceToken(token)));
+                continue;
+            }
+
+            #[cfg(unix)]
+            if self.children.contains_key(&token) {
+                self.reap(token);
+                continue;
+            }
+
+            #[cfg(unix)]
+            if self.is_signals(token) {
+                self.take_signals();
+                continue;
+            }
+
+            let us: &mut Owned = match self.tokens.get_mut(&token) {
+                Some(us) => us,
+                None => {
+                    idle += 1;
+                    self.anomaly(Anomaly::UnknownToken(token))?;
+                    continue;
+                }
+            };
+
+            info!("{} woke", token.0);
+
+            match us.mode {
+                OwnedMode::Server(_) => {
+                    if !self.accept_all(token)? {
+                        idle += 1;
+                    }
+                }
+                OwnedMode::Conn(ref mut conn) => {
+                    if conn.connecting {
+                        match conn.connect_result() {
+                            None => {
+                                idle += 1;
+                                continue;
+                            }
+                            Some(Ok(())) => {
+                                info!("{} connected", token.0);
+                                conn.connecting = false;
+                                // through a proxy, it's not connected until the proxy says so
+                                if !conn.tunnelling() {
+                                    self.events.push(Event::Connected(ConnToken(token)));
+                                }
+                            }
+                            Some(Err(e)) => {
+                                info!("{} connect-err {:?}", token.0, e);
+                                let before = conn.buffered();
+                                conn.abort();
+                                self.buffered = self.buffered - before + conn.buffered();
+                                self.events.push(Event::ConnectFailed(ConnToken(token), e));
+                                continue;
+                            }
+                        }
+                    }
+
+                    let before = conn.buffered();
+                    let (read, written) = (conn.bytes_read, conn.bytes_written);
+                    conn.refill_buckets(now);
+                    conn.write_buffer.share = share;
+                    let reactor = &mut *self.reactor;
+                    let mut progress = shunt_io(conn, token, !self.memory_pressure, reactor);
+                    while conn.run_mode(&mut self.mode_stats) {
+                        progress |= shunt_io(conn, token, !self.memory_pressure, reactor);
+                    }
+                    // stopped by its share, not the kernel, so it won't be told it's writable
+                    if Some(0) == conn.write_buffer.share.take()
+                        && conn.write_buffer.write_interest()
+                    {
+                        conn.registered = None;
+                    }
+                    if let Some((at, expiry)) = conn.throttled_until() {
+                        self.deadlines.push(at, token, expiry);
+                    }
+                    self.stats.bytes_read += conn.bytes_read - read;
+                    let written = conn.bytes_written - written;
+                    self.stats.bytes_written += written;
+                    // `forward_links` will do a spliced connection's reading and writing
+                    if progress || conn.spliced() {
+                        conn.last_active = now;
+                    } else {
+                        idle += 1;
+                    }
+                    self.buffered = self.buffered - before + conn.buffered();
+                    let bytes = mem::replace(&mut conn.read_buffer.discarded, 0);
+                    if 0 != bytes && !conn.unattended {
+                        self.anomaly(Anomaly::ReadAfterClose { token, bytes })?;
+                    }
+                    self.spend_egress(written);
+                }
+                OwnedMode::Udp(ref mut udp) => {
+                    let before = udp.buffered();
+                    if !udp.shunt(token, !self.memory_pressure) {
+                        idle += 1;
+                    }
+                    self.buffered = self.buffered - before + udp.buffered();
+                }
+            }
+        }
+
+        self.record_wakeups(delivered, idle);
+
+        self.update_memory_pressure();
+
+        self.police_pool();
+
+        self.forward_links();
+
+        self.generate_events();
+
+        self.record_reads()?;
+
+        self.report_metrics();
+
+        #[cfg(feature = "debug-invariants")]
+        self.check_invariants();
+
+        self.events.end_pass();
+
+        Ok(())
+    }
+
+    /// Edge triggered, so keep going until the backlog is empty. Returns whether there was anything.
+    fn accept_all(&mut self, listener: Token) -> Result<bool, Error> {
+        let mut accepted = false;
+        loop {
+            if self.at_capacity {
+                return Ok(accepted);
+            }
+            if self.full() {
+                self.reach_capacity()?;
+                return Ok(accepted);
+            }
+            if self.accepts_deferred(listener)? {
+                return Ok(accepted);
+            }
+            let server = match self.tokens.get(&listener).map(|o| &o.mode) {
+                Some(OwnedMode::Server(server)) => server,
+                _ => unreachable!("accepting on a non-listener"),
+            };
+            let first_byte_timeout = server.options.first_byte_timeout;
+            let establish_timeout = server.options.establish_timeout;
+            let mode = server.options.mode;
+            let proxy_protocol = server.options.proxy_protocol;
+            let socket_options = server.options.socket_options.clone();
+            let (sock, addr) = match server.inner.accept() {
+                Ok(o) => o,
+                Err(ref e) if io::ErrorKind::WouldBlock == e.kind() => return Ok(accepted),
+                // the peer gave up while it was in the backlog
+                Err(ref e) if io::ErrorKind::ConnectionAborted == e.kind() => {
+                    info!("{} accept-aborted", listener.0);
+                    accepted = true;
+                    continue;
+                }
+                Err(e) => return Err(Error::new(Operation::Accept, e).with_listener(listener)),
+            };
+            accepted = true;
+            if !server.options.permits(addr.ip()) {
+                info!("{} blocked {}", listener.0, addr);
+                limits::refuse(listener, sock);
+                self.stats.blocked += 1;
+                continue;
+            }
+            let now = self.clock.now();
+            if let Some(OwnedMode::Server(server)) =
+                self.tokens.get_mut(&listener).map(|o| &mut o.mode)
+            {
+                server.rate.accepted(now);
+            }
+            if !self.admit(addr.ip()) {
+                info!("{} rejected {}: too many from it", listener.0, addr);
+                limits::refuse(listener, sock);
+                self.stats.rejected += 1;
+                continue;
+            }
+            if let Err(e) = socket_options.apply(&sock) {
+                info!("{} socket-options-err {} {:?}", listener.0, addr, e);
+            }
+            let new = self.tokens.reserve()?;
+            if let Err(e) = self
+                .reactor
+                .register(Source::Stream(&sock), new, Interest::READABLE)
+            {
+                self.release(new);
+                return Err(Error::new(Operation::Register, e)
+                    .with_token(new)
+                    .with_peer(Some(addr))
+                    .with_listener(listener));
+            }
+            self.events.push(Event::NewConnection {
+                token: ConnToken(new),
+                listener: ListenerToken(listener),
+                peer: addr,
+            });
+            self.stats.accepted += 1;
+            self.accept_rate.accepted(self.clock.now());
+            let first_byte_deadline = first_byte_timeout.map(|t| self.clock.now() + t);
+            if let Some(at) = first_byte_deadline {
+                self.deadlines.push(at, new, Expiry::FirstByte);
+            }
+            let establish_deadline = establish_timeout.map(|t| now + t);
+            if let Some(at) = establish_deadline {
+                self.deadlines.push(at, new, Expiry::Establish);
+            }
+            let mut conn = self.new_conn(Transport::Tcp(sock), addr);
+            conn.registered = Some(Interest::READABLE);
+            conn.first_byte_deadline = first_byte_deadline;
+            conn.establish_deadline = establish_deadline;
+            conn.mode = mode;
+            if self.config.max_connections_per_ip.is_some() {
+                conn.per_ip = Some(addr.ip());
+            }
+            if proxy_protocol {
+                conn.proxy_pending = true;
+                conn.read_buffer.raise_wanted(proxy::MAX_HEADER);
+            }
+            self.tokens.insert(
+                new,
+                Owned {
+                    token: new,
+                    mode: OwnedMode::Conn(conn),
+                },
+            );
+        }
+    }
+
+    /// Give connections in a built-in mode a chance to act on things which happened outside
+    /// of `fill`, like being accepted or having their mode set.
+    fn run_modes(&mut self) {
+        for us in self.tokens.values_mut() {
+            if let OwnedMode::Conn(conn) = &mut us.mode {
+                let before = conn.buffered();
+                conn.run_mode(&mut self.mode_stats);
+                self.buffered = self.buffered - before + conn.buffered();
+            }
+        }
+    }
+
+    fn generate_events(&mut self) {
+        for (token, us) in self.tokens.iter_mut() {
+            match &mut us.mode {
+                OwnedMode::Server(_) => (),
+                OwnedMode::Conn(conn) if conn.is_pooled_idle() => (),
+                OwnedMode::Conn(conn) if conn.unattended => {
+                    for e in conn.take_errors() {
+                        info!("{} unattended err {:?}", token.0, e);
+                    }
+                }
+                OwnedMode::Conn(conn) if Mode::Events != conn.mode || conn.link.is_some() => {
+                    for e in conn.take_errors() {
+                        self.events.push(Event::Error(ConnToken(token), e));
+                    }
+                }
+                OwnedMode::Conn(conn) => {
+                    let before = conn.buffered();
+                    let mut read = conn.read_event(token);
+                    // what came in behind a proxy's reply has already been read, so nothing
+                    // else will come along to report it
+                    if let Ok(Some(Event::Connected(connected))) = read {
+                        self.events.push(Event::Connected(connected));
+                        read = conn.read_event(token);
+                    }
+                    self.buffered = self.buffered - before + conn.buffered();
+                    match read {
+                        Ok(Some(ev)) => self.events.push(ev),
+                        Ok(None) => (),
+                        Err(e) => {
+                            info!("{} undecodable {:?}", token.0, e);
+                            let connecting = conn.tunnelling();
+                            if connecting {
+                                // it never was connected, as far as anyone else knows
+                                conn.read_buffer.peer_done = false;
+                            }
+                            let before = conn.buffered();
+                            // servers get to say what was wrong; anything else is reset
+                            match conn.http.as_ref().and_then(|_| http1::refusal(&e)) {
+                                Some(response) => {
+                                    let drain_timeout = self.config.drain_timeout;
+                                    let now = self.clock.now();
+                                    let (_, at) = conn.respond_and_close(
+                                        token,
+                                        &response,
+                                        drain_timeout,
+                                        now,
+                                    );
+                                    self.deadlines.push(at, token, Expiry::Drain);
+                                }
+                                None => conn.abort(),
+                            }
+                            self.buffered = self.buffered - before + conn.buffered();
+                            self.events.push(if connecting {
+                                Event::ConnectFailed(ConnToken(token), e)
+                            } else {
+                                Event::Error(ConnToken(token), e)
+                            });
+                        }
+                    }
+                    if mem::replace(&mut conn.read_buffer.filled, false) {
+                        self.events.push(Event::ReadBufferFull(ConnToken(token)));
+                    }
+                    // why the read side finished, before saying that it has
+                    if let Some(e) = conn.read_buffer.error.take() {
+                        self.events.push(Event::Error(ConnToken(token), e));
+                    }
+                    if mem::replace(&mut conn.read_buffer.peer_done, false) {
+                        self.events
+                            .push(Event::Done(ConnToken(token), Direction::Read));
+                    }
+                    if mem::replace(&mut conn.write_buffer.flushed, false) {
+                        self.events.push(Event::Flushed(ConnToken(token)));
+                    }
+                    if let Some(e) = conn.write_buffer.error.take() {
+                        self.events.push(Event::Error(ConnToken(token), e));
+                    }
+                    if mem::replace(&mut conn.write_buffer.peer_done, false) {
+                        self.events
+                            .push(Event::Done(ConnToken(token), Direction::Write));
+                    }
+                    match conn.cross_watermarks() {
+                        Some(true) => self.events.push(Event::WriteBufferHigh(ConnToken(token))),
+                        Some(false) => self.events.push(Event::WriteBufferLow(ConnToken(token))),
+                        None => (),
+                    }
+                }
+                OwnedMode::Udp(udp) => {
+                    if udp.has_incoming() {
+                        self.events.push(Event::Datagram(UdpToken(token)));
+                    }
+                    if let Some(e) = udp.error.take() {
+                        self.events.push(Event::UdpError(UdpToken(token), e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+enum Step {
+    Again,
+    Stop,
+    Blocked,
+}
+
+/// Returns whether anything happened, as opposed to everything returning `WouldBlock`.
+fn shunt_io(conn: &mut Conn, token: Token, read: bool, reactor: &mut dyn Reactor) -> bool {
+    let mut progress = false;
+    // a spliced connection is read straight into its pipe, by `forward_links`
+    while read && conn.read_buffer.do_read() && !conn.spliced() {
+        match do_a_read(conn, token, reactor) {
+            Step::Again => progress = true,
+            Step::Stop => {
+                progress = true;
+                break;
+            }
+            Step::Blocked => break,
+        }
+    }
+    while conn.write_buffer.do_write() {
+        match do_a_write(conn, token, reactor) {
+            Step::Again => progress = true,
+            Step::Stop => {
+                progress = true;
+                break;
+            }
+            Step::Blocked => break,
+        }
+    }
+    progress
+}
+
+fn do_a_read(conn: &mut Conn, token: Token, reactor: &mut dyn Reactor) -> Step {
+    // straight into the end of the buffer; it's borrowed from the stream for the duration
+    let limit = conn.read_buffer.read_limit(conn.read_chunk);
+    let mut dest = conn
+        .read_buffer
+        .buf_mut()
+        .map(mem::take)
+        .unwrap_or_default();
+    let result = dest.read_with(limit, |buf| {
+        let mut sock = Through {
+            inner: &conn.inner,
+            reactor,
+            token,
+        };
+        #[cfg(feature = "fault-injection")]
+        {
+            // an injected failure leaves the kernel's readiness unconsumed; re-arm it
+            if conn.faults.reading() {
+                conn.registered = None;
+            }
+            conn.faults.read(&mut sock, buf)
+        }
+        #[cfg(not(feature = "fault-injection"))]
+        sock.read(buf)
+    });
+    if let Ok(r) = result {
+        let read = dest.as_slice();
+        let read = &read[read.len() - r..];
+        if let Some(recorded) = &mut conn.recorded {
+            recorded.extend_from_slice(read);
+        }
+        tap::tapped(&conn.tap, token, Direction::Read, read);
+    }
+    let discarding = match conn.read_buffer.buf_mut() {
+        Some(buf) => {
+            *buf = dest;
             false
         }
+        None => true,
+    };
+
+    match result {
+        Ok(0) => {
+            conn.read_buffer.peer_eof();
+            Step::Stop
+        }
+
+        Ok(r) => {
+            conn.bytes_read += r as u64;
+            conn.first_byte_deadline = None;
+            if discarding {
+                debug!("{} discarding {} bytes read after close", token.0, r);
+                conn.read_buffer.discarded += r;
+            }
+            if let Some(credit) = &mut conn.read_buffer.credit {
+                *credit = credit.saturating_sub(r);
+            }
+            if let Some(bucket) = &mut conn.read_buffer.bucket {
+                bucket.take(r);
+            }
+            if conn.read_buffer.is_full() {
+                debug!("{} read buffer full", token.0);
+                conn.read_buffer.filled = true;
+            }
+            Step::Again
+        }
+
+        Err(ref e) if io::ErrorKind::WouldBlock == e.kind() => Step::Blocked,
+
+        Err(e) => {
+            info!("{} read-err {:?}", token.0, e);
+            conn.read_buffer.peer_eof();
+            conn.read_buffer.error = Some(e);
+            Step::Stop
+        }
     }
 }
 
-fn block_to_none<T>(res: Result<T, io::Error>) -> Result<Option<T>, io::Error> {
-    match res {
-        Ok(o) => Ok(Some(o)),
-        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
-        Err(e) => Err(e),
+fn do_a_write(conn: &mut Conn, token: Token, reactor: &mut dyn Reactor) -> Step {
+    let limit = conn.write_buffer.io_limit();
+    let buf = conn
+        .write_buffer
+        .buf()
+        .expect("asked to write, should be able to see data to write");
+    let sending_file = buf.front().is_empty();
+    let mut sock = Through {
+        inner: &conn.inner,
+        reactor,
+        token,
+    };
+    #[cfg(feature = "fault-injection")]
+    let result = if !sending_file && conn.faults.writing() {
+        conn.registered = None;
+        let front = buf.front();
+        conn.faults
+            .write(&mut sock, &front[..front.len().min(limit)])
+    } else {
+        sock.write_chunks(buf, limit)
+    };
+    #[cfg(not(feature = "fault-injection"))]
+    let result = sock.write_chunks(buf, limit);
+    match result {
+        Ok(0) => {
+            info!("{} write-eof", token.0);
+            conn.write_buffer.totes_done();
+            conn.write_buffer.peer_done = true;
+            Step::Stop
+        }
+        Ok(w) => {
+            conn.bytes_written += w as u64;
+            if let Some(bucket) = &mut conn.write_buffer.bucket {
+                bucket.take(w);
+            }
+            if let Some(share) = &mut conn.write_buffer.share {
+                *share = share.saturating_sub(w);
+            }
+            let taps = &conn.tap;
+            let buf = conn
+                .write_buffer
+                .buf_mut()
+                .expect("wrote data, should be able to discard it");
+            if taps.is_some() {
+                buf.front_chunks(w, |data| tap::tapped(taps, token, Direction::Write, data));
+            }
+            buf.advance(w);
+            if buf.is_empty() {
+                conn.write_buffer.flushed = true;
+            }
+            Step::Again
+        }
+
+        Err(ref e) if io::ErrorKind::WouldBlock == e.kind() => Step::Blocked,
+
+        Err(e) => {
+            info!("{} write-err {:?}", token.0, e);
+            if sending_file {
+                // the peer can't otherwise tell that the rest of the file is missing
+                conn.abort();
+            }
+            conn.write_buffer.totes_done();
+            conn.write_buffer.peer_done = true;
+            conn.write_buffer.error = Some(e);
+            Step::Stop
+        }
     }
 }
@@ -0,0 +1,238 @@
+//! Who gets accepted, and how fast: `ListenOptions::allow` and `deny`,
+//! `NetBuilder::max_connections_per_ip`, and `ListenOptions::max_accepts_per_sec`.
+
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use log::info;
+use mio::net::TcpStream;
+use mio::Token;
+
+use crate::config;
+use crate::reactor::Interest;
+use crate::reactor::Source;
+use crate::timer::Expiry;
+use crate::Error;
+use crate::ListenOptions;
+use crate::Net;
+use crate::NetBuilder;
+use crate::Operation;
+use crate::OwnedMode;
+
+/// A range of addresses, like `10.0.0.0/8`, or `2001:db8::/32`, for `ListenOptions::allow`
+/// and `deny`. Parses from that notation, or from a lone address, which is a range of one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    /// Bits of `addr` beyond the `prefix` are ignored. `prefix` can't be longer than the
+    /// address.
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<IpNet, Error> {
+        let addr = match addr {
+            IpAddr::V4(v4) if prefix <= 32 => {
+                IpAddr::V4(Ipv4Addr::from(u32::from(v4) & mask(prefix, 32) as u32))
+            }
+            IpAddr::V6(v6) if prefix <= 128 => {
+                IpAddr::V6(Ipv6Addr::from(u128::from(v6) & mask(prefix, 128)))
+            }
+            _ => return Err(Error::invalid("prefix", prefix, "longer than the address")),
+        };
+        Ok(IpNet { addr, prefix })
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Whether `ip` is in the range; an IPv4 address as seen by an IPv6 socket,
+    /// `::ffff:a.b.c.d`, counts as the IPv4 address.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                u32::from(ip) & mask(self.prefix, 32) as u32 == u32::from(net)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                u128::from(ip) & mask(self.prefix, 128) == u128::from(net)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The top `prefix` bits of a `bits`-wide address.
+fn mask(prefix: u8, bits: u32) -> u128 {
+    match u32::from(prefix) {
+        0 => 0,
+        prefix => (u128::MAX << (128 - prefix)) >> (128 - bits),
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<IpNet, Error> {
+        let malformed =
+            || Error::invalid("network", s, "expected an address, and optional /prefix");
+        let (addr, prefix) = match s.find('/') {
+            Some(slash) => (&s[..slash], Some(&s[slash + 1..])),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| malformed())?;
+        let prefix = match (prefix, addr) {
+            (Some(prefix), _) => prefix.parse().map_err(|_| malformed())?,
+            (None, IpAddr::V4(_)) => 32,
+            (None, IpAddr::V6(_)) => 128,
+        };
+        IpNet::new(addr, prefix)
+    }
+}
+
+impl ListenOptions {
+    /// Accept only connections from addresses in one of the `allow`ed ranges, if any are
+    /// given, and in none of the `deny`ed ones. Anything else is reset as soon as it's
+    /// accepted, without an `Event::NewConnection`, and counted in `NetStats::blocked`.
+    pub fn allow(mut self, net: IpNet) -> ListenOptions {
+        self.allow.push(net);
+        self
+    }
+
+    /// See `allow`; a `deny` wins over an `allow` for the same address.
+    pub fn deny(mut self, net: IpNet) -> ListenOptions {
+        self.deny.push(net);
+        self
+    }
+
+    /// Accept at most this many connections a second; the rest wait in the backlog until the
+    /// next second, and each time that happens counts in `NetStats::deferred`. Zero is rejected
+    /// by the listen call.
+    pub fn max_accepts_per_sec(mut self, max: u32) -> ListenOptions {
+        self.max_accepts_per_sec = Some(max);
+        self
+    }
+}
+
+impl NetBuilder {
+    /// Reset connections accepted from an address which already has this many open, counting
+    /// them in `NetStats::rejected`; they never become `Event::NewConnection`s. It's the
+    /// socket's address which counts, not one given by `ListenOptions::proxy_protocol`. Zero is
+    /// rejected by `build`.
+    pub fn max_connections_per_ip(mut self, max: usize) -> NetBuilder {
+        self.config.max_connections_per_ip = Some(max);
+        self
+    }
+}
+
+pub(crate) fn check(options: &ListenOptions) -> Result<(), Error> {
+    if let Some(max) = options.max_accepts_per_sec {
+        config::check_limit("max_accepts_per_sec", max as usize, usize::MAX)?;
+    }
+    Ok(())
+}
+
+impl ListenOptions {
+    /// Whether `allow` and `deny` let `peer` in.
+    pub(crate) fn permits(&self, peer: IpAddr) -> bool {
+        if self.deny.iter().any(|net| net.contains(peer)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(peer))
+    }
+}
+
+/// Reset a connection which has just been accepted, before anything is known about it.
+pub(crate) fn refuse(listener: Token, sock: TcpStream) {
+    if let Err(e) = sock.set_linger(Some(Duration::from_secs(0))) {
+        info!("{} linger-err {:?}", listener.0, e);
+    }
+}
+
+impl<T> Net<T> {
+    /// Before accepting on `listener`: whether it's had its fill this second, in which case
+    /// it's stopped until the next.
+    pub(crate) fn accepts_deferred(&mut self, listener: Token) -> Result<bool, Error> {
+        let now = self.clock.now();
+        let server = match self.tokens.get_mut(&listener).map(|o| &mut o.mode) {
+            Some(OwnedMode::Server(server)) => server,
+            _ => unreachable!("accepting on a non-listener"),
+        };
+        if server.deferred {
+            return Ok(true);
+        }
+        let max = match server.options.max_accepts_per_sec {
+            Some(max) => u64::from(max),
+            None => return Ok(false),
+        };
+        let (accepted, until) = server.rate.this_second(now);
+        if accepted < max {
+            return Ok(false);
+        }
+        info!("{} accepts deferred", listener.0);
+        server.deferred = true;
+        self.reactor
+            .reregister(Source::Listener(&server.inner), listener, Interest::NONE)
+            .map_err(|e| Error::new(Operation::Register, e).with_listener(listener))?;
+        self.deadlines.push(until, listener, Expiry::AcceptRate);
+        self.stats.deferred += 1;
+        Ok(true)
+    }
+
+    /// The next second has come for a listener stopped by `accepts_deferred`.
+    pub(crate) fn resume_accepts(&mut self, listener: Token) -> Result<(), Error> {
+        let server = match self.tokens.get_mut(&listener).map(|o| &mut o.mode) {
+            Some(OwnedMode::Server(server)) => server,
+            // stopped listening in the meantime, and the token may have been reused
+            _ => return Ok(()),
+        };
+        if !server.deferred {
+            return Ok(());
+        }
+        server.deferred = false;
+        // leaving the capacity check to re-arm it
+        if self.at_capacity {
+            return Ok(());
+        }
+        self.reactor
+            .reregister(
+                Source::Listener(&server.inner),
+                listener,
+                Interest::READABLE,
+            )
+            .map_err(|e| Error::new(Operation::Register, e).with_listener(listener))?;
+        self.woken.push(listener);
+        Ok(())
+    }
+
+    /// After accepting from `peer`: whether to keep the connection, counting it if so.
+    pub(crate) fn admit(&mut self, peer: IpAddr) -> bool {
+        let max = match self.config.max_connections_per_ip {
+            Some(max) => max,
+            None => return true,
+        };
+        let open = self.per_ip.entry(peer).or_insert(0);
+        if *open >= max {
+            return false;
+        }
+        *open += 1;
+        true
+    }
+
+    /// A connection which was `admit`ted has gone.
+    pub(crate) fn forget_peer(&mut self, peer: IpAddr) {
+        if let Some(open) = self.per_ip.get_mut(&peer) {
+            *open -= 1;
+            if 0 == *open {
+                self.per_ip.remove(&peer);
+            }
+        }
+    }
+}
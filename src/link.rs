@@ -0,0 +1,321 @@
+//! Connections joined end to end, so whatever one reads, the other writes, without passing
+//! through the application.
+
+use std::io;
+
+use log::info;
+
+use crate::buffer::Buffer;
+use crate::http1;
+use crate::sys;
+use crate::Conn;
+use crate::ConnToken;
+use crate::Error;
+use crate::Mode;
+use crate::Net;
+use crate::OwnedMode;
+use crate::Token;
+
+/// How much may wait to be written to a linked connection without write watermarks, before
+/// reading from the other stops.
+const DEFAULT_HIGH: usize = 64 * 1024;
+
+/// One end of a joined pair.
+pub(crate) struct Link {
+    /// The other end.
+    pub(crate) peer: Token,
+    /// This end asked the other to be connected with a `CONNECT`, so it's owed an answer
+    /// once the other end has connected, or has failed to.
+    pub(crate) answer: bool,
+    /// What's on its way to this end from the other, if the pair is spliced.
+    pub(crate) pipe: Option<sys::Pipe>,
+}
+
+impl<T> Net<T> {
+    /// Join two connections, as a proxy would: from now on, whatever one reads, the other
+    /// writes, starting with anything already read. Once more than one's high write watermark
+    /// (or 64KiB, without watermarks) is waiting to be written to it, nothing more is taken
+    /// from the other, which stops reading when its read buffer is full.
+    ///
+    /// When one end has finished sending, the other's write side is closed once it's flushed;
+    /// when one is reset, so is the other; when one closes, so does the other. From now on,
+    /// neither reports anything but errors and `Event::Closed`, and for one which is still
+    /// connecting, how that went. Both must be TCP connections, in `Mode::Events`, and not
+    /// already linked.
+    pub fn link(&mut self, a: ConnToken, b: ConnToken) -> Result<(), Error> {
+        let (a, b) = (a.0, b.0);
+        if a == b {
+            return Err(Error::invalid("link", a, "the same connection twice"));
+        }
+        self.check_token(a)?;
+        self.check_token(b)?;
+        let (first, second) = self.tokens.get2_mut(&a, &b).expect("both just checked");
+        for conn in [&first.mode, &second.mode].iter() {
+            match conn {
+                OwnedMode::Conn(conn)
+                    if Mode::Events == conn.mode && conn.link.is_none() && !conn.tunnelling() => {}
+                _ => {
+                    return Err(Error::invalid(
+                        "link",
+                        (a, b),
+                        "not both unlinked TCP connections, in Mode::Events",
+                    ))
+                }
+            }
+        }
+        self.link_conns(a, b, false);
+        Ok(())
+    }
+
+    /// As `link`, but on Linux, data is moved with `splice(2)`, through a pipe in each
+    /// direction, so it's never copied into (or out of) the process; anything already read
+    /// still goes the slow way first. A full pipe stands in for the high write watermark.
+    ///
+    /// If pipes can't be made, or the kernel won't splice these sockets, or either end is a
+    /// `memory_pair`, or this isn't Linux, or the reactor reads and writes sockets itself, like
+    /// a `Uring`, the pair is linked exactly as by `link`, and carries on working.
+    pub fn link_spliced(&mut self, a: ConnToken, b: ConnToken) -> Result<(), Error> {
+        self.link(a, b)?;
+        if self.reactor.completes() {
+            return Ok(());
+        }
+        for token in [a.0, b.0] {
+            if self
+                .conn_mut(token)
+                .expect("just linked")
+                .inner
+                .tcp()
+                .is_err()
+            {
+                return Ok(());
+            }
+        }
+        let pipes = match (sys::Pipe::new(), sys::Pipe::new()) {
+            (Ok(into_a), Ok(into_b)) => [(a.0, into_a), (b.0, into_b)],
+            (Err(e), _) | (_, Err(e)) => {
+                info!("{} splice-unavailable {:?}", a.0 .0, e);
+                return Ok(());
+            }
+        };
+        for (token, pipe) in pipes {
+            let conn = self.conn_mut(token).expect("just linked");
+            if let Some(link) = &mut conn.link {
+                link.pipe = Some(pipe);
+            }
+        }
+        Ok(())
+    }
+
+    /// Join two connections, for `link`, or `http1::accept_connect`.
+    pub(crate) fn link_conns(&mut self, a: Token, b: Token, answer_a: bool) {
+        let (first, second) = self.tokens.get2_mut(&a, &b).expect("checked by the caller");
+        for (owned, peer, answer) in [(first, b, answer_a), (second, a, false)].iter_mut() {
+            if let OwnedMode::Conn(conn) = &mut owned.mode {
+                conn.link = Some(Link {
+                    peer: *peer,
+                    answer: *answer,
+                    pipe: None,
+                });
+            }
+        }
+    }
+
+    /// Move whatever's been read on one end of each pair to the other.
+    pub(crate) fn forward_links(&mut self) {
+        let pairs: Vec<(Token, Token)> = self
+            .tokens
+            .iter()
+            .filter_map(|(token, owned)| match &owned.mode {
+                OwnedMode::Conn(Conn {
+                    link: Some(link), ..
+                }) if token < link.peer => Some((token, link.peer)),
+                _ => None,
+            })
+            .collect();
+        for (a, b) in pairs {
+            let (first, second) = self.tokens.get2_mut(&a, &b).expect("links are unlinked");
+            let (first, second) = match (&mut first.mode, &mut second.mode) {
+                (OwnedMode::Conn(first), OwnedMode::Conn(second)) => (first, second),
+                _ => unreachable!("only connections are linked"),
+            };
+            let before = first.buffered() + second.buffered();
+            let read = first.bytes_read + second.bytes_read;
+            let written = first.bytes_written + second.bytes_written;
+            answer(first, second);
+            answer(second, first);
+            if let Err(e) = pump(first, second).and_then(|()| pump(second, first)) {
+                info!("{} splice-unsupported {:?}", a.0, e);
+                unsplice(first);
+                unsplice(second);
+            }
+            forward(first, second);
+            forward(second, first);
+            self.buffered = self.buffered - before + first.buffered() + second.buffered();
+            self.stats.bytes_read += first.bytes_read + second.bytes_read - read;
+            self.stats.bytes_written += first.bytes_written + second.bytes_written - written;
+        }
+    }
+
+    /// The other end of `token`'s link has gone: finish it off too.
+    pub(crate) fn unlink(&mut self, token: Token) {
+        let conn = match self.conn_mut(token) {
+            Some(conn) => conn,
+            None => return,
+        };
+        let before = conn.buffered();
+        if let Some(Link { answer: true, .. }) = conn.link.take() {
+            if let Some(buf) = conn.write_buffer.buf_mut() {
+                buf.push(http1::tunnel_answer(false));
+                conn.registered = None;
+            }
+        }
+        conn.read_buffer.become_at_least_truncating_close();
+        conn.write_buffer.become_at_least_draining_close();
+        let after = conn.buffered();
+        self.buffered = self.buffered - before + after;
+    }
+}
+
+/// Tell `conn` its tunnel is up, if it's owed that, and it is.
+fn answer(conn: &mut Conn, peer: &Conn) {
+    match &mut conn.link {
+        Some(link) if link.answer && !peer.connecting => link.answer = false,
+        _ => return,
+    }
+    if let Some(buf) = conn.write_buffer.buf_mut() {
+        if buf.is_empty() {
+            conn.registered = None;
+        }
+        buf.push(http1::tunnel_answer(true));
+    }
+}
+
+fn forward(from: &mut Conn, to: &mut Conn) {
+    // nothing goes back until the tunnel has been announced
+    if let Some(Link { answer: true, .. }) = &to.link {
+        return;
+    }
+    // a reset is passed on as a reset, so the other peer doesn't mistake it for a clean end
+    if from.read_buffer.error.is_some() {
+        to.abort();
+        return;
+    }
+    let high = to.watermarks.as_ref().map_or(DEFAULT_HIGH, |w| w.high);
+    if to.write_buffer.len() >= high {
+        return;
+    }
+    if let Some(src) = from.read_buffer.buf_mut() {
+        if !src.is_empty() {
+            let data = src.take(usize::MAX);
+            if let Some(dest) = to.write_buffer.buf_mut() {
+                // the edge which would have announced writability may already have passed
+                if dest.is_empty() {
+                    to.registered = None;
+                }
+                dest.push(data);
+            }
+        }
+    }
+    // nobody is told about the end of one side; the other just finishes writing, then closes
+    if from.read_buffer.peer_done && !to.pipe_waiting() {
+        from.read_buffer.peer_done = false;
+        to.write_buffer.become_at_least_draining_close();
+        to.registered = None;
+    }
+}
+
+/// Splice from one end to the other, if they're spliced, until neither will go any further.
+/// Fails only if splicing can't be used after all.
+fn pump(from: &mut Conn, to: &mut Conn) -> io::Result<()> {
+    let pipe = match &mut to.link {
+        Some(Link {
+            pipe: Some(pipe),
+            answer: false,
+            ..
+        }) => pipe,
+        _ => return Ok(()),
+    };
+    loop {
+        let mut moved = false;
+
+        // anything written to `to` the slow way goes first
+        let writable = to.write_buffer.buf().is_some_and(Buffer::is_empty);
+        if 0 != pipe.len && writable {
+            match pipe.empty_into(to.inner.tcp().expect("only sockets are spliced")) {
+                Ok(n) => {
+                    to.bytes_written += n as u64;
+                    moved = true;
+                }
+                Err(ref e) if io::ErrorKind::WouldBlock == e.kind() => (),
+                Err(e) if sys::splice_unsupported_by(&e) => return Err(e),
+                Err(e) => {
+                    info!("splice-write-err {:?}", e);
+                    to.write_buffer.totes_done();
+                    to.write_buffer.peer_done = true;
+                    to.write_buffer.error = Some(e);
+                    return Ok(());
+                }
+            }
+        }
+
+        // and anything read the slow way has to be forwarded before more is spliced
+        let readable =
+            from.read_buffer.do_read() && from.read_buffer.buf().is_some_and(Buffer::is_empty);
+        if pipe.len < pipe.capacity && readable {
+            match pipe.fill_from(from.inner.tcp().expect("only sockets are spliced")) {
+                Ok(0) => from.read_buffer.peer_eof(),
+                Ok(n) => {
+                    from.bytes_read += n as u64;
+                    from.first_byte_deadline = None;
+                    moved = true;
+                }
+                Err(ref e) if io::ErrorKind::WouldBlock == e.kind() => (),
+                Err(e) if sys::splice_unsupported_by(&e) => return Err(e),
+                Err(e) => {
+                    info!("splice-read-err {:?}", e);
+                    from.read_buffer.peer_eof();
+                    from.read_buffer.error = Some(e);
+                }
+            }
+        }
+
+        if !moved {
+            return Ok(());
+        }
+    }
+}
+
+/// Give up on splicing: whatever's in the pipe is written the slow way, after anything
+/// already waiting.
+fn unsplice(conn: &mut Conn) {
+    let mut pipe = match conn.link.as_mut().and_then(|link| link.pipe.take()) {
+        Some(pipe) => pipe,
+        None => return,
+    };
+    let data = match pipe.drain() {
+        Ok(data) => data,
+        Err(e) => {
+            info!("splice-drain-err {:?}", e);
+            conn.abort();
+            return;
+        }
+    };
+    if let Some(buf) = conn.write_buffer.buf_mut() {
+        if buf.is_empty() {
+            conn.registered = None;
+        }
+        buf.push(data);
+    }
+}
+
+impl Conn {
+    /// Whether the connection is read by splicing, instead of into its read buffer.
+    pub(crate) fn spliced(&self) -> bool {
+        matches!(&self.link, Some(Link { pipe: Some(_), .. }))
+    }
+
+    /// Whether there's data in the pipe waiting to be written to this connection.
+    pub(crate) fn pipe_waiting(&self) -> bool {
+        matches!(&self.link, Some(Link { pipe: Some(pipe), .. }) if 0 != pipe.len)
+    }
+}
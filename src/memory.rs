@@ -0,0 +1,437 @@
+//! Connections which never touch the OS: two ends of an in-memory pipe, for testing protocol
+//! handlers deterministically; see `Net::memory_pair`.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::Shutdown;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Duration;
+
+use iovec::IoVec;
+use mio::net::TcpStream;
+
+use crate::buffer::Chunks;
+#[cfg(unix)]
+use crate::pipes::Pipes;
+use crate::reactor::Reactor;
+use crate::reactor::Source;
+use crate::sys;
+use crate::ConnToken;
+use crate::Error;
+use crate::Net;
+use crate::Owned;
+use crate::OwnedMode;
+use crate::Token;
+
+/// How much each direction holds, sent and arrived, before writes would block.
+pub const MEMORY_PIPE_CAPACITY: usize = 64 * 1024;
+
+/// What a connection reads from and writes to.
+pub(crate) enum Transport {
+    Tcp(TcpStream),
+    Memory(End),
+    #[cfg(unix)]
+    Pipes(Pipes),
+}
+
+/// One end of a `memory_pair`.
+pub(crate) struct End {
+    pipe: Arc<Mutex<Pipe>>,
+    /// Which of the pipe's `ways` this end sends on.
+    side: usize,
+    local: SocketAddr,
+    peer: SocketAddr,
+}
+
+struct Pipe {
+    /// `ways[0]` carries what the first end sends, `ways[1]` the second.
+    ways: [Way; 2],
+    tokens: [Token; 2],
+}
+
+#[derive(Default)]
+struct Way {
+    /// Written, but not yet pumped.
+    sent: Vec<u8>,
+    /// Pumped, and waiting to be read.
+    arrived: VecDeque<u8>,
+    /// The sender has shut down, or gone; the reader sees the end once it's pumped through.
+    closing: bool,
+    closed: bool,
+    /// The sender has reset the connection; the reader sees it once it's pumped.
+    resetting: bool,
+    reset: bool,
+    /// The sender has gone; writing to it fails.
+    dropped: bool,
+    /// A write found it full, so the sender is owed a wakeup once there's room.
+    blocked: bool,
+}
+
+impl<T> Net<T> {
+    /// Two connections joined to each other in memory: what one writes, the other reads, with
+    /// the same events as over TCP. Nothing moves until `pump` is called, so a test decides
+    /// exactly when each side sees the other's bytes, end, or reset. Both are connected from
+    /// the start, so there's no `Event::Connected`. Their addresses are made up; anything which
+    /// needs a real socket, like `os::unix::IoExt::tcp_info`, fails.
+    pub fn memory_pair(&mut self) -> Result<(ConnToken, ConnToken), Error> {
+        let first = self.tokens.reserve()?;
+        let second = match self.tokens.reserve() {
+            Ok(second) => second,
+            Err(e) => {
+                self.release(first);
+                return Err(e);
+            }
+        };
+        let tokens = [first, second];
+        let pipe = Arc::new(Mutex::new(Pipe {
+            ways: Default::default(),
+            tokens,
+        }));
+        let addrs = [
+            SocketAddr::from(([127, 0, 0, 1], 1)),
+            SocketAddr::from(([127, 0, 0, 1], 2)),
+        ];
+        for side in 0..2 {
+            let end = End {
+                pipe: pipe.clone(),
+                side,
+                local: addrs[side],
+                peer: addrs[1 - side],
+            };
+            let conn = self.new_conn(Transport::Memory(end), addrs[1 - side]);
+            self.tokens.insert(
+                tokens[side],
+                Owned {
+                    token: tokens[side],
+                    mode: OwnedMode::Conn(conn),
+                },
+            );
+        }
+        Ok((ConnToken(tokens[0]), ConnToken(tokens[1])))
+    }
+
+    /// Move everything written to each `memory_pair` to the other end, along with any ends or
+    /// resets, and wake whichever connections that concerns. Returns how many bytes moved.
+    /// Events follow from the next `next()`, or `try_next()`.
+    pub fn pump(&mut self) -> usize {
+        let mut moved = 0;
+        let mut woken = Vec::new();
+        for (_, owned) in self.tokens.iter() {
+            if let OwnedMode::Conn(crate::Conn {
+                inner: Transport::Memory(end),
+                ..
+            }) = &owned.mode
+            {
+                moved += end.pipe().pump(&mut woken);
+            }
+        }
+        woken.retain(|token| self.tokens.get(token).is_some());
+        woken.sort();
+        woken.dedup();
+        self.woken.extend(woken);
+        moved
+    }
+}
+
+impl Pipe {
+    /// Both ways; a pair whose ends both still exist is pumped twice, which is harmless.
+    fn pump(&mut self, woken: &mut Vec<Token>) -> usize {
+        let mut moved = 0;
+        for side in 0..2 {
+            let (reader, writer) = (self.tokens[1 - side], self.tokens[side]);
+            let way = &mut self.ways[side];
+            let mut changed = !way.sent.is_empty();
+            moved += way.sent.len();
+            way.arrived.extend(way.sent.drain(..));
+            if way.closing && !way.closed {
+                way.closed = true;
+                changed = true;
+            }
+            if way.resetting && !way.reset {
+                way.reset = true;
+                changed = true;
+            }
+            if changed {
+                woken.push(reader);
+            }
+            if way.blocked && way.room() > 0 {
+                way.blocked = false;
+                woken.push(writer);
+            }
+        }
+        moved
+    }
+}
+
+impl Way {
+    fn room(&self) -> usize {
+        MEMORY_PIPE_CAPACITY.saturating_sub(self.sent.len() + self.arrived.len())
+    }
+}
+
+impl End {
+    fn pipe(&self) -> MutexGuard<'_, Pipe> {
+        self.pipe.lock().expect("nothing panics holding the pipe")
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut pipe = self.pipe();
+        let way = &mut pipe.ways[1 - self.side];
+        if way.reset {
+            return Err(io::ErrorKind::ConnectionReset.into());
+        }
+        if way.arrived.is_empty() {
+            return match way.closed {
+                true => Ok(0),
+                false => Err(io::ErrorKind::WouldBlock.into()),
+            };
+        }
+        let n = buf.len().min(way.arrived.len());
+        for (dest, src) in buf.iter_mut().zip(way.arrived.drain(..n)) {
+            *dest = src;
+        }
+        Ok(n)
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipe = self.pipe();
+        if pipe.ways[1 - self.side].reset {
+            return Err(io::ErrorKind::ConnectionReset.into());
+        }
+        if pipe.ways[1 - self.side].dropped {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        let way = &mut pipe.ways[self.side];
+        if way.closing {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        let n = buf.len().min(way.room());
+        if 0 == n && !buf.is_empty() {
+            way.blocked = true;
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        way.sent.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl Drop for End {
+    /// As closing a socket: the other end sees the end of the stream.
+    fn drop(&mut self) {
+        if let Ok(mut pipe) = self.pipe.lock() {
+            let way = &mut pipe.ways[self.side];
+            way.closing = true;
+            way.dropped = true;
+        }
+    }
+}
+
+impl Transport {
+    /// The socket, for things only a socket can do.
+    pub(crate) fn tcp(&self) -> io::Result<&TcpStream> {
+        match self {
+            Transport::Tcp(sock) => Ok(sock),
+            Transport::Memory(_) => Err(io::Error::other(
+                "not a socket, but an in-memory connection",
+            )),
+            #[cfg(unix)]
+            Transport::Pipes(_) => Err(io::Error::other("not a socket, but pipes")),
+        }
+    }
+
+    /// What the reactor should watch; nothing, for an in-memory connection, which `pump` wakes.
+    pub(crate) fn source(&self) -> Option<Source<'_>> {
+        match self {
+            Transport::Tcp(sock) => Some(Source::Stream(sock)),
+            Transport::Memory(_) => None,
+            #[cfg(unix)]
+            Transport::Pipes(pipes) => Some(pipes.source()),
+        }
+    }
+
+    pub(crate) fn take_error(&self) -> io::Result<Option<io::Error>> {
+        match self {
+            Transport::Tcp(sock) => sock.take_error(),
+            Transport::Memory(_) => Ok(None),
+            #[cfg(unix)]
+            Transport::Pipes(_) => Ok(None),
+        }
+    }
+
+    pub(crate) fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Transport::Tcp(sock) => sock.peer_addr(),
+            Transport::Memory(end) => Ok(end.peer),
+            #[cfg(unix)]
+            Transport::Pipes(_) => Err(io::Error::other("pipes have no address")),
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Transport::Tcp(sock) => sock.local_addr(),
+            Transport::Memory(end) => Ok(end.local),
+            #[cfg(unix)]
+            Transport::Pipes(_) => Err(io::Error::other("pipes have no address")),
+        }
+    }
+
+    /// Have the connection reset when it's closed, instead of closed politely.
+    pub(crate) fn reset_on_close(&self) -> io::Result<()> {
+        match self {
+            Transport::Tcp(sock) => sock.set_linger(Some(Duration::from_secs(0))),
+            Transport::Memory(end) => {
+                end.pipe().ways[end.side].resetting = true;
+                Ok(())
+            }
+            // there's nothing to reset; closing is all there is
+            #[cfg(unix)]
+            Transport::Pipes(_) => Ok(()),
+        }
+    }
+
+    pub(crate) fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Transport::Tcp(sock) => sock.shutdown(how),
+            Transport::Memory(end) => {
+                if Shutdown::Read != how {
+                    end.pipe().ways[end.side].closing = true;
+                }
+                Ok(())
+            }
+            #[cfg(unix)]
+            Transport::Pipes(pipes) => {
+                if Shutdown::Read != how {
+                    pipes.shutdown_write();
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn write_bufs(&self, bufs: &[&IoVec]) -> io::Result<usize> {
+        if let Transport::Tcp(sock) = self {
+            return sock.write_bufs(bufs);
+        }
+        let mut written = 0;
+        for buf in bufs {
+            match (&*self).write(buf) {
+                Ok(n) => {
+                    written += n;
+                    if n < buf.len() {
+                        break;
+                    }
+                }
+                Err(e) if 0 == written => return Err(e),
+                Err(_) => break,
+            }
+        }
+        Ok(written)
+    }
+
+    /// As `sys::send_file`.
+    pub(crate) fn send_file(&self, file: &File, offset: u64, len: usize) -> io::Result<usize> {
+        match self {
+            Transport::Tcp(sock) => sys::send_file(sock, file, offset, len),
+            _ => sys::copy_file(self, file, offset, len),
+        }
+    }
+}
+
+/// A connection's transport, read and written through its reactor, if that does the reading
+/// and writing of sockets itself, as `Reactor::completes`.
+pub(crate) struct Through<'t> {
+    pub(crate) inner: &'t Transport,
+    pub(crate) reactor: &'t mut dyn Reactor,
+    pub(crate) token: Token,
+}
+
+impl Through<'_> {
+    /// As `Chunks::write_to`.
+    pub(crate) fn write_chunks(&mut self, chunks: &Chunks, limit: usize) -> io::Result<usize> {
+        match self.inner {
+            Transport::Tcp(sock) if self.reactor.completes() => {
+                self.reactor
+                    .write(sock, self.token, &mut |out| chunks.copy_to(out, limit))
+            }
+            inner => chunks.write_to(inner, limit),
+        }
+    }
+}
+
+impl Read for Through<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner {
+            Transport::Tcp(sock) if self.reactor.completes() => {
+                self.reactor.read(sock, self.token, buf)
+            }
+            mut inner => inner.read(buf),
+        }
+    }
+}
+
+impl Write for Through<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.inner {
+            Transport::Tcp(sock) if self.reactor.completes() => {
+                self.reactor.write(sock, self.token, &mut |out| {
+                    out.extend_from_slice(buf);
+                    Ok(())
+                })
+            }
+            mut inner => inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for &Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Transport::Tcp(sock) => (&*sock).read(buf),
+            Transport::Memory(end) => end.read(buf),
+            #[cfg(unix)]
+            Transport::Pipes(pipes) => pipes.read(buf),
+        }
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Write for &Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Transport::Tcp(sock) => (&*sock).write(buf),
+            Transport::Memory(end) => end.write(buf),
+            #[cfg(unix)]
+            Transport::Pipes(pipes) => pipes.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
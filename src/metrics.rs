@@ -0,0 +1,255 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::mode::ModeMetrics;
+use crate::pool::PoolMetrics;
+use crate::ConnToken;
+use crate::Net;
+use crate::OwnedMode;
+use crate::StreamState;
+
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    /// Bytes currently held in read and write buffers, across all connections.
+    pub buffered_bytes: usize,
+    pub wakeups: Wakeups,
+    /// Connections aborted by `ListenOptions::first_byte_timeout`.
+    pub first_byte_timeouts: u64,
+    /// Connections aborted by `ListenOptions::establish_timeout`.
+    pub establish_timeouts: u64,
+    pub pool: PoolMetrics,
+    pub event_ages: EventAges,
+    /// Bytes handled by connections in a built-in `Mode`.
+    pub modes: ModeMetrics,
+    /// See `Anomaly`; counted even when not `strict`.
+    pub anomalies: u64,
+    /// Spare buffer capacity currently kept for new connections.
+    pub pooled_buffer_bytes: usize,
+    /// Connections which started out with a recycled buffer.
+    pub recycled_buffers: u64,
+}
+
+/// One connection's, from `Io::stats`.
+#[derive(Clone, Debug)]
+pub struct ConnStats {
+    /// Since it was accepted, or connected; including anything since thrown away.
+    pub bytes_read: u64,
+    /// Handed to the kernel.
+    pub bytes_written: u64,
+    /// Read, and not yet consumed.
+    pub read_buffered: usize,
+    /// Queued, and not yet written.
+    pub write_buffered: usize,
+    pub age: Duration,
+    /// When anything was last read or written, as `Io::set_idle_timeout` sees it.
+    pub last_active: Instant,
+}
+
+/// A connection, as listed by `Net::connections`.
+#[derive(Clone, Debug)]
+pub struct ConnInfo {
+    pub token: ConnToken,
+    pub peer: SocketAddr,
+    pub state: ConnState,
+    /// Read, and not yet consumed.
+    pub read_buffered: usize,
+    /// Queued, and not yet written.
+    pub write_buffered: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConnState {
+    /// A `tcp_connect` still in progress.
+    Connecting,
+    Open,
+    /// Kept in the pool, for reuse by `PoolOptions`.
+    PooledIdle,
+    /// One direction, or both, is closing, or closed.
+    Closing,
+    /// Both directions are finished with; it will be removed shortly.
+    Closed,
+}
+
+/// Everything the `Net` has done, from `Net::stats`.
+#[derive(Clone, Debug, Default)]
+pub struct NetStats {
+    /// Open, or still closing.
+    pub connections: usize,
+    pub accepted: u64,
+    /// Accepted during the last whole second.
+    pub accepts_per_sec: u64,
+    /// Accepted, then reset, by `ListenOptions::allow` or `deny`.
+    pub blocked: u64,
+    /// Accepted, then reset, by `NetBuilder::max_connections_per_ip`.
+    pub rejected: u64,
+    /// Times a listener stopped accepting for the rest of a second, by
+    /// `ListenOptions::max_accepts_per_sec`.
+    pub deferred: u64,
+    /// By every connection, ever; as `Io::stats`.
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Returned by `next()`, and friends.
+    pub events: u64,
+    /// Passes of the event loop, each of which polls the reactor once.
+    pub polls: u64,
+}
+
+/// Takes a `Net`'s numbers, after each pass of the event loop, to feed them to whatever
+/// metrics system the application uses; see `Net::set_metrics_sink`. Names are as the
+/// `NetStats` fields, prefixed with `nofio.`, e.g. `nofio.bytes_read`.
+pub trait MetricsSink: Send {
+    /// `name` has gone up by `delta` since it was last reported, which is not reported when
+    /// it's zero.
+    fn counter(&mut self, name: &'static str, delta: u64);
+
+    /// `name` is now `value`.
+    fn gauge(&mut self, name: &'static str, value: u64);
+}
+
+/// Accepts, counted in whole seconds.
+#[derive(Default)]
+pub(crate) struct AcceptRate {
+    started: Option<Instant>,
+    current: u64,
+    last: u64,
+}
+
+/// What `poll` woke us up for, to tell genuine load apart from spinning.
+#[derive(Clone, Debug, Default)]
+pub struct Wakeups {
+    pub poll_events: u64,
+    /// Events for which every read, write or accept immediately returned `WouldBlock`.
+    pub idle_wakeups: u64,
+    pub last_poll_events: usize,
+    pub last_idle_wakeups: usize,
+    /// Consecutive polls where every event was idle.
+    pub no_progress_passes: u32,
+}
+
+/// How long events waited between being generated and `next()` returning them. Bucket zero
+/// counts waits under a millisecond; bucket `i` those from `2^(i-1)` up to `2^i` milliseconds;
+/// the last bucket everything longer.
+#[derive(Clone, Debug, Default)]
+pub struct EventAges {
+    pub buckets: [u64; 12],
+}
+
+impl EventAges {
+    pub(crate) fn record(&mut self, age: Duration) {
+        let millis = age.as_millis();
+        let bucket = (128 - millis.leading_zeros()) as usize;
+        self.buckets[bucket.min(self.buckets.len() - 1)] += 1;
+    }
+}
+
+impl AcceptRate {
+    pub(crate) fn accepted(&mut self, now: Instant) {
+        self.roll(now);
+        self.current += 1;
+    }
+
+    /// Accepted so far this second, and when it ends.
+    pub(crate) fn this_second(&mut self, now: Instant) -> (u64, Instant) {
+        self.roll(now);
+        let started = self.started.expect("just rolled");
+        (self.current, started + Duration::from_secs(1))
+    }
+
+    pub(crate) fn per_sec(&mut self, now: Instant) -> u64 {
+        self.roll(now);
+        self.last
+    }
+
+    fn roll(&mut self, now: Instant) {
+        let second = Duration::from_secs(1);
+        let started = *self.started.get_or_insert(now);
+        let elapsed = now.saturating_duration_since(started);
+        if elapsed < second {
+            return;
+        }
+        // a quiet second in between means nothing was accepted in the last one
+        self.last = if elapsed < 2 * second {
+            self.current
+        } else {
+            0
+        };
+        self.current = 0;
+        self.started = Some(now - Duration::from_nanos(elapsed.subsec_nanos().into()));
+    }
+}
+
+impl<T> Net<T> {
+    /// Every TCP (or in-memory) connection, in no particular order.
+    pub fn connections(&self) -> impl Iterator<Item = ConnInfo> + '_ {
+        self.tokens.iter().filter_map(|(token, owned)| {
+            let conn = match &owned.mode {
+                OwnedMode::Conn(conn) => conn,
+                _ => return None,
+            };
+            let (read, write) = (&conn.read_buffer.state, &conn.write_buffer.state);
+            let open = matches!(read, StreamState::Normal { .. })
+                && matches!(write, StreamState::Normal { .. });
+            let done = matches!(read, StreamState::Done) && matches!(write, StreamState::Done);
+            let state = if conn.connecting {
+                ConnState::Connecting
+            } else if conn.is_pooled_idle() {
+                ConnState::PooledIdle
+            } else if open {
+                ConnState::Open
+            } else if done {
+                ConnState::Closed
+            } else {
+                ConnState::Closing
+            };
+            Some(ConnInfo {
+                token: ConnToken(token),
+                peer: conn.peer,
+                state,
+                read_buffered: conn.read_buffer.len(),
+                write_buffered: conn.write_buffer.len(),
+            })
+        })
+    }
+
+    pub fn stats(&mut self) -> NetStats {
+        let now = self.clock.now();
+        NetStats {
+            connections: self.tokens.conns(),
+            accepts_per_sec: self.accept_rate.per_sec(now),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Report to `sink` after every pass of the event loop; replaces any earlier sink.
+    pub fn set_metrics_sink(&mut self, sink: impl MetricsSink + 'static) {
+        self.metrics_sink = Some(Box::new(sink));
+        self.reported = NetStats::default();
+    }
+
+    pub(crate) fn report_metrics(&mut self) {
+        let sink = match &mut self.metrics_sink {
+            Some(sink) => sink,
+            None => return,
+        };
+        let (now, was) = (&self.stats, &self.reported);
+        let counters = [
+            ("nofio.accepted", now.accepted - was.accepted),
+            ("nofio.blocked", now.blocked - was.blocked),
+            ("nofio.rejected", now.rejected - was.rejected),
+            ("nofio.deferred", now.deferred - was.deferred),
+            ("nofio.bytes_read", now.bytes_read - was.bytes_read),
+            ("nofio.bytes_written", now.bytes_written - was.bytes_written),
+            ("nofio.events", now.events - was.events),
+            ("nofio.polls", now.polls - was.polls),
+        ];
+        for (name, delta) in counters.iter() {
+            if 0 != *delta {
+                sink.counter(name, *delta);
+            }
+        }
+        sink.gauge("nofio.connections", self.tokens.conns() as u64);
+        self.reported = self.stats.clone();
+    }
+}
@@ -0,0 +1,106 @@
+use crate::buffer::Buffer;
+use crate::config;
+use crate::Conn;
+use crate::Error;
+use crate::MAX_BUFFER_LIMIT;
+
+/// Built-in handling for a connection, so the reactor can be measured without a handler in
+/// the way. In anything but `Events`, the connection's `Data` and `Done` events are swallowed;
+/// `Error` and `Closed` are still reported.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Mode {
+    /// Tell the caller about everything.
+    #[default]
+    Events,
+    /// Send back whatever arrives, and close once the peer has.
+    Echo,
+    /// Read and discard everything, and close once the peer has.
+    Sink,
+    /// Send `total` bytes, keeping `chunk` bytes queued at a time, then close our side.
+    /// Anything which arrives is discarded.
+    Source { chunk: usize, total: u64 },
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ModeMetrics {
+    pub echoed_bytes: u64,
+    pub sunk_bytes: u64,
+    pub sourced_bytes: u64,
+}
+
+pub(crate) fn check(mode: Mode) -> Result<Mode, Error> {
+    if let Mode::Source { chunk, .. } = mode {
+        config::check_limit("chunk", chunk, MAX_BUFFER_LIMIT)?;
+    }
+    Ok(mode)
+}
+
+impl Conn {
+    /// Move bytes between the buffers as the mode dictates. Returns whether there's now
+    /// something new for `shunt_io` to do.
+    pub(crate) fn run_mode(&mut self, stats: &mut ModeMetrics) -> bool {
+        match self.mode {
+            Mode::Events => false,
+            Mode::Echo => {
+                // leave the rest in the read buffer, which stops us reading more
+                let wanted = match (self.read_buffer.buf(), self.write_buffer.buf()) {
+                    (Some(src), Some(out)) => src
+                        .len()
+                        .min(config::DEFAULT_WANTED.saturating_sub(out.len())),
+                    _ => 0,
+                };
+                let moved = match (self.read_buffer.buf_mut(), self.write_buffer.buf_mut()) {
+                    (Some(src), Some(dest)) if 0 != wanted => {
+                        dest.extend_from_slice(&src.as_slice()[..wanted]);
+                        src.consume(wanted);
+                        true
+                    }
+                    _ => false,
+                };
+                stats.echoed_bytes += wanted as u64;
+                self.close_after_peer();
+                moved
+            }
+            Mode::Sink => {
+                stats.sunk_bytes += self.discard_reads();
+                self.close_after_peer();
+                false
+            }
+            Mode::Source { chunk, total } => {
+                stats.sunk_bytes += self.discard_reads();
+                let remaining = total - self.sourced;
+                let dest = match self.write_buffer.buf_mut() {
+                    Some(dest) if dest.len() < chunk => dest,
+                    _ => return false,
+                };
+                let n = remaining.min(chunk as u64) as usize;
+                dest.push(vec![b'x'; n]);
+                self.sourced += n as u64;
+                stats.sourced_bytes += n as u64;
+                if total == self.sourced {
+                    self.write_buffer.become_at_least_draining_close();
+                }
+                0 != n
+            }
+        }
+    }
+
+    fn discard_reads(&mut self) -> u64 {
+        match self.read_buffer.buf_mut() {
+            Some(buf) => {
+                let len = buf.len();
+                buf.consume(len);
+                len as u64
+            }
+            None => 0,
+        }
+    }
+
+    /// Once everything the peer sent has been dealt with, finish our side too.
+    fn close_after_peer(&mut self) {
+        if self.read_buffer.eof && !self.read_buffer.could_read() {
+            self.write_buffer.become_at_least_draining_close();
+        }
+    }
+}
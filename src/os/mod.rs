@@ -0,0 +1,33 @@
+//! Platform-specific extensions, as in `std::os`.
+
+use std::io;
+
+use mio::net::TcpStream;
+
+use crate::Error;
+use crate::Io;
+use crate::Operation;
+
+#[cfg(unix)]
+pub mod unix;
+#[cfg(windows)]
+pub mod windows;
+
+mod private {
+    /// Stops the extension traits being implemented outside the crate, so methods can be added.
+    pub trait Sealed {}
+
+    impl<T> Sealed for crate::Io<'_, T> {}
+}
+
+impl<T> Io<'_, T> {
+    /// Ask something of the connection's socket, for the extension traits.
+    fn query_socket<R>(&self, query: impl FnOnce(&TcpStream) -> io::Result<R>) -> Result<R, Error> {
+        let conn = self.as_conn();
+        conn.inner.tcp().and_then(query).map_err(|e| {
+            Error::new(Operation::SocketQuery, e)
+                .with_token(self.token)
+                .with_peer(Some(conn.peer))
+        })
+    }
+}
@@ -0,0 +1,44 @@
+//! Unix-specific extensions.
+
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::RawFd;
+
+use crate::os::private::Sealed;
+use crate::sys;
+use crate::Error;
+use crate::Io;
+use crate::TcpInfo;
+
+pub trait IoExt: Sealed {
+    /// The connection's socket. Reading, writing or closing it behind our back will confuse us.
+    /// `-1` for a `Net::memory_pair`, which has no socket.
+    fn as_raw_fd(&self) -> RawFd;
+
+    /// Bytes we've handed to the kernel which it's still holding. Our own write buffer is not included.
+    ///
+    /// Linux uses `SIOCOUTQ`, which counts data the peer hasn't acknowledged, so zero means the
+    /// peer has everything. macOS uses `SO_NWRITE`, which only counts data not yet sent, so zero
+    /// there means "on the wire", not "received". Other platforms return an error.
+    fn kernel_send_queue(&self) -> Result<usize, Error>;
+
+    /// Round trip times, congestion window and so on, straight from the kernel. Linux only;
+    /// other platforms return an error.
+    fn tcp_info(&self) -> Result<TcpInfo, Error>;
+}
+
+impl<T> IoExt for Io<'_, T> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.as_conn()
+            .inner
+            .tcp()
+            .map_or(-1, |sock| sock.as_raw_fd())
+    }
+
+    fn kernel_send_queue(&self) -> Result<usize, Error> {
+        self.query_socket(sys::kernel_send_queue)
+    }
+
+    fn tcp_info(&self) -> Result<TcpInfo, Error> {
+        self.query_socket(sys::tcp_info)
+    }
+}
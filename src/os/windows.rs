@@ -0,0 +1,27 @@
+//! Windows-specific extensions. mio doesn't give out its sockets here, so there's no
+//! `as_raw_socket`.
+
+use crate::os::private::Sealed;
+use crate::sys;
+use crate::Error;
+use crate::Io;
+use crate::TcpInfo;
+
+pub trait IoExt: Sealed {
+    /// As `os::unix::IoExt::kernel_send_queue`, which Windows doesn't support: always an error,
+    /// but portable code can still ask.
+    fn kernel_send_queue(&self) -> Result<usize, Error>;
+
+    /// As `os::unix::IoExt::tcp_info`, which Windows doesn't support: always an error.
+    fn tcp_info(&self) -> Result<TcpInfo, Error>;
+}
+
+impl<T> IoExt for Io<'_, T> {
+    fn kernel_send_queue(&self) -> Result<usize, Error> {
+        self.query_socket(sys::kernel_send_queue)
+    }
+
+    fn tcp_info(&self) -> Result<TcpInfo, Error> {
+        self.query_socket(sys::tcp_info)
+    }
+}
@@ -0,0 +1,137 @@
+//! Two connections borrowed at once, so bytes can be moved from one to the other (as a proxy
+//! would) without first copying them somewhere that doesn't borrow the `Net`.
+
+use crate::buffer::Buffer;
+use crate::queue::EventQueue;
+use crate::Conn;
+use crate::ConnToken;
+use crate::Direction;
+use crate::Error;
+use crate::Event;
+use crate::Net;
+use crate::OwnedMode;
+use crate::Token;
+
+/// From `Net::io_pair`; `split` it to work with both connections.
+pub struct IoPair<'n> {
+    a: PairedIo<'n>,
+    b: PairedIo<'n>,
+    buffered: &'n mut usize,
+    events: &'n mut EventQueue,
+    /// What the pair had buffered between them when they were borrowed.
+    before: usize,
+}
+
+/// One half of an `IoPair`: the reading and writing parts of `Io`, for one connection.
+pub struct PairedIo<'n> {
+    conn: &'n mut Conn,
+    token: Token,
+}
+
+impl<T> Net<T> {
+    /// Borrow two different connections at once. UDP sockets can't be paired.
+    pub fn io_pair(&mut self, a: ConnToken, b: ConnToken) -> Result<IoPair<'_>, Error> {
+        let (a, b) = (a.0, b.0);
+        if a == b {
+            return Err(Error::invalid("io_pair", a, "the same connection twice"));
+        }
+        self.check_token(a)?;
+        self.check_token(b)?;
+
+        let Net {
+            tokens,
+            buffered,
+            events,
+            ..
+        } = self;
+        let (first, second) = tokens.get2_mut(&a, &b).expect("both just checked");
+        let (first, second) = match (&mut first.mode, &mut second.mode) {
+            (OwnedMode::Conn(first), OwnedMode::Conn(second)) => (first, second),
+            _ => {
+                return Err(Error::invalid(
+                    "io_pair",
+                    (a, b),
+                    "not both TCP connections",
+                ))
+            }
+        };
+        let before = first.buffered() + second.buffered();
+        Ok(IoPair {
+            a: PairedIo {
+                conn: first,
+                token: a,
+            },
+            b: PairedIo {
+                conn: second,
+                token: b,
+            },
+            buffered,
+            events,
+            before,
+        })
+    }
+}
+
+impl<'n> IoPair<'n> {
+    /// Both connections, in the order they were passed to `io_pair`.
+    pub fn split(&mut self) -> (&mut PairedIo<'n>, &mut PairedIo<'n>) {
+        (&mut self.a, &mut self.b)
+    }
+}
+
+impl Drop for IoPair<'_> {
+    fn drop(&mut self) {
+        let after = self.a.conn.buffered() + self.b.conn.buffered();
+        *self.buffered = *self.buffered - self.before + after;
+        for half in [&mut self.a, &mut self.b].iter_mut() {
+            if let Some(true) = half.conn.cross_watermarks() {
+                self.events
+                    .push(Event::WriteBufferHigh(ConnToken(half.token)));
+            }
+        }
+    }
+}
+
+impl PairedIo<'_> {
+    pub fn token(&self) -> ConnToken {
+        ConnToken(self.token)
+    }
+
+    /// As `Io::buf`.
+    pub fn buf(&self) -> Result<&[u8], Error> {
+        match self.conn.read_buffer.buf() {
+            Some(buf) => Ok(buf.as_slice()),
+            None => Err(Error::Closed(self.token, Direction::Read)),
+        }
+    }
+
+    /// As `Io::consume`.
+    pub fn consume(&mut self, len: usize) -> Result<usize, Error> {
+        match self.conn.read_buffer.buf_mut() {
+            Some(buf) => {
+                let len = len.min(buf.len());
+                buf.consume(len);
+                Ok(len)
+            }
+            None => Err(Error::Closed(self.token, Direction::Read)),
+        }
+    }
+
+    /// As `Io::take_buf`.
+    pub fn take_buf(&mut self) -> Result<Vec<u8>, Error> {
+        match self.conn.read_buffer.buf_mut() {
+            Some(buf) => Ok(buf.take(usize::MAX)),
+            None => Err(Error::Closed(self.token, Direction::Read)),
+        }
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.conn
+            .enqueue(self.token, |buf| buf.extend_from_slice(data))
+    }
+
+    /// As `Io::write_vec`.
+    pub fn write_vec(&mut self, data: Vec<u8>) -> Result<(), Error> {
+        self.conn.enqueue(self.token, |buf| buf.push(data))
+    }
+}
@@ -0,0 +1,193 @@
+//! Connections over pipes, or a terminal, instead of sockets: reading one file descriptor, and
+//! writing another, like the process's own stdin and stdout, with `Net::stdio`.
+
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use log::info;
+
+use crate::memory::Transport;
+use crate::reactor::Interest;
+use crate::reactor::Source;
+use crate::ConnToken;
+use crate::Error;
+use crate::Net;
+use crate::Operation;
+use crate::Owned;
+use crate::OwnedMode;
+
+/// What a pipe connection has instead of a peer address.
+pub(crate) const NO_ADDR: SocketAddr = SocketAddr::V4(std::net::SocketAddrV4::new(
+    std::net::Ipv4Addr::UNSPECIFIED,
+    0,
+));
+
+/// One connection's file descriptors; a missing `read` is at its end already, and a missing
+/// `write` refuses everything.
+pub(crate) struct Pipes {
+    read: Option<Fd>,
+    write: Option<Fd>,
+}
+
+/// A file descriptor, made non-blocking.
+struct Fd {
+    fd: RawFd,
+    /// Closed when we're done with it. Otherwise, it's someone else's, like stdin, and only
+    /// has its flags put back.
+    owned: bool,
+    /// Its flags before it was made non-blocking, to put back.
+    flags: libc::c_int,
+    /// Nothing more goes through it; an owned one has been closed, and its number may have
+    /// been reused.
+    closed: AtomicBool,
+}
+
+impl<T> Net<T> {
+    /// The process's stdin and stdout, as a connection: what arrives on stdin is read as from
+    /// a socket, and what's written goes to stdout. Both are made non-blocking until the
+    /// connection is closed; for a terminal, that's seen by anything else sharing it, like
+    /// the shell. There's no peer address. They can only be watched if they're pipes, sockets,
+    /// or terminals: a regular file, or `/dev/null`, can't be waited on, so fails with
+    /// `PermissionDenied`.
+    pub fn stdio(&mut self) -> Result<ConnToken, Error> {
+        let pipes = Pipes::new(Some((0, false)), Some((1, false)))
+            .map_err(|e| Error::new(Operation::Adopt, e))?;
+        self.add_pipes(pipes)
+    }
+
+    /// Watch `pipes`, and take them on as a connection.
+    pub(crate) fn add_pipes(&mut self, pipes: Pipes) -> Result<ConnToken, Error> {
+        let token = self.tokens.reserve()?;
+        if let Err(e) = self
+            .reactor
+            .register(pipes.source(), token, Interest::READABLE)
+        {
+            // one may have been watched before the other failed
+            let _ = self.reactor.deregister(pipes.source(), token);
+            self.release(token);
+            return Err(Error::new(Operation::Register, e).with_token(token));
+        }
+        info!("{} pipes", token.0);
+        let mut conn = self.new_conn(Transport::Pipes(pipes), NO_ADDR);
+        conn.registered = Some(Interest::READABLE);
+        self.tokens.insert(
+            token,
+            Owned {
+                token,
+                mode: OwnedMode::Conn(conn),
+            },
+        );
+        Ok(ConnToken(token))
+    }
+}
+
+impl Pipes {
+    /// Each end is a descriptor, and whether it's ours to close.
+    pub(crate) fn new(
+        read: Option<(RawFd, bool)>,
+        write: Option<(RawFd, bool)>,
+    ) -> io::Result<Pipes> {
+        // both tried, so neither is left open, or blocking, if the other fails
+        let read = read.map(|(fd, owned)| Fd::new(fd, owned));
+        let write = write.map(|(fd, owned)| Fd::new(fd, owned));
+        Ok(Pipes {
+            read: read.transpose()?,
+            write: write.transpose()?,
+        })
+    }
+
+    pub(crate) fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.read.as_ref().filter(|fd| fd.is_open()) {
+            Some(fd) => fd.read(buf),
+            None => Ok(0),
+        }
+    }
+
+    pub(crate) fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        match self.write.as_ref().filter(|fd| fd.is_open()) {
+            Some(fd) => fd.write(buf),
+            None => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    /// What the reactor should watch: whichever ends aren't gone.
+    pub(crate) fn source(&self) -> Source<'_> {
+        let fd = |fd: &Option<Fd>| fd.as_ref().filter(|fd| !fd.is_gone()).map(|fd| fd.fd);
+        Source::Pipes {
+            read: fd(&self.read),
+            write: fd(&self.write),
+        }
+    }
+
+    /// Stop writing; whoever's reading the other end sees its end, if it's ours to close.
+    pub(crate) fn shutdown_write(&self) {
+        if let Some(fd) = &self.write {
+            fd.close();
+        }
+    }
+}
+
+impl Fd {
+    fn new(fd: RawFd, owned: bool) -> io::Result<Fd> {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+            let e = io::Error::last_os_error();
+            if owned {
+                unsafe { libc::close(fd) };
+            }
+            return Err(e);
+        }
+        Ok(Fd {
+            fd,
+            owned,
+            flags,
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    fn is_open(&self) -> bool {
+        !self.closed.load(Ordering::Relaxed)
+    }
+
+    /// Closed, so the kernel has stopped watching it, too; someone else's is still watched
+    /// until it's deregistered.
+    fn is_gone(&self) -> bool {
+        self.owned && !self.is_open()
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        let n = unsafe { libc::write(self.fd, buf.as_ptr() as *const libc::c_void, buf.len()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+
+    fn close(&self) {
+        if self.closed.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        if self.owned {
+            unsafe { libc::close(self.fd) };
+        } else {
+            unsafe { libc::fcntl(self.fd, libc::F_SETFL, self.flags) };
+        }
+    }
+}
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::time::Instant;
+
+use log::info;
+use mio::Token;
+
+use crate::buffer::Buffer;
+use crate::ConnToken;
+use crate::Error;
+use crate::Event;
+use crate::Expiry;
+use crate::Io;
+use crate::Net;
+use crate::OwnedMode;
+use crate::StreamState;
+
+#[derive(Clone, Debug)]
+pub struct PoolOptions {
+    pub max_idle_per_target: usize,
+    pub idle_ttl: Duration,
+}
+
+impl Default for PoolOptions {
+    fn default() -> PoolOptions {
+        PoolOptions {
+            max_idle_per_target: 8,
+            idle_ttl: Duration::from_secs(90),
+        }
+    }
+}
+
+/// Outbound connections made through `pooled_connect` remember where they go.
+pub(crate) struct Pooled {
+    target: SocketAddr,
+    options: PoolOptions,
+    /// Set while the connection is sitting in the pool, and so belongs to nobody.
+    idle_until: Option<Instant>,
+}
+
+impl Pooled {
+    pub(crate) fn is_idle(&self) -> bool {
+        self.idle_until.is_some()
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Pool {
+    /// Most recently released at the back.
+    idle: HashMap<SocketAddr, VecDeque<Token>>,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PoolMetrics {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub idle: usize,
+}
+
+impl Pool {
+    pub(crate) fn metrics(&self) -> PoolMetrics {
+        PoolMetrics {
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            idle: self.idle.values().map(|q| q.len()).sum(),
+        }
+    }
+
+    fn forget(&mut self, target: &SocketAddr, token: Token) {
+        if let Some(queue) = self.idle.get_mut(target) {
+            queue.retain(|t| *t != token);
+            if queue.is_empty() {
+                self.idle.remove(target);
+            }
+        }
+    }
+}
+
+impl<T> Net<T> {
+    /// Hand back an idle connection to `target` from the pool if there is one, or start a new
+    /// one. Either way, `Event::Connected` (or `ConnectFailed`) follows; immediately, for a pooled
+    /// connection.
+    pub fn pooled_connect(
+        &mut self,
+        target: &SocketAddr,
+        options: &PoolOptions,
+    ) -> Result<ConnToken, Error> {
+        while let Some(token) = self.pool.idle.get_mut(target).and_then(|q| q.pop_back()) {
+            if self.pooled_healthy(token) {
+                let conn = self.conn_mut(token).expect("healthy, so present");
+                let pooled = conn.pooled.as_mut().expect("healthy, so pooled");
+                pooled.idle_until = None;
+                pooled.options = options.clone();
+                self.pool.hits += 1;
+                self.events.push(Event::Connected(ConnToken(token)));
+                return Ok(ConnToken(token));
+            }
+            self.evict(token);
+        }
+
+        self.pool.misses += 1;
+        let token = self.tcp_connect(target)?;
+        self.conn_mut(token.0).expect("just connected").pooled = Some(Pooled {
+            target: *target,
+            options: options.clone(),
+            idle_until: None,
+        });
+        Ok(token)
+    }
+
+    fn pooled_healthy(&self, token: Token) -> bool {
+        let conn = match self.tokens.get(&token).map(|o| &o.mode) {
+            Some(OwnedMode::Conn(conn)) => conn,
+            _ => return false,
+        };
+
+        !conn.connecting
+            && match (&conn.read_buffer.state, &conn.write_buffer.state) {
+                (StreamState::Normal { buf: read, .. }, StreamState::Normal { .. }) => {
+                    read.is_empty()
+                }
+                _ => false,
+            }
+    }
+
+    /// Idle connections produce no events; anything happening on one means it's no longer usable.
+    pub(crate) fn police_pool(&mut self) {
+        let idle: Vec<Token> = self.pool.idle.values().flatten().cloned().collect();
+        for token in idle {
+            if !self.pooled_healthy(token) {
+                info!("{} pooled connection went bad", token.0);
+                self.evict(token);
+            }
+        }
+    }
+
+    pub(crate) fn expire_pooled(&mut self, token: Token, at: Instant) {
+        let expired = self
+            .conn_mut(token)
+            .and_then(|conn| conn.pooled.as_ref())
+            .map(|pooled| pooled.idle_until == Some(at))
+            .unwrap_or(false);
+        if expired {
+            info!("{} pooled connection expired", token.0);
+            self.evict(token);
+        }
+    }
+
+    /// Close an idle connection; it's already been released, so nobody hears about it.
+    pub(crate) fn evict(&mut self, token: Token) {
+        let target = match self.conn_mut(token).and_then(|conn| conn.pooled.as_ref()) {
+            Some(pooled) => pooled.target,
+            None => return,
+        };
+        self.pool.forget(&target, token);
+        self.pool.evictions += 1;
+        Io { inner: self, token }.close();
+    }
+}
+
+impl<'n, T> Io<'n, T> {
+    /// Give the connection back for a later `pooled_connect` to the same target, instead of
+    /// closing it. Connections which weren't pooled, or have anything buffered, are just closed.
+    /// Either way, the token is no longer yours.
+    pub fn release_to_pool(&mut self) {
+        let token = self.token;
+        let poolable = self.inner.pooled_healthy(token) && {
+            let conn = self.as_conn();
+            0 == conn.write_buffer.len() && conn.pooled.is_some()
+        };
+        if !poolable {
+            self.close();
+            return;
+        }
+
+        let now = self.inner.clock.now();
+        let conn = self.as_conn_mut();
+        let pooled = conn.pooled.as_mut().expect("checked");
+        let until = now + pooled.options.idle_ttl;
+        pooled.idle_until = Some(until);
+        let target = pooled.target;
+        let max = pooled.options.max_idle_per_target;
+
+        let net = &mut *self.inner;
+        net.data.remove(&token);
+        net.deadlines.push(until, token, Expiry::PoolIdle);
+        let queue = net.pool.idle.entry(target).or_default();
+        queue.push_back(token);
+        if queue.len() > max {
+            let oldest = queue.pop_front().expect("over-full");
+            net.evict(oldest);
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! The types almost every user needs: `use nofio::prelude::*;`.
+//!
+//! A version of the prelude only ever gains items which can't clash with a caller's own names;
+//! anything else waits for `v2`.
+
+pub use self::v1::*;
+
+pub mod v1 {
+    pub use crate::ChildToken;
+    pub use crate::ConnToken;
+    pub use crate::Ctx;
+    pub use crate::Direction;
+    pub use crate::Error;
+    pub use crate::Event;
+    pub use crate::Io;
+    pub use crate::ListenOptions;
+    pub use crate::ListenerToken;
+    pub use crate::Net;
+    pub use crate::NetBuilder;
+    pub use crate::PeerClosedPolicy;
+    pub use crate::SourceToken;
+    pub use crate::TimerToken;
+    pub use crate::Token;
+    pub use crate::UdpIo;
+    pub use crate::UdpToken;
+
+    #[cfg(unix)]
+    pub use crate::os::unix::IoExt as _;
+    #[cfg(windows)]
+    pub use crate::os::windows::IoExt as _;
+}
@@ -0,0 +1,109 @@
+//! The PROXY protocol, versions 1 and 2: a header a load balancer sends before anything else,
+//! saying who the client really is; see `ListenOptions::proxy_protocol`.
+
+use std::convert::TryFrom;
+use std::io;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::str;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The longest a version 1 header can be, line ending included.
+const V1_MAX: usize = 107;
+
+/// The longest a header can be: version 2, with as many extensions as will fit.
+pub(crate) const MAX_HEADER: usize = 16 + u16::MAX as usize;
+
+/// The header at the start of `buf`, once it's all arrived: the client's address, unless the
+/// proxy didn't give one (e.g. for its own health checks), and how long the header was.
+pub(crate) fn parse(buf: &[u8]) -> io::Result<Option<(Option<SocketAddr>, usize)>> {
+    if V2_SIGNATURE.starts_with(&buf[..buf.len().min(V2_SIGNATURE.len())]) {
+        return v2(buf);
+    }
+    if V1_PREFIX.starts_with(&buf[..buf.len().min(V1_PREFIX.len())]) {
+        return v1(buf);
+    }
+    Err(malformed("no PROXY header"))
+}
+
+fn v1(buf: &[u8]) -> io::Result<Option<(Option<SocketAddr>, usize)>> {
+    let end = match buf[..buf.len().min(V1_MAX)]
+        .windows(2)
+        .position(|w| w == b"\r\n")
+    {
+        Some(end) => end,
+        None if buf.len() >= V1_MAX => return Err(malformed("version 1 line too long")),
+        None => return Ok(None),
+    };
+    let line = str::from_utf8(&buf[V1_PREFIX.len()..end])
+        .map_err(|_| malformed("version 1 line isn't ascii"))?;
+    let parts: Vec<&str> = line.split(' ').collect();
+    let source = match parts.as_slice() {
+        ["UNKNOWN", ..] => None,
+        [family @ ("TCP4" | "TCP6"), src, dst, src_port, dst_port] => {
+            // the destination is us, so it only has to make sense
+            let src = if "TCP4" == *family {
+                v1_field::<Ipv4Addr>(dst)?;
+                IpAddr::V4(v1_field(src)?)
+            } else {
+                v1_field::<Ipv6Addr>(dst)?;
+                IpAddr::V6(v1_field(src)?)
+            };
+            v1_field::<u16>(dst_port)?;
+            Some(SocketAddr::new(src, v1_field(src_port)?))
+        }
+        _ => return Err(malformed("version 1 line has the wrong fields")),
+    };
+    Ok(Some((source, end + 2)))
+}
+
+fn v1_field<F: str::FromStr>(field: &str) -> io::Result<F> {
+    field
+        .parse()
+        .map_err(|_| malformed("version 1 address or port"))
+}
+
+fn v2(buf: &[u8]) -> io::Result<Option<(Option<SocketAddr>, usize)>> {
+    let header = match buf.get(..16) {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    if 0x20 != header[12] & 0xf0 {
+        return Err(malformed("unsupported version"));
+    }
+    let len = 16 + u16::from_be_bytes([header[14], header[15]]) as usize;
+    let addresses = match buf.get(16..len) {
+        Some(addresses) => addresses,
+        None => return Ok(None),
+    };
+    let source = match (header[12] & 0x0f, header[13] >> 4) {
+        // LOCAL: the proxy talking for itself
+        (0x0, _) => None,
+        (0x1, 0x1) if addresses.len() >= 12 => {
+            let ip = <[u8; 4]>::try_from(&addresses[..4]).expect("sliced to length");
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Some(SocketAddr::new(Ipv4Addr::from(ip).into(), port))
+        }
+        (0x1, 0x2) if addresses.len() >= 36 => {
+            let ip = <[u8; 16]>::try_from(&addresses[..16]).expect("sliced to length");
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port))
+        }
+        // unspecified, or a unix socket: nothing we can call a peer
+        (0x1, 0x0) | (0x1, 0x3) => None,
+        (0x1, _) => return Err(malformed("bad version 2 address")),
+        _ => return Err(malformed("unsupported version 2 command")),
+    };
+    Ok(Some((source, len)))
+}
+
+fn malformed(why: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("malformed PROXY header: {}", why),
+    )
+}
@@ -0,0 +1,79 @@
+use std::collections::vec_deque;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use mio::Token;
+
+use crate::Event;
+
+/// Events, with when they were created. Rather than reading the clock for every event, each
+/// `fill` pass reads it once, after `poll` returns, and everything created that pass shares it.
+#[derive(Default)]
+pub(crate) struct EventQueue {
+    /// With when they were created, and how many were pushed before them.
+    events: VecDeque<(Option<Instant>, u64, Event)>,
+    now: Option<Instant>,
+    pushed: u64,
+}
+
+impl EventQueue {
+    pub(crate) fn push(&mut self, ev: Event) {
+        self.events.push_back((self.now, self.pushed, ev));
+        self.pushed += 1;
+    }
+
+    /// Identifies everything queued so far, for `any_before`.
+    pub(crate) fn mark(&self) -> u64 {
+        self.pushed
+    }
+
+    /// Whether anything for `token` which was queued before `mark` is still waiting.
+    pub(crate) fn any_before(&self, mark: u64, token: Token) -> bool {
+        self.events
+            .iter()
+            .take_while(|(_, pushed, _)| *pushed < mark)
+            .any(|(_, _, ev)| Some(token) == ev.token())
+    }
+
+    /// Drop everything waiting for `token`, which has gone without being closed.
+    pub(crate) fn forget(&mut self, token: Token) {
+        self.events.retain(|(_, _, ev)| Some(token) != ev.token());
+    }
+
+    /// Everything pushed since the last pass ended, and until `end_pass`, gets this time.
+    pub(crate) fn stamp(&mut self, now: Instant) {
+        self.now = Some(now);
+        for (created, _, _) in self.events.iter_mut().rev() {
+            if created.is_some() {
+                break;
+            }
+            *created = Some(now);
+        }
+    }
+
+    pub(crate) fn end_pass(&mut self) {
+        self.now = None;
+    }
+
+    /// Anything pushed outside a pass was created, as far as anyone can tell, `now`.
+    pub(crate) fn pop(&mut self, now: Instant) -> Option<(Instant, Event)> {
+        let (created, _, ev) = self.events.pop_front()?;
+        Some((created.unwrap_or(now), ev))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+impl<'q> IntoIterator for &'q EventQueue {
+    type Item = &'q Event;
+    type IntoIter = std::iter::Map<
+        vec_deque::Iter<'q, (Option<Instant>, u64, Event)>,
+        fn(&'q (Option<Instant>, u64, Event)) -> &'q Event,
+    >;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.events.iter().map(|(_, _, ev)| ev)
+    }
+}
@@ -0,0 +1,109 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::time::Duration;
+
+use mio::Token;
+
+use super::Interest;
+use super::Reactor;
+use super::Source;
+
+/// epoll, directly, edge triggered. `Source::Evented`s can't be watched, so fail to register.
+pub struct Epoll {
+    epoll: File,
+    events: Vec<libc::epoll_event>,
+}
+
+impl Epoll {
+    pub fn new() -> io::Result<Epoll> {
+        let fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Epoll {
+            epoll: unsafe { File::from_raw_fd(fd) },
+            events: Vec::new(),
+        })
+    }
+
+    fn ctl(
+        &mut self,
+        op: libc::c_int,
+        source: Source,
+        token: Token,
+        interest: Interest,
+    ) -> io::Result<()> {
+        for (fd, interest) in source.fds(interest)?.iter().flatten() {
+            let mut flags = libc::EPOLLET;
+            if interest.readable {
+                flags |= libc::EPOLLIN | libc::EPOLLRDHUP;
+            }
+            if interest.writable {
+                flags |= libc::EPOLLOUT;
+            }
+            let mut event = libc::epoll_event {
+                events: flags as u32,
+                u64: token.0 as u64,
+            };
+            if unsafe { libc::epoll_ctl(self.epoll.as_raw_fd(), op, *fd, &mut event) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Reactor for Epoll {
+    fn register(&mut self, source: Source, token: Token, interest: Interest) -> io::Result<()> {
+        self.ctl(libc::EPOLL_CTL_ADD, source, token, interest)
+    }
+
+    fn reregister(&mut self, source: Source, token: Token, interest: Interest) -> io::Result<()> {
+        self.ctl(libc::EPOLL_CTL_MOD, source, token, interest)
+    }
+
+    fn deregister(&mut self, source: Source, token: Token) -> io::Result<()> {
+        self.ctl(libc::EPOLL_CTL_DEL, source, token, Interest::NONE)
+    }
+
+    fn poll(
+        &mut self,
+        ready: &mut Vec<Token>,
+        max: usize,
+        timeout: Option<Duration>,
+    ) -> io::Result<()> {
+        // rounded up, so a short timeout doesn't become a busy loop
+        let timeout = match timeout {
+            Some(timeout) => {
+                let millis = (timeout + Duration::from_nanos(999_999)).as_millis();
+                libc::c_int::try_from(millis).unwrap_or(libc::c_int::MAX)
+            }
+            None => -1,
+        };
+        let max = max.clamp(1, libc::c_int::MAX as usize);
+        self.events.clear();
+        self.events.reserve(max);
+        let n = unsafe {
+            libc::epoll_wait(
+                self.epoll.as_raw_fd(),
+                self.events.as_mut_ptr(),
+                max as libc::c_int,
+                timeout,
+            )
+        };
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            // a signal arrived; as if the timeout had
+            if io::ErrorKind::Interrupted == e.kind() {
+                return Ok(());
+            }
+            return Err(e);
+        }
+        unsafe { self.events.set_len(n as usize) };
+        ready.extend(self.events.iter().map(|event| Token(event.u64 as usize)));
+        Ok(())
+    }
+}
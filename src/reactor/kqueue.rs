@@ -0,0 +1,135 @@
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::time::Duration;
+
+use mio::Token;
+
+use super::Interest;
+use super::Reactor;
+use super::Source;
+
+/// kqueue, directly, with `EV_CLEAR` for edge triggering. `Source::Evented`s can't be
+/// watched, so fail to register.
+pub struct Kqueue {
+    kqueue: File,
+    events: Vec<libc::kevent>,
+}
+
+impl Kqueue {
+    pub fn new() -> io::Result<Kqueue> {
+        let fd = unsafe { libc::kqueue() };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let kqueue = unsafe { File::from_raw_fd(fd) };
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Kqueue {
+            kqueue,
+            events: Vec::new(),
+        })
+    }
+
+    /// Add the filters `interest` wants, and remove the others.
+    fn apply(&mut self, fd: RawFd, token: Token, interest: Interest) -> io::Result<()> {
+        let mut changes = [
+            change(fd, libc::EVFILT_READ, interest.readable, token),
+            change(fd, libc::EVFILT_WRITE, interest.writable, token),
+        ];
+        // with EV_RECEIPT, each change's outcome comes back in its own `data`
+        let n = unsafe {
+            libc::kevent(
+                self.kqueue.as_raw_fd(),
+                changes.as_ptr(),
+                changes.len() as libc::c_int,
+                changes.as_mut_ptr(),
+                changes.len() as libc::c_int,
+                ptr::null(),
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        for receipt in &changes[..n as usize] {
+            let errno = receipt.data as i32;
+            // deleting a filter which was never added is fine
+            if 0 != errno && libc::ENOENT != errno {
+                return Err(io::Error::from_raw_os_error(errno));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn change(fd: RawFd, filter: i16, wanted: bool, token: Token) -> libc::kevent {
+    let mut event: libc::kevent = unsafe { mem::zeroed() };
+    event.ident = fd as _;
+    event.filter = filter as _;
+    event.flags = if wanted {
+        libc::EV_ADD | libc::EV_CLEAR | libc::EV_RECEIPT
+    } else {
+        libc::EV_DELETE | libc::EV_RECEIPT
+    };
+    event.udata = token.0 as _;
+    event
+}
+
+impl Reactor for Kqueue {
+    fn register(&mut self, source: Source, token: Token, interest: Interest) -> io::Result<()> {
+        for (fd, interest) in source.fds(interest)?.iter().flatten() {
+            self.apply(*fd, token, *interest)?;
+        }
+        Ok(())
+    }
+
+    fn reregister(&mut self, source: Source, token: Token, interest: Interest) -> io::Result<()> {
+        self.register(source, token, interest)
+    }
+
+    fn deregister(&mut self, source: Source, token: Token) -> io::Result<()> {
+        self.register(source, token, Interest::NONE)
+    }
+
+    fn poll(
+        &mut self,
+        ready: &mut Vec<Token>,
+        max: usize,
+        timeout: Option<Duration>,
+    ) -> io::Result<()> {
+        let timeout = timeout.map(|timeout| libc::timespec {
+            tv_sec: timeout.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+            tv_nsec: timeout.subsec_nanos() as _,
+        });
+        let max = max.clamp(1, libc::c_int::MAX as usize);
+        self.events.clear();
+        self.events.reserve(max);
+        let n = unsafe {
+            libc::kevent(
+                self.kqueue.as_raw_fd(),
+                ptr::null(),
+                0,
+                self.events.as_mut_ptr(),
+                max as libc::c_int,
+                timeout.as_ref().map_or(ptr::null(), |t| t as *const _),
+            )
+        };
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            // a signal arrived; as if the timeout had
+            if io::ErrorKind::Interrupted == e.kind() {
+                return Ok(());
+            }
+            return Err(e);
+        }
+        unsafe { self.events.set_len(n as usize) };
+        // a socket ready both ways is reported twice, which is harmless
+        ready.extend(self.events.iter().map(|event| Token(event.udata as usize)));
+        Ok(())
+    }
+}
@@ -0,0 +1,411 @@
+//! What a `Net` waits on: something which watches its sockets, and says which have become
+//! ready. `MioReactor` is the default; see `NetBuilder::build_with_reactor` for the others.
+
+use std::collections::HashMap;
+use std::io;
+use std::ops::BitAnd;
+use std::ops::BitOr;
+use std::ops::BitOrAssign;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use mio::net::TcpListener;
+use mio::net::TcpStream;
+use mio::net::UdpSocket;
+use mio::Evented;
+use mio::Events;
+use mio::PollOpt;
+use mio::Ready;
+use mio::Token;
+
+#[cfg(target_os = "linux")]
+mod epoll;
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+mod kqueue;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+
+#[cfg(target_os = "linux")]
+pub use self::epoll::Epoll;
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+pub use self::kqueue::Kqueue;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub use self::uring::Uring;
+
+/// Watches sockets for a `Net`. Readiness is edge triggered: a source is reported when it
+/// becomes ready for something it's registered for, and then not again until it has been
+/// read or written to `WouldBlock`, or reregistered. Reporting a source which isn't ready
+/// is harmless, just wasteful.
+pub trait Reactor: Send {
+    fn register(&mut self, source: Source, token: Token, interest: Interest) -> io::Result<()>;
+
+    /// Replace the interest the source was registered with.
+    fn reregister(&mut self, source: Source, token: Token, interest: Interest) -> io::Result<()>;
+
+    /// Stop watching the source. Closing it has the same effect, without this being called,
+    /// once `closing` has been.
+    fn deregister(&mut self, source: Source, token: Token) -> io::Result<()>;
+
+    /// The source is about to be closed, without being deregistered. Only a reactor whose
+    /// watching holds it open, like `Uring`, need do anything.
+    fn closing(&mut self, _source: Source, _token: Token) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Wait until something is ready, or `timeout` has passed (or forever, without one), then
+    /// add the tokens of up to `max` ready sources to `ready`.
+    fn poll(
+        &mut self,
+        ready: &mut Vec<Token>,
+        max: usize,
+        timeout: Option<Duration>,
+    ) -> io::Result<()>;
+
+    /// Whether `Source::Stream`s are read and written with `read` and `write`, which the
+    /// reactor does itself, reporting a stream when something it started has finished, as
+    /// much as when it's ready. Only `Uring` does; everything else is still read and written
+    /// directly.
+    fn completes(&self) -> bool {
+        false
+    }
+
+    /// What's been read from `stream`, registered as `token`, copied into `buf`: `0` at its
+    /// end, or the error reading hit. With nothing yet, fails with `WouldBlock`, having
+    /// started reading, up to `buf`'s length, if it wasn't already.
+    fn read(&mut self, _stream: &TcpStream, _token: Token, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// How much of what `fill` last added to its `Vec` has been written to `stream`, or the
+    /// error writing hit. With nothing yet, fails with `WouldBlock`, having called `fill` to
+    /// start writing, if it wasn't already. What `fill` added must stay at the front of what
+    /// it would add until it's been answered for.
+    fn write(
+        &mut self,
+        _stream: &TcpStream,
+        _token: Token,
+        _fill: &mut dyn FnMut(&mut Vec<u8>) -> io::Result<()>,
+    ) -> io::Result<usize> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Something a `Net` wants watched.
+#[derive(Copy, Clone)]
+pub enum Source<'s> {
+    Listener(&'s TcpListener),
+    Stream(&'s TcpStream),
+    Udp(&'s UdpSocket),
+    /// Anything else with a file descriptor, like a pipe, a signalfd, or a pidfd.
+    #[cfg(unix)]
+    Fd(RawFd),
+    /// A connection over two file descriptors, as one source: `read` is only watched for
+    /// reading, and `write` only for writing. Either may be missing, e.g. once it's closed.
+    #[cfg(unix)]
+    Pipes {
+        read: Option<RawFd>,
+        write: Option<RawFd>,
+    },
+    /// Only mio can watch this, as it's not a file descriptor, but e.g. mio's own
+    /// `Registration`; the other reactors fail with `Unsupported`.
+    Evented(&'s dyn Evented),
+}
+
+/// What a source should be reported for.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Interest {
+    pub readable: bool,
+    pub writable: bool,
+}
+
+impl Interest {
+    pub const NONE: Interest = Interest {
+        readable: false,
+        writable: false,
+    };
+    pub const READABLE: Interest = Interest {
+        readable: true,
+        writable: false,
+    };
+    pub const WRITABLE: Interest = Interest {
+        readable: false,
+        writable: true,
+    };
+}
+
+impl BitOr for Interest {
+    type Output = Interest;
+
+    fn bitor(self, other: Interest) -> Interest {
+        Interest {
+            readable: self.readable || other.readable,
+            writable: self.writable || other.writable,
+        }
+    }
+}
+
+impl BitAnd for Interest {
+    type Output = Interest;
+
+    fn bitand(self, other: Interest) -> Interest {
+        Interest {
+            readable: self.readable && other.readable,
+            writable: self.writable && other.writable,
+        }
+    }
+}
+
+impl BitOrAssign for Interest {
+    fn bitor_assign(&mut self, other: Interest) {
+        *self = *self | other;
+    }
+}
+
+impl Source<'_> {
+    /// Each file descriptor to watch, and what for; a source has at most two.
+    #[cfg(unix)]
+    fn fds(&self, interest: Interest) -> io::Result<[Option<(RawFd, Interest)>; 2]> {
+        use std::os::unix::io::AsRawFd;
+
+        Ok(match *self {
+            Source::Listener(listener) => [Some((listener.as_raw_fd(), interest)), None],
+            Source::Stream(stream) => [Some((stream.as_raw_fd(), interest)), None],
+            Source::Udp(udp) => [Some((udp.as_raw_fd(), interest)), None],
+            Source::Fd(fd) => [Some((fd, interest)), None],
+            Source::Pipes { read, write } => [
+                read.map(|fd| (fd, interest & Interest::READABLE)),
+                write.map(|fd| (fd, interest & Interest::WRITABLE)),
+            ],
+            Source::Evented(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "only a MioReactor can watch a Source::Evented",
+                ))
+            }
+        })
+    }
+}
+
+/// mio's `Poll`, which is epoll, kqueue, or IOCP, depending on the platform.
+pub struct MioReactor {
+    poll: mio::Poll,
+    events: Events,
+}
+
+impl MioReactor {
+    pub fn new() -> io::Result<MioReactor> {
+        Ok(MioReactor {
+            poll: mio::Poll::new()?,
+            events: Events::with_capacity(crate::DEFAULT_EVENTS_CAPACITY),
+        })
+    }
+}
+
+impl MioReactor {
+    /// Call `f` with each part of `source` mio can watch, and what it's to be watched for.
+    fn each(
+        source: Source,
+        interest: Interest,
+        mut f: impl FnMut(&dyn Evented, Interest) -> io::Result<()>,
+    ) -> io::Result<()> {
+        match source {
+            Source::Listener(listener) => f(listener, interest),
+            Source::Stream(stream) => f(stream, interest),
+            Source::Udp(udp) => f(udp, interest),
+            Source::Evented(evented) => f(evented, interest),
+            #[cfg(unix)]
+            Source::Fd(_) | Source::Pipes { .. } => {
+                for (fd, interest) in source.fds(interest)?.iter().flatten() {
+                    f(&mio::unix::EventedFd(fd), *interest)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Reactor for MioReactor {
+    fn register(&mut self, source: Source, token: Token, interest: Interest) -> io::Result<()> {
+        let poll = &self.poll;
+        MioReactor::each(source, interest, |evented, interest| {
+            poll.register(evented, token, interest.into(), PollOpt::edge())
+        })
+    }
+
+    fn reregister(&mut self, source: Source, token: Token, interest: Interest) -> io::Result<()> {
+        let poll = &self.poll;
+        MioReactor::each(source, interest, |evented, interest| {
+            poll.reregister(evented, token, interest.into(), PollOpt::edge())
+        })
+    }
+
+    fn deregister(&mut self, source: Source, _token: Token) -> io::Result<()> {
+        let poll = &self.poll;
+        MioReactor::each(source, Interest::NONE, |evented, _| {
+            poll.deregister(evented)
+        })
+    }
+
+    fn poll(
+        &mut self,
+        ready: &mut Vec<Token>,
+        max: usize,
+        timeout: Option<Duration>,
+    ) -> io::Result<()> {
+        if self.events.capacity() != max {
+            self.events = Events::with_capacity(max);
+        }
+        self.poll.poll(&mut self.events, timeout)?;
+        ready.extend(self.events.iter().map(|ev| ev.token()));
+        Ok(())
+    }
+}
+
+impl From<Interest> for Ready {
+    fn from(interest: Interest) -> Ready {
+        let mut ready = Ready::empty();
+        if interest.readable {
+            ready |= Ready::readable();
+        }
+        if interest.writable {
+            ready |= Ready::writable();
+        }
+        ready
+    }
+}
+
+/// Wraps another reactor, to watch what a `Net` asks of it, and to report sources which
+/// aren't ready, which a `Net` must cope with. See `TestReactor::handle`.
+pub struct TestReactor {
+    inner: Box<dyn Reactor>,
+    state: Arc<Mutex<TestState>>,
+}
+
+/// Looks into a `TestReactor` after it's been handed to a `Net`.
+#[derive(Clone)]
+pub struct TestHandle {
+    state: Arc<Mutex<TestState>>,
+}
+
+#[derive(Default)]
+struct TestState {
+    interest: HashMap<Token, Interest>,
+    registrations: u64,
+    polls: u64,
+    wake: Vec<Token>,
+}
+
+impl TestReactor {
+    pub fn new(inner: impl Reactor + 'static) -> TestReactor {
+        TestReactor {
+            inner: Box::new(inner),
+            state: Arc::default(),
+        }
+    }
+
+    pub fn handle(&self) -> TestHandle {
+        TestHandle {
+            state: self.state.clone(),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, TestState> {
+        self.state.lock().expect("a TestHandle panicked")
+    }
+}
+
+impl Reactor for TestReactor {
+    fn register(&mut self, source: Source, token: Token, interest: Interest) -> io::Result<()> {
+        self.inner.register(source, token, interest)?;
+        let mut state = self.state();
+        state.interest.insert(token, interest);
+        state.registrations += 1;
+        Ok(())
+    }
+
+    fn reregister(&mut self, source: Source, token: Token, interest: Interest) -> io::Result<()> {
+        self.inner.reregister(source, token, interest)?;
+        let mut state = self.state();
+        state.interest.insert(token, interest);
+        state.registrations += 1;
+        Ok(())
+    }
+
+    fn deregister(&mut self, source: Source, token: Token) -> io::Result<()> {
+        self.inner.deregister(source, token)?;
+        self.state().interest.remove(&token);
+        Ok(())
+    }
+
+    fn closing(&mut self, source: Source, token: Token) -> io::Result<()> {
+        self.inner.closing(source, token)
+    }
+
+    fn poll(
+        &mut self,
+        ready: &mut Vec<Token>,
+        max: usize,
+        timeout: Option<Duration>,
+    ) -> io::Result<()> {
+        let wake = {
+            let mut state = self.state();
+            state.polls += 1;
+            std::mem::take(&mut state.wake)
+        };
+        // don't wait for the real thing if there's something to report anyway
+        let timeout = if wake.is_empty() {
+            timeout
+        } else {
+            Some(Duration::from_secs(0))
+        };
+        self.inner.poll(ready, max, timeout)?;
+        ready.extend(wake);
+        Ok(())
+    }
+
+    fn completes(&self) -> bool {
+        self.inner.completes()
+    }
+
+    fn read(&mut self, stream: &TcpStream, token: Token, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(stream, token, buf)
+    }
+
+    fn write(
+        &mut self,
+        stream: &TcpStream,
+        token: Token,
+        fill: &mut dyn FnMut(&mut Vec<u8>) -> io::Result<()>,
+    ) -> io::Result<usize> {
+        self.inner.write(stream, token, fill)
+    }
+}
+
+impl TestHandle {
+    /// Report `token` as ready on the next poll, whether it is or not.
+    pub fn wake(&self, token: Token) {
+        self.state().wake.push(token);
+    }
+
+    /// What `token` was last (re)registered for, unless it's been deregistered. Sockets which
+    /// were simply closed are still here.
+    pub fn interest(&self, token: Token) -> Option<Interest> {
+        self.state().interest.get(&token).copied()
+    }
+
+    /// Calls to `register` and `reregister`.
+    pub fn registrations(&self) -> u64 {
+        self.state().registrations
+    }
+
+    pub fn polls(&self) -> u64 {
+        self.state().polls
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, TestState> {
+        self.state.lock().expect("the TestReactor panicked")
+    }
+}
@@ -0,0 +1,812 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use mio::net::TcpStream;
+use mio::Token;
+
+use crate::buffer::BufferPool;
+
+use super::Interest;
+use super::Reactor;
+use super::Source;
+
+/// Submission queue entries; the completion queue is twice as long, and the kernel holds on
+/// to anything more.
+const ENTRIES: u32 = 1024;
+
+const IORING_SETUP_CLAMP: u32 = 1 << 4;
+const IORING_FEAT_SINGLE_MMAP: u32 = 1 << 0;
+const IORING_FEAT_NODROP: u32 = 1 << 1;
+const IORING_FEAT_EXT_ARG: u32 = 1 << 8;
+/// Not needed itself, but arrived with multishot polls, in 5.13.
+const IORING_FEAT_RSRC_TAGS: u32 = 1 << 10;
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_SQES: libc::off_t = 0x1000_0000;
+const IORING_OP_POLL_ADD: u8 = 6;
+const IORING_OP_POLL_REMOVE: u8 = 7;
+const IORING_OP_ASYNC_CANCEL: u8 = 14;
+const IORING_OP_SEND: u8 = 26;
+const IORING_OP_RECV: u8 = 27;
+const IORING_POLL_ADD_MULTI: u32 = 1 << 0;
+const IORING_CQE_F_MORE: u32 = 1 << 1;
+const IORING_ENTER_GETEVENTS: u32 = 1 << 0;
+const IORING_ENTER_EXT_ARG: u32 = 1 << 3;
+
+/// The `user_data` of a removal, whose completion says nothing anyone needs.
+const REMOVAL: u64 = u64::MAX;
+
+/// How many seconds a dropped `Uring` waits for what it cancelled to finish.
+const DROP_WAITS: usize = 5;
+
+/// Spare capacity kept from finished recvs and sends, for the next.
+const SPARE_BYTES: usize = 1024 * 1024;
+
+/// io_uring, directly. `Source::Stream`s are read and written on the ring, with
+/// `IORING_OP_RECV` and `IORING_OP_SEND`, into and out of buffers it owns, and reported when
+/// those complete; see `Reactor::completes`. Everything else is watched with a multishot poll,
+/// which completes whenever it's woken, so is as edge triggered as epoll. Needs Linux 5.13;
+/// `new` fails with `Unsupported` on anything older. `Source::Evented`s can't be watched, so
+/// fail to register.
+///
+/// Whatever's in flight holds on to its file, so a socket isn't really closed until it's been
+/// cancelled, by `deregister`, or `closing`; anything read by then is lost.
+pub struct Uring {
+    sq: Ring,
+    cq: Ring,
+    sqes: Map,
+    /// Both queues; only held, as `sq` and `cq` point into it.
+    _rings: Map,
+    ring: File,
+    /// Submissions written, but not yet handed to the kernel.
+    unsubmitted: u32,
+    /// What each token is watching, and with which poll, if one is armed.
+    watches: HashMap<Token, Vec<Watch>>,
+    /// The token of each armed poll, by `user_data`.
+    polls: HashMap<u64, Token>,
+    /// The next `user_data`, for polls and ops alike.
+    next_poll: u64,
+    /// Tokens whose polls have stopped, to be armed again before waiting.
+    rearm: Vec<Token>,
+    streams: HashMap<Token, Stream>,
+    /// Everything in flight for a stream, by `user_data`, until it completes, even once the
+    /// stream's gone: the kernel may be using its buffer until then.
+    ops: HashMap<u64, Op>,
+    /// Streams with something to collect since they were reregistered.
+    woken: Vec<Token>,
+    spare: BufferPool,
+}
+
+/// A stream, read and written on the ring.
+struct Stream {
+    fd: RawFd,
+    interest: Interest,
+    /// The recv in flight.
+    recv: Option<u64>,
+    /// What the last recv to complete got, and hasn't yet been read: nothing, at the end.
+    received: Option<io::Result<Vec<u8>>>,
+    /// The send in flight.
+    send: Option<u64>,
+    /// How much the last send to complete wrote, and hasn't yet been told.
+    sent: Option<io::Result<usize>>,
+    /// A one-shot poll, and what for, while nothing in flight will say when to carry on: a
+    /// new stream, one which has been idle, or one which is still connecting.
+    poll: Option<(u64, u32)>,
+}
+
+/// Something in flight for a stream, holding what the kernel is using.
+enum Op {
+    Poll(Token),
+    Recv(Token, Vec<u8>),
+    Send(Token, Vec<u8>),
+}
+
+struct Watch {
+    fd: RawFd,
+    interest: Interest,
+    poll: Option<u64>,
+}
+
+/// One of the queues, in a mapping shared with the kernel.
+struct Ring {
+    head: *const AtomicU32,
+    tail: *const AtomicU32,
+    mask: u32,
+    entries: u32,
+    /// The submission queue's index array, or the completion queue's entries.
+    array: *mut u8,
+}
+
+struct Map {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// the mappings are only touched through `&mut self`, and the kernel's side through atomics
+unsafe impl Send for Uring {}
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+#[repr(C)]
+struct GeteventsArg {
+    sigmask: u64,
+    sigmask_sz: u32,
+    pad: u32,
+    ts: u64,
+}
+
+/// The kernel's `__kernel_timespec`, which is 64-bit everywhere, unlike `libc::timespec`.
+#[repr(C)]
+struct KernelTimespec {
+    tv_sec: i64,
+    tv_nsec: i64,
+}
+
+impl Uring {
+    pub fn new() -> io::Result<Uring> {
+        let mut params = Params {
+            flags: IORING_SETUP_CLAMP,
+            ..Params::default()
+        };
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                ENTRIES,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let ring = unsafe { File::from_raw_fd(fd as RawFd) };
+        let wanted = IORING_FEAT_SINGLE_MMAP
+            | IORING_FEAT_NODROP
+            | IORING_FEAT_EXT_ARG
+            | IORING_FEAT_RSRC_TAGS;
+        if wanted != params.features & wanted {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "io_uring is too old: multishot polls need Linux 5.13",
+            ));
+        }
+
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len =
+            params.cq_off.cqes as usize + params.cq_entries as usize * mem::size_of::<Cqe>();
+        // with `SINGLE_MMAP`, both queues are in the one mapping
+        let rings = Map::new(&ring, sq_len.max(cq_len), IORING_OFF_SQ_RING)?;
+        let sqes = Map::new(
+            &ring,
+            params.sq_entries as usize * mem::size_of::<Sqe>(),
+            IORING_OFF_SQES,
+        )?;
+        let sq = Ring::new(
+            &rings,
+            [
+                params.sq_off.head,
+                params.sq_off.tail,
+                params.sq_off.ring_mask,
+                params.sq_off.ring_entries,
+                params.sq_off.array,
+            ],
+        );
+        let cq = Ring::new(
+            &rings,
+            [
+                params.cq_off.head,
+                params.cq_off.tail,
+                params.cq_off.ring_mask,
+                params.cq_off.ring_entries,
+                params.cq_off.cqes,
+            ],
+        );
+        Ok(Uring {
+            sq,
+            cq,
+            sqes,
+            _rings: rings,
+            ring,
+            unsubmitted: 0,
+            watches: HashMap::new(),
+            polls: HashMap::new(),
+            next_poll: 0,
+            rearm: Vec::new(),
+            streams: HashMap::new(),
+            ops: HashMap::new(),
+            woken: Vec::new(),
+            spare: BufferPool::new(SPARE_BYTES),
+        })
+    }
+
+    /// Queue a submission, handing everything queued so far to the kernel if there's no room.
+    fn push(&mut self, sqe: Sqe) -> io::Result<()> {
+        let tail = self.sq.tail().load(Ordering::Relaxed);
+        if self.sq_is_full(tail) {
+            self.enter(0, 0, None)?;
+            // the kernel won't take any more until the completions it's holding are taken
+            if self.sq_is_full(tail) {
+                return Err(io::Error::from_raw_os_error(libc::EBUSY));
+            }
+        }
+        let index = tail & self.sq.mask;
+        unsafe {
+            ptr::write((self.sqes.ptr as *mut Sqe).add(index as usize), sqe);
+            ptr::write((self.sq.array as *mut u32).add(index as usize), index);
+        }
+        self.sq
+            .tail()
+            .store(tail.wrapping_add(1), Ordering::Release);
+        self.unsubmitted += 1;
+        Ok(())
+    }
+
+    /// Submit everything queued, then wait for `wait` completions, or the timeout.
+    fn enter(&mut self, wait: u32, flags: u32, timeout: Option<&KernelTimespec>) -> io::Result<()> {
+        let arg = GeteventsArg {
+            sigmask: 0,
+            sigmask_sz: 0,
+            pad: 0,
+            ts: timeout.map_or(0, |ts| ts as *const _ as u64),
+        };
+        let n = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_enter,
+                self.ring.as_raw_fd(),
+                self.unsubmitted,
+                wait,
+                flags | IORING_ENTER_EXT_ARG,
+                &arg as *const GeteventsArg,
+                mem::size_of::<GeteventsArg>(),
+            )
+        };
+        if n >= 0 {
+            self.unsubmitted -= n as u32;
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            // out of time; or a signal arrived, as if it were
+            Some(libc::ETIME) | Some(libc::EINTR) => Ok(()),
+            // completions must be taken before more can be submitted, which `poll` does
+            Some(libc::EBUSY) if 0 == wait => Ok(()),
+            _ => Err(e),
+        }
+    }
+
+    fn sq_is_full(&self, tail: u32) -> bool {
+        tail.wrapping_sub(self.sq.head().load(Ordering::Acquire)) == self.sq.entries
+    }
+
+    fn arm(&mut self, token: Token, index: usize) -> io::Result<()> {
+        let poll = self.next_poll;
+        let watch = &mut self.watches.get_mut(&token).expect("armed while watched")[index];
+        if Interest::NONE == watch.interest || watch.poll.is_some() {
+            return Ok(());
+        }
+        let sqe = Sqe {
+            opcode: IORING_OP_POLL_ADD,
+            fd: watch.fd,
+            len: IORING_POLL_ADD_MULTI,
+            op_flags: poll_events(watch.interest),
+            user_data: poll,
+            ..Sqe::default()
+        };
+        watch.poll = Some(poll);
+        self.next_poll += 1;
+        self.polls.insert(poll, token);
+        self.push(sqe)
+    }
+
+    fn disarm(&mut self, poll: u64) -> io::Result<()> {
+        self.polls.remove(&poll);
+        self.push(Sqe {
+            opcode: IORING_OP_POLL_REMOVE,
+            fd: -1,
+            addr: poll,
+            user_data: REMOVAL,
+            ..Sqe::default()
+        })
+    }
+
+    /// Stop whatever `user_data` is doing, which may still complete anyway.
+    fn cancel(&mut self, op: u64) -> io::Result<()> {
+        self.push(Sqe {
+            opcode: IORING_OP_ASYNC_CANCEL,
+            fd: -1,
+            addr: op,
+            user_data: REMOVAL,
+            ..Sqe::default()
+        })
+    }
+
+    /// Stop watching everything for `token`, and cancel anything in flight; it's submitted
+    /// now, so nothing is held open for long.
+    fn forget(&mut self, token: Token) -> io::Result<()> {
+        let watches = self.watches.remove(&token).unwrap_or_default();
+        for poll in watches.iter().filter_map(|watch| watch.poll) {
+            self.disarm(poll)?;
+        }
+        if let Some(stream) = self.streams.remove(&token) {
+            let poll = stream.poll.map(|(poll, _)| poll);
+            for op in [stream.recv, stream.send, poll].iter().flatten() {
+                self.cancel(*op)?;
+            }
+        }
+        self.enter(0, 0, None)
+    }
+
+    fn register_stream(&mut self, fd: RawFd, token: Token, interest: Interest) -> io::Result<()> {
+        let stream = Stream {
+            fd,
+            interest,
+            recv: None,
+            received: None,
+            send: None,
+            sent: None,
+            poll: None,
+        };
+        self.streams.insert(token, stream);
+        self.update(token)
+    }
+
+    /// Poll the stream for what it's interested in, unless something in flight, or waiting to
+    /// be collected, will say when to carry on anyway.
+    fn update(&mut self, token: Token) -> io::Result<()> {
+        let stream = self
+            .streams
+            .get_mut(&token)
+            .expect("updated while registered");
+        let interest = Interest {
+            readable: stream.interest.readable
+                && stream.recv.is_none()
+                && stream.received.is_none(),
+            writable: stream.interest.writable && stream.send.is_none() && stream.sent.is_none(),
+        };
+        let events = poll_events(interest);
+        let fd = stream.fd;
+        match stream.poll {
+            Some((_, armed)) if armed == events => return Ok(()),
+            Some((poll, _)) => {
+                stream.poll = None;
+                self.cancel(poll)?;
+            }
+            None => (),
+        }
+        if Interest::NONE == interest {
+            return Ok(());
+        }
+        let poll = self.next_id();
+        self.push(Sqe {
+            opcode: IORING_OP_POLL_ADD,
+            fd,
+            op_flags: events,
+            user_data: poll,
+            ..Sqe::default()
+        })?;
+        self.ops.insert(poll, Op::Poll(token));
+        self.stream(token).poll = Some((poll, events));
+        Ok(())
+    }
+
+    fn next_id(&mut self) -> u64 {
+        self.next_poll += 1;
+        self.next_poll - 1
+    }
+
+    fn stream(&mut self, token: Token) -> &mut Stream {
+        self.streams.get_mut(&token).expect("still registered")
+    }
+
+    /// Note what `op` got; returns the stream to report, unless it's been forgotten, or it was
+    /// a poll which has since been replaced.
+    fn finish(&mut self, user_data: u64, op: Op, res: i32) -> Option<Token> {
+        let result = usize::try_from(res).map_err(|_| io::Error::from_raw_os_error(-res));
+        match op {
+            Op::Poll(token) => {
+                let stream = self.streams.get_mut(&token)?;
+                if Some(user_data) != stream.poll.map(|(poll, _)| poll) {
+                    return None;
+                }
+                stream.poll = None;
+                Some(token)
+            }
+            Op::Recv(token, mut buf) => {
+                let stream = self.streams.get_mut(&token)?;
+                if Some(user_data) != stream.recv {
+                    return None;
+                }
+                stream.recv = None;
+                stream.received = Some(result.map(|n| {
+                    buf.truncate(n);
+                    buf
+                }));
+                Some(token)
+            }
+            Op::Send(token, buf) => {
+                self.spare.put(buf);
+                let stream = self.streams.get_mut(&token)?;
+                if Some(user_data) != stream.send {
+                    return None;
+                }
+                stream.send = None;
+                stream.sent = Some(result);
+                Some(token)
+            }
+        }
+    }
+
+    /// Add the token of each completion, up to `max`.
+    fn complete(&mut self, ready: &mut Vec<Token>, max: usize) {
+        let mut head = self.cq.head().load(Ordering::Relaxed);
+        let tail = self.cq.tail().load(Ordering::Acquire);
+        let mut taken = 0;
+        while head != tail && taken < max {
+            let cqe = unsafe {
+                ptr::read((self.cq.array as *const Cqe).add((head & self.cq.mask) as usize))
+            };
+            head = head.wrapping_add(1);
+            if let Some(op) = self.ops.remove(&cqe.user_data) {
+                if let Some(token) = self.finish(cqe.user_data, op, cqe.res) {
+                    taken += 1;
+                    ready.push(token);
+                }
+                continue;
+            }
+            let token = match self.polls.get(&cqe.user_data) {
+                Some(token) => *token,
+                // removed, or a removal
+                None => continue,
+            };
+            taken += 1;
+            ready.push(token);
+            if 0 != cqe.flags & IORING_CQE_F_MORE {
+                continue;
+            }
+            // the poll is over: it failed, which the `Net` finds out about when it looks, or
+            // the kernel stopped it, e.g. to make room, and it's armed again
+            self.polls.remove(&cqe.user_data);
+            if let Some(watch) = self
+                .watches
+                .get_mut(&token)
+                .and_then(|watches| watches.iter_mut().find(|w| Some(cqe.user_data) == w.poll))
+            {
+                watch.poll = None;
+                if cqe.res >= 0 {
+                    self.rearm.push(token);
+                }
+            }
+        }
+        self.cq.head().store(head, Ordering::Release);
+    }
+
+    fn cq_is_empty(&self) -> bool {
+        self.cq.head().load(Ordering::Relaxed) == self.cq.tail().load(Ordering::Acquire)
+    }
+}
+
+impl Reactor for Uring {
+    fn register(&mut self, source: Source, token: Token, interest: Interest) -> io::Result<()> {
+        if self.watches.contains_key(&token) || self.streams.contains_key(&token) {
+            return Err(io::Error::from_raw_os_error(libc::EEXIST));
+        }
+        if let Source::Stream(stream) = source {
+            return self.register_stream(stream.as_raw_fd(), token, interest);
+        }
+        let watches = source
+            .fds(interest)?
+            .iter()
+            .flatten()
+            .map(|&(fd, interest)| Watch {
+                fd,
+                interest,
+                poll: None,
+            })
+            .collect::<Vec<_>>();
+        let count = watches.len();
+        self.watches.insert(token, watches);
+        for index in 0..count {
+            self.arm(token, index)?;
+        }
+        Ok(())
+    }
+
+    fn reregister(&mut self, source: Source, token: Token, interest: Interest) -> io::Result<()> {
+        if let Some(stream) = self.streams.get_mut(&token) {
+            stream.interest = interest;
+            // as epoll would report what's ready when it's changed
+            if (interest.readable && stream.received.is_some())
+                || (interest.writable && stream.sent.is_some())
+            {
+                self.woken.push(token);
+            }
+            return self.update(token);
+        }
+        let old = match self.watches.remove(&token) {
+            Some(old) => old,
+            None => return Err(io::Error::from_raw_os_error(libc::ENOENT)),
+        };
+        // including any for descriptors the source no longer has, like a closed pipe
+        for poll in old.iter().filter_map(|watch| watch.poll) {
+            self.disarm(poll)?;
+        }
+        self.register(source, token, interest)
+    }
+
+    fn deregister(&mut self, _source: Source, token: Token) -> io::Result<()> {
+        self.forget(token)
+    }
+
+    fn closing(&mut self, _source: Source, token: Token) -> io::Result<()> {
+        self.forget(token)
+    }
+
+    fn poll(
+        &mut self,
+        ready: &mut Vec<Token>,
+        max: usize,
+        timeout: Option<Duration>,
+    ) -> io::Result<()> {
+        for token in mem::take(&mut self.rearm) {
+            let count = self.watches.get(&token).map_or(0, Vec::len);
+            for index in 0..count {
+                self.arm(token, index)?;
+            }
+        }
+        let timeout = timeout.map(|timeout| KernelTimespec {
+            tv_sec: i64::try_from(timeout.as_secs()).unwrap_or(i64::MAX),
+            tv_nsec: i64::from(timeout.subsec_nanos()),
+        });
+        // anything left from last time is ready already
+        let wait = u32::from(self.cq_is_empty() && self.woken.is_empty());
+        self.enter(wait, IORING_ENTER_GETEVENTS, timeout.as_ref())?;
+        ready.append(&mut self.woken);
+        self.complete(ready, max.max(1));
+        Ok(())
+    }
+
+    fn completes(&self) -> bool {
+        true
+    }
+
+    fn read(&mut self, _stream: &TcpStream, token: Token, buf: &mut [u8]) -> io::Result<usize> {
+        let stream = self.streams.get_mut(&token).ok_or_else(unregistered)?;
+        match stream.received.take() {
+            Some(Ok(mut received)) => {
+                let n = received.len().min(buf.len());
+                buf[..n].copy_from_slice(&received[..n]);
+                if n < received.len() {
+                    received.drain(..n);
+                    stream.received = Some(Ok(received));
+                } else {
+                    self.spare.put(received);
+                }
+                return Ok(n);
+            }
+            Some(Err(e)) => return Err(e),
+            None => (),
+        }
+        if stream.recv.is_none() {
+            let fd = stream.fd;
+            let mut into = self.spare.get();
+            into.resize(buf.len().min(u32::MAX as usize), 0);
+            let recv = self.next_id();
+            self.push(Sqe {
+                opcode: IORING_OP_RECV,
+                fd,
+                addr: into.as_mut_ptr() as u64,
+                len: into.len() as u32,
+                user_data: recv,
+                ..Sqe::default()
+            })?;
+            // the allocation doesn't move with the `Vec`
+            self.ops.insert(recv, Op::Recv(token, into));
+            self.stream(token).recv = Some(recv);
+        }
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+
+    fn write(
+        &mut self,
+        _stream: &TcpStream,
+        token: Token,
+        fill: &mut dyn FnMut(&mut Vec<u8>) -> io::Result<()>,
+    ) -> io::Result<usize> {
+        let stream = self.streams.get_mut(&token).ok_or_else(unregistered)?;
+        if let Some(sent) = stream.sent.take() {
+            return sent;
+        }
+        if stream.send.is_none() {
+            let fd = stream.fd;
+            let mut from = self.spare.get();
+            fill(&mut from)?;
+            let send = self.next_id();
+            self.push(Sqe {
+                opcode: IORING_OP_SEND,
+                fd,
+                addr: from.as_ptr() as u64,
+                len: from.len().min(u32::MAX as usize) as u32,
+                op_flags: libc::MSG_NOSIGNAL as u32,
+                user_data: send,
+                ..Sqe::default()
+            })?;
+            self.ops.insert(send, Op::Send(token, from));
+            self.stream(token).send = Some(send);
+        }
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+}
+
+impl Drop for Uring {
+    fn drop(&mut self) {
+        // the kernel may still be using the buffers of anything in flight, so they can't be
+        // freed until it says it's done
+        let ops = self.ops.keys().copied().collect::<Vec<_>>();
+        for op in ops {
+            if self.cancel(op).is_err() {
+                break;
+            }
+        }
+        let second = KernelTimespec {
+            tv_sec: 1,
+            tv_nsec: 0,
+        };
+        for _ in 0..DROP_WAITS {
+            if self.ops.is_empty()
+                || self
+                    .enter(1, IORING_ENTER_GETEVENTS, Some(&second))
+                    .is_err()
+            {
+                break;
+            }
+            self.complete(&mut Vec::new(), usize::MAX);
+        }
+        // leaked, rather than freed while the kernel could still write to them
+        for (_, op) in self.ops.drain() {
+            mem::forget(op);
+        }
+    }
+}
+
+/// What a poll waits for, in the form the kernel expects it.
+fn poll_events(interest: Interest) -> u32 {
+    let mut events = 0;
+    if interest.readable {
+        events |= libc::POLLIN | libc::POLLRDHUP;
+    }
+    if interest.writable {
+        events |= libc::POLLOUT;
+    }
+    let events = u32::from(events as u16);
+    // the kernel swaps the halves back on big-endian machines
+    #[cfg(target_endian = "big")]
+    let events = events.rotate_left(16);
+    events
+}
+
+fn unregistered() -> io::Error {
+    io::Error::from_raw_os_error(libc::ENOENT)
+}
+
+impl Ring {
+    /// `offsets` are of the head, tail, mask, entries, and array.
+    fn new(map: &Map, offsets: [u32; 5]) -> Ring {
+        let at = |offset: u32| unsafe { (map.ptr as *mut u8).add(offset as usize) };
+        let [head, tail, mask, entries, array] = offsets;
+        Ring {
+            head: at(head) as *const AtomicU32,
+            tail: at(tail) as *const AtomicU32,
+            mask: unsafe { *(at(mask) as *const u32) },
+            entries: unsafe { *(at(entries) as *const u32) },
+            array: at(array),
+        }
+    }
+
+    fn head(&self) -> &AtomicU32 {
+        unsafe { &*self.head }
+    }
+
+    fn tail(&self) -> &AtomicU32 {
+        unsafe { &*self.tail }
+    }
+}
+
+impl Map {
+    fn new(ring: &File, len: usize, offset: libc::off_t) -> io::Result<Map> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                ring.as_raw_fd(),
+                offset,
+            )
+        };
+        if libc::MAP_FAILED == ptr {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Map { ptr, len })
+    }
+}
+
+impl Drop for Map {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
@@ -0,0 +1,566 @@
+//! Writing down what a `Net` did, to do it again offline: `Net::record` writes every event,
+//! and every byte read, and `NetBuilder::build_replay` plays them back through a simulated
+//! `Net`, for reproducing protocol bugs from production.
+//!
+//! A recording is text, one record per line: microseconds since recording started, what
+//! happened, then its details, with bytes in hex, e.g. `1520 read 2 474554202f`, or
+//! `1520 data 2`.
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::mem;
+use std::process::ExitStatus;
+use std::time::Duration;
+use std::time::Instant;
+
+use mio::Token;
+
+use crate::error::ResultExt;
+use crate::ChildToken;
+use crate::ConnToken;
+use crate::Direction;
+use crate::Error;
+use crate::Event;
+use crate::Handler;
+use crate::ListenerToken;
+use crate::Net;
+use crate::NetBuilder;
+use crate::Operation;
+use crate::OwnedMode;
+use crate::Signal;
+use crate::SimClock;
+use crate::SourceToken;
+use crate::TimerToken;
+use crate::UdpToken;
+
+/// Where a recording `Net` writes to.
+pub(crate) struct Recorder {
+    out: BufWriter<Box<dyn Write + Send>>,
+    started: Instant,
+}
+
+/// A recording, being played back to an application, which drives the simulated `Net` from
+/// `net()` as it would the real one. Connections get new tokens, and are `memory_pair`s,
+/// whose other ends send what was read in the recording, when it was read; the clock is
+/// advanced to match, so the application's own timers fire where they did. Events are
+/// those recorded, except `Timer`s, which come from the timers set during the replay.
+pub struct Replay<T = ()> {
+    net: Net<T>,
+    clock: SimClock,
+    started: Instant,
+    records: VecDeque<(Duration, Record)>,
+    /// Recorded connections, by their recorded token.
+    conns: HashMap<usize, Ends>,
+    /// The application's timers, which went off while the `Net` was being fed.
+    timers: VecDeque<Event>,
+    /// What the application wrote, by its token.
+    sent: HashMap<ConnToken, Vec<u8>>,
+}
+
+#[derive(Copy, Clone)]
+struct Ends {
+    /// The application's.
+    app: ConnToken,
+    /// Ours, standing in for the recorded peer.
+    peer: ConnToken,
+}
+
+enum Record {
+    Read(usize, Vec<u8>),
+    /// With the recorded tokens.
+    Event(Event),
+}
+
+impl<T> Net<T> {
+    /// From now on, write every event returned, and everything read by each connection, to
+    /// `out`, for `NetBuilder::build_replay`; replaces any earlier recording. It's buffered,
+    /// so is only complete after `stop_recording`, or the `Net` being dropped. Should writing
+    /// fail, recording stops, and the error is returned from the next `next()`.
+    pub fn record(&mut self, out: impl Write + Send + 'static) {
+        self.recorder = Some(Recorder {
+            out: BufWriter::new(Box::new(out)),
+            started: self.clock.now(),
+        });
+        for (_, owned) in self.tokens.iter_mut() {
+            if let OwnedMode::Conn(conn) = &mut owned.mode {
+                conn.recorded = Some(Vec::new());
+            }
+        }
+    }
+
+    /// Finish the recording, if there is one.
+    pub fn stop_recording(&mut self) -> Result<(), Error> {
+        for (_, owned) in self.tokens.iter_mut() {
+            if let OwnedMode::Conn(conn) = &mut owned.mode {
+                conn.recorded = None;
+            }
+        }
+        match self.recorder.take() {
+            Some(mut recorder) => recorder.out.flush().during(Operation::Record),
+            None => Ok(()),
+        }
+    }
+
+    /// Write out what each connection read this pass, before the events it caused.
+    pub(crate) fn record_reads(&mut self) -> Result<(), Error> {
+        let recorder = match &mut self.recorder {
+            Some(recorder) => recorder,
+            None => return Ok(()),
+        };
+        let now = self.clock.now();
+        let mut result = Ok(());
+        for (token, owned) in self.tokens.iter_mut() {
+            let conn = match &mut owned.mode {
+                OwnedMode::Conn(conn) => conn,
+                _ => continue,
+            };
+            let read = match &mut conn.recorded {
+                Some(read) if !read.is_empty() => mem::take(read),
+                _ => continue,
+            };
+            result = recorder.line(now, &format!("read {} {}", token.0, to_hex(&read)));
+            if result.is_err() {
+                break;
+            }
+        }
+        if result.is_err() {
+            self.stop_recording()?;
+        }
+        result.during(Operation::Record)
+    }
+
+    /// Write down an event which is being returned.
+    pub(crate) fn record_event(&mut self, ev: &Event) {
+        let recorder = match &mut self.recorder {
+            Some(recorder) => recorder,
+            None => return,
+        };
+        if let Err(e) = recorder.line(self.clock.now(), &describe(ev)) {
+            let _ = self.stop_recording();
+            self.deferred_error
+                .get_or_insert(Error::new(Operation::Record, e));
+        }
+    }
+}
+
+impl Recorder {
+    fn line(&mut self, now: Instant, line: &str) -> io::Result<()> {
+        let micros = now.saturating_duration_since(self.started).as_micros();
+        writeln!(self.out, "{} {}", micros, line)
+    }
+}
+
+impl NetBuilder {
+    /// A `Replay` of `recording`, from `Net::record`, with a simulated `Net` built as
+    /// `build_simulated`.
+    pub fn build_replay<T>(self, recording: impl Read) -> Result<Replay<T>, Error> {
+        let (net, clock) = self.build_simulated()?;
+        let mut records = VecDeque::new();
+        for (number, line) in BufReader::new(recording).lines().enumerate() {
+            let line = line.during(Operation::Replay)?;
+            let record = parse(&line).ok_or_else(|| {
+                let e = io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unrecognised record on line {}: {:?}", number + 1, line),
+                );
+                Error::new(Operation::Replay, e)
+            })?;
+            records.push_back(record);
+        }
+        Ok(Replay {
+            net,
+            started: clock.now(),
+            clock,
+            records,
+            conns: HashMap::new(),
+            timers: VecDeque::new(),
+            sent: HashMap::new(),
+        })
+    }
+}
+
+impl<T> Replay<T> {
+    pub fn net(&mut self) -> &mut Net<T> {
+        &mut self.net
+    }
+
+    /// The next event, or `None` once the recording has run out.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<Event>, Error> {
+        loop {
+            if let Some(ev) = self.timers.pop_front() {
+                return Ok(Some(ev));
+            }
+            let (at, record) = match self.records.pop_front() {
+                Some(record) => record,
+                None => {
+                    // for what the application did about the last event
+                    self.settle()?;
+                    return Ok(self.timers.pop_front());
+                }
+            };
+            let due = self.started + at;
+            let now = self.clock.now();
+            if due > now {
+                self.clock.advance(due - now);
+            }
+            match record {
+                Record::Read(token, bytes) => {
+                    let peer = self.ends(token)?.peer;
+                    self.net.io(peer)?.write(&bytes)?;
+                }
+                Record::Event(ev) => {
+                    self.settle()?;
+                    if let Some(ev) = self.translate(ev)? {
+                        return Ok(Some(ev));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Feed every event to `handler`, as `Net::run`, until the recording runs out.
+    pub fn run(&mut self, handler: &mut impl Handler<T>) -> Result<(), Error> {
+        while let Some(ev) = self.next()? {
+            self.net.dispatch(handler, ev);
+        }
+        Ok(())
+    }
+
+    /// Everything the application has written to `token` so far, until the recorded peer
+    /// closed.
+    pub fn sent(&self, token: ConnToken) -> &[u8] {
+        self.sent.get(&token).map_or(&[], Vec::as_slice)
+    }
+
+    /// The ends standing in for a recorded connection, which are made the first time it's
+    /// mentioned.
+    fn ends(&mut self, recorded: usize) -> Result<Ends, Error> {
+        if let Some(ends) = self.conns.get(&recorded) {
+            return Ok(*ends);
+        }
+        let (app, peer) = self.net.memory_pair()?;
+        let ends = Ends { app, peer };
+        self.conns.insert(recorded, ends);
+        Ok(ends)
+    }
+
+    /// Let the `Net` move everything it can, keeping only the application's timers, and
+    /// what it wrote. The application's unhandled `Data` and the like come up every pass, so
+    /// this stops once a pass has nothing else.
+    fn settle(&mut self) -> Result<(), Error> {
+        let peers: HashMap<ConnToken, ConnToken> = self
+            .conns
+            .values()
+            .map(|ends| (ends.peer, ends.app))
+            .collect();
+        let mut events = Vec::new();
+        loop {
+            let mut progress = 0 != self.net.pump();
+            events.clear();
+            self.net
+                .poll_events(&mut events, Some(Duration::from_secs(0)))?;
+            for ev in events.drain(..) {
+                match ev {
+                    Event::Timer(timer) => self.timers.push_back(Event::Timer(timer)),
+                    Event::Data(token) if peers.contains_key(&token) => {
+                        let sent = self.net.io(token)?.take_buf()?;
+                        self.sent.entry(peers[&token]).or_default().extend(sent);
+                    }
+                    Event::Data(_)
+                    | Event::Frame(_)
+                    | Event::WsMessage(_)
+                    | Event::HttpRequest(_)
+                    | Event::HttpResponse(_) => continue,
+                    _ => (),
+                }
+                progress = true;
+            }
+            if !progress {
+                return Ok(());
+            }
+        }
+    }
+
+    /// The recorded event, as it concerns the replay, if it does.
+    fn translate(&mut self, ev: Event) -> Result<Option<Event>, Error> {
+        let ev = match ev {
+            // replaced by the replay's own
+            Event::Timer(_) => return Ok(None),
+            // the replay has no UDP sockets
+            Event::Datagram(_) | Event::UdpError(..) | Event::UdpClosed(_) => return Ok(None),
+            // there's nothing in the replay to be ready, or to exit
+            Event::Ready(_) | Event::ChildExited(..) => return Ok(None),
+            Event::NewConnection {
+                token,
+                listener,
+                peer,
+            } => {
+                let ends = self.ends(token.0 .0)?;
+                if let Some(conn) = self.net.conn_mut(ends.app.0) {
+                    conn.peer = peer;
+                }
+                Event::NewConnection {
+                    token: ends.app,
+                    listener,
+                    peer,
+                }
+            }
+            Event::Done(token, Direction::Read) => {
+                let ends = self.ends(token.0 .0)?;
+                self.net.io(ends.peer)?.close();
+                Event::Done(ends.app, Direction::Read)
+            }
+            Event::Closed(token) => {
+                let ends = self.ends(token.0 .0)?;
+                self.conns.remove(&token.0 .0);
+                for end in &[ends.app, ends.peer] {
+                    if let Ok(mut io) = self.net.io(*end) {
+                        io.close();
+                    }
+                }
+                Event::Closed(ends.app)
+            }
+            Event::Connected(token) => Event::Connected(self.ends(token.0 .0)?.app),
+            Event::ConnectFailed(token, e) => Event::ConnectFailed(self.ends(token.0 .0)?.app, e),
+            Event::TimedOut(token) => Event::TimedOut(self.ends(token.0 .0)?.app),
+            Event::WriteBufferHigh(token) => Event::WriteBufferHigh(self.ends(token.0 .0)?.app),
+            Event::WriteBufferLow(token) => Event::WriteBufferLow(self.ends(token.0 .0)?.app),
+            Event::Flushed(token) => Event::Flushed(self.ends(token.0 .0)?.app),
+            Event::ReadBufferFull(token) => Event::ReadBufferFull(self.ends(token.0 .0)?.app),
+            Event::Data(token) => Event::Data(self.ends(token.0 .0)?.app),
+            Event::Frame(token) => Event::Frame(self.ends(token.0 .0)?.app),
+            Event::WsMessage(token) => Event::WsMessage(self.ends(token.0 .0)?.app),
+            Event::HttpRequest(token) => Event::HttpRequest(self.ends(token.0 .0)?.app),
+            Event::HttpResponse(token) => Event::HttpResponse(self.ends(token.0 .0)?.app),
+            Event::Done(token, direction) => Event::Done(self.ends(token.0 .0)?.app, direction),
+            Event::Error(token, e) => Event::Error(self.ends(token.0 .0)?.app, e),
+            ev => ev,
+        };
+        Ok(Some(ev))
+    }
+}
+
+fn describe(ev: &Event) -> String {
+    let token = ev.token().map_or(0, |token| token.0);
+    match ev {
+        Event::Listening { addr, .. } => format!("listening {} {}", token, addr),
+        Event::NewConnection { listener, peer, .. } => {
+            format!("new {} {} {}", token, listener.0 .0, peer)
+        }
+        Event::Connected(_) => format!("connected {}", token),
+        Event::ConnectFailed(_, e) => format!("connect-failed {} {:?} {}", token, e.kind(), e),
+        Event::Datagram(_) => format!("datagram {}", token),
+        Event::UdpError(_, e) => format!("udp-error {} {:?} {}", token, e.kind(), e),
+        Event::UdpClosed(_) => format!("udp-closed {}", token),
+        Event::Timer(_) => format!("timer {}", token),
+        Event::Ready(_) => format!("ready {}", token),
+        Event::ChildExited(_, status) => format!("child-exited {} {}", token, exit_raw(status)),
+        Event::TimedOut(_) => format!("timed-out {}", token),
+        Event::WriteBufferHigh(_) => format!("write-high {}", token),
+        Event::WriteBufferLow(_) => format!("write-low {}", token),
+        Event::Flushed(_) => format!("flushed {}", token),
+        Event::ReadBufferFull(_) => format!("read-buffer-full {}", token),
+        Event::Data(_) => format!("data {}", token),
+        Event::Frame(_) => format!("frame {}", token),
+        Event::WsMessage(_) => format!("ws-message {}", token),
+        Event::HttpRequest(_) => format!("http-request {}", token),
+        Event::HttpResponse(_) => format!("http-response {}", token),
+        Event::Done(_, Direction::Read) => format!("done {} read", token),
+        Event::Done(_, Direction::Write) => format!("done {} write", token),
+        Event::MemoryPressure { buffered } => format!("memory-pressure {}", buffered),
+        Event::AtCapacity { connections } => format!("at-capacity {}", connections),
+        Event::BelowCapacity { connections } => format!("below-capacity {}", connections),
+        Event::Closed(_) => format!("closed {}", token),
+        Event::Spinning { passes } => format!("spinning {}", passes),
+        Event::Error(_, e) => format!("error {} {:?} {}", token, e.kind(), e),
+        Event::Shutdown => "shutdown".to_string(),
+        Event::Signal(signal) => format!("signal {:?}", signal),
+    }
+}
+
+fn parse(line: &str) -> Option<(Duration, Record)> {
+    let mut words = line.splitn(3, ' ');
+    let at = Duration::from_micros(words.next()?.parse().ok()?);
+    let what = words.next()?;
+    let rest = words.next().unwrap_or("");
+    let mut args = rest.split(' ');
+    let args = &mut args;
+    let token = |args: &mut dyn Iterator<Item = &str>| -> Option<Token> {
+        args.next()?.parse().ok().map(Token)
+    };
+    let conn = |args: &mut dyn Iterator<Item = &str>| token(args).map(ConnToken);
+    let ev = match what {
+        "read" => {
+            let mut parts = rest.splitn(2, ' ');
+            let token = parts.next()?.parse().ok()?;
+            let bytes = from_hex(parts.next()?)?;
+            return Some((at, Record::Read(token, bytes)));
+        }
+        "listening" => {
+            let (token, addr) = split_token(rest)?;
+            Event::Listening {
+                token: ListenerToken(token),
+                addr: addr.parse().ok()?,
+            }
+        }
+        "new" => {
+            let conn = token(args)?;
+            let listener = token(args)?;
+            let peer = args.next()?.parse().ok()?;
+            Event::NewConnection {
+                token: ConnToken(conn),
+                listener: ListenerToken(listener),
+                peer,
+            }
+        }
+        "connected" => Event::Connected(conn(args)?),
+        "connect-failed" => {
+            let (token, e) = split_token(rest)?;
+            Event::ConnectFailed(ConnToken(token), parse_error(e)?)
+        }
+        "datagram" => Event::Datagram(UdpToken(token(args)?)),
+        "udp-error" => {
+            let (token, e) = split_token(rest)?;
+            Event::UdpError(UdpToken(token), parse_error(e)?)
+        }
+        "udp-closed" => Event::UdpClosed(UdpToken(token(args)?)),
+        "timer" => Event::Timer(TimerToken(token(args)?)),
+        "ready" => Event::Ready(SourceToken(token(args)?)),
+        "child-exited" => {
+            let (token, status) = split_token(rest)?;
+            Event::ChildExited(ChildToken(token), exit_from_raw(status.parse().ok()?))
+        }
+        "timed-out" => Event::TimedOut(conn(args)?),
+        "write-high" => Event::WriteBufferHigh(conn(args)?),
+        "write-low" => Event::WriteBufferLow(conn(args)?),
+        "flushed" => Event::Flushed(conn(args)?),
+        "read-buffer-full" => Event::ReadBufferFull(conn(args)?),
+        "data" => Event::Data(conn(args)?),
+        "frame" => Event::Frame(conn(args)?),
+        "ws-message" => Event::WsMessage(conn(args)?),
+        "http-request" => Event::HttpRequest(conn(args)?),
+        "http-response" => Event::HttpResponse(conn(args)?),
+        "done" => {
+            let token = conn(args)?;
+            match args.next()? {
+                "read" => Event::Done(token, Direction::Read),
+                "write" => Event::Done(token, Direction::Write),
+                _ => return None,
+            }
+        }
+        "memory-pressure" => Event::MemoryPressure {
+            buffered: rest.parse().ok()?,
+        },
+        "at-capacity" => Event::AtCapacity {
+            connections: rest.parse().ok()?,
+        },
+        "below-capacity" => Event::BelowCapacity {
+            connections: rest.parse().ok()?,
+        },
+        "closed" => Event::Closed(conn(args)?),
+        "signal" => Event::Signal(
+            Signal::ALL
+                .iter()
+                .copied()
+                .find(|signal| format!("{:?}", signal) == rest)?,
+        ),
+        "spinning" => Event::Spinning {
+            passes: rest.parse().ok()?,
+        },
+        "error" => {
+            let (token, e) = split_token(rest)?;
+            Event::Error(ConnToken(token), parse_error(e)?)
+        }
+        "shutdown" => Event::Shutdown,
+        _ => return None,
+    };
+    Some((at, Record::Event(ev)))
+}
+
+fn split_token(rest: &str) -> Option<(Token, &str)> {
+    let mut parts = rest.splitn(2, ' ');
+    let token = parts.next()?.parse().ok()?;
+    Some((Token(token), parts.next()?))
+}
+
+/// As the OS gave it, so it comes back exactly.
+#[cfg(unix)]
+fn exit_raw(status: &ExitStatus) -> i64 {
+    std::os::unix::process::ExitStatusExt::into_raw(*status).into()
+}
+
+#[cfg(unix)]
+fn exit_from_raw(raw: i64) -> ExitStatus {
+    std::os::unix::process::ExitStatusExt::from_raw(raw as i32)
+}
+
+#[cfg(windows)]
+fn exit_raw(status: &ExitStatus) -> i64 {
+    status.code().map_or(0, |code| code as u32).into()
+}
+
+#[cfg(windows)]
+fn exit_from_raw(raw: i64) -> ExitStatus {
+    std::os::windows::process::ExitStatusExt::from_raw(raw as u32)
+}
+
+/// A kind, as `Debug` prints it, then the message.
+fn parse_error(recorded: &str) -> Option<io::Error> {
+    use std::io::ErrorKind::*;
+
+    let mut parts = recorded.splitn(2, ' ');
+    let kind = parts.next()?;
+    let message = parts.next().unwrap_or("").to_string();
+    let kind = [
+        NotFound,
+        PermissionDenied,
+        ConnectionRefused,
+        ConnectionReset,
+        ConnectionAborted,
+        NotConnected,
+        AddrInUse,
+        AddrNotAvailable,
+        BrokenPipe,
+        AlreadyExists,
+        WouldBlock,
+        InvalidInput,
+        InvalidData,
+        TimedOut,
+        WriteZero,
+        Interrupted,
+        UnexpectedEof,
+        Unsupported,
+        OutOfMemory,
+    ]
+    .iter()
+    .copied()
+    .find(|known| format!("{:?}", known) == kind)
+    .unwrap_or(Other);
+    Some(io::Error::new(kind, message))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
@@ -0,0 +1,133 @@
+//! Asking a `Net` to do something from another thread, without waiting for it; see
+//! `Net::remote`.
+
+use std::io;
+#[cfg(unix)]
+use std::io::Read;
+#[cfg(unix)]
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::sync::mpsc;
+#[cfg(unix)]
+use std::sync::Arc;
+
+#[cfg(not(unix))]
+use mio_extras::channel as mio_chanel;
+
+use crate::reactor::Source;
+use crate::Command;
+use crate::ConnToken;
+use crate::Net;
+
+/// Queues commands for a `Net`, from any thread: it's woken, and carries them out before
+/// anything else it finds ready. Commands for a `Net` which has gone are dropped.
+#[derive(Clone)]
+pub struct Remote {
+    #[cfg(unix)]
+    send: mpsc::Sender<Command>,
+    /// Written to after each command, to wake the `Net`.
+    #[cfg(unix)]
+    wake: Arc<UnixStream>,
+    #[cfg(not(unix))]
+    send: mio_chanel::Sender<Command>,
+}
+
+/// The `Net`'s end of its `Remote`s.
+pub(crate) struct CommandChannel {
+    remote: Remote,
+    #[cfg(unix)]
+    recv: mpsc::Receiver<Command>,
+    /// Readable whenever a `Remote` has sent something since it was last emptied.
+    #[cfg(unix)]
+    woken: UnixStream,
+    #[cfg(not(unix))]
+    recv: mio_chanel::Receiver<Command>,
+}
+
+impl<T> Net<T> {
+    /// Something to hand to other threads, so they can write to, or close, connections.
+    pub fn remote(&self) -> Remote {
+        self.channel.remote.clone()
+    }
+}
+
+impl Remote {
+    /// As `Ctx::write`.
+    pub fn write(&self, token: ConnToken, data: &[u8]) {
+        self.send(Command::Write(token.0, data.to_vec()));
+    }
+
+    /// As `Ctx::close`.
+    pub fn close(&self, token: ConnToken) {
+        self.send(Command::Close(token.0));
+    }
+
+    #[cfg(unix)]
+    fn send(&self, command: Command) {
+        if self.send.send(command).is_err() {
+            return;
+        }
+        // if it's full, the `Net` has a wake up waiting already
+        let _ = (&*self.wake).write(&[0]);
+    }
+
+    #[cfg(not(unix))]
+    fn send(&self, command: Command) {
+        let _ = self.send.send(command);
+    }
+}
+
+impl CommandChannel {
+    #[cfg(unix)]
+    pub(crate) fn new() -> io::Result<CommandChannel> {
+        let (wake, woken) = UnixStream::pair()?;
+        wake.set_nonblocking(true)?;
+        woken.set_nonblocking(true)?;
+        let (send, recv) = mpsc::channel();
+        Ok(CommandChannel {
+            remote: Remote {
+                send,
+                wake: Arc::new(wake),
+            },
+            recv,
+            woken,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn new() -> io::Result<CommandChannel> {
+        let (send, recv) = mio_chanel::channel();
+        Ok(CommandChannel {
+            remote: Remote { send },
+            recv,
+        })
+    }
+
+    /// What the reactor should watch, for `COMMANDS_TOKEN`.
+    #[cfg(unix)]
+    pub(crate) fn source(&self) -> Source<'_> {
+        Source::Fd(self.woken.as_raw_fd())
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn source(&self) -> Source<'_> {
+        Source::Evented(&self.recv)
+    }
+
+    /// Before `try_recv`ing, so nothing sent after this is missed: it wakes the `Net` again.
+    pub(crate) fn clear(&self) {
+        #[cfg(unix)]
+        {
+            let mut buf = [0; 64];
+            while let Ok(1..) = (&self.woken).read(&mut buf) {}
+        }
+    }
+
+    pub(crate) fn try_recv(&self) -> Option<Command> {
+        self.recv.try_recv().ok()
+    }
+}
@@ -0,0 +1,265 @@
+//! OS signals, as events, so e.g. a `SIGTERM` can start a `Net::shutdown_gracefully` from the
+//! loop, instead of from a handler; see `Net::signals`.
+
+/// The signals `Net::signals` can catch.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Signal {
+    /// `SIGHUP`, conventionally asking a server to reload its configuration.
+    Hangup,
+    /// `SIGINT`, e.g. from ctrl-c.
+    Interrupt,
+    /// `SIGQUIT`, e.g. from ctrl-\.
+    Quit,
+    /// `SIGTERM`, asking politely for an exit.
+    Terminate,
+    /// `SIGUSR1`.
+    User1,
+    /// `SIGUSR2`.
+    User2,
+    /// `SIGCHLD`; a `Net::spawn`ed child's exit is already an `Event::ChildExited`.
+    Child,
+    /// `SIGWINCH`, the terminal was resized.
+    WindowChange,
+}
+
+impl Signal {
+    pub(crate) const ALL: [Signal; 8] = [
+        Signal::Hangup,
+        Signal::Interrupt,
+        Signal::Quit,
+        Signal::Terminate,
+        Signal::User1,
+        Signal::User2,
+        Signal::Child,
+        Signal::WindowChange,
+    ];
+}
+
+#[cfg(unix)]
+pub(crate) use self::unix::Signals;
+
+#[cfg(unix)]
+mod unix {
+    use std::fs::File;
+    use std::io;
+    use std::io::Read;
+    use std::os::unix::io::AsRawFd;
+    #[cfg(not(target_os = "linux"))]
+    use std::os::unix::io::FromRawFd;
+
+    use log::info;
+    use mio::Token;
+
+    use super::Signal;
+    use crate::reactor::Interest;
+    use crate::reactor::Source;
+    use crate::Error;
+    use crate::Event;
+    use crate::Net;
+    use crate::Operation;
+
+    /// What the `Net` reads signals from: a signalfd, or the read end of a pipe which a
+    /// handler writes to.
+    pub(crate) struct Signals {
+        token: Token,
+        fd: File,
+        #[cfg(target_os = "linux")]
+        mask: libc::sigset_t,
+    }
+
+    impl Signal {
+        /// As `libc::SIGTERM` and friends.
+        pub fn number(self) -> libc::c_int {
+            match self {
+                Signal::Hangup => libc::SIGHUP,
+                Signal::Interrupt => libc::SIGINT,
+                Signal::Quit => libc::SIGQUIT,
+                Signal::Terminate => libc::SIGTERM,
+                Signal::User1 => libc::SIGUSR1,
+                Signal::User2 => libc::SIGUSR2,
+                Signal::Child => libc::SIGCHLD,
+                Signal::WindowChange => libc::SIGWINCH,
+            }
+        }
+
+        fn from_number(number: libc::c_int) -> Option<Signal> {
+            Signal::ALL.iter().copied().find(|s| s.number() == number)
+        }
+    }
+
+    impl<T> Net<T> {
+        /// Have these signals arrive as `Event::Signal`s, instead of doing whatever they would
+        /// have done, like exiting; calling it again adds to them. Only one `Net` in a process
+        /// should catch signals. On Linux, they're blocked, and read from a signalfd: call this
+        /// before starting any threads, which otherwise won't have them blocked, and may be
+        /// the ones they're delivered to. Elsewhere, they're caught by a handler, and passed on
+        /// through a pipe. Either way, they stay caught after the `Net` is dropped.
+        pub fn signals(&mut self, signals: &[Signal]) -> Result<(), Error> {
+            let during = |e| Error::new(Operation::Signals, e);
+            let fd = match self.signals.take() {
+                Some(existing) => existing,
+                None => {
+                    let token = self.tokens.reserve()?;
+                    let fd = match Signals::new(token) {
+                        Ok(fd) => fd,
+                        Err(e) => {
+                            self.release(token);
+                            return Err(during(e));
+                        }
+                    };
+                    let raw = fd.fd.as_raw_fd();
+                    if let Err(e) =
+                        self.reactor
+                            .register(Source::Fd(raw), token, Interest::READABLE)
+                    {
+                        self.release(token);
+                        return Err(Error::new(Operation::Register, e).with_token(token));
+                    }
+                    fd
+                }
+            };
+            let fd = self.signals.insert(fd);
+            fd.catch(signals).map_err(during)
+        }
+
+        /// Turn everything caught since last time into events.
+        pub(crate) fn take_signals(&mut self) {
+            let signals = match &mut self.signals {
+                Some(signals) => signals,
+                None => return,
+            };
+            loop {
+                match signals.next() {
+                    Ok(Some(signal)) => self.events.push(Event::Signal(signal)),
+                    // nothing we asked for; a stray write to the pipe, or something odd
+                    Ok(None) => (),
+                    Err(ref e) if io::ErrorKind::WouldBlock == e.kind() => return,
+                    Err(ref e) if io::ErrorKind::Interrupted == e.kind() => (),
+                    Err(e) => {
+                        info!("{} signals-err {:?}", signals.token.0, e);
+                        return;
+                    }
+                }
+            }
+        }
+
+        /// Whether `token` is where signals are read from.
+        pub(crate) fn is_signals(&self, token: Token) -> bool {
+            self.signals.as_ref().is_some_and(|s| s.token == token)
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    impl Signals {
+        fn new(token: Token) -> io::Result<Signals> {
+            use std::os::unix::io::FromRawFd;
+
+            let mut mask = unsafe { std::mem::zeroed() };
+            unsafe { libc::sigemptyset(&mut mask) };
+            let fd = unsafe { libc::signalfd(-1, &mask, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Signals {
+                token,
+                fd: unsafe { File::from_raw_fd(fd) },
+                mask,
+            })
+        }
+
+        fn catch(&mut self, signals: &[Signal]) -> io::Result<()> {
+            let mut mask = self.mask;
+            for signal in signals {
+                unsafe { libc::sigaddset(&mut mask, signal.number()) };
+            }
+            // blocked first, so none are missed, or act as if nobody had asked for them
+            let ret =
+                unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &mask, std::ptr::null_mut()) };
+            if 0 != ret {
+                return Err(io::Error::from_raw_os_error(ret));
+            }
+            let flags = libc::SFD_NONBLOCK | libc::SFD_CLOEXEC;
+            if unsafe { libc::signalfd(self.fd.as_raw_fd(), &mask, flags) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.mask = mask;
+            Ok(())
+        }
+
+        fn next(&mut self) -> io::Result<Option<Signal>> {
+            let mut info: libc::signalfd_siginfo = unsafe { std::mem::zeroed() };
+            let len = std::mem::size_of::<libc::signalfd_siginfo>();
+            let buf =
+                unsafe { std::slice::from_raw_parts_mut(&mut info as *mut _ as *mut u8, len) };
+            let n = self.fd.read(buf)?;
+            if n != len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            Ok(Signal::from_number(info.ssi_signo as libc::c_int))
+        }
+    }
+
+    /// Where the handler writes; -1 until a `Net` wants signals.
+    #[cfg(not(target_os = "linux"))]
+    static PIPE: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(-1);
+
+    #[cfg(not(target_os = "linux"))]
+    extern "C" fn handler(number: libc::c_int) {
+        let fd = PIPE.load(std::sync::atomic::Ordering::Relaxed);
+        if fd >= 0 {
+            // if the pipe is full, the `Net` has plenty to be getting on with already
+            let byte = number as u8;
+            unsafe { libc::write(fd, &byte as *const u8 as *const libc::c_void, 1) };
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    impl Signals {
+        fn new(token: Token) -> io::Result<Signals> {
+            let mut fds = [0; 2];
+            if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let (read, write) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+            for fd in &[&read, &write] {
+                let fd = fd.as_raw_fd();
+                let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+                if flags < 0
+                    || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0
+                    || unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            // the handler writes to it for as long as the process runs
+            let write = std::os::unix::io::IntoRawFd::into_raw_fd(write);
+            let previous = PIPE.swap(write, std::sync::atomic::Ordering::Relaxed);
+            if previous >= 0 {
+                unsafe { libc::close(previous) };
+            }
+            Ok(Signals { token, fd: read })
+        }
+
+        fn catch(&mut self, signals: &[Signal]) -> io::Result<()> {
+            for signal in signals {
+                let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+                action.sa_sigaction = handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                action.sa_flags = libc::SA_RESTART;
+                unsafe { libc::sigemptyset(&mut action.sa_mask) };
+                if unsafe { libc::sigaction(signal.number(), &action, std::ptr::null_mut()) } < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        }
+
+        fn next(&mut self) -> io::Result<Option<Signal>> {
+            let mut byte = [0];
+            match self.fd.read(&mut byte)? {
+                0 => Err(io::ErrorKind::UnexpectedEof.into()),
+                _ => Ok(Signal::from_number(byte[0].into())),
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! A `Net` which never touches the OS, and whose time only moves when the test says so; see
+//! `NetBuilder::build_simulated`.
+
+use std::io;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use mio::Token;
+
+use crate::reactor::Interest;
+use crate::reactor::Reactor;
+use crate::reactor::Source;
+use crate::Error;
+use crate::Net;
+use crate::NetBuilder;
+use crate::COMMANDS_TOKEN;
+
+/// Where a `Net` gets the time from.
+#[derive(Clone)]
+pub(crate) enum Clock {
+    Real,
+    Simulated(SimClock),
+}
+
+/// The time in a simulated `Net`. It starts at whenever it was built, and stands still until
+/// `advance`d.
+#[derive(Clone)]
+pub struct SimClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+/// Watches nothing but the command channel, and never waits: a simulated `Net` has only
+/// `memory_pair`s, which `pump` wakes, and timers, which come due when the clock is advanced.
+struct SimReactor;
+
+impl NetBuilder {
+    /// As `build_with_data`, for tests: every connection is a `Net::memory_pair`, and time is
+    /// the returned `SimClock`'s. Anything needing a socket, like `listen` or `tcp_connect`,
+    /// fails. As nothing can happen while it waits, `next()` fails (with `Operation::Poll`)
+    /// instead of waiting; use `try_next`, after `pump`ing, or advancing the clock.
+    pub fn build_simulated<T>(self) -> Result<(Net<T>, SimClock), Error> {
+        let clock = SimClock {
+            now: Arc::new(Mutex::new(Instant::now())),
+        };
+        let net = self.build_with_clock(SimReactor, Clock::Simulated(clock.clone()))?;
+        Ok((net, clock))
+    }
+}
+
+impl<T> Net<T> {
+    /// The time, as far as this `Net` is concerned: the real time, unless it's simulated.
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+}
+
+impl Clock {
+    pub(crate) fn now(&self) -> Instant {
+        match self {
+            Clock::Real => Instant::now(),
+            Clock::Simulated(clock) => clock.now(),
+        }
+    }
+}
+
+impl SimClock {
+    pub fn now(&self) -> Instant {
+        *self.now.lock().expect("nothing panics holding the clock")
+    }
+
+    /// Move time on; whatever comes due is handled by the `Net`'s next `try_next`.
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().expect("nothing panics holding the clock") += by;
+    }
+}
+
+fn no_sockets() -> io::Error {
+    io::Error::other("a simulated Net has only in-memory connections")
+}
+
+impl Reactor for SimReactor {
+    fn register(&mut self, _source: Source, token: Token, _interest: Interest) -> io::Result<()> {
+        match token {
+            // the `Net`'s own command channel, which `poll` always reports
+            COMMANDS_TOKEN => Ok(()),
+            _ => Err(no_sockets()),
+        }
+    }
+
+    fn reregister(&mut self, source: Source, token: Token, interest: Interest) -> io::Result<()> {
+        self.register(source, token, interest)
+    }
+
+    fn deregister(&mut self, source: Source, token: Token) -> io::Result<()> {
+        self.register(source, token, Interest::NONE)
+    }
+
+    fn poll(
+        &mut self,
+        ready: &mut Vec<Token>,
+        _max: usize,
+        timeout: Option<Duration>,
+    ) -> io::Result<()> {
+        if Some(Duration::from_secs(0)) == timeout {
+            // a `Remote` may have sent something; nothing else says so
+            ready.push(COMMANDS_TOKEN);
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "a simulated Net can't wait; pump, or advance the clock, first",
+        ))
+    }
+}
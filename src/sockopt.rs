@@ -0,0 +1,108 @@
+//! TCP socket options: for one connection, with `Io::set_nodelay` and friends, or for every
+//! connection a listener accepts, with `ListenOptions::socket_options`.
+
+use std::io;
+use std::time::Duration;
+
+use mio::net::TcpStream;
+
+use crate::Error;
+use crate::Io;
+use crate::ListenOptions;
+use crate::Operation;
+
+/// What to set on every socket a listener accepts; anything left alone keeps the operating
+/// system's default.
+#[derive(Clone, Debug, Default)]
+pub struct SocketOptions {
+    nodelay: Option<bool>,
+    keepalive: Option<Option<Duration>>,
+    linger: Option<Option<Duration>>,
+    ttl: Option<u32>,
+}
+
+impl SocketOptions {
+    /// As `Io::set_nodelay`.
+    pub fn nodelay(mut self, nodelay: bool) -> SocketOptions {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// As `Io::set_keepalive`.
+    pub fn keepalive(mut self, keepalive: Option<Duration>) -> SocketOptions {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// As `Io::set_linger`.
+    pub fn linger(mut self, linger: Option<Duration>) -> SocketOptions {
+        self.linger = Some(linger);
+        self
+    }
+
+    /// As `Io::set_ttl`.
+    pub fn ttl(mut self, ttl: u32) -> SocketOptions {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub(crate) fn apply(&self, sock: &TcpStream) -> io::Result<()> {
+        if let Some(nodelay) = self.nodelay {
+            sock.set_nodelay(nodelay)?;
+        }
+        if let Some(keepalive) = self.keepalive {
+            sock.set_keepalive(keepalive)?;
+        }
+        if let Some(linger) = self.linger {
+            sock.set_linger(linger)?;
+        }
+        if let Some(ttl) = self.ttl {
+            sock.set_ttl(ttl)?;
+        }
+        Ok(())
+    }
+}
+
+impl ListenOptions {
+    /// Set these on every accepted socket, before `Event::NewConnection`. A socket which
+    /// refuses one is kept anyway, as it would be without them.
+    pub fn socket_options(mut self, options: SocketOptions) -> ListenOptions {
+        self.socket_options = options;
+        self
+    }
+}
+
+impl<T> Io<'_, T> {
+    /// Send small writes straight away, instead of holding them back to coalesce with whatever
+    /// comes next (disabling Nagle's algorithm), for latency over throughput.
+    pub fn set_nodelay(&mut self, nodelay: bool) -> Result<(), Error> {
+        self.set_option(|sock| sock.set_nodelay(nodelay))
+    }
+
+    /// Have the kernel probe the peer once the connection's been quiet for this long, so a
+    /// peer which has vanished is eventually noticed, as an error; `None` stops probing.
+    pub fn set_keepalive(&mut self, keepalive: Option<Duration>) -> Result<(), Error> {
+        self.set_option(|sock| sock.set_keepalive(keepalive))
+    }
+
+    /// How long closing the socket may wait for unsent data to go; `None` leaves it to the
+    /// kernel, in the background, and zero resets the connection instead.
+    pub fn set_linger(&mut self, linger: Option<Duration>) -> Result<(), Error> {
+        self.set_option(|sock| sock.set_linger(linger))
+    }
+
+    /// The time-to-live, or hop limit, of every packet sent.
+    pub fn set_ttl(&mut self, ttl: u32) -> Result<(), Error> {
+        self.set_option(|sock| sock.set_ttl(ttl))
+    }
+
+    /// Fails for a `Net::memory_pair`, which has no socket.
+    fn set_option(&mut self, set: impl FnOnce(&TcpStream) -> io::Result<()>) -> Result<(), Error> {
+        let conn = self.as_conn();
+        conn.inner.tcp().and_then(set).map_err(|e| {
+            Error::new(Operation::SocketOption, e)
+                .with_token(self.token)
+                .with_peer(Some(conn.peer))
+        })
+    }
+}
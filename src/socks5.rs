@@ -0,0 +1,243 @@
+//! Connecting through a SOCKS5 proxy (RFC 1928), such as Tor, or one at the edge of a corporate
+//! network; see `Net::connect_via_socks5`.
+
+use std::io;
+use std::net::SocketAddr;
+
+use crate::Conn;
+use crate::ConnToken;
+use crate::Error;
+use crate::Net;
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const USERNAME_PASSWORD: u8 = 2;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+
+/// Where the proxy should connect to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    Addr(SocketAddr),
+    /// A name for the proxy to resolve, and a port; Tor needs these for onion services.
+    Domain(String, u16),
+}
+
+/// Per-connection state, from `connect_via_socks5` until the proxy says the tunnel is up.
+pub(crate) struct Handshake {
+    stage: Stage,
+}
+
+/// Which reply is expected next. Everything was sent up front, so there's nothing to decide.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Stage {
+    Method { auth: bool },
+    Auth,
+    Reply,
+}
+
+impl From<SocketAddr> for Target {
+    fn from(addr: SocketAddr) -> Target {
+        Target::Addr(addr)
+    }
+}
+
+impl<T> Net<T> {
+    /// Connect to `target` through the SOCKS5 proxy at `proxy`, without authenticating.
+    /// `Event::Connected` follows only once the proxy has connected onwards, or
+    /// `Event::ConnectFailed` if it won't; nothing can be written before then. The connection's
+    /// `peer_addr` is the proxy's.
+    pub fn connect_via_socks5(
+        &mut self,
+        proxy: &SocketAddr,
+        target: impl Into<Target>,
+    ) -> Result<ConnToken, Error> {
+        self.socks5(proxy, &target.into(), None)
+    }
+
+    /// As `connect_via_socks5`, authenticating with a username and password, each of which
+    /// must be between one and 255 bytes.
+    pub fn connect_via_socks5_with_auth(
+        &mut self,
+        proxy: &SocketAddr,
+        target: impl Into<Target>,
+        username: &str,
+        password: &str,
+    ) -> Result<ConnToken, Error> {
+        self.socks5(proxy, &target.into(), Some((username, password)))
+    }
+
+    fn socks5(
+        &mut self,
+        proxy: &SocketAddr,
+        target: &Target,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<ConnToken, Error> {
+        let request = request(target, credentials)?;
+        let token = self.tcp_connect(proxy)?;
+        let conn = self.conn_mut(token.0).expect("just connected");
+        let before = conn.buffered();
+        // everything at once: only one method is offered, so the proxy can't pick another
+        conn.enqueue(token.0, |buf| buf.push(request))?;
+        conn.socks = Some(Handshake {
+            stage: Stage::Method {
+                auth: credentials.is_some(),
+            },
+        });
+        let after = conn.buffered();
+        self.buffered = self.buffered - before + after;
+        Ok(token)
+    }
+}
+
+/// The greeting, any authentication, and the connect request, one after another.
+fn request(target: &Target, credentials: Option<(&str, &str)>) -> Result<Vec<u8>, Error> {
+    let mut request = vec![VERSION, 1];
+    match credentials {
+        None => request.push(NO_AUTH),
+        Some((username, password)) => {
+            request.push(USERNAME_PASSWORD);
+            request.push(1);
+            for (setting, value) in &[("username", username), ("password", password)] {
+                if value.is_empty() || value.len() > 255 {
+                    return Err(Error::invalid(setting, value.len(), "must be 1-255 bytes"));
+                }
+                request.push(value.len() as u8);
+                request.extend_from_slice(value.as_bytes());
+            }
+        }
+    }
+
+    // CONNECT
+    request.extend_from_slice(&[VERSION, 1, 0]);
+    let port = match target {
+        Target::Addr(SocketAddr::V4(addr)) => {
+            request.push(1);
+            request.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Target::Addr(SocketAddr::V6(addr)) => {
+            request.push(4);
+            request.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Target::Domain(name, port) => {
+            if name.is_empty() || name.len() > 255 {
+                return Err(Error::invalid("domain", name, "must be 1-255 bytes"));
+            }
+            request.push(3);
+            request.push(name.len() as u8);
+            request.extend_from_slice(name.as_bytes());
+            *port
+        }
+    };
+    request.extend_from_slice(&port.to_be_bytes());
+    Ok(request)
+}
+
+impl Conn {
+    /// Take the proxy's replies from the read buffer. Returns whether the tunnel is now up, at
+    /// which point the connection is an ordinary one.
+    pub(crate) fn socks_process(&mut self) -> io::Result<bool> {
+        loop {
+            let stage = match &self.socks {
+                Some(handshake) => handshake.stage,
+                None => return Ok(false),
+            };
+            let buf = match self.read_buffer.buf() {
+                Some(buf) => buf.as_slice(),
+                None => return Ok(false),
+            };
+            let (len, next) = match parse(stage, buf)? {
+                Some(parsed) => parsed,
+                None if self.read_buffer.eof => {
+                    return Err(failed(
+                        io::ErrorKind::UnexpectedEof,
+                        "closed during the handshake",
+                    ));
+                }
+                None => return Ok(false),
+            };
+            self.read_buffer
+                .buf_mut()
+                .expect("just parsed from it")
+                .consume(len);
+            match next {
+                Some(stage) => self.socks = Some(Handshake { stage }),
+                None => {
+                    self.socks = None;
+                    return Ok(true);
+                }
+            }
+        }
+    }
+}
+
+/// The reply expected at `stage`, if it's all arrived: how long it was, and what's next, if
+/// anything.
+fn parse(stage: Stage, buf: &[u8]) -> io::Result<Option<(usize, Option<Stage>)>> {
+    let reply = match stage {
+        Stage::Reply => return connect_reply(buf),
+        _ => match buf.get(..2) {
+            Some(reply) => reply,
+            None => return Ok(None),
+        },
+    };
+    let next = match (stage, reply) {
+        (Stage::Method { .. }, [VERSION, NO_ACCEPTABLE_METHODS]) => {
+            return Err(failed(
+                io::ErrorKind::PermissionDenied,
+                "no acceptable authentication method",
+            ))
+        }
+        (Stage::Method { auth: false }, [VERSION, NO_AUTH]) => Stage::Reply,
+        (Stage::Method { auth: true }, [VERSION, USERNAME_PASSWORD]) => Stage::Auth,
+        (Stage::Auth, [1, 0]) => Stage::Reply,
+        (Stage::Auth, [1, _]) => {
+            return Err(failed(
+                io::ErrorKind::PermissionDenied,
+                "credentials refused",
+            ))
+        }
+        _ => return Err(failed(io::ErrorKind::InvalidData, "malformed reply")),
+    };
+    Ok(Some((2, Some(next))))
+}
+
+fn connect_reply(buf: &[u8]) -> io::Result<Option<(usize, Option<Stage>)>> {
+    let header = match buf.get(..5) {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    if VERSION != header[0] {
+        return Err(failed(io::ErrorKind::InvalidData, "malformed reply"));
+    }
+    let refused = match header[1] {
+        0 => None,
+        2 => Some((io::ErrorKind::PermissionDenied, "not allowed by ruleset")),
+        3 => Some((io::ErrorKind::Other, "network unreachable")),
+        4 => Some((io::ErrorKind::Other, "host unreachable")),
+        5 => Some((io::ErrorKind::ConnectionRefused, "connection refused")),
+        6 => Some((io::ErrorKind::TimedOut, "TTL expired")),
+        7 => Some((io::ErrorKind::Other, "command not supported")),
+        8 => Some((io::ErrorKind::Other, "address type not supported")),
+        _ => Some((io::ErrorKind::Other, "general failure")),
+    };
+    if let Some((kind, why)) = refused {
+        return Err(failed(kind, why));
+    }
+    // the address it bound, which nobody needs, then its port
+    let len = match header[3] {
+        1 => 4 + 4 + 2,
+        3 => 4 + 1 + header[4] as usize + 2,
+        4 => 4 + 16 + 2,
+        _ => return Err(failed(io::ErrorKind::InvalidData, "malformed reply")),
+    };
+    if buf.len() < len {
+        return Ok(None);
+    }
+    Ok(Some((len, None)))
+}
+
+fn failed(kind: io::ErrorKind, why: &str) -> io::Error {
+    io::Error::new(kind, format!("SOCKS5 proxy: {}", why))
+}
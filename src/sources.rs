@@ -0,0 +1,96 @@
+//! Anything else the loop can wait on, like an eventfd, a pipe, or another library's mio
+//! `Registration`, so it needn't have a poller of its own; see `Net::register_source`.
+
+use log::info;
+use mio::Evented;
+
+use crate::reactor::Interest;
+use crate::reactor::Source;
+use crate::Error;
+use crate::Net;
+use crate::Operation;
+use crate::SourceToken;
+
+/// What a `SourceToken` names.
+pub(crate) enum Registered {
+    Evented(Box<dyn Evented + Send>),
+    /// Someone else's, for `Net::register_fd`.
+    #[cfg(unix)]
+    Fd(std::os::unix::io::RawFd),
+}
+
+impl<T> Net<T> {
+    /// Watch `source` for `interest`, with an `Event::Ready` whenever it may have become
+    /// ready. It's owned by the `Net` until `deregister_source`; keep a handle to it, like
+    /// mio's `SetReadiness`, to make it ready, or a copy of its file descriptor, to use it.
+    /// Only the default `MioReactor` can watch these; the others fail with `Unsupported`, so
+    /// use `register_fd` where there's a file descriptor.
+    pub fn register_source(
+        &mut self,
+        source: impl Evented + Send + 'static,
+        interest: Interest,
+    ) -> Result<SourceToken, Error> {
+        self.add_source(Registered::Evented(Box::new(source)), interest)
+    }
+
+    /// As `register_source`, for a file descriptor, like an eventfd or a pipe. It's still
+    /// the caller's, to use and to close, but only after `deregister_source`.
+    #[cfg(unix)]
+    pub fn register_fd(
+        &mut self,
+        fd: std::os::unix::io::RawFd,
+        interest: Interest,
+    ) -> Result<SourceToken, Error> {
+        self.add_source(Registered::Fd(fd), interest)
+    }
+
+    fn add_source(&mut self, source: Registered, interest: Interest) -> Result<SourceToken, Error> {
+        let token = self.tokens.reserve()?;
+        if let Err(e) = self.reactor.register(source.source(), token, interest) {
+            self.release(token);
+            return Err(Error::new(Operation::Register, e).with_token(token));
+        }
+        info!("{} source registered", token.0);
+        self.sources.insert(token, source);
+        Ok(SourceToken(token))
+    }
+
+    /// Watch a source for something else, e.g. to start waiting for it to be writable.
+    pub fn reregister_source(
+        &mut self,
+        token: SourceToken,
+        interest: Interest,
+    ) -> Result<(), Error> {
+        let token = token.0;
+        let source = self.sources.get(&token).ok_or(Error::UnknownToken(token))?;
+        self.reactor
+            .reregister(source.source(), token, interest)
+            .map_err(|e| Error::new(Operation::Register, e).with_token(token))
+    }
+
+    /// Stop watching a source, and drop it; any `Event::Ready`s for it still waiting are
+    /// dropped too. The token names nothing from now on.
+    pub fn deregister_source(&mut self, token: SourceToken) -> Result<(), Error> {
+        let token = token.0;
+        let source = self
+            .sources
+            .remove(&token)
+            .ok_or(Error::UnknownToken(token))?;
+        if let Err(e) = self.reactor.deregister(source.source(), token) {
+            info!("{} deregister-err {:?}", token.0, e);
+        }
+        self.events.forget(token);
+        self.release(token);
+        Ok(())
+    }
+}
+
+impl Registered {
+    fn source(&self) -> Source<'_> {
+        match self {
+            Registered::Evented(evented) => Source::Evented(&**evented),
+            #[cfg(unix)]
+            Registered::Fd(fd) => Source::Fd(*fd),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! `std::io` traits for `Io`, for code written against readers and writers. Nothing here
+//! blocks: reading what hasn't arrived yet fails with `WouldBlock`, so try again after the
+//! next `Event::Data`. Writes are only queued, so they always take everything, or fail.
+
+use std::io;
+
+use crate::Io;
+
+impl<T> Io<'_, T> {
+    /// Nothing more will be read: the peer has finished, or we've closed our side.
+    fn read_finished(&self) -> bool {
+        let read_buffer = &self.as_conn().read_buffer;
+        read_buffer.eof || read_buffer.buf().is_none()
+    }
+}
+
+impl<T> io::Read for Io<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = io::BufRead::fill_buf(self)?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        io::BufRead::consume(self, len);
+        Ok(len)
+    }
+}
+
+impl<T> io::BufRead for Io<'_, T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let empty = self.buf().map_or(true, |buf| buf.is_empty());
+        if empty && !self.read_finished() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        Ok(self.buf().unwrap_or_default())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // only fails if there's nothing left to consume
+        let _ = Io::consume(self, amt);
+    }
+}
+
+impl<T> io::Write for Io<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Io::write(self, buf)?;
+        Ok(buf.len())
+    }
+
+    /// Writing happens as the peer is ready for it; see `Event::Flushed`.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+use std::fs::File;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use mio::net::TcpListener;
+use mio::net::TcpStream;
+use net2::TcpBuilder;
+
+use crate::config::DEFAULT_BACKLOG;
+
+/// The most `copy_file` reads at once.
+const COPY_CHUNK: usize = 64 * 1024;
+
+#[cfg(target_os = "linux")]
+pub fn kernel_send_queue(sock: &TcpStream) -> io::Result<usize> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut queued: libc::c_int = 0;
+    // SIOCOUTQ has the same value as TIOCOUTQ, which is the name libc exposes
+    let ret = unsafe { libc::ioctl(sock.as_raw_fd(), libc::TIOCOUTQ, &mut queued) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(queued as usize)
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub fn kernel_send_queue(sock: &TcpStream) -> io::Result<usize> {
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+
+    let mut queued: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_NWRITE,
+            &mut queued as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(queued as usize)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "ios")))]
+pub fn kernel_send_queue(_sock: &TcpStream) -> io::Result<usize> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "kernel send queue size is unsupported on this platform",
+    ))
+}
+
+/// A descriptor which becomes readable when the process `pid` exits.
+#[cfg(target_os = "linux")]
+pub fn pidfd_open(pid: u32) -> io::Result<File> {
+    use std::os::unix::io::FromRawFd;
+
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd as libc::c_int) })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pidfd_open(_pid: u32) -> io::Result<File> {
+    Err(io::Error::other("pidfds are unsupported on this platform"))
+}
+
+/// How `bind_listener` sets up a listening socket; see `ListenOptions`.
+#[derive(Clone, Debug)]
+pub struct BindOptions {
+    pub backlog: usize,
+    pub reuse_address: bool,
+    pub reuse_port: bool,
+    pub only_v6: Option<bool>,
+}
+
+impl Default for BindOptions {
+    fn default() -> BindOptions {
+        BindOptions {
+            backlog: DEFAULT_BACKLOG,
+            // as mio does, so a restarted server needn't wait for its old connections to time out
+            reuse_address: cfg!(unix),
+            reuse_port: false,
+            only_v6: None,
+        }
+    }
+}
+
+/// As `TcpListener::bind`, with everything set up before the bind and listen.
+pub fn bind_listener(addr: &SocketAddr, options: &BindOptions) -> io::Result<TcpListener> {
+    let builder = match addr {
+        SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+        SocketAddr::V6(_) => TcpBuilder::new_v6()?,
+    };
+    if options.reuse_address {
+        builder.reuse_address(true)?;
+    }
+    if options.reuse_port {
+        set_reuse_port(&builder)?;
+    }
+    if let Some(only_v6) = options.only_v6 {
+        builder.only_v6(only_v6)?;
+    }
+    builder.bind(addr)?;
+    // checked against `MAX_BACKLOG` by the listen call
+    TcpListener::from_std(builder.listen(options.backlog as i32)?)
+}
+
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+fn set_reuse_port(builder: &TcpBuilder) -> io::Result<()> {
+    use net2::unix::UnixTcpBuilderExt;
+    builder.reuse_port(true)?;
+    Ok(())
+}
+
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+fn set_reuse_port(_builder: &TcpBuilder) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "SO_REUSEPORT is unsupported on this platform",
+    ))
+}
+
+/// A mio socket back as its std equivalent, still non-blocking; `None` where mio won't let go
+/// of it, i.e. anywhere but unix.
+#[cfg(unix)]
+pub fn into_std<S: std::os::unix::io::FromRawFd>(
+    sock: impl std::os::unix::io::IntoRawFd,
+) -> Option<S> {
+    Some(unsafe { S::from_raw_fd(sock.into_raw_fd()) })
+}
+
+#[cfg(not(unix))]
+pub fn into_std<S, M>(_sock: M) -> Option<S> {
+    None
+}
+
+/// Send up to `len` bytes of `file`, from `offset`, as `write` would. Fails with
+/// `UnexpectedEof` if the file is shorter than that.
+#[cfg(target_os = "linux")]
+pub fn send_file(sock: &TcpStream, file: &File, offset: u64, len: usize) -> io::Result<usize> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut off = offset as libc::off_t;
+    let ret = unsafe { libc::sendfile(sock.as_raw_fd(), file.as_raw_fd(), &mut off, len) };
+    match ret {
+        0 => Err(file_ended()),
+        n if n > 0 => Ok(n as usize),
+        _ => match io::Error::last_os_error() {
+            // not something the kernel can send from, e.g. some special files
+            e if Some(libc::EINVAL) == e.raw_os_error()
+                || Some(libc::ENOSYS) == e.raw_os_error() =>
+            {
+                copy_file(sock, file, offset, len)
+            }
+            e => Err(e),
+        },
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn send_file(sock: &TcpStream, file: &File, offset: u64, len: usize) -> io::Result<usize> {
+    copy_file(sock, file, offset, len)
+}
+
+/// `send_file`, through userspace. If `out` takes less than was read, the rest is read
+/// again next time.
+pub fn copy_file(
+    mut out: impl io::Write,
+    file: &File,
+    offset: u64,
+    len: usize,
+) -> io::Result<usize> {
+    #[cfg(unix)]
+    use std::os::unix::fs::FileExt;
+    #[cfg(windows)]
+    use std::os::windows::fs::FileExt;
+
+    let mut buf = vec![0; len.min(COPY_CHUNK)];
+    #[cfg(unix)]
+    let read = file.read_at(&mut buf, offset)?;
+    #[cfg(windows)]
+    let read = file.seek_read(&mut buf, offset)?;
+    if 0 == read {
+        return Err(file_ended());
+    }
+    out.write(&buf[..read])
+}
+
+fn file_ended() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "file ended before everything was sent",
+    )
+}
+
+/// A pipe for `splice(2)`ing from one socket to another, so the data never leaves the kernel.
+#[cfg(target_os = "linux")]
+pub struct Pipe {
+    read: File,
+    write: File,
+    /// How much is in the pipe.
+    pub len: usize,
+    pub capacity: usize,
+}
+
+/// Splicing needs Linux; `Pipe::new` always fails elsewhere.
+#[cfg(not(target_os = "linux"))]
+pub struct Pipe {
+    pub len: usize,
+    pub capacity: usize,
+}
+
+#[cfg(target_os = "linux")]
+impl Pipe {
+    pub fn new() -> io::Result<Pipe> {
+        use std::os::unix::io::FromRawFd;
+
+        let mut fds = [0 as libc::c_int; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (read, write) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        let capacity = unsafe { libc::fcntl(fds[1], libc::F_GETPIPE_SZ) };
+        if capacity <= 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Pipe {
+            read,
+            write,
+            len: 0,
+            capacity: capacity as usize,
+        })
+    }
+
+    /// Move as much as will fit from `sock` into the pipe, as `read` would.
+    pub fn fill_from(&mut self, sock: &TcpStream) -> io::Result<usize> {
+        use std::os::unix::io::AsRawFd;
+
+        let n = splice(
+            sock.as_raw_fd(),
+            self.write.as_raw_fd(),
+            self.capacity - self.len,
+        )?;
+        self.len += n;
+        Ok(n)
+    }
+
+    /// Move as much as `sock` will take out of the pipe, as `write` would.
+    pub fn empty_into(&mut self, sock: &TcpStream) -> io::Result<usize> {
+        use std::os::unix::io::AsRawFd;
+
+        let n = splice(self.read.as_raw_fd(), sock.as_raw_fd(), self.len)?;
+        self.len -= n;
+        Ok(n)
+    }
+
+    /// Everything in the pipe, the slow way.
+    pub fn drain(&mut self) -> io::Result<Vec<u8>> {
+        let mut out = vec![0; self.len];
+        io::Read::read_exact(&mut self.read, &mut out)?;
+        self.len = 0;
+        Ok(out)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn splice(from: libc::c_int, to: libc::c_int, len: usize) -> io::Result<usize> {
+    let flags = libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK;
+    let ret = unsafe {
+        libc::splice(
+            from,
+            std::ptr::null_mut(),
+            to,
+            std::ptr::null_mut(),
+            len,
+            flags,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Pipe {
+    pub fn new() -> io::Result<Pipe> {
+        Err(splice_unsupported())
+    }
+
+    pub fn fill_from(&mut self, _sock: &TcpStream) -> io::Result<usize> {
+        Err(splice_unsupported())
+    }
+
+    pub fn empty_into(&mut self, _sock: &TcpStream) -> io::Result<usize> {
+        Err(splice_unsupported())
+    }
+
+    pub fn drain(&mut self) -> io::Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn splice_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        "splice is unsupported on this platform",
+    )
+}
+
+/// Whether a `splice` failed because it can't be used here, rather than because of either
+/// socket; the data can still be moved the slow way.
+pub fn splice_unsupported_by(e: &io::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        Some(libc::EINVAL) == e.raw_os_error() || Some(libc::ENOSYS) == e.raw_os_error()
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = e;
+        true
+    }
+}
+
+/// Some of what the kernel knows about a TCP connection. Fields the running kernel is too old
+/// to report are `None`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TcpInfo {
+    /// Smoothed round trip time.
+    pub rtt: Duration,
+    pub rttvar: Duration,
+    /// Retransmits of the segment currently outstanding.
+    pub retransmits: u8,
+    /// Retransmits over the life of the connection.
+    pub total_retrans: u32,
+    /// Congestion window, in segments.
+    pub snd_cwnd: u32,
+    /// Bytes per second.
+    pub delivery_rate: Option<u64>,
+    pub min_rtt: Option<Duration>,
+}
+
+#[cfg(target_os = "linux")]
+pub fn tcp_info(sock: &TcpStream) -> io::Result<TcpInfo> {
+    use std::convert::TryInto;
+    use std::os::unix::io::AsRawFd;
+
+    // `struct tcp_info` grows with the kernel; ask for plenty, and only believe what we're given
+    let mut buf = [0u8; 256];
+    let mut len = buf.len() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            sock.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            buf.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let buf = &buf[..len as usize];
+
+    let u32_at = |off: usize| {
+        buf.get(off..off + 4)
+            .map(|b| u32::from_ne_bytes(b.try_into().expect("four bytes")))
+    };
+    let u64_at = |off: usize| {
+        buf.get(off..off + 8)
+            .map(|b| u64::from_ne_bytes(b.try_into().expect("eight bytes")))
+    };
+    let micros = |v: u32| Duration::from_micros(u64::from(v));
+
+    Ok(TcpInfo {
+        rtt: u32_at(68).map(micros).unwrap_or_default(),
+        rttvar: u32_at(72).map(micros).unwrap_or_default(),
+        retransmits: buf.get(2).copied().unwrap_or_default(),
+        total_retrans: u32_at(100).unwrap_or_default(),
+        snd_cwnd: u32_at(80).unwrap_or_default(),
+        delivery_rate: u64_at(160),
+        min_rtt: u32_at(148).map(micros),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn tcp_info(_sock: &TcpStream) -> io::Result<TcpInfo> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "TCP_INFO is unsupported on this platform",
+    ))
+}
@@ -0,0 +1,67 @@
+//! Seeing every byte which goes through connections' sockets, e.g. to hexdump it.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use mio::Token;
+
+use crate::ConnToken;
+use crate::Direction;
+use crate::Io;
+use crate::Net;
+use crate::OwnedMode;
+
+/// Sees everything read from, and written to, connections' sockets, as it happens; see
+/// `Net::set_tap`, and `Io::set_tap`. What's spliced by `Net::link_spliced`, or sent from a
+/// file by `Io::send_file`, never reaches memory, so isn't seen.
+pub trait Tap: Send {
+    /// `data` has just been read from, or written to, `token`'s socket.
+    fn chunk(&mut self, token: ConnToken, direction: Direction, data: &[u8]);
+}
+
+/// Shared between connections, for `Net::set_tap`.
+pub(crate) type SharedTap = Arc<Mutex<dyn Tap>>;
+
+impl<T> Net<T> {
+    /// Tap every connection, now and from now on, replacing any taps they already have.
+    pub fn set_tap(&mut self, tap: impl Tap + 'static) {
+        let tap: SharedTap = Arc::new(Mutex::new(tap));
+        for (_, owned) in self.tokens.iter_mut() {
+            if let OwnedMode::Conn(conn) = &mut owned.mode {
+                conn.tap = Some(tap.clone());
+            }
+        }
+        self.tap = Some(tap);
+    }
+
+    /// Untap every connection.
+    pub fn remove_tap(&mut self) {
+        for (_, owned) in self.tokens.iter_mut() {
+            if let OwnedMode::Conn(conn) = &mut owned.mode {
+                conn.tap = None;
+            }
+        }
+        self.tap = None;
+    }
+}
+
+impl<T> Io<'_, T> {
+    /// Tap just this connection, instead of with any tap from `Net::set_tap`.
+    pub fn set_tap(&mut self, tap: impl Tap + 'static) {
+        self.as_conn_mut().tap = Some(Arc::new(Mutex::new(tap)));
+    }
+
+    /// Untap this connection, even if there's a `Net::set_tap`.
+    pub fn remove_tap(&mut self) {
+        self.as_conn_mut().tap = None;
+    }
+}
+
+pub(crate) fn tapped(tap: &Option<SharedTap>, token: Token, direction: Direction, data: &[u8]) {
+    if let Some(tap) = tap {
+        // a tap which panicked has had its chance
+        if let Ok(mut tap) = tap.lock() {
+            tap.chunk(ConnToken(token), direction, data);
+        }
+    }
+}
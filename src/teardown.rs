@@ -0,0 +1,321 @@
+use std::net;
+use std::time::Duration;
+use std::time::Instant;
+
+use log::info;
+use log::warn;
+use mio::Token;
+
+use crate::buffer::Buffer;
+use crate::config;
+use crate::memory::Transport;
+use crate::reactor::Source;
+use crate::shunt_io;
+use crate::sys;
+use crate::timer::Expiry;
+#[cfg(unix)]
+use crate::ConnToken;
+use crate::Error;
+use crate::Event;
+use crate::FinalBuffers;
+use crate::Io;
+use crate::Net;
+#[cfg(unix)]
+use crate::Operation;
+use crate::OwnedMode;
+use crate::UdpIo;
+
+/// Only write buffers up to this size get a last chance to flush when a `Net` is dropped.
+const DROP_FLUSH_LIMIT: usize = 64 * 1024;
+
+/// Total time a dropped `Net` will spend trying to flush.
+const DROP_FLUSH_TIME: Duration = Duration::from_millis(100);
+
+/// Everything a `Net` was holding, back as std types. The sockets are still non-blocking.
+pub struct Parts {
+    pub listeners: Vec<(Token, net::TcpListener)>,
+    /// `Net::memory_pair`s and `Net::stdio` aren't sockets, so are dropped.
+    pub streams: Vec<(Token, net::TcpStream, FinalBuffers)>,
+    /// Anything queued to send, or waiting to be received, is lost.
+    pub datagrams: Vec<(Token, net::UdpSocket)>,
+}
+
+impl<T> Net<T> {
+    /// Take the sockets out, unflushed and unclosed, instead of letting `Drop` deal with them.
+    /// What can't be taken out is dropped, with a warning if that loses anything: pending
+    /// timers, and anything buffered for a connection which isn't a socket. Away from unix,
+    /// mio won't give its sockets back, so they're all dropped too.
+    pub fn into_parts(mut self) -> Parts {
+        let mut parts = Parts {
+            listeners: Vec::new(),
+            streams: Vec::new(),
+            datagrams: Vec::new(),
+        };
+
+        if !self.timers.is_empty() {
+            warn!("into_parts discarded {} pending timers", self.timers.len());
+            self.timers.clear();
+        }
+
+        for (token, owned) in self.tokens.drain() {
+            match owned.mode {
+                OwnedMode::Server(server) => {
+                    let _ = self
+                        .reactor
+                        .deregister(Source::Listener(&server.inner), token);
+                    match sys::into_std(server.inner) {
+                        Some(listener) => parts.listeners.push((token, listener)),
+                        None => warn!("{} into_parts closed the listener", token.0),
+                    }
+                }
+                OwnedMode::Conn(conn) => {
+                    self.buffered -= conn.buffered();
+                    if let Some(source) = conn.inner.source() {
+                        let _ = self.reactor.deregister(source, token);
+                    }
+                    let remains = FinalBuffers {
+                        unread: conn.read_buffer.into_remains(),
+                        unwritten: conn.write_buffer.into_remains(),
+                    };
+                    let sock = match conn.inner {
+                        Transport::Tcp(sock) => sys::into_std(sock),
+                        _ => None,
+                    };
+                    match sock {
+                        Some(sock) => parts.streams.push((token, sock, remains)),
+                        None if !remains.unwritten.is_empty() || !remains.unread.is_empty() => {
+                            warn!(
+                                "{} into_parts discarded {} unwritten and {} unread bytes",
+                                token.0,
+                                remains.unwritten.len(),
+                                remains.unread.len()
+                            );
+                        }
+                        None => (),
+                    }
+                }
+                OwnedMode::Udp(udp) => {
+                    let _ = self.reactor.deregister(Source::Udp(&udp.inner), token);
+                    let buffered = udp.buffered();
+                    self.buffered -= buffered;
+                    if 0 != buffered {
+                        warn!(
+                            "{} into_parts discarded {} bytes of datagrams",
+                            token.0, buffered
+                        );
+                    }
+                    match sys::into_std(udp.inner) {
+                        Some(sock) => parts.datagrams.push((token, sock)),
+                        None => warn!("{} into_parts closed the socket", token.0),
+                    }
+                }
+            }
+        }
+
+        parts
+    }
+
+    /// Take one connection's socket out, e.g. for another library to speak some other protocol
+    /// over, like after an HTTP `Upgrade`. Comes with whatever was read but not consumed, and
+    /// whatever was queued but not yet sent; neither is lost, but they're now the caller's
+    /// problem. The token is gone at once, along with any events still waiting for it, as if
+    /// `Event::Closed` had been delivered. The socket is still non-blocking.
+    #[cfg(unix)]
+    pub fn detach(&mut self, token: ConnToken) -> Result<(net::TcpStream, FinalBuffers), Error> {
+        use std::os::unix::io::FromRawFd;
+        use std::os::unix::io::IntoRawFd;
+
+        let token = token.0;
+        match self.tokens.get(&token).map(|o| &o.mode) {
+            // pooled connections aren't anyone's until they're handed out again
+            Some(OwnedMode::Conn(conn)) if !conn.is_pooled_idle() => {
+                conn.inner
+                    .tcp()
+                    .map_err(|e| Error::new(Operation::Detach, e).with_token(token))?;
+            }
+            _ => return Err(Error::UnknownToken(token)),
+        }
+
+        let conn = match self.tokens.remove(&token).map(|o| o.mode) {
+            Some(OwnedMode::Conn(conn)) => conn,
+            _ => unreachable!("just checked"),
+        };
+        self.buffered -= conn.buffered();
+        if let Some(ip) = conn.per_ip {
+            self.forget_peer(ip);
+        }
+        if let Some(link) = &conn.link {
+            self.unlink(link.peer);
+        }
+        let sock = match conn.inner {
+            Transport::Tcp(sock) => sock,
+            _ => unreachable!("just checked"),
+        };
+        if let Err(e) = self.reactor.deregister(Source::Stream(&sock), token) {
+            info!("{} deregister-err {:?}", token.0, e);
+        }
+        info!("{} detached", token.0);
+        let remains = FinalBuffers {
+            unread: conn.read_buffer.into_remains(),
+            unwritten: conn.write_buffer.into_remains(),
+        };
+        self.events.forget(token);
+        self.release(token);
+        self.check_shutdown();
+        let fd = sock.into_raw_fd();
+        Ok((unsafe { net::TcpStream::from_raw_fd(fd) }, remains))
+    }
+
+    /// Stop listening, and close everything, giving what's already been written up to
+    /// `timeout` to drain; anything still open after that is reset. Keep calling `next()` for
+    /// whatever happens meanwhile, up to `Event::Shutdown`. Idle pooled connections are closed
+    /// without any events.
+    pub fn shutdown_gracefully(&mut self, timeout: Duration) -> Result<(), Error> {
+        let timeout = config::check_timeout("shutdown timeout", timeout)?;
+        let deadline = self.clock.now() + timeout;
+        self.drop_listeners();
+
+        let sockets: Vec<Token> = self
+            .tokens
+            .iter()
+            .filter(|(_, owned)| matches!(owned.mode, OwnedMode::Udp(_)))
+            .map(|(token, _)| token)
+            .collect();
+        for token in sockets {
+            UdpIo { inner: self, token }.close();
+        }
+
+        let open: Vec<(Token, bool)> = self
+            .tokens
+            .iter()
+            .filter_map(|(token, owned)| match &owned.mode {
+                OwnedMode::Conn(conn) => Some((token, conn.is_pooled_idle())),
+                OwnedMode::Udp(_) | OwnedMode::Server(_) => None,
+            })
+            .collect();
+        for (token, pooled_idle) in open {
+            if pooled_idle {
+                self.evict(token);
+                continue;
+            }
+            Io { inner: self, token }.close();
+            let sooner = match self.conn_mut(token) {
+                Some(conn) if conn.drain_deadline.is_none_or(|at| at > deadline) => {
+                    conn.drain_deadline = Some(deadline);
+                    true
+                }
+                _ => false,
+            };
+            if sooner {
+                self.deadlines.push(deadline, token, Expiry::Drain);
+            }
+        }
+
+        self.shutting_down = true;
+        self.check_shutdown();
+        Ok(())
+    }
+
+    /// Queue `Event::Shutdown` once everything `shutdown_gracefully` closed has gone.
+    pub(crate) fn check_shutdown(&mut self) {
+        let open = self
+            .tokens
+            .iter()
+            .any(|(_, owned)| !matches!(owned.mode, OwnedMode::Server(_)));
+        if self.shutting_down && !open {
+            self.shutting_down = false;
+            self.events.push(Event::Shutdown);
+        }
+    }
+
+    fn drop_listeners(&mut self) {
+        let listeners: Vec<Token> = self
+            .tokens
+            .iter()
+            .filter(|(_, owned)| matches!(owned.mode, OwnedMode::Server(_)))
+            .map(|(token, _)| token)
+            .collect();
+        for token in listeners {
+            self.unlisten(token);
+        }
+    }
+
+    /// Stop watching every UDP socket, and close it; anything queued for it is dropped.
+    fn drop_udp_sockets(&mut self) {
+        let sockets: Vec<Token> = self
+            .tokens
+            .iter()
+            .filter(|(_, owned)| matches!(owned.mode, OwnedMode::Udp(_)))
+            .map(|(token, _)| token)
+            .collect();
+        for token in sockets {
+            if let Some(OwnedMode::Udp(udp)) = self.tokens.remove(&token).map(|o| o.mode) {
+                self.buffered -= udp.buffered();
+                if let Err(e) = self.reactor.deregister(Source::Udp(&udp.inner), token) {
+                    info!("{} deregister-err {:?}", token.0, e);
+                }
+            }
+            self.release(token);
+        }
+    }
+
+    /// Returns whether anything small enough to bother with is still waiting to be written.
+    fn flush_small(&mut self) -> bool {
+        let mut pending = false;
+        for (token, owned) in self.tokens.iter_mut() {
+            if let OwnedMode::Conn(conn) = &mut owned.mode {
+                let len = conn.write_buffer.len();
+                if 0 == len || len > DROP_FLUSH_LIMIT || conn.write_buffer.buf().is_none() {
+                    continue;
+                }
+                shunt_io(conn, token, false, &mut *self.reactor);
+                self.buffered = self.buffered - len + conn.write_buffer.len();
+                pending |= conn.write_buffer.buf().is_some_and(|buf| !buf.is_empty());
+            }
+        }
+        pending
+    }
+}
+
+impl<T> Drop for Net<T> {
+    fn drop(&mut self) {
+        self.drop_listeners();
+        self.drop_udp_sockets();
+
+        let deadline = Instant::now() + DROP_FLUSH_TIME;
+        let mut ready = Vec::new();
+        while self.flush_small() {
+            let now = Instant::now();
+            if now >= deadline || self.reregister().is_err() {
+                break;
+            }
+            ready.clear();
+            if let Err(e) = self.reactor.poll(&mut ready, 32, Some(deadline - now)) {
+                info!("drop-poll-err {:?}", e);
+                break;
+            }
+        }
+
+        let mut unflushed = Vec::new();
+        for (token, owned) in self.tokens.drain() {
+            if let OwnedMode::Conn(conn) = owned.mode {
+                let len = conn.write_buffer.len();
+                if 0 != len {
+                    unflushed.push(format!("{} ({} bytes)", token.0, len));
+                }
+                if let Some(source) = conn.inner.source() {
+                    if let Err(e) = self.reactor.deregister(source, token) {
+                        info!("{} deregister-err {:?}", token.0, e);
+                    }
+                }
+            }
+        }
+
+        if !unflushed.is_empty() {
+            warn!(
+                "dropped with unflushed connections: {}",
+                unflushed.join(", ")
+            );
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Holding connections to so many bytes a second, each way, with `Io::set_rate_limit`; and
+//! the whole `Net`'s writing, with `NetBuilder::max_egress_bps`.
+
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::timer::Expiry;
+use crate::Conn;
+use crate::Error;
+use crate::Io;
+use crate::Net;
+use crate::NetBuilder;
+use crate::COMMANDS_TOKEN;
+
+const NANOS: u128 = 1_000_000_000;
+
+/// A token bucket, counted in bytes.
+pub(crate) struct Bucket {
+    /// Bytes a second.
+    rate: u64,
+    /// The most which can be saved up: a tenth of a second's worth.
+    burst: u64,
+    available: u64,
+    /// When `available` was last topped up, less any fraction of a byte earned since.
+    last: Instant,
+}
+
+impl<T> Io<'_, T> {
+    /// Hold reads from, and writes to, this connection's socket to at most this many bytes a
+    /// second, each; `None` leaves that direction unlimited, and replaces any earlier limit.
+    /// Up to a tenth of a second's worth goes at once. Each must be non-zero. A connection
+    /// being `Net::link_spliced` isn't held back, as its bytes never come through here.
+    pub fn set_rate_limit(
+        &mut self,
+        read_bps: Option<u64>,
+        write_bps: Option<u64>,
+    ) -> Result<(), Error> {
+        let read = check("read_bps", read_bps)?;
+        let write = check("write_bps", write_bps)?;
+        let now = self.inner.clock.now();
+        let conn = self.as_conn_mut();
+        conn.read_buffer.bucket = read.map(|rate| Bucket::new(rate, now));
+        conn.write_buffer.bucket = write.map(|rate| Bucket::new(rate, now));
+        Ok(())
+    }
+}
+
+impl NetBuilder {
+    /// Write at most this many bytes a second, across every connection, sharing it out evenly
+    /// between those with something to write. Up to a tenth of a second's worth goes at once.
+    /// What's `Net::link_spliced` isn't counted. Zero is rejected by `build`.
+    pub fn max_egress_bps(mut self, bps: u64) -> NetBuilder {
+        self.config.max_egress_bps = Some(bps);
+        self
+    }
+}
+
+pub(crate) fn check(setting: &'static str, bps: Option<u64>) -> Result<Option<u64>, Error> {
+    match bps {
+        Some(0) => Err(Error::invalid(setting, 0, "must be non-zero")),
+        bps => Ok(bps),
+    }
+}
+
+impl Bucket {
+    pub(crate) fn new(rate: u64, now: Instant) -> Bucket {
+        let burst = (rate / 10).max(1);
+        Bucket {
+            rate,
+            burst,
+            available: burst,
+            last: now,
+        }
+    }
+
+    pub(crate) fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last);
+        let earned = elapsed.as_nanos() * u128::from(self.rate) / NANOS;
+        if 0 == earned {
+            return;
+        }
+        let available = u128::from(self.available) + earned;
+        if available >= u128::from(self.burst) {
+            self.available = self.burst;
+            self.last = now;
+        } else {
+            self.available = available as u64;
+            let spent = earned * NANOS / u128::from(self.rate);
+            self.last += Duration::from_nanos(spent as u64);
+        }
+    }
+
+    pub(crate) fn available(&self) -> usize {
+        self.available.min(usize::MAX as u64) as usize
+    }
+
+    pub(crate) fn take(&mut self, bytes: usize) {
+        self.available = self.available.saturating_sub(bytes as u64);
+    }
+
+    /// When it's worth coming back, having run dry: once there's a hundredth of a second's
+    /// worth, so a slow rate isn't paid out a byte at a time.
+    pub(crate) fn ready_at(&self) -> Instant {
+        let bytes = u128::from((self.rate / 100).max(1));
+        let wait = (bytes * NANOS).div_ceil(u128::from(self.rate));
+        self.last + Duration::from_nanos(wait as u64)
+    }
+}
+
+impl Conn {
+    /// Top up the buckets; before reading or writing, or when `throttle_deadline` comes.
+    pub(crate) fn refill_buckets(&mut self, now: Instant) {
+        let read = self.read_buffer.bucket.iter_mut();
+        for bucket in read.chain(self.write_buffer.bucket.iter_mut()) {
+            bucket.refill(now);
+        }
+    }
+
+    /// After reading and writing: when to top up the buckets, if either has run dry; `None`
+    /// if neither has, or it's already arranged.
+    pub(crate) fn throttled_until(&mut self) -> Option<(Instant, Expiry)> {
+        let at = [&self.read_buffer.bucket, &self.write_buffer.bucket]
+            .iter()
+            .filter_map(|bucket| bucket.as_ref())
+            .filter(|bucket| 0 == bucket.available)
+            .map(|bucket| bucket.ready_at())
+            .min()?;
+        if self.throttle_deadline.is_some_and(|set| set <= at) {
+            return None;
+        }
+        self.throttle_deadline = Some(at);
+        Some((at, Expiry::Throttle))
+    }
+}
+
+impl<T> Net<T> {
+    /// How much each connection may write in this pass, as its part of `max_egress_bps`.
+    pub(crate) fn egress_share(&self) -> Option<usize> {
+        let available = self.egress.as_ref()?.available();
+        if 0 == available {
+            return Some(0);
+        }
+        Some((available / self.writers.max(1)).max(1))
+    }
+
+    /// Count what's been written against `max_egress_bps`; once it's all gone, nothing is
+    /// written until it's topped up.
+    pub(crate) fn spend_egress(&mut self, bytes: u64) {
+        let egress = match &mut self.egress {
+            Some(egress) => egress,
+            None => return,
+        };
+        egress.take(bytes.min(usize::MAX as u64) as usize);
+        if 0 != egress.available() || self.egress_blocked {
+            return;
+        }
+        self.egress_blocked = true;
+        self.deadlines
+            .push(egress.ready_at(), COMMANDS_TOKEN, Expiry::Egress);
+    }
+
+    pub(crate) fn resume_egress(&mut self, now: Instant) {
+        if let Some(egress) = &mut self.egress {
+            egress.refill(now);
+        }
+        // writing is re-armed by `reregister`
+        self.egress_blocked = false;
+    }
+}
@@ -0,0 +1,57 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::time::Duration;
+use std::time::Instant;
+
+use mio::Token;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Expiry {
+    FirstByte,
+    Establish,
+    PoolIdle,
+    Drain,
+    /// Not tied to a connection.
+    TcpInfoSample,
+    /// `Net::timer`.
+    User,
+    Idle,
+    /// For a listener, stopped by `ListenOptions::max_accepts_per_sec`.
+    AcceptRate,
+    Throttle,
+    /// Not tied to a connection: `NetBuilder::max_egress_bps` can be topped up.
+    Egress,
+    /// For a `Net::spawn`ed child, where there's nothing to say when it's exited.
+    Reap,
+}
+
+/// Deadlines are never removed early; whoever armed one checks it's still wanted when it fires.
+#[derive(Default)]
+pub(crate) struct Deadlines {
+    heap: BinaryHeap<Reverse<(Instant, usize, Expiry)>>,
+}
+
+impl Deadlines {
+    pub(crate) fn push(&mut self, at: Instant, token: Token, expiry: Expiry) {
+        self.heap.push(Reverse((at, token.0, expiry)));
+    }
+
+    pub(crate) fn timeout(&self, now: Instant) -> Option<Duration> {
+        self.heap.peek().map(|Reverse((at, _, _))| {
+            if *at > now {
+                *at - now
+            } else {
+                Duration::from_secs(0)
+            }
+        })
+    }
+
+    pub(crate) fn pop_due(&mut self, now: Instant) -> Option<(Instant, Token, Expiry)> {
+        match self.heap.peek() {
+            Some(Reverse((at, _, _))) if *at <= now => (),
+            _ => return None,
+        }
+        let Reverse((at, token, expiry)) = self.heap.pop().expect("just peeked");
+        Some((at, Token(token), expiry))
+    }
+}
@@ -0,0 +1,231 @@
+use mio::Token;
+use slab::Slab;
+
+use crate::Error;
+use crate::Owned;
+use crate::OwnedMode;
+
+/// A connection: anything `Net::io` works with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnToken(pub(crate) Token);
+
+/// A UDP socket, from `Net::udp_bind`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UdpToken(pub(crate) Token);
+
+/// A listener, from `Net::tcp_listen`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ListenerToken(pub(crate) Token);
+
+/// A timer, from `Net::timer`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TimerToken(pub(crate) Token);
+
+/// A child process, from `Net::spawn`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChildToken(pub(crate) Token);
+
+/// Something else being watched, from `Net::register_source`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SourceToken(pub(crate) Token);
+
+impl ConnToken {
+    /// The untyped token, as used by `Error` and `Event::token`.
+    pub fn token(self) -> Token {
+        self.0
+    }
+}
+
+impl UdpToken {
+    pub fn token(self) -> Token {
+        self.0
+    }
+}
+
+impl ListenerToken {
+    pub fn token(self) -> Token {
+        self.0
+    }
+}
+
+impl TimerToken {
+    pub fn token(self) -> Token {
+        self.0
+    }
+}
+
+impl ChildToken {
+    pub fn token(self) -> Token {
+        self.0
+    }
+}
+
+impl SourceToken {
+    pub fn token(self) -> Token {
+        self.0
+    }
+}
+
+/// Everything registered, indexed by token. A slot is reserved when its token is handed out,
+/// filled once the socket is registered, and only freed (for reuse) once nothing can still
+/// refer to it; `None` is a slot which is reserved but empty, e.g. a timer, or a connection
+/// whose `Event::Closed` hasn't been delivered yet.
+///
+/// The top half of a token is the slot's generation, which changes every time the slot is
+/// freed, so a token kept after its `Event::Closed` names nothing, rather than whatever is
+/// using the slot now.
+#[derive(Default)]
+pub(crate) struct Tokens {
+    slab: Slab<Option<Owned>>,
+    /// Indexed by slot; only ever grows, as the slab reuses slots.
+    generations: Vec<usize>,
+    /// Filled slots which are connections.
+    conns: usize,
+}
+
+const INDEX_BITS: u32 = usize::BITS / 2;
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+
+/// The most slots; the index half of a slot's token is never all ones, which is left for
+/// timers.
+pub(crate) const MAX_SLOTS: usize = INDEX_MASK - 1;
+
+/// `Token(0)` is the command channel, so slot `n` is index `n + 1`.
+fn token(key: usize, generation: usize) -> Token {
+    Token((generation << INDEX_BITS) | (key + 1))
+}
+
+/// Hands out timers' tokens. Their index half is all ones, so they never name a slot, and the
+/// other half counts up, so one isn't reused until `MAX_TOKENS` more timers have been made.
+#[derive(Copy, Clone, Default)]
+pub(crate) struct TimerTokens {
+    made: usize,
+}
+
+impl TimerTokens {
+    pub(crate) fn next(&mut self) -> Token {
+        let token = Token((self.made << INDEX_BITS) | INDEX_MASK);
+        // all ones in both halves would be `Token(usize::MAX)`, which mio keeps for itself
+        self.made = (self.made + 1) % INDEX_MASK;
+        token
+    }
+}
+
+impl Tokens {
+    /// A slot, unless they're all taken.
+    pub(crate) fn reserve(&mut self) -> Result<Token, Error> {
+        if self.exhausted() {
+            return Err(Error::NoTokens);
+        }
+        let key = self.slab.insert(None);
+        if key == self.generations.len() {
+            self.generations.push(0);
+        }
+        Ok(token(key, self.generations[key]))
+    }
+
+    /// Every slot is taken, so `reserve` fails until one is released.
+    pub(crate) fn exhausted(&self) -> bool {
+        self.slab.len() >= MAX_SLOTS
+    }
+
+    /// The slot for `token`, if it's from the slot's current generation.
+    fn key(&self, token: Token) -> Option<usize> {
+        let key = (token.0 & INDEX_MASK).checked_sub(1)?;
+        if Some(&(token.0 >> INDEX_BITS)) != self.generations.get(key) {
+            return None;
+        }
+        Some(key)
+    }
+
+    /// Fill a slot from `reserve`.
+    pub(crate) fn insert(&mut self, token: Token, owned: Owned) {
+        let slot = self
+            .key(token)
+            .and_then(|key| self.slab.get_mut(key))
+            .expect("inserting an unreserved token");
+        debug_assert!(slot.is_none(), "{} inserted twice", token.0);
+        *slot = Some(owned);
+        if let Some(Owned {
+            mode: OwnedMode::Conn(_),
+            ..
+        }) = slot
+        {
+            self.conns += 1;
+        }
+    }
+
+    /// Empty the slot, leaving it reserved until `release`.
+    pub(crate) fn remove(&mut self, token: &Token) -> Option<Owned> {
+        let owned = self.slot_mut(*token)?.take()?;
+        if let OwnedMode::Conn(_) = owned.mode {
+            self.conns -= 1;
+        }
+        Some(owned)
+    }
+
+    /// How many connections there are.
+    pub(crate) fn conns(&self) -> usize {
+        self.conns
+    }
+
+    /// Let the slot be reused, under a new token. Slots which are still filled are left alone.
+    pub(crate) fn release(&mut self, token: Token) {
+        if let Some(key) = self.key(token) {
+            if let Some(None) = self.slab.get(key) {
+                self.slab.remove(key);
+                let generation = &mut self.generations[key];
+                *generation = generation.wrapping_add(1) & INDEX_MASK;
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, token: &Token) -> Option<&Owned> {
+        self.slab.get(self.key(*token)?)?.as_ref()
+    }
+
+    pub(crate) fn get_mut(&mut self, token: &Token) -> Option<&mut Owned> {
+        self.slot_mut(*token)?.as_mut()
+    }
+
+    /// Two different filled slots at once.
+    pub(crate) fn get2_mut(&mut self, a: &Token, b: &Token) -> Option<(&mut Owned, &mut Owned)> {
+        let (a, b) = (self.key(*a)?, self.key(*b)?);
+        match self.slab.get2_mut(a, b)? {
+            (Some(a), Some(b)) => Some((a, b)),
+            _ => None,
+        }
+    }
+
+    fn slot_mut(&mut self, token: Token) -> Option<&mut Option<Owned>> {
+        let key = self.key(token)?;
+        self.slab.get_mut(key)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (Token, &Owned)> {
+        let generations = &self.generations;
+        self.slab
+            .iter()
+            .filter_map(move |(key, slot)| Some((token(key, generations[key]), slot.as_ref()?)))
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = (Token, &mut Owned)> {
+        let generations = &self.generations;
+        self.slab
+            .iter_mut()
+            .filter_map(move |(key, slot)| Some((token(key, generations[key]), slot.as_mut()?)))
+    }
+
+    pub(crate) fn values_mut(&mut self) -> impl Iterator<Item = &mut Owned> {
+        self.iter_mut().map(|(_, owned)| owned)
+    }
+
+    /// Empty everything out, forgetting every reservation.
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = (Token, Owned)> + '_ {
+        self.conns = 0;
+        self.slab
+            .drain()
+            .flatten()
+            .map(|owned| (owned.token, owned))
+    }
+}
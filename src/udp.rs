@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+
+use log::info;
+use mio::net::UdpSocket;
+use mio::Token;
+
+use crate::reactor::Interest;
+use crate::reactor::Source;
+use crate::Direction;
+use crate::Error;
+use crate::Net;
+use crate::Operation;
+use crate::Owned;
+use crate::OwnedMode;
+use crate::UdpToken;
+
+/// Reading stops while this many datagrams are waiting for `recv_from`.
+const MAX_QUEUED_DATAGRAMS: usize = 256;
+
+/// Big enough for anything IPv4 or (non-jumbogram) IPv6 can carry.
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+pub(crate) struct Udp {
+    pub(crate) inner: UdpSocket,
+    incoming: VecDeque<(SocketAddr, Vec<u8>)>,
+    outgoing: VecDeque<(SocketAddr, Vec<u8>)>,
+    pub(crate) error: Option<io::Error>,
+    pub(crate) closing: bool,
+    /// As for `Conn::registered`.
+    pub(crate) registered: Option<Interest>,
+}
+
+impl Udp {
+    pub(crate) fn buffered(&self) -> usize {
+        self.incoming
+            .iter()
+            .chain(self.outgoing.iter())
+            .map(|(_, data)| data.len())
+            .sum()
+    }
+
+    pub(crate) fn read_interest(&self) -> bool {
+        !self.closing && self.incoming.len() < MAX_QUEUED_DATAGRAMS
+    }
+
+    pub(crate) fn write_interest(&self) -> bool {
+        !self.outgoing.is_empty()
+    }
+
+    pub(crate) fn has_incoming(&self) -> bool {
+        !self.incoming.is_empty()
+    }
+
+    /// Everything queued has been sent, or given up on.
+    pub(crate) fn finished(&self) -> bool {
+        self.closing && self.outgoing.is_empty()
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        format!(
+            "udp {:?}, {} in, {} out",
+            self.inner.local_addr(),
+            self.incoming.len(),
+            self.outgoing.len()
+        )
+    }
+
+    /// Like `shunt_io`, but a datagram at a time. Returns whether anything happened.
+    pub(crate) fn shunt(&mut self, token: Token, read: bool) -> bool {
+        let mut progress = false;
+
+        let mut buf = [0u8; MAX_DATAGRAM];
+        while read && self.read_interest() {
+            match self.inner.recv_from(&mut buf) {
+                Ok((len, from)) => {
+                    progress = true;
+                    self.incoming.push_back((from, buf[..len].to_vec()));
+                }
+                Err(ref e) if io::ErrorKind::WouldBlock == e.kind() => break,
+                Err(e) => {
+                    // e.g. an ICMP unreachable for something we sent earlier; the socket is fine
+                    info!("{} recv-err {:?}", token.0, e);
+                    progress = true;
+                    self.error.get_or_insert(e);
+                }
+            }
+        }
+
+        while let Some((to, data)) = self.outgoing.front() {
+            match self.inner.send_to(data, to) {
+                Ok(_) => {
+                    progress = true;
+                    self.outgoing.pop_front();
+                }
+                Err(ref e) if io::ErrorKind::WouldBlock == e.kind() => break,
+                Err(e) => {
+                    info!("{} send-err {:?}", token.0, e);
+                    progress = true;
+                    self.outgoing.pop_front();
+                    self.error.get_or_insert(e);
+                }
+            }
+        }
+
+        progress
+    }
+}
+
+impl<T> Net<T> {
+    /// Bind a UDP socket. `Event::Datagram` reports when there's something for
+    /// `UdpIo::recv_from`.
+    pub fn udp_bind(&mut self, addr: &SocketAddr) -> Result<UdpToken, Error> {
+        let inner =
+            UdpSocket::bind(addr).map_err(|e| Error::new(Operation::Bind, e).with_addr(*addr))?;
+        let token = self.tokens.reserve()?;
+        if let Err(e) = self
+            .reactor
+            .register(Source::Udp(&inner), token, Interest::READABLE)
+        {
+            self.release(token);
+            return Err(Error::new(Operation::Register, e)
+                .with_token(token)
+                .with_addr(*addr));
+        }
+        self.tokens.insert(
+            token,
+            Owned {
+                token,
+                mode: OwnedMode::Udp(Udp {
+                    inner,
+                    incoming: VecDeque::new(),
+                    outgoing: VecDeque::new(),
+                    error: None,
+                    closing: false,
+                    registered: Some(Interest::READABLE),
+                }),
+            },
+        );
+        Ok(UdpToken(token))
+    }
+
+    /// Work with a UDP socket from `udp_bind`.
+    pub fn udp(&mut self, token: UdpToken) -> Result<UdpIo<'_, T>, Error> {
+        let token = token.0;
+        debug_assert!(!self.in_handler, "Net::udp called from inside with_io");
+        match self.tokens.get(&token).map(|o| &o.mode) {
+            Some(OwnedMode::Udp(_)) => Ok(UdpIo { inner: self, token }),
+            _ => Err(Error::UnknownToken(token)),
+        }
+    }
+}
+
+/// A UDP socket, from `Net::udp`.
+pub struct UdpIo<'n, T = ()> {
+    pub(crate) inner: &'n mut Net<T>,
+    pub(crate) token: Token,
+}
+
+impl<T> UdpIo<'_, T> {
+    fn as_udp(&self) -> &Udp {
+        match self.inner.tokens.get(&self.token).map(|o| &o.mode) {
+            Some(OwnedMode::Udp(udp)) => udp,
+            _ => unreachable!("checked by Net::udp"),
+        }
+    }
+
+    fn as_udp_mut(&mut self) -> &mut Udp {
+        match self.inner.tokens.get_mut(&self.token).map(|o| &mut o.mode) {
+            Some(OwnedMode::Udp(udp)) => udp,
+            _ => unreachable!("checked by Net::udp"),
+        }
+    }
+
+    pub fn token(&self) -> UdpToken {
+        UdpToken(self.token)
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.as_udp()
+            .inner
+            .local_addr()
+            .map_err(|e| Error::new(Operation::SocketQuery, e).with_token(self.token))
+    }
+
+    /// The oldest datagram which has arrived, and who sent it.
+    pub fn recv_from(&mut self) -> Option<(SocketAddr, Vec<u8>)> {
+        let received = self.as_udp_mut().incoming.pop_front();
+        if let Some((_, data)) = &received {
+            self.inner.buffered -= data.len();
+        }
+        received
+    }
+
+    /// Queue `data` to go out as a single datagram. Failures to send are reported as
+    /// `Event::UdpError`, without affecting the socket.
+    pub fn send_to(&mut self, data: &[u8], to: &SocketAddr) -> Result<(), Error> {
+        if data.len() > MAX_DATAGRAM {
+            return Err(Error::invalid("datagram length", data.len(), "too large"));
+        }
+        let token = self.token;
+        let udp = self.as_udp_mut();
+        if udp.closing {
+            return Err(Error::Closed(token, Direction::Write));
+        }
+        udp.outgoing.push_back((*to, data.to_vec()));
+        self.inner.buffered += data.len();
+        Ok(())
+    }
+
+    /// Stop receiving, and remove the socket once everything queued has been sent, with an
+    /// `Event::UdpClosed`. Unread datagrams are dropped.
+    pub fn close(&mut self) {
+        let udp = self.as_udp_mut();
+        udp.closing = true;
+        let dropped: usize = udp.incoming.drain(..).map(|(_, data)| data.len()).sum();
+        self.inner.buffered -= dropped;
+    }
+}
@@ -0,0 +1,418 @@
+//! The server's side of WebSockets (RFC 6455): answer the upgrade request, then exchange whole
+//! messages. Pings are answered, fragments are reassembled and a `Close` is echoed, all inside
+//! the event loop; the application sees `Event::WsMessage` once there's something for `recv`.
+
+use std::convert::TryFrom;
+use std::io;
+
+use crate::buffer::Buffer;
+use crate::config;
+use crate::http1::Request;
+use crate::Conn;
+use crate::Direction;
+use crate::Error;
+use crate::Io;
+use crate::Operation;
+use crate::MAX_BUFFER_LIMIT;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The longest a frame header can be, for a masked frame with a 64-bit length.
+const MAX_HEADER: usize = 14;
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// Received pings are answered automatically, and aren't passed on.
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The status code and reason, if there were any. Receiving one sends one back, then
+    /// closes our side; sending one means nothing else can be sent.
+    Close(Option<(u16, String)>),
+}
+
+/// Per-connection state, once the handshake is done.
+pub(crate) struct Session {
+    max_message: usize,
+    /// The opcode of a fragmented message, and the fragments which have arrived.
+    partial: Option<(u8, Vec<u8>)>,
+    /// Nothing more is decoded until this is taken, so what's waiting stays in the read
+    /// buffer, where it's counted, and holds back reading.
+    message: Option<Message>,
+    sent_close: bool,
+    received_close: bool,
+}
+
+/// The `Sec-WebSocket-Accept` to answer `request` with, if it's a WebSocket handshake.
+pub fn accept_key(request: &Request<'_>) -> Option<String> {
+    let upgrade = request.header("upgrade")?;
+    let connection = request.header("connection")?;
+    if "GET" != request.method
+        || request.minor_version < 1
+        || !upgrade.eq_ignore_ascii_case(b"websocket")
+        || !connection
+            .split(|&b| b == b',')
+            .any(|v| trim(v).eq_ignore_ascii_case(b"upgrade"))
+        || Some(&b"13"[..]) != request.header("sec-websocket-version")
+    {
+        return None;
+    }
+    let key = request.header("sec-websocket-key")?;
+    let mut input = key.to_vec();
+    input.extend_from_slice(GUID.as_bytes());
+    Some(base64(&sha1(&input)))
+}
+
+/// Finish the handshake with the `key` from `accept_key`, once the request has been consumed.
+/// From now on `Event::WsMessage` replaces `Event::Data`. Messages longer than `max_message`
+/// are refused: a peer which sends one is reset. Must be between one and `MAX_BUFFER_LIMIT`.
+pub fn accept<T>(io: &mut Io<'_, T>, key: &str, max_message: usize) -> Result<(), Error> {
+    let max_message = config::check_limit("max_message", max_message, MAX_BUFFER_LIMIT)?;
+    io.write(
+        format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            key
+        )
+        .as_bytes(),
+    )?;
+    let conn = io.as_conn_mut();
+    conn.read_buffer.raise_wanted(MAX_HEADER + max_message);
+    conn.ws = Some(Session {
+        max_message,
+        partial: None,
+        message: None,
+        sent_close: false,
+        received_close: false,
+    });
+    Ok(())
+}
+
+/// The next whole message, if there is one. Fails if `accept` hasn't been called, or if the
+/// peer isn't speaking the protocol, which the loop resets the connection for.
+pub fn recv<T>(io: &mut Io<'_, T>) -> Result<Option<Message>, Error> {
+    let token = io.token;
+    let peer = io.peer_addr();
+    io.with_conn(|conn| {
+        match conn.ws.as_mut().map(|session| session.message.take()) {
+            Some(Some(message)) => return Ok(Some(message)),
+            Some(None) => (),
+            None => return Err(Error::invalid("websocket", token, "not accepted")),
+        }
+        conn.ws_process().map_err(|e| {
+            Error::new(Operation::Read, e)
+                .with_token(token)
+                .with_peer(Some(peer))
+        })?;
+        Ok(conn.ws.as_mut().and_then(|session| session.message.take()))
+    })
+}
+
+/// Queue `message`, as a single frame. Fails once a `Close` has been sent.
+pub fn send<T>(io: &mut Io<'_, T>, message: &Message) -> Result<(), Error> {
+    let token = io.token;
+    match &mut io.as_conn_mut().ws {
+        Some(session) if session.sent_close => return Err(Error::Closed(token, Direction::Write)),
+        Some(session) => session.sent_close = matches!(message, Message::Close(_)),
+        None => return Err(Error::invalid("websocket", token, "not accepted")),
+    }
+    let (opcode, payload) = encode(message);
+    io.write_vec(frame(opcode, &payload))?;
+    let conn = io.as_conn_mut();
+    if conn.ws.as_ref().is_some_and(|s| s.received_close) {
+        conn.read_buffer.become_at_least_truncating_close();
+        conn.write_buffer.become_at_least_draining_close();
+    }
+    Ok(())
+}
+
+impl Conn {
+    /// Decode what's arrived until there's a message, answering anything which needs answering.
+    /// Returns whether there's a message waiting. Fails if the peer isn't speaking the protocol,
+    /// leaving what it sent to fail again.
+    pub(crate) fn ws_process(&mut self) -> io::Result<bool> {
+        let Conn {
+            ws,
+            read_buffer,
+            write_buffer,
+            registered,
+            ..
+        } = self;
+        let session = match ws {
+            Some(session) => session,
+            None => return Ok(false),
+        };
+        while !session.received_close && session.message.is_none() {
+            let read = match read_buffer.buf_mut() {
+                Some(read) => read,
+                None => break,
+            };
+            let (opcode, payload, len) = match session.parse(read.as_slice())? {
+                Some(frame) => frame,
+                None => break,
+            };
+            let reply = session.receive(opcode, payload)?;
+            read.consume(len);
+            if let Some((opcode, payload)) = reply {
+                if let (false, Some(write)) = (session.sent_close, write_buffer.buf_mut()) {
+                    // the kernel may have said it's writable already, and won't say so again
+                    if write.is_empty() {
+                        *registered = None;
+                    }
+                    write.push(frame(opcode, &payload));
+                    if CLOSE == opcode {
+                        session.sent_close = true;
+                    }
+                }
+            }
+            // the close handshake is done, so the connection can go, once our `Close` is sent
+            if session.received_close && session.sent_close {
+                read_buffer.become_at_least_truncating_close();
+                write_buffer.become_at_least_draining_close();
+            }
+        }
+        Ok(session.message.is_some())
+    }
+}
+
+impl Session {
+    /// The first frame in `buf`, unmasked, and how long it was, once it's all arrived.
+    fn parse(&self, buf: &[u8]) -> io::Result<Option<(u8, Vec<u8>, usize)>> {
+        let (b0, b1) = match buf {
+            [b0, b1, ..] => (*b0, *b1),
+            _ => return Ok(None),
+        };
+        let fin = 0 != b0 & 0x80;
+        let opcode = b0 & 0x0f;
+        if 0 != b0 & 0x70 {
+            return Err(invalid("reserved bits set"));
+        }
+        if 0 == b1 & 0x80 {
+            return Err(invalid("unmasked frame from a client"));
+        }
+        let (len, mut at) = match b1 & 0x7f {
+            126 => match buf.get(2..4) {
+                Some(len) => (u64::from(u16::from_be_bytes(array(len))), 4),
+                None => return Ok(None),
+            },
+            127 => match buf.get(2..10) {
+                Some(len) => (u64::from_be_bytes(array(len)), 10),
+                None => return Ok(None),
+            },
+            len => (u64::from(len), 2),
+        };
+        if 0 != opcode & 0x8 && (!fin || len > 125) {
+            return Err(invalid("fragmented or oversized control frame"));
+        }
+        let len = match usize::try_from(len) {
+            Ok(len) if len <= self.max_message => len,
+            _ => return Err(invalid("frame longer than max_message")),
+        };
+        let mask: [u8; 4] = match buf.get(at..at + 4) {
+            Some(mask) => array(mask),
+            None => return Ok(None),
+        };
+        at += 4;
+        let mut payload = match buf.get(at..at + len) {
+            Some(payload) => payload.to_vec(),
+            None => return Ok(None),
+        };
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+        let opcode = if fin { opcode | 0x80 } else { opcode };
+        Ok(Some((opcode, payload, at + len)))
+    }
+
+    /// Deal with a frame, as returned by `parse`, returning any reply which should be sent.
+    fn receive(&mut self, opcode: u8, payload: Vec<u8>) -> io::Result<Option<(u8, Vec<u8>)>> {
+        let fin = 0 != opcode & 0x80;
+        match opcode & 0x0f {
+            PING => return Ok(Some((PONG, payload))),
+            PONG => self.message = Some(Message::Pong(payload)),
+            CLOSE => {
+                let close = match payload.len() {
+                    0 => None,
+                    1 => return Err(invalid("truncated close status")),
+                    _ => {
+                        let reason = String::from_utf8(payload[2..].to_vec())
+                            .map_err(|_| invalid("close reason isn't utf-8"))?;
+                        Some((u16::from_be_bytes(array(&payload[..2])), reason))
+                    }
+                };
+                self.received_close = true;
+                self.message = Some(Message::Close(close));
+                return Ok(Some((CLOSE, payload[..payload.len().min(2)].to_vec())));
+            }
+            TEXT | BINARY if self.partial.is_some() => {
+                return Err(invalid("new message before the last one finished"))
+            }
+            opcode @ TEXT | opcode @ BINARY if !fin => self.partial = Some((opcode, payload)),
+            opcode @ TEXT | opcode @ BINARY => self.complete(opcode, payload)?,
+            CONTINUATION => {
+                let (opcode, mut so_far) = self
+                    .partial
+                    .take()
+                    .ok_or_else(|| invalid("continuation of nothing"))?;
+                if so_far.len() + payload.len() > self.max_message {
+                    return Err(invalid("message longer than max_message"));
+                }
+                so_far.extend_from_slice(&payload);
+                if fin {
+                    self.complete(opcode, so_far)?;
+                } else {
+                    self.partial = Some((opcode, so_far));
+                }
+            }
+            _ => return Err(invalid("unknown opcode")),
+        }
+        Ok(None)
+    }
+
+    fn complete(&mut self, opcode: u8, payload: Vec<u8>) -> io::Result<()> {
+        self.message = Some(match opcode {
+            TEXT => Message::Text(
+                String::from_utf8(payload).map_err(|_| invalid("text message isn't utf-8"))?,
+            ),
+            _ => Message::Binary(payload),
+        });
+        Ok(())
+    }
+}
+
+fn encode(message: &Message) -> (u8, Vec<u8>) {
+    match message {
+        Message::Text(text) => (TEXT, text.as_bytes().to_vec()),
+        Message::Binary(data) => (BINARY, data.clone()),
+        Message::Ping(data) => (PING, data.clone()),
+        Message::Pong(data) => (PONG, data.clone()),
+        Message::Close(None) => (CLOSE, Vec::new()),
+        Message::Close(Some((code, reason))) => {
+            let mut payload = code.to_be_bytes().to_vec();
+            payload.extend_from_slice(reason.as_bytes());
+            (CLOSE, payload)
+        }
+    }
+}
+
+/// An unmasked, unfragmented frame, as a server sends.
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(MAX_HEADER + payload.len());
+    out.push(0x80 | opcode);
+    let len = payload.len();
+    match len {
+        0..=125 => out.push(len as u8),
+        126..=0xffff => {
+            out.push(126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            out.push(127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(payload);
+    out
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn array<const N: usize>(s: &[u8]) -> [u8; N] {
+    <[u8; N]>::try_from(s).expect("sliced to length")
+}
+
+fn trim(mut s: &[u8]) -> &[u8] {
+    while let [b' ' | b'\t', rest @ ..] = s {
+        s = rest;
+    }
+    while let [rest @ .., b' ' | b'\t'] = s {
+        s = rest;
+    }
+    s
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [
+        0x6745_2301,
+        0xEFCD_AB89,
+        0x98BA_DCFE,
+        0x1032_5476,
+        0xC3D2_E1F0,
+    ];
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while 56 != padded.len() % 64 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in padded.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes(array(&block[i * 4..i * 4 + 4]));
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, w) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e].iter()) {
+            *h = h.wrapping_add(*v);
+        }
+    }
+
+    let mut out = [0u8; 20];
+    for (out, h) in out.chunks_mut(4).zip(h.iter()) {
+        out.copy_from_slice(&h.to_be_bytes());
+    }
+    out
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            out.push(if i <= chunk.len() {
+                char::from(ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f])
+            } else {
+                '='
+            });
+        }
+    }
+    out
+}
@@ -0,0 +1,204 @@
+mod common;
+
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::time::Duration;
+use std::time::Instant;
+
+use common::next;
+use common::wait_for;
+use common::WAIT;
+use nofio::ConnToken;
+use nofio::Event;
+use nofio::ListenOptions;
+use nofio::Net;
+use nofio::NetStats;
+
+fn listen(net: &mut Net, options: &ListenOptions) -> SocketAddr {
+    net.tcp_listen_all(&["127.0.0.1:0".parse().unwrap()], options)
+        .unwrap();
+    match next(net) {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    }
+}
+
+fn new_connection(net: &mut Net) -> ConnToken {
+    match wait_for(net, |ev| matches!(ev, Event::NewConnection { .. })) {
+        Event::NewConnection { token, .. } => token,
+        _ => unreachable!(),
+    }
+}
+
+/// Everything which happens in the next little while.
+fn quietly(net: &mut Net) -> Vec<Event> {
+    let mut events = Vec::new();
+    while let Some(ev) = net.next_timeout(Duration::from_millis(100)).unwrap() {
+        events.push(ev);
+    }
+    events
+}
+
+/// Whether the connection was reset by the other end, without a word.
+fn refused(mut client: TcpStream) -> bool {
+    client.set_read_timeout(Some(WAIT)).unwrap();
+    match client.read(&mut [0; 1]) {
+        Ok(0) => true,
+        Err(e) => io::ErrorKind::ConnectionReset == e.kind(),
+        Ok(_) => false,
+    }
+}
+
+#[test]
+fn accepting_stops_at_capacity_and_resumes_below_it() {
+    let mut net = Net::builder().max_connections(1).build().unwrap();
+    let addr = listen(&mut net, &ListenOptions::default());
+    let first = TcpStream::connect(addr).unwrap();
+    let token = new_connection(&mut net);
+    assert!(matches!(
+        next(&mut net),
+        Event::AtCapacity { connections: 1 }
+    ));
+
+    let _second = TcpStream::connect(addr).unwrap();
+    let waiting = quietly(&mut net);
+    assert!(
+        !waiting
+            .iter()
+            .any(|ev| matches!(ev, Event::NewConnection { .. })),
+        "{:?}",
+        waiting
+    );
+
+    drop(first);
+    net.io(token).unwrap().close();
+    wait_for(&mut net, |ev| matches!(ev, Event::Closed(t) if *t == token));
+    wait_for(&mut net, |ev| {
+        matches!(ev, Event::BelowCapacity { connections: 0 })
+    });
+    new_connection(&mut net);
+}
+
+#[test]
+fn one_address_gets_only_so_many_connections() {
+    let mut net = Net::builder().max_connections_per_ip(1).build().unwrap();
+    let addr = listen(&mut net, &ListenOptions::default());
+    let _first = TcpStream::connect(addr).unwrap();
+    new_connection(&mut net);
+    let second = TcpStream::connect(addr).unwrap();
+    let seen = quietly(&mut net);
+    assert!(
+        !seen
+            .iter()
+            .any(|ev| matches!(ev, Event::NewConnection { .. })),
+        "{:?}",
+        seen
+    );
+    assert!(refused(second));
+    let NetStats {
+        accepted, rejected, ..
+    } = net.stats();
+    assert_eq!((1, 1), (accepted, rejected));
+}
+
+#[test]
+fn accepts_beyond_the_rate_wait_for_the_next_second() {
+    let mut net = Net::empty().unwrap();
+    let addr = listen(&mut net, &ListenOptions::default().max_accepts_per_sec(1));
+    let _first = TcpStream::connect(addr).unwrap();
+    let _second = TcpStream::connect(addr).unwrap();
+    new_connection(&mut net);
+    let first_at = Instant::now();
+    assert_eq!(1, net.stats().deferred, "stopped after the first");
+    new_connection(&mut net);
+    assert!(first_at.elapsed() > Duration::from_millis(500));
+    assert_eq!(2, net.stats().deferred, "and again after the second");
+}
+
+#[test]
+fn only_allowed_addresses_get_in() {
+    let mut net = Net::empty().unwrap();
+    let elsewhere = ListenOptions::default().allow("10.0.0.0/8".parse().unwrap());
+    let blocked = listen(&mut net, &elsewhere);
+    let local = "127.0.0.0/8".parse().unwrap();
+    let allowed = listen(&mut net, &elsewhere.clone().allow(local));
+    let denied = listen(&mut net, &ListenOptions::default().allow(local).deny(local));
+
+    let first = TcpStream::connect(blocked).unwrap();
+    let _second = TcpStream::connect(allowed).unwrap();
+    let third = TcpStream::connect(denied).unwrap();
+    let seen = quietly(&mut net);
+    assert_eq!(
+        1,
+        seen.iter()
+            .filter(|ev| matches!(ev, Event::NewConnection { .. }))
+            .count(),
+        "{:?}",
+        seen
+    );
+    assert!(refused(first) && refused(third));
+    assert_eq!(2, net.stats().blocked);
+}
+
+/// Everything which happens over a little longer than the timeouts below, with `meanwhile`
+/// doing whatever it does between events, and data taken as it comes.
+fn for_a_while(net: &mut Net, mut meanwhile: impl FnMut()) -> Vec<Event> {
+    let mut events = Vec::new();
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_millis(400) {
+        meanwhile();
+        if let Some(ev) = net.next_timeout(Duration::from_millis(10)).unwrap() {
+            if let Event::Data(token) = ev {
+                net.io(token).unwrap().consume_all().unwrap();
+            }
+            events.push(ev);
+        }
+    }
+    events
+}
+
+fn timed_out(events: &[Event], token: ConnToken) -> bool {
+    events.iter().any(
+        |ev| matches!(ev, Event::Error(t, e) if *t == token && io::ErrorKind::TimedOut == e.kind()),
+    )
+}
+
+#[test]
+fn a_slow_first_byte_is_timed_out() {
+    let mut net = Net::empty().unwrap();
+    let options = ListenOptions::default().first_byte_timeout(Duration::from_millis(200));
+    let addr = listen(&mut net, &options);
+    let _quiet = TcpStream::connect(addr).unwrap();
+    let quiet = new_connection(&mut net);
+    let mut prompt = TcpStream::connect(addr).unwrap();
+    prompt.write_all(b"x").unwrap();
+    let prompt = new_connection(&mut net);
+
+    let seen = for_a_while(&mut net, || ());
+    assert!(timed_out(&seen, quiet), "{:?}", seen);
+    assert!(!timed_out(&seen, prompt), "{:?}", seen);
+    assert_eq!(1, net.metrics().first_byte_timeouts);
+}
+
+#[test]
+fn a_connection_must_be_established_in_time() {
+    let mut net = Net::empty().unwrap();
+    let options = ListenOptions::default().establish_timeout(Duration::from_millis(200));
+    let addr = listen(&mut net, &options);
+    let mut trickle = TcpStream::connect(addr).unwrap();
+    let trickling = new_connection(&mut net);
+    let _fast = TcpStream::connect(addr).unwrap();
+    let fast = new_connection(&mut net);
+    net.io(fast).unwrap().establish();
+
+    // still sending, just never enough
+    let seen = for_a_while(&mut net, || {
+        let _ = trickle.write_all(b"x");
+    });
+    assert!(timed_out(&seen, trickling), "{:?}", seen);
+    assert!(!timed_out(&seen, fast), "{:?}", seen);
+    assert_eq!(1, net.metrics().establish_timeouts);
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use common::next;
+use common::WAIT;
+use nofio::Event;
+use nofio::ListenOptions;
+use nofio::Net;
+
+#[test]
+fn a_listener_bound_elsewhere_is_accepted_on() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let bound = listener.local_addr().unwrap();
+    let mut net = Net::empty().unwrap();
+    let token = net
+        .adopt_listener(listener, &ListenOptions::default())
+        .unwrap();
+    match next(&mut net) {
+        Event::Listening { token: t, addr } => assert_eq!((token, bound), (t, addr)),
+        other => panic!("expected Listening, not {:?}", other),
+    }
+
+    let client = TcpStream::connect(bound).unwrap();
+    match next(&mut net) {
+        Event::NewConnection { listener, peer, .. } => {
+            assert_eq!((token, client.local_addr().unwrap()), (listener, peer))
+        }
+        other => panic!("expected NewConnection, not {:?}", other),
+    }
+}
+
+#[test]
+fn a_connection_made_elsewhere_keeps_what_it_was_sent() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    client.write_all(b"early").unwrap();
+
+    let mut net = Net::empty().unwrap();
+    let token = net.adopt_stream(stream).unwrap();
+    match next(&mut net) {
+        Event::Data(t) => assert_eq!(token, t),
+        other => panic!("expected Data, not {:?}", other),
+    }
+    assert_eq!(b"early", net.io(token).unwrap().buf().unwrap());
+    assert_eq!(
+        client.local_addr().unwrap(),
+        net.io(token).unwrap().peer_addr()
+    );
+
+    net.io(token).unwrap().write(b"late").unwrap();
+    net.next_timeout(Duration::from_millis(10)).unwrap();
+    client.set_read_timeout(Some(WAIT)).unwrap();
+    let mut got = [0; 4];
+    client.read_exact(&mut got).unwrap();
+    assert_eq!(b"late", &got);
+}
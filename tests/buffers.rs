@@ -0,0 +1,247 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use nofio::ConnToken;
+use nofio::Direction;
+use nofio::Event;
+use nofio::Fault;
+use nofio::Net;
+use nofio::Tap;
+
+/// Plenty of bytes which aren't all the same, so anything out of place shows.
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+/// Everything `token` reads over `rounds` of pumping, taken out as it arrives.
+fn drain(net: &mut Net, token: ConnToken, rounds: usize) -> Vec<u8> {
+    let mut got = Vec::new();
+    for _ in 0..rounds {
+        net.pump();
+        net.try_next().unwrap();
+        got.extend(net.io(token).unwrap().take_buf().unwrap());
+    }
+    got
+}
+
+/// Let what's been written arrive: it's written, moved along, then read, a round each.
+fn arrive(net: &mut Net) {
+    for _ in 0..3 {
+        net.pump();
+        net.try_next().unwrap();
+    }
+}
+
+/// Many reads' worth, each landing after the last.
+#[test]
+fn reads_arrive_whole_and_in_order() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    let sent = pattern(1024 * 1024);
+    net.io(a).unwrap().write(&sent).unwrap();
+
+    let got = drain(&mut net, b, 100);
+    assert!(sent == got, "{} of {} bytes", got.len(), sent.len());
+    assert_eq!(sent.len() as u64, net.io(b).unwrap().stats().bytes_read);
+}
+
+/// Lots of little writes, with one cut short part way through a chunk.
+#[test]
+fn queued_writes_go_out_in_order() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    let sent = pattern(100 * 1000);
+    let mut io = net.io(a).unwrap();
+    io.inject_fault_after(1500, Fault::ShortWrite(3));
+    for chunk in sent.chunks(1000) {
+        io.write(chunk).unwrap();
+    }
+    assert_eq!(sent.len(), io.stats().write_buffered);
+
+    let got = drain(&mut net, b, 100);
+    assert!(sent == got, "{} of {} bytes", got.len(), sent.len());
+    assert_eq!(0, net.io(a).unwrap().stats().write_buffered);
+}
+
+/// Small frames consumed one at a time, with more arriving part way through.
+#[test]
+fn consuming_a_little_at_a_time_keeps_the_rest() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    let sent = pattern(10 * 1000);
+    let (first, second) = sent.split_at(6000);
+    net.io(a).unwrap().write(first).unwrap();
+    arrive(&mut net);
+
+    let mut seen = Vec::new();
+    for frame in 0..10 {
+        if 5 == frame {
+            net.io(a).unwrap().write(second).unwrap();
+            arrive(&mut net);
+        }
+        let mut io = net.io(b).unwrap();
+        seen.extend_from_slice(&io.buf().unwrap()[..600]);
+        assert_eq!(600, io.consume(600).unwrap());
+    }
+    let io = net.io(b).unwrap();
+    assert_eq!(sent.len() - seen.len(), io.buf().unwrap().len());
+    seen.extend_from_slice(io.buf().unwrap());
+    assert!(sent == seen);
+}
+
+/// A `Bytes` is a snapshot: later consuming, and more arriving, don't change it.
+#[test]
+fn handed_out_bytes_stay_as_they_were() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a).unwrap().write(b"hello").unwrap();
+    arrive(&mut net);
+    let hello = net.io(b).unwrap().bytes().unwrap();
+    let held = hello.slice(1..);
+
+    net.io(b).unwrap().consume(2).unwrap();
+    net.io(a).unwrap().write(b" world").unwrap();
+    arrive(&mut net);
+    assert_eq!(b"llo world", net.io(b).unwrap().buf().unwrap());
+    assert_eq!(b"hello", &hello[..]);
+    assert_eq!(b"ello", &held[..]);
+
+    let rest = net.io(b).unwrap().bytes().unwrap();
+    assert_eq!(b"llo world", &rest[..]);
+}
+
+#[test]
+fn taking_moves_bytes_out() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a).unwrap().write(b"one two three").unwrap();
+    arrive(&mut net);
+
+    let mut io = net.io(b).unwrap();
+    assert_eq!(b"one ", &io.take_up_to(4).unwrap()[..]);
+    assert_eq!(b"two three", io.buf().unwrap());
+    assert_eq!(b"two three", &io.take_up_to(100).unwrap()[..]);
+    assert!(io.take_buf().unwrap().is_empty());
+    assert_eq!(0, io.stats().read_buffered);
+    assert_eq!(0, net.metrics().buffered_bytes);
+}
+
+/// Two connections, which read something, then close.
+fn churn(net: &mut Net) {
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a).unwrap().write(b"something").unwrap();
+    net.io(b).unwrap().write(b"anything").unwrap();
+    arrive(net);
+    net.io(a).unwrap().close();
+    net.io(b).unwrap().close();
+    let mut closed = 0;
+    for _ in 0..10 {
+        net.pump();
+        if let Some(Event::Closed(_)) = net.try_next().unwrap() {
+            closed += 1;
+        }
+    }
+    assert_eq!(2, closed);
+}
+
+#[test]
+fn closed_connections_leave_buffers_for_new_ones() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    churn(&mut net);
+    let pooled = net.metrics().pooled_buffer_bytes;
+    assert!(pooled > 0);
+    assert_eq!(0, net.metrics().recycled_buffers);
+
+    churn(&mut net);
+    let metrics = net.metrics();
+    assert_eq!(2, metrics.recycled_buffers);
+    assert_eq!(pooled, metrics.pooled_buffer_bytes, "and back again");
+}
+
+#[test]
+fn nothing_is_kept_without_room() {
+    let (mut net, _clock) = Net::builder()
+        .buffer_pool_bytes(0)
+        .build_simulated()
+        .unwrap();
+    churn(&mut net);
+    churn(&mut net);
+    let metrics = net.metrics();
+    assert_eq!(
+        (0, 0),
+        (metrics.pooled_buffer_bytes, metrics.recycled_buffers)
+    );
+}
+
+/// How much each read got.
+#[derive(Clone, Default)]
+struct Reads(Arc<Mutex<Vec<usize>>>);
+
+impl Tap for Reads {
+    fn chunk(&mut self, _token: ConnToken, direction: Direction, data: &[u8]) {
+        if Direction::Read == direction {
+            self.0.lock().unwrap().push(data.len());
+        }
+    }
+}
+
+#[test]
+fn reads_ask_for_no_more_than_the_limit() {
+    let (mut net, _clock) = Net::builder().read_chunk(100).build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    let (c, d) = net.memory_pair().unwrap();
+    let (by_net, by_conn) = (Reads::default(), Reads::default());
+    net.io(b).unwrap().set_tap(by_net.clone());
+    net.io(d).unwrap().set_tap(by_conn.clone());
+    net.io(d).unwrap().set_read_limit(10).unwrap();
+
+    net.io(a).unwrap().write(&pattern(250)).unwrap();
+    net.io(c).unwrap().write(&pattern(25)).unwrap();
+    arrive(&mut net);
+    assert_eq!(vec![100, 100, 50], *by_net.0.lock().unwrap());
+    assert_eq!(vec![10, 10, 5], *by_conn.0.lock().unwrap());
+}
+
+#[test]
+fn consuming_too_much_consumes_what_there_is() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a).unwrap().write(b"hello").unwrap();
+    arrive(&mut net);
+
+    let mut io = net.io(b).unwrap();
+    assert_eq!(3, io.consume(3).unwrap());
+    assert_eq!(2, io.consume(100).unwrap());
+    assert_eq!(0, io.consume_all().unwrap());
+    net.io(a).unwrap().write(b"world").unwrap();
+    arrive(&mut net);
+    assert_eq!(5, net.io(b).unwrap().consume_all().unwrap());
+}
+
+#[test]
+fn reading_stops_when_the_buffer_is_full() {
+    let (mut net, _clock) = Net::builder()
+        .max_read_buffer(10)
+        .build_simulated()
+        .unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a).unwrap().write(&pattern(25)).unwrap();
+
+    let mut full = 0;
+    for _ in 0..10 {
+        net.pump();
+        if let Some(Event::ReadBufferFull(t)) = net.try_next().unwrap() {
+            assert_eq!(b, t);
+            full += 1;
+        }
+    }
+    assert_eq!(1, full);
+    assert_eq!(&pattern(25)[..10], net.io(b).unwrap().buf().unwrap());
+
+    net.io(b).unwrap().consume(4).unwrap();
+    arrive(&mut net);
+    assert_eq!(&pattern(25)[4..14], net.io(b).unwrap().buf().unwrap());
+    net.io(b).unwrap().set_max_read_buffer(100).unwrap();
+    let got = drain(&mut net, b, 10);
+    assert_eq!(&pattern(25)[4..], &got[..]);
+}
@@ -0,0 +1,48 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::collections::HashMap;
+use std::process::Command;
+use std::time::Instant;
+
+use common::WAIT;
+use nofio::ConnToken;
+use nofio::Direction;
+use nofio::Event;
+use nofio::Net;
+
+#[test]
+fn a_child_talks_over_connections_and_its_exit_is_an_event() {
+    let mut net = Net::empty().unwrap();
+    let spawned = net
+        .spawn(Command::new("sh").args(["-c", "read x; echo \"got $x\"; echo oops >&2; exit 3"]))
+        .unwrap();
+    let mut stdin = net.io(spawned.stdio).unwrap();
+    stdin.write(b"hello\n").unwrap();
+    stdin.close_write();
+
+    let mut heard: HashMap<ConnToken, Vec<u8>> = HashMap::new();
+    let mut ended = Vec::new();
+    let mut status = None;
+    let start = Instant::now();
+    while status.is_none() || ended.len() < 2 {
+        assert!(start.elapsed() < WAIT, "{:?} {:?}", status, ended);
+        match net.next_timeout(WAIT).unwrap() {
+            Some(Event::Data(t)) => {
+                let got = net.io(t).unwrap().take_buf().unwrap();
+                heard.entry(t).or_default().extend(got);
+            }
+            Some(Event::Done(t, Direction::Read)) => ended.push(t),
+            Some(Event::ChildExited(t, s)) => {
+                assert_eq!(spawned.child, t);
+                status = Some(s);
+            }
+            _ => (),
+        }
+    }
+
+    assert_eq!(Some(3), status.unwrap().code());
+    assert_eq!(b"got hello\n", &heard[&spawned.stdio][..]);
+    assert_eq!(b"oops\n", &heard[&spawned.stderr][..]);
+}
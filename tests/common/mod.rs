@@ -0,0 +1,35 @@
+//! Helpers shared by the integration tests; each test uses only some of them.
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+use nofio::Event;
+use nofio::Net;
+
+/// As long as anything should take to happen, over real sockets.
+pub const WAIT: Duration = Duration::from_secs(5);
+
+/// The next event, which must come within `WAIT`.
+pub fn next(net: &mut Net) -> Event {
+    net.next_timeout(WAIT).unwrap().expect("an event in time")
+}
+
+/// The first event `want` accepts, passing over anything else.
+pub fn wait_for(net: &mut Net, want: impl Fn(&Event) -> bool) -> Event {
+    loop {
+        let ev = next(net);
+        if want(&ev) {
+            return ev;
+        }
+    }
+}
+
+/// What happens over a few rounds of pumping; unconsumed data is reported every round.
+pub fn settle(net: &mut Net) -> Vec<Event> {
+    let mut events = Vec::new();
+    for _ in 0..10 {
+        net.pump();
+        events.extend(net.try_next().unwrap());
+    }
+    events
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+
+use common::wait_for;
+use common::WAIT;
+use nofio::ConnToken;
+use nofio::Event;
+use nofio::Net;
+
+fn connected(token: ConnToken) -> impl Fn(&Event) -> bool {
+    move |ev| matches!(ev, Event::Connected(t) if *t == token)
+}
+
+#[test]
+fn writes_before_connecting_are_sent_on_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut net = Net::empty().unwrap();
+    let token = net.tcp_connect(&listener.local_addr().unwrap()).unwrap();
+    net.io(token).unwrap().write(b"early").unwrap();
+
+    let (mut server, _) = listener.accept().unwrap();
+    server.set_read_timeout(Some(WAIT)).unwrap();
+    wait_for(&mut net, connected(token));
+    let mut got = [0; 5];
+    server.read_exact(&mut got).unwrap();
+    assert_eq!(b"early", &got);
+
+    server.write_all(b"reply").unwrap();
+    wait_for(&mut net, |ev| matches!(ev, Event::Data(t) if *t == token));
+    assert_eq!(b"reply", net.io(token).unwrap().buf().unwrap());
+    assert_eq!(
+        listener.local_addr().unwrap(),
+        net.io(token).unwrap().peer_addr()
+    );
+}
+
+#[test]
+fn a_refused_connection_fails_then_closes() {
+    // a port that was free a moment ago, which nothing is listening on now
+    let addr = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    };
+    let mut net = Net::empty().unwrap();
+    let token = net.tcp_connect(&addr).unwrap();
+    match wait_for(
+        &mut net,
+        |ev| matches!(ev, Event::ConnectFailed(t, _) | Event::Connected(t) if *t == token),
+    ) {
+        Event::ConnectFailed(_, e) => assert_eq!(io::ErrorKind::ConnectionRefused, e.kind()),
+        other => panic!("expected ConnectFailed, not {:?}", other),
+    }
+    wait_for(&mut net, |ev| matches!(ev, Event::Closed(t) if *t == token));
+}
@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use nofio::Event;
+use nofio::Net;
+
+#[test]
+fn ctx_timers_fire_and_cancel() {
+    let (mut net, clock) = Net::builder().build_simulated::<()>().unwrap();
+    let (a, _b) = net.memory_pair().unwrap();
+
+    let (kept, cancelled) = net
+        .with_io(a, |_io, ctx| {
+            let kept = ctx.timer(Duration::from_secs(1)).unwrap();
+            let cancelled = ctx.timer(Duration::from_secs(1)).unwrap();
+            ctx.cancel_timer(cancelled);
+            (kept, cancelled)
+        })
+        .unwrap();
+    assert_ne!(kept, cancelled);
+
+    let direct = net.timer(Duration::from_secs(1)).unwrap();
+    assert_ne!(kept, direct);
+    assert!(net.try_next().unwrap().is_none());
+
+    clock.advance(Duration::from_secs(1));
+    let mut fired = Vec::new();
+    while let Some(ev) = net.try_next().unwrap() {
+        match ev {
+            Event::Timer(token) => fired.push(token),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    fired.sort();
+    let mut expected = vec![kept, direct];
+    expected.sort();
+    assert_eq!(expected, fired);
+}
+
+#[test]
+fn ctx_rejects_long_timers() {
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    let (a, _b) = net.memory_pair().unwrap();
+    let long = nofio::MAX_TIMEOUT + Duration::from_secs(1);
+    let result = net.with_io(a, |_io, ctx| ctx.timer(long)).unwrap();
+    assert!(result.is_err());
+}
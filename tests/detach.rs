@@ -0,0 +1,64 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use common::next;
+use common::WAIT;
+use nofio::Error;
+use nofio::Event;
+use nofio::Net;
+use nofio::Operation;
+
+#[test]
+fn a_detached_socket_comes_with_what_was_left() {
+    let mut net = Net::empty().unwrap();
+    net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = match next(&mut net) {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    let mut client = TcpStream::connect(addr).unwrap();
+    client.write_all(b"UPGRADE\r\nhello").unwrap();
+    let token = match next(&mut net) {
+        Event::NewConnection { token, .. } => token,
+        other => panic!("expected NewConnection, not {:?}", other),
+    };
+    while net.io(token).unwrap().buf().unwrap().len() < 14 {
+        next(&mut net);
+    }
+    let mut io = net.io(token).unwrap();
+    assert_eq!(Some(b"UPGRADE".to_vec()), io.read_line().unwrap());
+    io.write(b"never sent").unwrap();
+
+    let (mut stream, left) = net.detach(token).unwrap();
+    assert_eq!(b"hello", &left.unread[..]);
+    assert_eq!(b"never sent", &left.unwritten[..]);
+    assert!(net.io(token).is_err(), "gone");
+    assert!(net
+        .next_timeout(Duration::from_millis(10))
+        .unwrap()
+        .is_none());
+
+    stream.set_nonblocking(false).unwrap();
+    stream.write_all(b"upgraded").unwrap();
+    client.set_read_timeout(Some(WAIT)).unwrap();
+    let mut got = [0; 8];
+    client.read_exact(&mut got).unwrap();
+    assert_eq!(b"upgraded", &got);
+}
+
+#[test]
+fn only_a_socket_can_be_detached() {
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    let (a, _b) = net.memory_pair().unwrap();
+    match net.detach(a) {
+        Err(e @ Error::Os { .. }) => assert_eq!(Some(Operation::Detach), e.operation()),
+        other => panic!("expected a detach error, not {:?}", other),
+    }
+    assert!(net.io(a).is_ok(), "still there");
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use std::io;
+
+use common::settle;
+use nofio::ConnToken;
+use nofio::Direction;
+use nofio::Error;
+use nofio::Event;
+use nofio::Fault;
+use nofio::Net;
+use nofio::Operation;
+
+/// The kinds of the errors reported for `token`.
+fn errors(events: &[Event], token: ConnToken) -> Vec<io::ErrorKind> {
+    events
+        .iter()
+        .filter_map(|ev| match ev {
+            Event::Error(t, e) if *t == token => Some(e.kind()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn a_failed_read_says_why_before_saying_its_done() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a)
+        .unwrap()
+        .inject_fault(Fault::ReadError(io::ErrorKind::ConnectionReset));
+    net.io(b).unwrap().write(b"lost").unwrap();
+
+    let seen = settle(&mut net);
+    assert_eq!(vec![io::ErrorKind::ConnectionReset], errors(&seen, a));
+    let error = seen
+        .iter()
+        .position(|ev| matches!(ev, Event::Error(t, _) if *t == a))
+        .unwrap();
+    let done = seen
+        .iter()
+        .position(|ev| matches!(ev, Event::Done(t, Direction::Read) if *t == a))
+        .expect("reading is over");
+    assert!(error < done);
+}
+
+#[test]
+fn a_failed_write_says_why() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a)
+        .unwrap()
+        .inject_fault(Fault::WriteError(io::ErrorKind::BrokenPipe));
+    net.io(a).unwrap().write(b"unsent").unwrap();
+
+    let seen = settle(&mut net);
+    assert_eq!(vec![io::ErrorKind::BrokenPipe], errors(&seen, a));
+    assert!(errors(&seen, b).is_empty());
+}
+
+#[test]
+fn a_closed_side_is_an_error_not_a_panic() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, _b) = net.memory_pair().unwrap();
+    let mut io = net.io(a).unwrap();
+    io.close();
+    assert!(matches!(io.buf(), Err(Error::Closed(t, Direction::Read)) if t == a.token()));
+    assert!(matches!(
+        io.consume(1),
+        Err(Error::Closed(_, Direction::Read))
+    ));
+    assert!(matches!(
+        io.take_buf(),
+        Err(Error::Closed(_, Direction::Read))
+    ));
+    assert!(matches!(
+        io.take_up_to(1),
+        Err(Error::Closed(_, Direction::Read))
+    ));
+    // writing is fine until what was written before has gone
+    io.write(b"last words").unwrap();
+
+    settle(&mut net);
+    assert!(matches!(
+        net.io(a).unwrap().write(b"too late"),
+        Err(Error::Closed(t, Direction::Write)) if t == a.token()
+    ));
+}
+
+#[test]
+fn errors_say_what_went_wrong() {
+    let mut net = Net::empty().unwrap();
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = taken.local_addr().unwrap();
+    let e = net.tcp_listen(&addr).unwrap_err();
+    assert_eq!(Some(Operation::Bind), e.operation());
+    assert_eq!(Some(addr), e.addr());
+    assert_eq!(
+        Some(io::ErrorKind::AddrInUse),
+        e.io_error().map(io::Error::kind)
+    );
+    assert_eq!(io::ErrorKind::AddrInUse, io::Error::from(e).kind());
+
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a).unwrap().close();
+    net.io(b).unwrap().close();
+    settle(&mut net);
+    let e = net.io(a).map(|_| ()).unwrap_err();
+    assert!(matches!(e, Error::UnknownToken(t) if t == a.token()));
+    assert_eq!(Some(a.token()), e.token());
+    assert_eq!(None, e.operation());
+    assert_eq!(io::ErrorKind::NotFound, io::Error::from(e).kind());
+
+    let e = net.timer(nofio::MAX_TIMEOUT * 2).unwrap_err();
+    assert!(matches!(e, Error::InvalidConfig { .. }), "{}", e);
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use common::settle;
+use nofio::Direction;
+use nofio::Event;
+use nofio::Fault;
+use nofio::Net;
+
+#[test]
+fn an_end_lands_exactly_where_it_was_asked_for() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(b).unwrap().inject_fault_after(5, Fault::Eof);
+    net.io(a).unwrap().write(b"helloworld").unwrap();
+
+    let seen = settle(&mut net);
+    assert!(seen
+        .iter()
+        .any(|ev| matches!(ev, Event::Done(t, Direction::Read) if *t == b)));
+    assert_eq!(b"hello", net.io(b).unwrap().buf().unwrap());
+}
+
+/// Nothing is lost to faults which a real socket could produce without anything going wrong.
+#[test]
+fn hiccups_lose_nothing() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    let mut io = net.io(a).unwrap();
+    io.inject_fault(Fault::WriteWouldBlock);
+    io.inject_fault(Fault::ShortWrite(1));
+    let mut io = net.io(b).unwrap();
+    io.inject_fault_after(3, Fault::ReadWouldBlock);
+    io.inject_fault(Fault::ShortRead(2));
+    net.io(a).unwrap().write(b"all of it").unwrap();
+
+    let seen = settle(&mut net);
+    assert!(!seen
+        .iter()
+        .any(|ev| matches!(ev, Event::Error(..) | Event::Done(..))));
+    assert_eq!(b"all of it", net.io(b).unwrap().buf().unwrap());
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use common::settle;
+use nofio::ConnToken;
+use nofio::Direction;
+use nofio::Event;
+use nofio::Net;
+
+/// What happens over a few rounds of pumping, with `reader` taking everything it reads.
+fn settle_reading(net: &mut Net, reader: ConnToken) -> Vec<Event> {
+    let mut events = Vec::new();
+    for _ in 0..10 {
+        net.pump();
+        events.extend(net.try_next().unwrap());
+        net.io(reader).unwrap().take_buf().unwrap();
+    }
+    events
+}
+
+fn has(events: &[Event], want: impl Fn(&Event) -> bool) -> bool {
+    events.iter().any(want)
+}
+
+#[test]
+fn a_growing_write_buffer_is_reported_until_it_drains() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    let mut io = net.io(a).unwrap();
+    io.set_write_watermarks(16 * 1024, 100 * 1024).unwrap();
+    io.write(&[0; 99 * 1024]).unwrap();
+    io.write(&[0; 101 * 1024]).unwrap();
+
+    // the reader isn't keeping up
+    net.io(b).unwrap().pause_reads();
+    let seen = settle(&mut net);
+    assert_eq!(
+        1,
+        seen.iter()
+            .filter(|ev| matches!(ev, Event::WriteBufferHigh(t) if *t == a))
+            .count()
+    );
+    assert!(!has(&seen, |ev| matches!(ev, Event::WriteBufferLow(_))));
+
+    net.io(b).unwrap().resume_reads();
+    let seen = settle_reading(&mut net, b);
+    let low = seen
+        .iter()
+        .position(|ev| matches!(ev, Event::WriteBufferLow(t) if *t == a))
+        .expect("drained");
+    assert!(!has(&seen[..low], |ev| matches!(ev, Event::Flushed(_))));
+    assert_eq!(0, net.io(a).unwrap().stats().write_buffered);
+}
+
+#[test]
+fn paused_reads_leave_everything_with_the_peer() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(b).unwrap().pause_reads();
+    net.io(a).unwrap().write(b"later").unwrap();
+    net.io(a).unwrap().close();
+
+    let seen = settle(&mut net);
+    assert!(
+        seen.iter().all(|ev| ev.token() != Some(b.token())),
+        "{:?}",
+        seen
+    );
+    assert!(net.io(b).unwrap().buf().unwrap().is_empty());
+    assert!(net.io(b).unwrap().reads_paused());
+
+    net.io(b).unwrap().resume_reads();
+    let seen = settle(&mut net);
+    assert!(has(&seen, |ev| matches!(ev, Event::Data(t) if *t == b)));
+    assert!(has(
+        &seen,
+        |ev| matches!(ev, Event::Done(t, Direction::Read) if *t == b)
+    ));
+    assert_eq!(b"later", net.io(b).unwrap().buf().unwrap());
+}
+
+#[test]
+fn flushed_once_per_drained_buffer() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    let flushed = |events: &[Event]| {
+        events
+            .iter()
+            .filter(|ev| matches!(ev, Event::Flushed(t) if *t == a))
+            .count()
+    };
+    assert_eq!(0, flushed(&settle_reading(&mut net, b)));
+
+    for _ in 0..2 {
+        let mut io = net.io(a).unwrap();
+        io.write(b"one").unwrap();
+        io.write(b"two").unwrap();
+        assert_eq!(1, flushed(&settle_reading(&mut net, b)));
+    }
+}
+
+/// Configured when the `Net` is built, rather than for each connection. It's checked between
+/// rounds, so it can be overshot.
+#[test]
+fn too_much_buffered_in_total_stops_reading() {
+    let (mut net, _clock) = Net::builder()
+        .max_buffered_bytes(10 * 1000)
+        .build_simulated()
+        .unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    let (c, d) = net.memory_pair().unwrap();
+    net.io(a).unwrap().write(&[0; 6000]).unwrap();
+    net.io(c).unwrap().write(&[0; 6000]).unwrap();
+    let seen = settle(&mut net);
+    assert!(has(&seen, |ev| matches!(ev, Event::MemoryPressure { .. })));
+
+    net.io(a).unwrap().write(b"more").unwrap();
+    settle(&mut net);
+    assert_eq!(6000, net.io(b).unwrap().take_buf().unwrap().len());
+
+    // that's few enough to read again
+    settle(&mut net);
+    assert_eq!(b"more", net.io(b).unwrap().buf().unwrap());
+    assert_eq!(6000, net.io(d).unwrap().buf().unwrap().len());
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use std::io;
+
+use common::settle;
+use nofio::codec::Codec;
+use nofio::codec::Frame;
+use nofio::codec::LengthDelimited;
+use nofio::codec::Lines;
+use nofio::ConnToken;
+use nofio::Error;
+use nofio::Event;
+use nofio::Net;
+
+#[test]
+fn lines_are_taken_whole_or_not_at_all() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a)
+        .unwrap()
+        .write(b"HELO there\r\nkey=value;par")
+        .unwrap();
+    settle(&mut net);
+
+    let mut io = net.io(b).unwrap();
+    assert_eq!(Some(b"HELO there".to_vec()), io.read_line().unwrap());
+    assert_eq!(Some(b"key=".to_vec()), io.read_until(b'=').unwrap());
+    assert_eq!(Some(b"value;".to_vec()), io.read_until(b';').unwrap());
+    assert_eq!(None, io.read_line().unwrap());
+    assert_eq!(b"par", io.buf().unwrap(), "nothing taken");
+
+    net.io(a).unwrap().write(b"tial\n").unwrap();
+    settle(&mut net);
+    assert_eq!(
+        Some(b"partial".to_vec()),
+        net.io(b).unwrap().read_line().unwrap()
+    );
+}
+
+fn frames(events: &[Event], token: ConnToken) -> usize {
+    events
+        .iter()
+        .filter(|ev| matches!(ev, Event::Frame(t) if *t == token))
+        .count()
+}
+
+#[test]
+fn length_prefixed_frames_arrive_whole() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a)
+        .unwrap()
+        .set_codec(LengthDelimited::new(100).unwrap());
+    net.io(b)
+        .unwrap()
+        .set_codec(LengthDelimited::new(100).unwrap());
+    net.io(a).unwrap().write_frame(b"one").unwrap();
+    net.io(a).unwrap().write(&[0, 0, 0, 3, b't']).unwrap();
+
+    let seen = settle(&mut net);
+    assert!(frames(&seen, b) > 0);
+    assert!(!seen.iter().any(|ev| matches!(ev, Event::Data(_))));
+    let mut io = net.io(b).unwrap();
+    assert_eq!(b"one", &io.next_frame().unwrap().unwrap()[..]);
+    assert_eq!(None, io.next_frame().unwrap(), "half a frame");
+
+    net.io(a).unwrap().write(b"wo").unwrap();
+    settle(&mut net);
+    let mut io = net.io(b).unwrap();
+    assert_eq!(b"two", &io.next_frame().unwrap().unwrap()[..]);
+    assert!(io.write_frame(&[0; 101]).is_err());
+}
+
+#[test]
+fn a_frame_too_big_resets_the_connection() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(b)
+        .unwrap()
+        .set_codec(LengthDelimited::new(100).unwrap());
+    net.io(a).unwrap().write(&[0, 0, 0, 101]).unwrap();
+
+    let seen = settle(&mut net);
+    assert_eq!(0, frames(&seen, b));
+    assert!(seen.iter().any(|ev| matches!(
+        ev,
+        Event::Error(t, e) if *t == b && io::ErrorKind::InvalidData == e.kind()
+    )));
+    assert!(seen
+        .iter()
+        .any(|ev| matches!(ev, Event::Closed(t) if *t == b)));
+}
+
+/// Frames end with a NUL, as some old protocols do.
+struct NulTerminated;
+
+impl Codec for NulTerminated {
+    fn decode(&mut self, buf: &[u8]) -> io::Result<Option<Frame>> {
+        Ok(buf.iter().position(|&b| 0 == b).map(|end| Frame {
+            len: end + 1,
+            payload: 0..end,
+        }))
+    }
+
+    fn encode(&mut self, payload: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        out.extend_from_slice(payload);
+        out.push(0);
+        Ok(())
+    }
+
+    fn max_buffered(&self) -> usize {
+        1024
+    }
+}
+
+#[test]
+fn any_codec_can_be_plugged_in() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a).unwrap().set_codec(NulTerminated);
+    net.io(b).unwrap().set_codec(Lines::new(100).unwrap());
+    net.io(a).unwrap().write_frame(b"first").unwrap();
+    net.io(a).unwrap().write(b"\nsecond\r\n").unwrap();
+    net.io(b).unwrap().write_frame(b"back").unwrap();
+
+    settle(&mut net);
+    let mut io = net.io(b).unwrap();
+    assert_eq!(b"first\0", &io.next_frame().unwrap().unwrap()[..]);
+    assert_eq!(b"second", &io.next_frame().unwrap().unwrap()[..]);
+    // each end only knows its own codec
+    assert_eq!(None, net.io(a).unwrap().next_frame().unwrap());
+    net.io(b).unwrap().write(&[0]).unwrap();
+    settle(&mut net);
+    assert_eq!(
+        b"back\n",
+        &net.io(a).unwrap().next_frame().unwrap().unwrap()[..]
+    );
+}
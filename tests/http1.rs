@@ -0,0 +1,321 @@
+mod common;
+
+use std::iter;
+
+use common::settle;
+use nofio::codec::Codec;
+use nofio::codec::Frame;
+use nofio::http1;
+use nofio::http1::HttpError;
+use nofio::ConnToken;
+use nofio::Error;
+use nofio::Event;
+use nofio::Net;
+
+/// `a` is served, and `b` sends it `request`.
+fn serving(request: &[u8]) -> (Net, ConnToken, ConnToken) {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    http1::serve(&mut net.io(a).unwrap(), 1024).unwrap();
+    net.io(b).unwrap().write(request).unwrap();
+    (net, a, b)
+}
+
+/// What `b` gets back for `request`, which `a` should refuse.
+fn refused(request: &[u8]) -> Vec<u8> {
+    let (mut net, a, b) = serving(request);
+    let events = settle(&mut net);
+    assert!(
+        !events
+            .iter()
+            .any(|ev| matches!(ev, Event::HttpRequest(t) if *t == a)),
+        "accepted"
+    );
+    net.io(b).unwrap().take_buf().unwrap()
+}
+
+fn request(head: &str) -> http1::Request<'_> {
+    http1::parse_request(head.as_bytes())
+        .unwrap()
+        .expect("a whole head")
+        .0
+}
+
+#[test]
+fn differing_lengths_are_refused() {
+    let head = "POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\n";
+    assert_eq!(
+        Err(HttpError::ContentLength),
+        request(head).content_length()
+    );
+    let head = "POST / HTTP/1.1\r\nContent-Length: 5, 6\r\n\r\n";
+    assert_eq!(
+        Err(HttpError::ContentLength),
+        request(head).content_length()
+    );
+
+    let answer = refused(b"POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab");
+    assert!(answer.starts_with(b"HTTP/1.1 400 "));
+}
+
+#[test]
+fn a_repeated_length_is_the_length() {
+    let head = "POST / HTTP/1.1\r\nContent-Length: 5\r\ncontent-length: 5\r\n\r\n";
+    assert_eq!(Ok(5), request(head).content_length());
+    let head = "POST / HTTP/1.1\r\nContent-Length: 5 , 5\r\n\r\n";
+    assert_eq!(Ok(5), request(head).content_length());
+    let head = "POST / HTTP/1.1\r\nContent-Length: 5,\r\n\r\n";
+    assert_eq!(
+        Err(HttpError::ContentLength),
+        request(head).content_length()
+    );
+}
+
+#[test]
+fn a_length_and_chunking_are_refused() {
+    let answer = refused(
+        b"POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+    );
+    assert!(answer.starts_with(b"HTTP/1.1 400 "));
+
+    // whichever comes first
+    let answer = refused(
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n0\r\n\r\n",
+    );
+    assert!(answer.starts_with(b"HTTP/1.1 400 "));
+}
+
+#[test]
+fn only_chunked_alone_is_understood() {
+    let answer = refused(
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: gzip\r\n\r\n0\r\n\r\n",
+    );
+    assert!(answer.starts_with(b"HTTP/1.1 501 "));
+    let answer = refused(b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n0\r\n\r\n");
+    assert!(answer.starts_with(b"HTTP/1.1 501 "));
+}
+
+#[test]
+fn connection_is_a_list_of_options() {
+    let head = "GET / HTTP/1.0\r\nConnection: Keep-Alive, Upgrade\r\n\r\n";
+    assert!(request(head).keep_alive());
+    let head = "GET / HTTP/1.1\r\nConnection: upgrade, CLOSE\r\n\r\n";
+    assert!(!request(head).keep_alive());
+    let head = "GET / HTTP/1.1\r\nConnection: upgrade\r\nConnection: Close\r\n\r\n";
+    assert!(!request(head).keep_alive());
+    let head = "GET / HTTP/1.1\r\nConnection: closed\r\n\r\n";
+    assert!(request(head).keep_alive());
+    let head = "GET / HTTP/1.0\r\n\r\n";
+    assert!(!request(head).keep_alive());
+}
+
+fn chunk(buf: &[u8]) -> Result<Option<Frame>, HttpError> {
+    let mut codec = http1::Chunked::new(64).unwrap();
+    codec.decode(buf).map_err(|e| {
+        e.get_ref()
+            .and_then(|e| e.downcast_ref::<HttpError>())
+            .expect("an HttpError")
+            .clone()
+    })
+}
+
+#[test]
+fn chunk_extensions_are_passed_over() {
+    let frame = chunk(b"5;name=value;other\r\nhello\r\n6").unwrap().unwrap();
+    assert_eq!(27, frame.len);
+    assert_eq!(20..25, frame.payload);
+}
+
+#[test]
+fn the_last_chunk_takes_its_trailers() {
+    let last = b"0\r\nExpires: never\r\nX-Sum: 1\r\n\r\n";
+    let mut buf = last.to_vec();
+    buf.extend_from_slice(b"GET / HTTP/1.1\r\n");
+    let frame = chunk(&buf).unwrap().unwrap();
+    assert_eq!(last.len(), frame.len);
+    assert!(frame.payload.is_empty());
+
+    assert_eq!(None, chunk(b"0\r\nExpires: never\r\n").unwrap());
+    assert_eq!(5, chunk(b"0\r\n\r\n").unwrap().unwrap().len);
+}
+
+#[test]
+fn oversized_chunk_lengths_are_refused() {
+    // doesn't fit in a usize
+    assert_eq!(Err(HttpError::Chunk), chunk(b"10000000000000000\r\n"));
+    // fits, but is more than `max_chunk`
+    assert_eq!(Err(HttpError::BodyTooLarge), chunk(b"ffffffffffffffff\r\n"));
+    assert_eq!(Err(HttpError::BodyTooLarge), chunk(b"41\r\n"));
+    assert_eq!(Err(HttpError::Chunk), chunk(b"-1\r\n"));
+    assert_eq!(Err(HttpError::Chunk), chunk(b"\r\n"));
+    // a size line which never ends
+    assert_eq!(Err(HttpError::Chunk), chunk(&[b'0'; 2048]));
+}
+
+#[test]
+fn chunk_data_must_end_with_a_line_ending() {
+    assert_eq!(Err(HttpError::Chunk), chunk(b"5\r\nhelloXY"));
+    assert_eq!(Err(HttpError::Chunk), chunk(b"5\r\nhello!\r\n"));
+    assert_eq!(None, chunk(b"5\r\nhello").unwrap());
+    assert_eq!(None, chunk(b"5\r\nhello\r").unwrap());
+}
+
+#[test]
+fn chunks_encode_to_what_decodes() {
+    let mut codec = http1::Chunked::new(64).unwrap();
+    let mut out = Vec::new();
+    codec.encode(b"hello", &mut out).unwrap();
+    codec.encode(b"", &mut out).unwrap();
+    assert_eq!(b"5\r\nhello\r\n0\r\n\r\n", &out[..]);
+    let frame = codec.decode(&out).unwrap().unwrap();
+    assert_eq!(b"hello", &out[frame.payload]);
+    assert!(codec.encode(&[0; 65], &mut out).is_err());
+}
+
+#[test]
+fn a_chunked_body_arrives_whole() {
+    let (mut net, a, _b) = serving(
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+        3;x=y\r\nabc\r\n2\r\nde\r\n0\r\nTrailer: t\r\n\r\n\
+        GET /next HTTP/1.1\r\n\r\n",
+    );
+    let events = settle(&mut net);
+    assert!(events
+        .iter()
+        .any(|ev| matches!(ev, Event::HttpRequest(t) if *t == a)));
+    let mut io = net.io(a).unwrap();
+    let request = http1::next_request(&mut io).unwrap().unwrap();
+    assert_eq!(b"abcde", &request.body[..]);
+    let request = http1::next_request(&mut io).unwrap().unwrap();
+    assert_eq!("/next", request.target);
+}
+
+#[test]
+fn a_chunk_without_its_line_ending_is_refused() {
+    let answer =
+        refused(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabcd\r\n0\r\n\r\n");
+    assert!(answer.starts_with(b"HTTP/1.1 400 "));
+}
+
+/// `a` is a client, which has sent `b` a request for each of `methods`.
+fn asking(methods: &[&str]) -> (Net, ConnToken, ConnToken) {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    let mut io = net.io(a).unwrap();
+    http1::client(&mut io, 1024).unwrap();
+    for method in methods {
+        http1::write_request(&mut io, method, "/", &[("Host", "example.com")], b"").unwrap();
+    }
+    settle(&mut net);
+    let sent = net.io(b).unwrap().take_buf().unwrap();
+    assert!(
+        sent.starts_with(format!("{} / HTTP/1.1\r\nHost: example.com\r\n", methods[0]).as_bytes())
+    );
+    (net, a, b)
+}
+
+/// What `b` sends back to `a`.
+fn answered(
+    net: &mut Net,
+    a: ConnToken,
+    b: ConnToken,
+    response: &[u8],
+) -> Vec<http1::OwnedResponse> {
+    net.io(b).unwrap().write(response).unwrap();
+    let events = settle(net);
+    assert!(events
+        .iter()
+        .any(|ev| matches!(ev, Event::HttpResponse(t) if *t == a)));
+    let mut io = net.io(a).unwrap();
+    iter::from_fn(|| http1::next_response(&mut io).unwrap()).collect()
+}
+
+#[test]
+fn responses_are_delimited_by_their_length_or_chunks() {
+    let (mut net, a, b) = asking(&["GET", "GET"]);
+    let responses = answered(
+        &mut net,
+        a,
+        b,
+        b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello\
+        HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nnot\r\n0\r\n\r\n",
+    );
+    assert_eq!(2, responses.len());
+    assert_eq!(200, responses[0].status);
+    assert_eq!(b"hello", &responses[0].body[..]);
+    assert!(responses[0].keep_alive());
+    assert_eq!("Not Found", responses[1].reason);
+    assert_eq!(b"not", &responses[1].body[..]);
+}
+
+#[test]
+fn a_head_response_has_no_body() {
+    let (mut net, a, b) = asking(&["HEAD", "GET"]);
+    let responses = answered(
+        &mut net,
+        a,
+        b,
+        b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n\
+        HTTP/1.1 204 No Content\r\n\r\n",
+    );
+    assert_eq!(2, responses.len());
+    assert!(responses[0].body.is_empty());
+    assert_eq!(Some(&b"5"[..]), responses[0].header("content-length"));
+    assert_eq!(204, responses[1].status);
+}
+
+#[test]
+fn a_body_without_a_length_ends_with_the_connection() {
+    let (mut net, a, b) = asking(&["GET"]);
+    net.io(b)
+        .unwrap()
+        .write(b"HTTP/1.0 200 OK\r\n\r\nall of it")
+        .unwrap();
+    let events = settle(&mut net);
+    assert!(!events
+        .iter()
+        .any(|ev| matches!(ev, Event::HttpResponse(t) if *t == a)));
+
+    net.io(b).unwrap().close();
+    let events = settle(&mut net);
+    assert!(events
+        .iter()
+        .any(|ev| matches!(ev, Event::HttpResponse(t) if *t == a)));
+    let response = http1::next_response(&mut net.io(a).unwrap())
+        .unwrap()
+        .unwrap();
+    assert_eq!(b"all of it", &response.body[..]);
+    assert!(!response.keep_alive());
+}
+
+#[test]
+fn nothing_can_split_a_message() {
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    let (a, _b) = net.memory_pair().unwrap();
+    let mut io = net.io(a).unwrap();
+    let smuggled = "x\r\nContent-Length: 0\r\n\r\nGET /admin HTTP/1.1";
+    let refusals = [
+        http1::write_request(&mut io, "GET", "/", &[("Host", smuggled)], b""),
+        http1::write_request(&mut io, "GET", "/", &[("Ho\nst", "x")], b""),
+        http1::write_request(&mut io, "GET", "/", &[("X", "nul\0")], b""),
+        http1::write_request(&mut io, "GET / HTTP/1.1\r\n", "/", &[], b""),
+        http1::write_request(&mut io, "GET", "/ HTTP/1.1\r\n", &[], b""),
+        http1::write_response(&mut io, 200, &[("X", smuggled)], b"", true),
+        http1::write_response(&mut io, 200, &[("", "x")], b"", true),
+        http1::write_response_head(&mut io, 200, &[("X", smuggled)], true),
+    ];
+    for refusal in refusals {
+        assert!(
+            matches!(refusal, Err(Error::InvalidConfig { .. })),
+            "{:?}",
+            refusal
+        );
+    }
+    assert_eq!(0, io.stats().write_buffered);
+
+    let proxy = "127.0.0.1:1".parse().unwrap();
+    let refused = net.connect_via_http_proxy(&proxy, "example.com:443", &[("X", smuggled)]);
+    assert!(matches!(refused, Err(Error::InvalidConfig { .. })));
+    assert_eq!(0, net.metrics().buffered_bytes);
+    assert!(settle(&mut net).is_empty());
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use std::time::Duration;
+
+use common::settle;
+use nofio::Direction;
+use nofio::Event;
+use nofio::Net;
+
+fn has(events: &[Event], want: impl Fn(&Event) -> bool) -> bool {
+    events.iter().any(want)
+}
+
+#[test]
+fn reading_and_writing_put_the_timeout_off() {
+    let (mut net, clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a)
+        .unwrap()
+        .set_idle_timeout(Duration::from_secs(30))
+        .unwrap();
+
+    clock.advance(Duration::from_secs(20));
+    net.io(b).unwrap().write(b"still here").unwrap();
+    settle(&mut net);
+    assert_eq!(b"still here", &net.io(a).unwrap().take_buf().unwrap()[..]);
+
+    clock.advance(Duration::from_secs(20));
+    net.io(a).unwrap().write(b"me too").unwrap();
+    assert!(!has(&settle(&mut net), |ev| matches!(
+        ev,
+        Event::TimedOut(_)
+    )));
+
+    clock.advance(Duration::from_secs(29));
+    assert!(!has(&settle(&mut net), |ev| matches!(
+        ev,
+        Event::TimedOut(_)
+    )));
+    clock.advance(Duration::from_secs(1));
+    assert!(has(
+        &settle(&mut net),
+        |ev| matches!(ev, Event::TimedOut(t) if *t == a)
+    ));
+}
+
+#[test]
+fn a_quiet_connection_is_closed() {
+    let (mut net, clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a)
+        .unwrap()
+        .set_idle_timeout(Duration::from_secs(30))
+        .unwrap();
+
+    clock.advance(Duration::from_secs(30));
+    let seen = settle(&mut net);
+    assert!(has(&seen, |ev| matches!(ev, Event::TimedOut(t) if *t == a)));
+    assert!(has(
+        &seen,
+        |ev| matches!(ev, Event::Done(t, Direction::Read) if *t == b)
+    ));
+
+    net.io(b).unwrap().close();
+    let seen = settle(&mut net);
+    assert!(has(&seen, |ev| matches!(ev, Event::Closed(t) if *t == a)));
+    assert!(has(&seen, |ev| matches!(ev, Event::Closed(t) if *t == b)));
+}
@@ -0,0 +1,18 @@
+#[test]
+fn tests_check_invariants() {
+    let capabilities = nofio::capabilities();
+    assert!(capabilities.debug_invariants, "see the dev-dependencies");
+    assert!(capabilities.fault_injection);
+    assert_eq!(cfg!(target_os = "linux"), capabilities.io_uring);
+}
+
+#[test]
+fn the_dump_says_what_the_build_can_do() {
+    let (net, _clock) = nofio::Net::builder().build_simulated::<()>().unwrap();
+    let dump = net.debug_dump();
+    assert!(
+        dump.starts_with(&format!("{:?}\n", nofio::capabilities())),
+        "{}",
+        dump
+    );
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use std::collections::HashSet;
+
+use common::settle;
+use nofio::ConnToken;
+use nofio::Error;
+use nofio::Event;
+use nofio::Net;
+
+/// Close both ends, and wait for them to go.
+fn closed(net: &mut Net, a: ConnToken, b: ConnToken) -> Vec<Event> {
+    net.io(a).unwrap().close();
+    net.io(b).unwrap().close();
+    settle(net)
+}
+
+#[test]
+fn closed_is_the_last_word() {
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a).unwrap().write(b"bye").unwrap();
+    let seen = closed(&mut net, a, b);
+
+    for token in [a, b] {
+        let about: Vec<&Event> = seen
+            .iter()
+            .filter(|ev| ev.token() == Some(token.token()))
+            .collect();
+        assert_eq!(
+            1,
+            about
+                .iter()
+                .filter(|ev| matches!(ev, Event::Closed(_)))
+                .count()
+        );
+        assert!(
+            matches!(about.last(), Some(Event::Closed(_))),
+            "{:?}",
+            about
+        );
+        assert!(matches!(net.io(token), Err(Error::UnknownToken(_))));
+    }
+}
+
+#[test]
+fn an_old_token_never_names_the_slots_next_connection() {
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    closed(&mut net, a, b);
+
+    let (c, d) = net.memory_pair().unwrap();
+    for old in [a, b] {
+        assert_ne!(old, c);
+        assert_ne!(old, d);
+        match net.io(old) {
+            Err(Error::UnknownToken(token)) => assert_eq!(old.token(), token),
+            other => panic!("expected UnknownToken, not {:?}", other.map(|_| ())),
+        }
+    }
+    net.io(c).unwrap().write(b"fresh").unwrap();
+    settle(&mut net);
+    assert_eq!(b"fresh", net.io(d).unwrap().buf().unwrap());
+}
+
+#[test]
+fn data_kept_with_a_connection_is_handed_back_as_it_closes() {
+    let (mut net, _clock) = Net::builder().build_simulated::<String>().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    assert!(net.io(a).unwrap().data().is_none());
+    assert_eq!(None, net.io(a).unwrap().set_data("first".to_string()));
+    assert_eq!(
+        Some("first".to_string()),
+        net.io(a).unwrap().set_data("second".to_string())
+    );
+    net.io(a).unwrap().data_mut().unwrap().push_str(" thoughts");
+    assert_eq!("second thoughts", net.io(a).unwrap().data().unwrap());
+
+    // left behind, so dropped after its Closed
+    net.io(b).unwrap().set_data("forgotten".to_string());
+
+    net.io(a).unwrap().close();
+    net.io(b).unwrap().close();
+    let mut handed_back = None;
+    for _ in 0..10 {
+        net.pump();
+        if let Some(Event::Closed(token)) = net.try_next().unwrap() {
+            if token == a {
+                handed_back = net.take_data(a);
+            }
+        }
+    }
+    assert_eq!(Some("second thoughts".to_string()), handed_back);
+    assert_eq!(None, net.take_data(b));
+}
+
+/// Slots are reused, with new tokens, so churning through connections never runs out.
+#[test]
+fn churning_through_connections_never_repeats_a_token() {
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    let mut seen = HashSet::new();
+    for _ in 0..1000 {
+        let (a, b) = net.memory_pair().unwrap();
+        assert!(seen.insert(a) && seen.insert(b), "{:?} {:?} again", a, b);
+        net.io(a).unwrap().close();
+        net.io(b).unwrap().close();
+        for _ in 0..3 {
+            net.pump();
+            net.try_next().unwrap();
+        }
+    }
+    settle(&mut net);
+    assert_eq!(0, net.connections().count());
+    assert_eq!(0, net.stats().connections);
+}
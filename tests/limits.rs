@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use nofio::Error;
+use nofio::ListenOptions;
+use nofio::Net;
+use nofio::NetBuilder;
+use nofio::MAX_BACKLOG;
+use nofio::MAX_BUFFER_LIMIT;
+use nofio::MAX_TIMEOUT;
+use nofio::MAX_TOKENS;
+
+const TICK: Duration = Duration::from_nanos(1);
+
+/// Fails unless `result` is an `InvalidConfig` for `setting`.
+fn rejected<V: std::fmt::Debug>(setting: &str, result: Result<V, Error>) {
+    match result {
+        Err(Error::InvalidConfig { setting: s, .. }) if s == setting => (),
+        other => panic!("{} should have been rejected, not {:?}", setting, other),
+    }
+}
+
+fn builds(builder: NetBuilder) {
+    builder
+        .build_simulated::<()>()
+        .expect("a value within range");
+}
+
+fn build(builder: NetBuilder) -> Result<(), Error> {
+    builder.build_simulated::<()>().map(|_| ())
+}
+
+#[test]
+fn builder_sizes() {
+    type Setter = fn(NetBuilder, usize) -> NetBuilder;
+    let sizes: &[(&str, Setter, usize)] = &[
+        ("read_chunk", NetBuilder::read_chunk, MAX_BUFFER_LIMIT),
+        ("wanted", NetBuilder::wanted, MAX_BUFFER_LIMIT),
+        (
+            "max_read_buffer",
+            NetBuilder::max_read_buffer,
+            MAX_BUFFER_LIMIT,
+        ),
+        (
+            "max_buffered_bytes",
+            NetBuilder::max_buffered_bytes,
+            usize::MAX,
+        ),
+        ("max_connections", NetBuilder::max_connections, MAX_TOKENS),
+        (
+            "max_connections_per_ip",
+            NetBuilder::max_connections_per_ip,
+            MAX_TOKENS,
+        ),
+        ("events_capacity", NetBuilder::events_capacity, 64 * 1024),
+    ];
+    for &(setting, set, max) in sizes {
+        rejected(setting, build(set(Net::builder(), 0)));
+        builds(set(Net::builder(), 1));
+        builds(set(Net::builder(), max));
+        if max < usize::MAX {
+            rejected(setting, build(set(Net::builder(), max + 1)));
+        }
+    }
+}
+
+#[test]
+fn builder_durations() {
+    type Setter = fn(NetBuilder, Duration) -> NetBuilder;
+    let durations: &[(&str, Setter)] = &[
+        ("drain_timeout", NetBuilder::drain_timeout),
+        ("tcp_info_interval", NetBuilder::tcp_info_interval),
+    ];
+    for &(setting, set) in durations {
+        rejected(setting, build(set(Net::builder(), Duration::from_secs(0))));
+        builds(set(Net::builder(), TICK));
+        builds(set(Net::builder(), MAX_TIMEOUT));
+        rejected(setting, build(set(Net::builder(), MAX_TIMEOUT + TICK)));
+    }
+}
+
+#[test]
+fn builder_rates_and_pool() {
+    rejected("max_egress_bps", build(Net::builder().max_egress_bps(0)));
+    builds(Net::builder().max_egress_bps(1));
+    builds(Net::builder().max_egress_bps(u64::MAX));
+
+    builds(Net::builder().buffer_pool_bytes(0));
+    builds(Net::builder().buffer_pool_bytes(usize::MAX));
+}
+
+#[test]
+fn builder_watermarks() {
+    rejected(
+        "high watermark",
+        build(Net::builder().write_watermarks(0, 0)),
+    );
+    rejected(
+        "low watermark",
+        build(Net::builder().write_watermarks(1, 1)),
+    );
+    rejected(
+        "low watermark",
+        build(Net::builder().write_watermarks(usize::MAX, usize::MAX)),
+    );
+    builds(Net::builder().write_watermarks(0, 1));
+    builds(Net::builder().write_watermarks(usize::MAX - 1, usize::MAX));
+}
+
+#[test]
+fn connection_sizes() {
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    let (a, _b) = net.memory_pair().unwrap();
+    let mut io = net.io(a).unwrap();
+
+    rejected("wanted", io.want(0));
+    io.want(1).unwrap();
+    io.want(MAX_BUFFER_LIMIT).unwrap();
+    rejected("wanted", io.want(MAX_BUFFER_LIMIT + 1));
+
+    rejected("max_read_buffer", io.set_max_read_buffer(0));
+    io.set_max_read_buffer(1).unwrap();
+    io.set_max_read_buffer(MAX_BUFFER_LIMIT).unwrap();
+    rejected(
+        "max_read_buffer",
+        io.set_max_read_buffer(MAX_BUFFER_LIMIT + 1),
+    );
+
+    rejected("read_limit", io.set_read_limit(0));
+    io.set_read_limit(1).unwrap();
+    io.set_read_limit(MAX_BUFFER_LIMIT).unwrap();
+    rejected("read_limit", io.set_read_limit(MAX_BUFFER_LIMIT + 1));
+
+    rejected("high watermark", io.set_write_watermarks(0, 0));
+    rejected("low watermark", io.set_write_watermarks(1, 1));
+    io.set_write_watermarks(0, 1).unwrap();
+    io.set_write_watermarks(usize::MAX - 1, usize::MAX).unwrap();
+
+    rejected("read_bps", io.set_rate_limit(Some(0), None));
+    rejected("write_bps", io.set_rate_limit(None, Some(0)));
+    io.set_rate_limit(Some(1), Some(1)).unwrap();
+    io.set_rate_limit(Some(u64::MAX), Some(u64::MAX)).unwrap();
+    io.set_rate_limit(None, None).unwrap();
+}
+
+#[test]
+fn connection_durations() {
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    let (a, _b) = net.memory_pair().unwrap();
+    let mut io = net.io(a).unwrap();
+
+    rejected("idle_timeout", io.set_idle_timeout(Duration::from_secs(0)));
+    io.set_idle_timeout(TICK).unwrap();
+    io.set_idle_timeout(MAX_TIMEOUT).unwrap();
+    rejected("idle_timeout", io.set_idle_timeout(MAX_TIMEOUT + TICK));
+}
+
+#[test]
+fn timers() {
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    net.timer(Duration::from_secs(0)).unwrap();
+    net.timer(MAX_TIMEOUT).unwrap();
+    rejected("timer", net.timer(MAX_TIMEOUT + TICK));
+}
+
+#[test]
+fn listen_options() {
+    let mut net = Net::empty().unwrap();
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let mut listen = |options: ListenOptions| net.tcp_listen_all(&[addr], &options);
+
+    type Setter = fn(ListenOptions, Duration) -> ListenOptions;
+    let durations: &[(&str, Setter)] = &[
+        ("first_byte_timeout", ListenOptions::first_byte_timeout),
+        ("establish_timeout", ListenOptions::establish_timeout),
+    ];
+    for &(setting, set) in durations {
+        let options = ListenOptions::default();
+        rejected(
+            setting,
+            listen(set(options.clone(), Duration::from_secs(0))),
+        );
+        listen(set(options.clone(), TICK)).unwrap();
+        listen(set(options.clone(), MAX_TIMEOUT)).unwrap();
+        rejected(setting, listen(set(options, MAX_TIMEOUT + TICK)));
+    }
+
+    rejected("backlog", listen(ListenOptions::default().backlog(0)));
+    listen(ListenOptions::default().backlog(1)).unwrap();
+    listen(ListenOptions::default().backlog(MAX_BACKLOG)).unwrap();
+    rejected(
+        "backlog",
+        listen(ListenOptions::default().backlog(MAX_BACKLOG + 1)),
+    );
+
+    rejected(
+        "max_accepts_per_sec",
+        listen(ListenOptions::default().max_accepts_per_sec(0)),
+    );
+    listen(ListenOptions::default().max_accepts_per_sec(1)).unwrap();
+    listen(ListenOptions::default().max_accepts_per_sec(u32::MAX)).unwrap();
+}
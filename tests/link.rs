@@ -0,0 +1,172 @@
+mod common;
+
+use common::settle;
+use nofio::ConnToken;
+use nofio::Direction;
+use nofio::Event;
+use nofio::Net;
+
+/// A client, and the proxy's end of it, linked to the proxy's end of a server, and the server:
+/// `client -> (near <=> far) -> server`.
+fn proxied(net: &mut Net) -> [ConnToken; 4] {
+    let (client, near) = net.memory_pair().unwrap();
+    let (far, server) = net.memory_pair().unwrap();
+    net.link(near, far).unwrap();
+    [client, near, far, server]
+}
+
+#[test]
+fn what_one_end_reads_the_other_writes() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (client, near) = net.memory_pair().unwrap();
+    let (far, server) = net.memory_pair().unwrap();
+
+    // read before the link is made, and not consumed, so it goes first
+    net.io(client).unwrap().write(b"early ").unwrap();
+    settle(&mut net);
+    assert_eq!(b"early ", net.io(near).unwrap().buf().unwrap());
+    net.link(near, far).unwrap();
+
+    net.io(client).unwrap().write(b"request").unwrap();
+    let events = settle(&mut net);
+    assert!(
+        !events
+            .iter()
+            .any(|ev| matches!(ev, Event::Data(t) if *t == near || *t == far)),
+        "linked ends say nothing"
+    );
+    assert_eq!(
+        b"early request",
+        &net.io(server).unwrap().take_buf().unwrap()[..]
+    );
+
+    net.io(server).unwrap().write(b"response").unwrap();
+    settle(&mut net);
+    assert_eq!(
+        b"response",
+        &net.io(client).unwrap().take_buf().unwrap()[..]
+    );
+}
+
+#[test]
+fn the_end_is_passed_along() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let [client, near, far, server] = proxied(&mut net);
+
+    net.io(server).unwrap().write(b"last words").unwrap();
+    net.io(server).unwrap().close();
+    let events = settle(&mut net);
+    assert!(events
+        .iter()
+        .any(|ev| matches!(ev, Event::Done(t, Direction::Read) if *t == client)));
+    assert_eq!(
+        b"last words",
+        &net.io(client).unwrap().take_buf().unwrap()[..]
+    );
+
+    net.io(client).unwrap().close();
+    let events = settle(&mut net);
+    for token in &[client, near, far, server] {
+        assert!(events
+            .iter()
+            .any(|ev| matches!(ev, Event::Closed(t) if t == token)));
+    }
+}
+
+#[test]
+fn only_two_unlinked_connections_can_be_linked() {
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    let [client, near, far, _server] = proxied(&mut net);
+    assert!(net.link(client, client).is_err());
+    assert!(net.link(client, near).is_err());
+    assert!(net.link(far, client).is_err());
+}
+
+#[test]
+fn spliced_memory_pairs_are_just_linked() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (client, near) = net.memory_pair().unwrap();
+    let (far, server) = net.memory_pair().unwrap();
+    net.link_spliced(near, far).unwrap();
+    net.io(client).unwrap().write(b"request").unwrap();
+    settle(&mut net);
+    assert_eq!(b"request", &net.io(server).unwrap().take_buf().unwrap()[..]);
+}
+
+/// What a proxy does without a link: move bytes from one connection to the other itself.
+#[test]
+fn a_pair_can_be_worked_on_at_once() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (client, near) = net.memory_pair().unwrap();
+    let (far, server) = net.memory_pair().unwrap();
+    net.io(client).unwrap().write(b"request").unwrap();
+    settle(&mut net);
+
+    let mut pair = net.io_pair(near, far).unwrap();
+    let (near_io, far_io) = pair.split();
+    assert_eq!(far, far_io.token());
+    far_io.write(near_io.buf().unwrap()).unwrap();
+    assert_eq!(7, near_io.consume(100).unwrap());
+    drop(pair);
+
+    settle(&mut net);
+    assert!(net.io(near).unwrap().buf().unwrap().is_empty());
+    assert_eq!(b"request", &net.io(server).unwrap().take_buf().unwrap()[..]);
+    assert!(net.io_pair(near, near).is_err());
+}
+
+/// Plenty, so the pipes fill up, and have to wait for the other end.
+#[cfg(target_os = "linux")]
+#[test]
+fn spliced_sockets_carry_everything() {
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::Shutdown;
+    use std::net::TcpListener;
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    let wait = Duration::from_secs(5);
+    let mut net = Net::empty().unwrap();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let far = net.tcp_connect(&server.local_addr().unwrap()).unwrap();
+    let (mut server, _) = server.accept().unwrap();
+    net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = loop {
+        if let Event::Listening { addr, .. } = net.next_timeout(wait).unwrap().unwrap() {
+            break addr;
+        }
+    };
+    let mut client = TcpStream::connect(addr).unwrap();
+    let near = loop {
+        if let Event::NewConnection { token, .. } = net.next_timeout(wait).unwrap().unwrap() {
+            break token;
+        }
+    };
+    net.link_spliced(near, far).unwrap();
+
+    let sent: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let expected = sent.clone();
+    let writer = thread::spawn(move || {
+        client.write_all(&sent).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut back = Vec::new();
+        client.read_to_end(&mut back).unwrap();
+        back
+    });
+    let reader = thread::spawn(move || {
+        let mut got = Vec::new();
+        server.read_to_end(&mut got).unwrap();
+        server.write_all(b"thanks").unwrap();
+        got
+    });
+    let start = Instant::now();
+    while !writer.is_finished() {
+        assert!(start.elapsed() < wait, "still going");
+        net.next_timeout(Duration::from_millis(10)).unwrap();
+    }
+    assert_eq!(b"thanks", &writer.join().unwrap()[..]);
+    assert!(expected == reader.join().unwrap());
+}
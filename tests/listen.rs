@@ -0,0 +1,210 @@
+mod common;
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use common::next;
+use common::WAIT;
+use nofio::Error;
+use nofio::Event;
+use nofio::ListenOptions;
+use nofio::ListenerToken;
+use nofio::Net;
+
+fn listening(net: &mut Net) -> (ListenerToken, SocketAddr) {
+    let token = net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    match next(net) {
+        Event::Listening { token: t, addr } if t == token => (token, addr),
+        other => panic!("expected Listening, not {:?}", other),
+    }
+}
+
+#[test]
+fn each_connection_says_which_listener_took_it() {
+    let mut net = Net::empty().unwrap();
+    let (first, first_addr) = listening(&mut net);
+    let (second, second_addr) = listening(&mut net);
+    assert_ne!(first, second);
+
+    let clients: Vec<(TcpStream, ListenerToken)> = [first_addr, second_addr, first_addr]
+        .iter()
+        .zip([first, second, first])
+        .map(|(addr, listener)| (TcpStream::connect(addr).unwrap(), listener))
+        .collect();
+    let listener_of: HashMap<SocketAddr, ListenerToken> = clients
+        .iter()
+        .map(|(client, listener)| (client.local_addr().unwrap(), *listener))
+        .collect();
+
+    for _ in 0..clients.len() {
+        match next(&mut net) {
+            Event::NewConnection { listener, peer, .. } => {
+                assert_eq!(listener_of[&peer], listener, "from {}", peer)
+            }
+            other => panic!("expected NewConnection, not {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn a_connection_knows_both_its_addresses() {
+    let mut net = Net::empty().unwrap();
+    let (_, addr) = listening(&mut net);
+    let client = TcpStream::connect(addr).unwrap();
+    let (token, peer) = match next(&mut net) {
+        Event::NewConnection { token, peer, .. } => (token, peer),
+        other => panic!("expected NewConnection, not {:?}", other),
+    };
+    assert_eq!(client.local_addr().unwrap(), peer);
+
+    let io = net.io(token).unwrap();
+    assert_eq!(peer, io.peer_addr());
+    assert_eq!(addr, io.local_addr().unwrap());
+}
+
+#[test]
+fn listeners_and_connections_have_their_own_tokens() {
+    let mut net = Net::empty().unwrap();
+    let listener = net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let listening = next(&mut net);
+    assert_eq!(Some(listener.token()), listening.token());
+    let addr = match listening {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    };
+
+    let _client = TcpStream::connect(addr).unwrap();
+    let accepted = next(&mut net);
+    let token = match accepted {
+        Event::NewConnection { token, .. } => token,
+        ref other => panic!("expected NewConnection, not {:?}", other),
+    };
+    assert_eq!(Some(token.token()), accepted.token());
+    assert_ne!(listener.token(), token.token());
+    assert!(net.io(token).is_ok());
+}
+
+#[test]
+fn a_listener_can_be_stopped() {
+    let mut net = Net::empty().unwrap();
+    let (listener, addr) = listening(&mut net);
+    let mut kept = TcpStream::connect(addr).unwrap();
+    let token = match next(&mut net) {
+        Event::NewConnection { token, .. } => token,
+        other => panic!("expected NewConnection, not {:?}", other),
+    };
+
+    net.stop_listening(listener).unwrap();
+    assert!(TcpStream::connect(addr).is_err(), "nobody's listening");
+    assert!(net.stop_listening(listener).is_err());
+
+    kept.write_all(b"still here").unwrap();
+    assert!(matches!(next(&mut net), Event::Data(t) if t == token));
+    assert_eq!(b"still here", net.io(token).unwrap().buf().unwrap());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn nets_can_share_a_port() {
+    use std::io;
+    use std::time::Instant;
+
+    let shared = ListenOptions::default().reuse_port(true);
+    let mut nets = [Net::empty().unwrap(), Net::empty().unwrap()];
+    nets[0]
+        .tcp_listen_all(&["127.0.0.1:0".parse().unwrap()], &shared)
+        .unwrap();
+    let addr = match next(&mut nets[0]) {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    nets[1].tcp_listen_all(&[addr], &shared).unwrap();
+    assert!(matches!(next(&mut nets[1]), Event::Listening { addr: a, .. } if a == addr));
+    let e = Net::empty()
+        .unwrap()
+        .tcp_listen_all(&[addr], &ListenOptions::default())
+        .unwrap_err();
+    assert_eq!(
+        Some(io::ErrorKind::AddrInUse),
+        e.io_error().map(|e| e.kind()),
+        "only for those which asked"
+    );
+
+    let clients: Vec<TcpStream> = (0..20).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    let mut accepted = 0;
+    let start = Instant::now();
+    while accepted < clients.len() {
+        assert!(start.elapsed() < WAIT, "{} accepted", accepted);
+        for net in &mut nets {
+            if let Some(Event::NewConnection { .. }) =
+                net.next_timeout(Duration::from_millis(10)).unwrap()
+            {
+                accepted += 1;
+            }
+        }
+    }
+}
+
+#[test]
+fn one_listener_can_take_both_families() {
+    let mut net = Net::empty().unwrap();
+    let any = "[::]:0".parse().unwrap();
+    let dual = ListenOptions::default().only_v6(false);
+    net.tcp_listen_all(&[any], &dual).unwrap();
+    let port = match next(&mut net) {
+        Event::Listening { addr, .. } => addr.port(),
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    let _client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    match next(&mut net) {
+        Event::NewConnection { peer, .. } => {
+            assert_eq!("[::ffff:127.0.0.1]", &peer.to_string()[..18], "{}", peer)
+        }
+        other => panic!("expected NewConnection, not {:?}", other),
+    }
+
+    let v6 = ListenOptions::default().only_v6(true);
+    net.tcp_listen_all(&[any], &v6).unwrap();
+    let port = match next(&mut net) {
+        Event::Listening { addr, .. } => addr.port(),
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+    let v4 = "127.0.0.1:0".parse().unwrap();
+    assert!(matches!(
+        net.tcp_listen_all(&[v4], &v6),
+        Err(Error::InvalidConfig {
+            setting: "only_v6",
+            ..
+        })
+    ));
+}
+
+/// Lingering after we closed first is what `reuse_address` is for.
+#[cfg(unix)]
+#[test]
+fn an_address_can_be_reused_while_connections_linger() {
+    let mut net = Net::empty().unwrap();
+    let (listener, addr) = listening(&mut net);
+    let mut client = TcpStream::connect(addr).unwrap();
+    let token = match next(&mut net) {
+        Event::NewConnection { token, .. } => token,
+        other => panic!("expected NewConnection, not {:?}", other),
+    };
+    net.io(token).unwrap().close();
+    net.next_timeout(Duration::from_millis(10)).unwrap();
+    client.set_read_timeout(Some(WAIT)).unwrap();
+    assert_eq!(0, client.read(&mut [0; 1]).unwrap(), "we closed first");
+    drop(client);
+    while !matches!(next(&mut net), Event::Closed(t) if t == token) {}
+    net.stop_listening(listener).unwrap();
+
+    let strict = ListenOptions::default().reuse_address(false);
+    assert!(net.tcp_listen_all(&[addr], &strict).is_err());
+    net.tcp_listen_all(&[addr], &ListenOptions::default())
+        .unwrap();
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use common::settle;
+use nofio::Direction;
+use nofio::Event;
+use nofio::Net;
+
+/// What's happened, without pumping; unconsumed data is reported every round.
+fn events(net: &mut Net) -> Vec<Event> {
+    (0..10).filter_map(|_| net.try_next().unwrap()).collect()
+}
+
+fn has(events: &[Event], want: impl Fn(&Event) -> bool) -> bool {
+    events.iter().any(want)
+}
+
+#[test]
+fn nothing_moves_until_pumped() {
+    let mut net = Net::empty().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a).unwrap().write(b"hello").unwrap();
+    let before = events(&mut net);
+    assert!(!has(&before, |ev| matches!(ev, Event::Data(_))));
+
+    assert_eq!(5, net.pump());
+    let after = events(&mut net);
+    assert!(has(&after, |ev| matches!(ev, Event::Data(t) if *t == b)));
+    assert_eq!(b"hello", net.io(b).unwrap().buf().unwrap());
+    assert_eq!(0, net.pump());
+}
+
+#[test]
+fn each_end_has_the_others_address() {
+    let mut net = Net::empty().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    let a_local = net.io(a).unwrap().local_addr().unwrap();
+    assert_eq!(a_local, net.io(b).unwrap().peer_addr());
+    assert_ne!(a_local, net.io(a).unwrap().peer_addr());
+}
+
+/// Closing is as over TCP: the other end reads to the end, and is removed once it closes too.
+#[test]
+fn the_end_arrives_after_the_data() {
+    let mut net = Net::empty().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a).unwrap().write(b"bye").unwrap();
+    net.io(a).unwrap().close();
+    let seen = settle(&mut net);
+    assert!(has(
+        &seen,
+        |ev| matches!(ev, Event::Done(t, Direction::Read) if *t == b)
+    ));
+    assert!(!has(&seen, |ev| matches!(ev, Event::Closed(t) if *t == a)));
+    assert_eq!(b"bye", net.io(b).unwrap().buf().unwrap());
+
+    net.io(b).unwrap().close();
+    let seen = settle(&mut net);
+    assert!(has(&seen, |ev| matches!(ev, Event::Closed(t) if *t == a)));
+    assert!(has(&seen, |ev| matches!(ev, Event::Closed(t) if *t == b)));
+}
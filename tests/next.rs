@@ -0,0 +1,58 @@
+mod common;
+
+use std::net::TcpStream;
+use std::time::Duration;
+use std::time::Instant;
+
+use common::WAIT;
+use nofio::Event;
+use nofio::Net;
+
+#[test]
+fn waiting_can_be_bounded_or_skipped() {
+    let mut net = Net::empty().unwrap();
+    let start = Instant::now();
+    assert!(net.try_next().unwrap().is_none());
+    assert!(start.elapsed() < Duration::from_millis(50));
+
+    let start = Instant::now();
+    assert!(net
+        .next_timeout(Duration::from_millis(50))
+        .unwrap()
+        .is_none());
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    // something to report ends the wait early
+    net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let start = Instant::now();
+    assert!(matches!(
+        net.next_timeout(WAIT).unwrap(),
+        Some(Event::Listening { .. })
+    ));
+    assert!(start.elapsed() < WAIT);
+}
+
+#[test]
+fn everything_waiting_comes_at_once() {
+    let mut net = Net::empty().unwrap();
+    net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let mut events = Vec::new();
+    assert_eq!(1, net.poll_events(&mut events, Some(WAIT)).unwrap());
+    let addr = match events[0] {
+        Event::Listening { addr, .. } => addr,
+        ref other => panic!("expected Listening, not {:?}", other),
+    };
+
+    let _clients: Vec<TcpStream> = (0..3).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    assert_eq!(3, net.poll_events(&mut events, Some(WAIT)).unwrap());
+    assert_eq!(4, events.len(), "appended");
+    assert!(events[1..]
+        .iter()
+        .all(|ev| matches!(ev, Event::NewConnection { .. })));
+
+    assert_eq!(
+        0,
+        net.poll_events(&mut events, Some(Duration::from_millis(10)))
+            .unwrap()
+    );
+}
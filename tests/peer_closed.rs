@@ -0,0 +1,170 @@
+mod common;
+
+use std::io::Read;
+use std::io::Write;
+use std::net::Shutdown;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use common::next;
+use common::settle;
+use common::wait_for;
+use common::WAIT;
+use nofio::ConnToken;
+use nofio::Direction;
+use nofio::Event;
+use nofio::Net;
+use nofio::PeerClosedPolicy;
+
+/// A connection from a plain socket, so the peer can shut down each half when it likes.
+fn accepted(net: &mut Net) -> (ConnToken, TcpStream) {
+    net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = match next(net) {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    let peer = TcpStream::connect(addr).unwrap();
+    peer.set_read_timeout(Some(WAIT)).unwrap();
+    match next(net) {
+        Event::NewConnection { token, .. } => (token, peer),
+        other => panic!("expected NewConnection, not {:?}", other),
+    }
+}
+
+fn read_done(token: ConnToken) -> impl Fn(&Event) -> bool {
+    move |ev| matches!(ev, Event::Done(t, Direction::Read) if *t == token)
+}
+
+#[test]
+fn continue_writing_reaches_a_half_closed_peer() {
+    let mut net = Net::empty().unwrap();
+    let (token, mut peer) = accepted(&mut net);
+
+    peer.shutdown(Shutdown::Write).unwrap();
+    wait_for(&mut net, read_done(token));
+
+    net.io(token).unwrap().write(b"still here").unwrap();
+    wait_for(
+        &mut net,
+        |ev| matches!(ev, Event::Flushed(t) if *t == token),
+    );
+    let mut got = [0; 10];
+    peer.read_exact(&mut got).unwrap();
+    assert_eq!(b"still here", &got);
+}
+
+#[test]
+fn continue_writing_reports_the_peer_going_away() {
+    let mut net = Net::empty().unwrap();
+    let (token, peer) = accepted(&mut net);
+
+    peer.shutdown(Shutdown::Write).unwrap();
+    wait_for(&mut net, read_done(token));
+    drop(peer);
+
+    // the first write only provokes the reset; one of the next fails
+    let mut error = None;
+    for _ in 0..100 {
+        if net
+            .io(token)
+            .and_then(|mut io| io.write(b"anyone?"))
+            .is_err()
+        {
+            break;
+        }
+        if let Some(Event::Error(t, e)) = net.next_timeout(Duration::from_millis(20)).unwrap() {
+            assert_eq!(token, t);
+            error = Some(e);
+            break;
+        }
+    }
+    let error = error.expect("the failed write is reported");
+    assert!(
+        [
+            std::io::ErrorKind::BrokenPipe,
+            std::io::ErrorKind::ConnectionReset
+        ]
+        .contains(&error.kind()),
+        "{:?}",
+        error
+    );
+    match next(&mut net) {
+        Event::Done(t, Direction::Write) => assert_eq!(token, t),
+        other => panic!("expected the write side's Done, not {:?}", other),
+    }
+    wait_for(&mut net, |ev| matches!(ev, Event::Closed(t) if *t == token));
+}
+
+#[test]
+fn fail_fast_refuses_writes_after_the_peer_finishes() {
+    let mut net = Net::empty().unwrap();
+    let (token, peer) = accepted(&mut net);
+
+    let mut io = net.io(token).unwrap();
+    io.set_peer_closed_policy(PeerClosedPolicy::FailFast);
+    io.write(b"before").unwrap();
+
+    peer.shutdown(Shutdown::Write).unwrap();
+    wait_for(&mut net, read_done(token));
+
+    let e = net.io(token).unwrap().write(b"after").unwrap_err();
+    assert_eq!(Some(token.token()), e.token());
+}
+
+#[test]
+fn drain_and_close_flushes_then_closes() {
+    let mut net = Net::empty().unwrap();
+    let (token, mut peer) = accepted(&mut net);
+    net.io(token)
+        .unwrap()
+        .set_peer_closed_policy(PeerClosedPolicy::DrainAndClose);
+
+    peer.shutdown(Shutdown::Write).unwrap();
+    wait_for(&mut net, read_done(token));
+    net.io(token).unwrap().write(b"goodbye").unwrap();
+    wait_for(&mut net, |ev| matches!(ev, Event::Closed(t) if *t == token));
+
+    let mut got = Vec::new();
+    peer.read_to_end(&mut got).unwrap();
+    assert_eq!(b"goodbye", &got[..]);
+}
+
+#[test]
+fn peer_shutting_down_reading_can_still_send() {
+    let mut net = Net::empty().unwrap();
+    let (token, mut peer) = accepted(&mut net);
+
+    peer.shutdown(Shutdown::Read).unwrap();
+    peer.write_all(b"one way").unwrap();
+    wait_for(&mut net, |ev| matches!(ev, Event::Data(t) if *t == token));
+    assert_eq!(b"one way", net.io(token).unwrap().buf().unwrap());
+
+    peer.shutdown(Shutdown::Write).unwrap();
+    wait_for(&mut net, read_done(token));
+    assert_eq!(
+        b"one way",
+        net.io(token).unwrap().buf().unwrap(),
+        "what arrived before the end is kept"
+    );
+}
+
+#[test]
+fn fail_fast_in_memory() {
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a)
+        .unwrap()
+        .set_peer_closed_policy(PeerClosedPolicy::FailFast);
+
+    net.io(b).unwrap().close_write();
+    let events = settle(&mut net);
+    assert!(
+        events
+            .iter()
+            .any(|ev| matches!(ev, Event::Done(t, Direction::Read) if *t == a)),
+        "{:?}",
+        events
+    );
+    assert!(net.io(a).unwrap().write(b"too late").is_err());
+    assert!(net.io(b).unwrap().write(b"").is_err(), "b closed its side");
+}
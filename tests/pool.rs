@@ -0,0 +1,92 @@
+mod common;
+
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use common::wait_for;
+use nofio::ConnToken;
+use nofio::Event;
+use nofio::Net;
+use nofio::PoolOptions;
+
+/// A pooled connection to `server`, once it's connected, and the server's end, if it's new.
+fn connected(net: &mut Net, server: &TcpListener) -> (ConnToken, Option<TcpStream>) {
+    let token = net
+        .pooled_connect(&server.local_addr().unwrap(), &PoolOptions::default())
+        .unwrap();
+    wait_for(net, |ev| matches!(ev, Event::Connected(t) if *t == token));
+    let accepted = server.accept().ok().map(|(stream, _)| stream);
+    (token, accepted)
+}
+
+fn listener() -> TcpListener {
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    server.set_nonblocking(true).unwrap();
+    server
+}
+
+#[test]
+fn a_released_connection_is_used_again() {
+    let server = listener();
+    let mut net = Net::empty().unwrap();
+    let (first, accepted) = connected(&mut net, &server);
+    assert!(accepted.is_some());
+    net.io(first).unwrap().release_to_pool();
+    assert_eq!(1, net.metrics().pool.idle);
+
+    let (second, accepted) = connected(&mut net, &server);
+    assert_eq!(first, second);
+    assert!(accepted.is_none(), "connected again");
+    let pool = net.metrics().pool;
+    assert_eq!((1, 1, 0), (pool.hits, pool.misses, pool.idle));
+}
+
+#[test]
+fn a_connection_the_server_closed_is_not_handed_out() {
+    let server = listener();
+    let mut net = Net::empty().unwrap();
+    let (first, accepted) = connected(&mut net, &server);
+    net.io(first).unwrap().release_to_pool();
+    drop(accepted);
+    // nothing is reported for an idle connection, so give the end time to arrive
+    for _ in 0..500 {
+        if 0 == net.metrics().pool.idle {
+            break;
+        }
+        assert!(net
+            .next_timeout(Duration::from_millis(10))
+            .unwrap()
+            .is_none());
+    }
+    assert_eq!(1, net.metrics().pool.evictions);
+
+    let (_, accepted) = connected(&mut net, &server);
+    assert!(accepted.is_some(), "a new connection");
+    assert_eq!(2, net.metrics().pool.misses);
+}
+
+#[test]
+fn only_so_many_wait_in_the_pool() {
+    let server = listener();
+    let mut net = Net::empty().unwrap();
+    let options = PoolOptions {
+        max_idle_per_target: 1,
+        ..PoolOptions::default()
+    };
+    let addr = server.local_addr().unwrap();
+    let tokens: Vec<ConnToken> = (0..2)
+        .map(|_| net.pooled_connect(&addr, &options).unwrap())
+        .collect();
+    for token in &tokens {
+        wait_for(
+            &mut net,
+            |ev| matches!(ev, Event::Connected(t) if t == token),
+        );
+    }
+    for token in &tokens {
+        net.io(*token).unwrap().release_to_pool();
+    }
+    let pool = net.metrics().pool;
+    assert_eq!((1, 1), (pool.idle, pool.evictions));
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use std::io;
+use std::io::Write;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use common::next;
+use common::wait_for;
+use nofio::ConnToken;
+use nofio::Event;
+use nofio::ListenOptions;
+use nofio::Net;
+
+/// A connection through a listener expecting PROXY headers, from a plain socket standing in
+/// for the load balancer.
+fn accepted(net: &mut Net) -> (ConnToken, TcpStream) {
+    let options = ListenOptions::default().proxy_protocol(true);
+    net.tcp_listen_all(&["127.0.0.1:0".parse().unwrap()], &options)
+        .unwrap();
+    let addr = match next(net) {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    let balancer = TcpStream::connect(addr).unwrap();
+    match next(net) {
+        Event::NewConnection { token, .. } => (token, balancer),
+        other => panic!("expected NewConnection, not {:?}", other),
+    }
+}
+
+fn data(token: ConnToken) -> impl Fn(&Event) -> bool {
+    move |ev| matches!(ev, Event::Data(t) if *t == token)
+}
+
+#[test]
+fn a_version_1_header_gives_the_client() {
+    let mut net = Net::empty().unwrap();
+    let (token, mut balancer) = accepted(&mut net);
+    assert_eq!(None, net.io(token).unwrap().proxied_peer_addr());
+
+    // split, so the header has to wait to be whole
+    balancer.write_all(b"PROXY TCP4 192.0.2.7 ").unwrap();
+    balancer.flush().unwrap();
+    thread::sleep(Duration::from_millis(20));
+    balancer
+        .write_all(b"198.51.100.1 56324 443\r\nhello")
+        .unwrap();
+    wait_for(&mut net, data(token));
+
+    let io = net.io(token).unwrap();
+    let client: SocketAddr = "192.0.2.7:56324".parse().unwrap();
+    assert_eq!(Some(client), io.proxied_peer_addr());
+    assert_eq!(b"hello", io.buf().unwrap());
+}
+
+#[test]
+fn a_version_2_header_gives_the_client() {
+    let mut net = Net::empty().unwrap();
+    let (token, mut balancer) = accepted(&mut net);
+
+    let mut header = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    // version 2, PROXY; TCP over IPv6
+    header.extend_from_slice(&[0x21, 0x21, 0, 36]);
+    let client: Ipv6Addr = "2001:db8::1".parse().unwrap();
+    header.extend_from_slice(&client.octets());
+    header.extend_from_slice(&[0; 16]);
+    header.extend_from_slice(&[0x1f, 0x90, 0x01, 0xbb]);
+    header.extend_from_slice(b"hello");
+    balancer.write_all(&header).unwrap();
+    wait_for(&mut net, data(token));
+
+    let io = net.io(token).unwrap();
+    assert_eq!(
+        Some(SocketAddr::new(client.into(), 8080)),
+        io.proxied_peer_addr()
+    );
+    assert_eq!(b"hello", io.buf().unwrap());
+}
+
+#[test]
+fn a_health_check_has_no_client() {
+    let mut net = Net::empty().unwrap();
+    let (token, mut balancer) = accepted(&mut net);
+    balancer.write_all(b"PROXY UNKNOWN\r\nping").unwrap();
+    wait_for(&mut net, data(token));
+
+    let io = net.io(token).unwrap();
+    assert_eq!(None, io.proxied_peer_addr());
+    assert_eq!(b"ping", io.buf().unwrap());
+}
+
+#[test]
+fn anything_else_is_refused() {
+    let mut net = Net::empty().unwrap();
+    let (token, mut balancer) = accepted(&mut net);
+    balancer.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    match wait_for(
+        &mut net,
+        |ev| matches!(ev, Event::Error(t, _) | Event::Data(t) if *t == token),
+    ) {
+        Event::Error(_, e) => assert_eq!(io::ErrorKind::InvalidData, e.kind()),
+        other => panic!("expected Error, not {:?}", other),
+    }
+}
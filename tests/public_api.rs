@@ -0,0 +1,417 @@
+//! A snapshot of everything the crate exports, read from the source, so that anything which
+//! changes, appears or disappears by accident fails here. After a deliberate change,
+//! regenerate it with `NOFIO_BLESS=1 cargo test --test public_api`, and review the diff.
+//!
+//! This leans on the source being `rustfmt`ed: items start in the first column, and their
+//! members four spaces in.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+const SNAPSHOT: &str = "tests/public_api.txt";
+
+/// Attributes which change what an item means to a caller.
+const KEPT_ATTRIBUTES: &[&str] = &[
+    "#[cfg",
+    "#[derive",
+    "#[deprecated",
+    "#[must_use",
+    "#[non_exhaustive",
+    "#[repr",
+];
+
+struct Module {
+    /// Relative to `src`, for the snapshot.
+    name: String,
+    lines: Vec<String>,
+    /// Every module from the crate root down to this one is `pub`.
+    public: bool,
+}
+
+#[test]
+fn public_api_matches_the_snapshot() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut modules = Vec::new();
+    walk(
+        &root.join("src"),
+        &root.join("src/lib.rs"),
+        true,
+        &mut modules,
+    );
+    let exported = exported_names(&modules);
+
+    let mut api = String::new();
+    for module in &modules {
+        for item in items(module, &exported) {
+            api.push_str(&module.name);
+            api.push_str(": ");
+            api.push_str(&item);
+            api.push('\n');
+        }
+    }
+
+    let path = root.join(SNAPSHOT);
+    if env::var_os("NOFIO_BLESS").is_some() {
+        fs::write(&path, &api).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_default();
+    if expected == api {
+        return;
+    }
+    let old: HashSet<&str> = expected.lines().collect();
+    let new: HashSet<&str> = api.lines().collect();
+    let mut diff = String::new();
+    for line in expected.lines().filter(|line| !new.contains(line)) {
+        diff.push_str(&format!("- {}\n", line));
+    }
+    for line in api.lines().filter(|line| !old.contains(line)) {
+        diff.push_str(&format!("+ {}\n", line));
+    }
+    panic!(
+        "the public API has changed; if that's deliberate, rerun with NOFIO_BLESS=1:\n{}",
+        diff
+    );
+}
+
+/// `file`, then every module it declares, depth first, in the order they're declared.
+fn walk(src: &Path, file: &Path, public: bool, modules: &mut Vec<Module>) {
+    let text = fs::read_to_string(file).unwrap();
+    let lines: Vec<String> = text.lines().map(str::to_string).collect();
+    // a `foo.rs` keeps its submodules in `foo/`; `lib.rs` and `mod.rs` beside themselves
+    let dir = match file.file_name().and_then(|name| name.to_str()) {
+        Some("lib.rs") | Some("mod.rs") => file.parent().unwrap().to_path_buf(),
+        _ => file.with_extension(""),
+    };
+    modules.push(Module {
+        name: file
+            .strip_prefix(src)
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/"),
+        lines: lines.clone(),
+        public,
+    });
+
+    for line in &lines {
+        let (is_pub, rest) = match line.strip_prefix("pub mod ") {
+            Some(rest) => (true, rest),
+            None => match line.strip_prefix("mod ") {
+                Some(rest) => (false, rest),
+                None => continue,
+            },
+        };
+        let name = match rest.strip_suffix(';') {
+            Some(name) => name,
+            None => continue,
+        };
+        let child = [
+            dir.join(format!("{}.rs", name)),
+            dir.join(name).join("mod.rs"),
+        ]
+        .iter()
+        .find(|path| path.exists())
+        .cloned()
+        .unwrap_or_else(|| panic!("can't find mod {} from {:?}", name, file));
+        walk(src, &child, public && is_pub, modules);
+    }
+}
+
+/// Names which are visible outside the crate, under some path: whatever a public module
+/// defines or re-exports.
+fn exported_names(modules: &[Module]) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for module in modules.iter().filter(|module| module.public) {
+        for line in &module.lines {
+            if let Some(path) = line.trim_start().strip_prefix("pub use ") {
+                let path = path.trim_end_matches(';');
+                let name = match path.rsplit_once(" as ") {
+                    Some((_, alias)) => alias,
+                    None => path.rsplit("::").next().unwrap(),
+                };
+                names.insert(name.to_string());
+            } else if line.starts_with("pub ") {
+                // only items in the first column; methods don't name types
+                if let Some(name) = item_name(line) {
+                    names.insert(name);
+                }
+            }
+        }
+    }
+    names
+}
+
+/// What kind of block a line in the first column opened, until its closing brace.
+enum Block {
+    /// Members four spaces in are listed, after the label.
+    Listed(Kind, String),
+    Unlisted,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+    Impl,
+    TraitImpl,
+    Trait,
+    Enum,
+    Struct,
+    Mod,
+}
+
+fn items(module: &Module, exported: &HashSet<String>) -> Vec<String> {
+    let lines = &module.lines;
+    let mut items = Vec::new();
+    let mut block = Block::Unlisted;
+    let mut attributes: Vec<String> = Vec::new();
+    // a struct-like enum variant, whose fields are eight spaces in
+    let mut variant: Option<String> = None;
+
+    let mut i = 0;
+    while i < lines.len() {
+        let line = &lines[i];
+        let indent = line.len() - line.trim_start().len();
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with("//") {
+            i += 1;
+            continue;
+        }
+
+        if trimmed.starts_with("#[") || trimmed.starts_with("#![") {
+            let (attribute, next) = joined(lines, i, |s| s.ends_with(']'));
+            let member = 4 == indent && matches!(block, Block::Listed(..));
+            if (0 == indent || member)
+                && KEPT_ATTRIBUTES
+                    .iter()
+                    .any(|kept| attribute.starts_with(kept))
+            {
+                attributes.push(attribute);
+            }
+            i = next;
+            continue;
+        }
+
+        if 0 == indent {
+            if trimmed.starts_with('}') {
+                block = Block::Unlisted;
+                attributes.clear();
+                i += 1;
+                continue;
+            }
+            let (signature, next) = joined(lines, i, ends_item);
+            i = next;
+            let opens = signature.ends_with('{');
+            let signature = tidy(&signature);
+            let listed =
+                module.public || item_name(&signature).is_some_and(|n| exported.contains(&n));
+            let is_pub = signature.starts_with("pub ") && !signature.starts_with("pub(");
+
+            if signature.starts_with("impl") || signature.starts_with("unsafe impl") {
+                let (kind, self_type) = impl_type(&signature);
+                block = if opens && exported.contains(&self_type) {
+                    if Kind::TraitImpl == kind {
+                        items.push(with_attributes(&attributes, &signature));
+                    }
+                    Block::Listed(kind, signature)
+                } else {
+                    Block::Unlisted
+                };
+            } else if is_pub && (listed || signature.starts_with("pub use ")) {
+                items.push(with_attributes(&attributes, &signature));
+                block = match opens.then(|| item_kind(&signature)).flatten() {
+                    Some(kind) => Block::Listed(kind, signature),
+                    None => Block::Unlisted,
+                };
+            } else if opens {
+                block = Block::Unlisted;
+            }
+            attributes.clear();
+            continue;
+        }
+
+        let (kind, label) = match &block {
+            Block::Listed(kind, label) => (*kind, label.clone()),
+            Block::Unlisted => {
+                i += 1;
+                continue;
+            }
+        };
+
+        if 8 == indent && Kind::Enum == kind {
+            if let Some(variant) = &variant {
+                let (field, next) = joined(lines, i, |s| s.ends_with(','));
+                items.push(format!("{}: {} {}", label, variant, tidy(&field)));
+                i = next;
+                continue;
+            }
+        }
+        if 4 != indent {
+            i += 1;
+            continue;
+        }
+        if trimmed.starts_with('}') {
+            variant = None;
+            i += 1;
+            continue;
+        }
+
+        let member = match kind {
+            Kind::Impl => trimmed.starts_with("pub ") && !trimmed.starts_with("pub("),
+            Kind::TraitImpl => trimmed.starts_with("type "),
+            Kind::Trait => !trimmed.starts_with("pub"),
+            Kind::Enum => true,
+            Kind::Struct => trimmed.starts_with("pub ") && !trimmed.starts_with("pub("),
+            Kind::Mod => trimmed.starts_with("pub "),
+        };
+        let (signature, next) = match kind {
+            Kind::Enum | Kind::Struct => joined(lines, i, |s| {
+                (s.ends_with(',') || s.ends_with('{')) && balanced(s)
+            }),
+            _ => joined(lines, i, ends_item),
+        };
+        i = next;
+        if member {
+            let opens = signature.ends_with('{');
+            let signature = tidy(&signature);
+            if Kind::Enum == kind && opens {
+                variant = Some(signature.clone());
+            }
+            items.push(format!(
+                "{}: {}",
+                label,
+                with_attributes(&attributes, &signature)
+            ));
+        }
+        attributes.clear();
+    }
+    items
+}
+
+/// The line at `start`, with those after it until one `done` likes, and the index after that.
+fn joined(lines: &[String], start: usize, done: impl Fn(&str) -> bool) -> (String, usize) {
+    let mut joined = String::new();
+    let mut i = start;
+    while i < lines.len() {
+        if !joined.is_empty() {
+            joined.push(' ');
+        }
+        joined.push_str(lines[i].trim());
+        i += 1;
+        if done(&joined) {
+            break;
+        }
+    }
+    (joined, i)
+}
+
+/// A signature ends with the block it opens, or on its own, or with an empty body.
+fn ends_item(s: &str) -> bool {
+    (s.ends_with('{') || s.ends_with(';') || s.ends_with("{}")) && balanced(s)
+}
+
+fn balanced(s: &str) -> bool {
+    let opened = s.matches(['(', '[']).count();
+    let closed = s.matches([')', ']']).count();
+    opened == closed
+}
+
+/// One line, as rustfmt would write it if it were allowed to be as long as it liked, without
+/// any body.
+fn tidy(signature: &str) -> String {
+    let mut tidy = signature
+        .replace("( ", "(")
+        .replace(", )", ")")
+        .replace(",)", ")")
+        .replace(", {", " {");
+    for end in [" {}", " {", "{", ";", ","] {
+        if let Some(stripped) = tidy.strip_suffix(end) {
+            tidy = stripped.to_string();
+            break;
+        }
+    }
+    tidy
+}
+
+fn with_attributes(attributes: &[String], signature: &str) -> String {
+    let mut line = attributes.join(" ");
+    if !line.is_empty() {
+        line.push(' ');
+    }
+    line.push_str(signature);
+    line
+}
+
+/// For `pub struct Foo<T> {` and so on, `Foo`.
+fn item_name(signature: &str) -> Option<String> {
+    let words: Vec<&str> = signature.split_whitespace().collect();
+    let keyword = words.iter().position(|word| {
+        matches!(
+            *word,
+            "fn" | "struct" | "enum" | "trait" | "type" | "static" | "mod" | "union" | "const"
+        )
+    })?;
+    // a `const fn` is named after the `fn`
+    let keyword = match words.get(keyword + 1) {
+        Some(&"fn") => keyword + 1,
+        _ => keyword,
+    };
+    let name: String = words
+        .get(keyword + 1)?
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || '_' == *c)
+        .collect();
+    Some(name).filter(|name| !name.is_empty())
+}
+
+fn item_kind(signature: &str) -> Option<Kind> {
+    let words: Vec<&str> = signature.split_whitespace().collect();
+    let keyword = words
+        .iter()
+        .find(|word| matches!(**word, "struct" | "enum" | "trait" | "mod"))?;
+    Some(match *keyword {
+        "struct" => Kind::Struct,
+        "enum" => Kind::Enum,
+        "trait" => Kind::Trait,
+        _ => Kind::Mod,
+    })
+}
+
+/// Whether an `impl` is of a trait, and the name of the type it's for.
+fn impl_type(signature: &str) -> (Kind, String) {
+    let rest = signature
+        .trim_start_matches("unsafe ")
+        .trim_start_matches("impl");
+    let rest = skip_generics(rest);
+    let (kind, ty) = match rest.split_once(" for ") {
+        Some((_, ty)) => (Kind::TraitImpl, ty),
+        None => (Kind::Impl, rest),
+    };
+    let ty = ty.trim().trim_start_matches('&');
+    let path: String = ty
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || '_' == *c || ':' == *c)
+        .collect();
+    (kind, path.rsplit("::").next().unwrap().to_string())
+}
+
+/// `<T: Foo<U>> Bar` to ` Bar`.
+fn skip_generics(s: &str) -> &str {
+    if !s.starts_with('<') {
+        return s;
+    }
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => {
+                depth -= 1;
+                if 0 == depth {
+                    return &s[i + 1..];
+                }
+            }
+            _ => (),
+        }
+    }
+    s
+}
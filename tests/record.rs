@@ -0,0 +1,86 @@
+mod common;
+
+use std::io;
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use common::next;
+use common::wait_for;
+use nofio::ConnToken;
+use nofio::Event;
+use nofio::Net;
+
+/// Somewhere to record to, which can still be read once the `Net` has it.
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A shouting echo server: what it writes depends only on what it reads.
+fn answer(net: &mut Net, token: ConnToken) {
+    let mut io = net.io(token).unwrap();
+    let got = io.take_buf().unwrap();
+    io.write(&got.to_ascii_uppercase()).unwrap();
+}
+
+/// A recording of a client saying `hello`, then `again`.
+fn recorded() -> Vec<u8> {
+    let recording = Shared::default();
+    let mut net = Net::empty().unwrap();
+    net.record(recording.clone());
+    net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = match next(&mut net) {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    let mut client = TcpStream::connect(addr).unwrap();
+    let token = match next(&mut net) {
+        Event::NewConnection { token, .. } => token,
+        other => panic!("expected NewConnection, not {:?}", other),
+    };
+    for said in &[&b"hello"[..], b"again"] {
+        client.write_all(said).unwrap();
+        wait_for(&mut net, |ev| matches!(ev, Event::Data(t) if *t == token));
+        answer(&mut net, token);
+    }
+    net.stop_recording().unwrap();
+    let recording = recording.0.lock().unwrap().clone();
+    recording
+}
+
+#[test]
+fn a_replay_gets_the_same_answers() {
+    let recording = recorded();
+    let text = String::from_utf8(recording.clone()).unwrap();
+    assert!(text.contains("68656c6c6f"), "hello, in hex: {}", text);
+
+    let mut replay = Net::builder().build_replay(&recording[..]).unwrap();
+    let mut conn = None;
+    while let Some(ev) = replay.next().unwrap() {
+        match ev {
+            Event::NewConnection { token, .. } => conn = Some(token),
+            Event::Data(token) => answer(replay.net(), token),
+            _ => (),
+        }
+    }
+    let conn = conn.expect("the connection was replayed");
+    assert_eq!(b"HELLOAGAIN", replay.sent(conn));
+}
+
+#[test]
+fn a_broken_recording_is_refused() {
+    assert!(Net::builder()
+        .build_replay::<()>(&b"12 nonsense\n"[..])
+        .is_err());
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use std::io;
+use std::io::Read;
+use std::net::TcpStream;
+use std::thread;
+
+use common::next;
+use common::WAIT;
+use nofio::ConnToken;
+use nofio::Event;
+use nofio::Net;
+
+/// A connection from a plain socket, so what the `Net` writes can be read back.
+fn accepted(net: &mut Net) -> (ConnToken, TcpStream) {
+    net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = match next(net) {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    let peer = TcpStream::connect(addr).unwrap();
+    peer.set_read_timeout(Some(WAIT)).unwrap();
+    match next(net) {
+        Event::NewConnection { token, .. } => (token, peer),
+        other => panic!("expected NewConnection, not {:?}", other),
+    }
+}
+
+/// Nothing but the command can end the wait, so it's only seen if the `Net` was woken.
+fn remote_write_arrives(mut net: Net) {
+    let (token, mut peer) = accepted(&mut net);
+
+    let remote = net.remote();
+    let sender = thread::spawn(move || remote.write(token, b"from afar"));
+    match next(&mut net) {
+        Event::Flushed(t) => assert_eq!(token, t),
+        other => panic!("expected Flushed, not {:?}", other),
+    }
+    sender.join().unwrap();
+
+    let mut got = [0; 9];
+    peer.read_exact(&mut got).unwrap();
+    assert_eq!(b"from afar", &got);
+}
+
+#[test]
+fn a_remote_wakes_the_default_reactor() {
+    remote_write_arrives(Net::empty().unwrap());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn a_remote_wakes_epoll() {
+    let reactor = nofio::reactor::Epoll::new().unwrap();
+    remote_write_arrives(Net::builder().build_with_reactor(reactor).unwrap());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn epoll_refuses_what_only_mio_can_watch() {
+    let reactor = nofio::reactor::Epoll::new().unwrap();
+    let mut net: Net = Net::builder().build_with_reactor(reactor).unwrap();
+    let (registration, _readiness) = mio::Registration::new2();
+    let err = net
+        .register_source(registration, nofio::reactor::Interest::READABLE)
+        .unwrap_err();
+    let cause = err.io_error().expect("the reactor's error");
+    assert_eq!(io::ErrorKind::Unsupported, cause.kind());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn epoll_watches_a_registered_fd() {
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    let reactor = nofio::reactor::Epoll::new().unwrap();
+    let mut net: Net = Net::builder().build_with_reactor(reactor).unwrap();
+    let (mut ours, theirs) = UnixStream::pair().unwrap();
+    let token = net
+        .register_fd(theirs.as_raw_fd(), nofio::reactor::Interest::READABLE)
+        .unwrap();
+    io::Write::write_all(&mut ours, b"!").unwrap();
+    match next(&mut net) {
+        Event::Ready(t) => assert_eq!(token, t),
+        other => panic!("expected Ready, not {:?}", other),
+    }
+    net.deregister_source(token).unwrap();
+}
+
+#[test]
+fn a_remote_reaches_a_simulated_net() {
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+
+    net.remote().write(a, b"hi");
+    net.remote().close(a);
+    let mut got = Vec::new();
+    for _ in 0..10 {
+        net.pump();
+        if let Some(Event::Data(t)) = net.try_next().unwrap() {
+            assert_eq!(b, t);
+            got = net.io(b).unwrap().take_buf().unwrap();
+            break;
+        }
+    }
+    assert_eq!(b"hi", &got[..]);
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use common::next;
+use nofio::reactor::MioReactor;
+use nofio::reactor::TestHandle;
+use nofio::reactor::TestReactor;
+use nofio::ConnToken;
+use nofio::Event;
+use nofio::Net;
+
+/// A turn of the loop with nothing to report.
+fn quiet(net: &mut Net) {
+    assert!(net
+        .next_timeout(Duration::from_millis(1))
+        .unwrap()
+        .is_none());
+}
+
+/// A `Net` watched by a `TestReactor`, with an accepted connection.
+fn watched() -> (Net, TestHandle, ConnToken, TcpStream) {
+    let reactor = TestReactor::new(MioReactor::new().unwrap());
+    let handle = reactor.handle();
+    let mut net = Net::builder().build_with_reactor(reactor).unwrap();
+    net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = match next(&mut net) {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    let client = TcpStream::connect(addr).unwrap();
+    let token = match next(&mut net) {
+        Event::NewConnection { token, .. } => token,
+        other => panic!("expected NewConnection, not {:?}", other),
+    };
+    (net, handle, token, client)
+}
+
+#[test]
+fn nothing_is_reregistered_until_interest_changes() {
+    let (mut net, handle, token, mut client) = watched();
+    let before = handle.registrations();
+    for _ in 0..5 {
+        quiet(&mut net);
+    }
+    assert_eq!(before, handle.registrations(), "quiet turns cost nothing");
+
+    client.write_all(b"ping").unwrap();
+    assert!(matches!(next(&mut net), Event::Data(t) if t == token));
+    net.io(token).unwrap().take_buf().unwrap();
+    net.io(token).unwrap().write(b"pong").unwrap();
+    assert!(matches!(next(&mut net), Event::Flushed(t) if t == token));
+    let mut got = [0u8; 4];
+    client.read_exact(&mut got).unwrap();
+    assert_eq!(b"pong", &got);
+
+    // the turn after, it stops watching for writability, and leaves it at that
+    quiet(&mut net);
+    let after = handle.registrations();
+    let interest = handle.interest(token.token()).unwrap();
+    assert!(interest.readable && !interest.writable);
+    quiet(&mut net);
+    quiet(&mut net);
+    assert_eq!(after, handle.registrations());
+}
@@ -0,0 +1,58 @@
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use nofio::Event;
+use nofio::Handler;
+use nofio::Io;
+use nofio::ListenerToken;
+use nofio::Net;
+
+/// Shouts back whatever it hears, and stops once its one client has gone.
+#[derive(Default)]
+struct Shouter {
+    accepted: usize,
+    closed: usize,
+}
+
+impl Handler for Shouter {
+    fn on_accept(&mut self, _io: &mut Io<'_>, _listener: ListenerToken) {
+        self.accepted += 1;
+    }
+
+    fn on_data(&mut self, io: &mut Io<'_>) {
+        let got = io.take_buf().unwrap();
+        io.write(&got.to_ascii_uppercase()).unwrap();
+    }
+
+    fn on_event(&mut self, net: &mut Net, event: Event) {
+        if let Event::Closed(_) = event {
+            self.closed += 1;
+            net.shutdown_gracefully(Duration::from_secs(1)).unwrap();
+        }
+    }
+}
+
+#[test]
+fn run_drives_a_handler_until_shutdown() {
+    let mut net = Net::empty().unwrap();
+    net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = match net.next().unwrap() {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    let client = thread::spawn(move || {
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"hello").unwrap();
+        let mut got = [0u8; 5];
+        client.read_exact(&mut got).unwrap();
+        got
+    });
+
+    let mut handler = Shouter::default();
+    net.run(&mut handler).unwrap();
+    assert_eq!(b"HELLO", &client.join().unwrap());
+    assert_eq!((1, 1), (handler.accepted, handler.closed));
+}
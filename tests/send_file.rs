@@ -0,0 +1,90 @@
+mod common;
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+use common::next;
+use common::WAIT;
+use nofio::ConnToken;
+use nofio::Event;
+use nofio::Net;
+
+/// A file holding `contents`, for this test alone.
+fn file(name: &str, contents: &[u8]) -> (File, PathBuf) {
+    let path = std::env::temp_dir().join(format!("nofio-{}-{}", std::process::id(), name));
+    fs::write(&path, contents).unwrap();
+    (File::open(&path).unwrap(), path)
+}
+
+fn accepted(net: &mut Net) -> (ConnToken, TcpStream) {
+    net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = match next(net) {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    let client = TcpStream::connect(addr).unwrap();
+    match next(net) {
+        Event::NewConnection { token, .. } => (token, client),
+        other => panic!("expected NewConnection, not {:?}", other),
+    }
+}
+
+#[test]
+fn a_file_goes_out_between_what_was_written_around_it() {
+    let contents: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let (sent, path) = file("between", &contents);
+    let mut net = Net::empty().unwrap();
+    let (token, mut client) = accepted(&mut net);
+    let mut io = net.io(token).unwrap();
+    io.write(b"before ").unwrap();
+    io.send_file(sent, 10, contents.len() - 20).unwrap();
+    io.write(b" after").unwrap();
+    io.close();
+    assert_eq!(13, io.stats().write_buffered, "the file isn't counted");
+
+    let reader = std::thread::spawn(move || {
+        let mut got = Vec::new();
+        client.read_to_end(&mut got).unwrap();
+        got
+    });
+    let start = Instant::now();
+    while !reader.is_finished() {
+        assert!(start.elapsed() < WAIT, "still going");
+        net.next_timeout(Duration::from_millis(10)).unwrap();
+    }
+    let got = reader.join().unwrap();
+    fs::remove_file(path).unwrap();
+
+    let mut expected = b"before ".to_vec();
+    expected.extend_from_slice(&contents[10..contents.len() - 10]);
+    expected.extend_from_slice(b" after");
+    assert!(expected == got, "{} of {} bytes", got.len(), expected.len());
+}
+
+#[test]
+fn a_short_file_resets_the_connection() {
+    let (sent, path) = file("short", b"not much");
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    let (a, _b) = net.memory_pair().unwrap();
+    net.io(a).unwrap().send_file(sent, 0, 100).unwrap();
+
+    let mut seen = Vec::new();
+    for _ in 0..10 {
+        net.pump();
+        seen.extend(net.try_next().unwrap());
+    }
+    fs::remove_file(path).unwrap();
+    assert!(seen.iter().any(|ev| matches!(
+        ev,
+        Event::Error(t, e) if *t == a && io::ErrorKind::UnexpectedEof == e.kind()
+    )));
+    assert!(seen
+        .iter()
+        .any(|ev| matches!(ev, Event::Closed(t) if *t == a)));
+}
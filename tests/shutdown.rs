@@ -0,0 +1,86 @@
+mod common;
+
+use std::io::Read;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::time::Duration;
+use std::time::Instant;
+
+use common::next;
+use common::WAIT;
+use nofio::ConnToken;
+use nofio::Event;
+use nofio::Net;
+
+/// A listener, and a connection to it.
+fn accepted(net: &mut Net) -> (ConnToken, TcpStream, SocketAddr) {
+    net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = match next(net) {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    let client = TcpStream::connect(addr).unwrap();
+    let token = match next(net) {
+        Event::NewConnection { token, .. } => token,
+        other => panic!("expected NewConnection, not {:?}", other),
+    };
+    (token, client, addr)
+}
+
+/// Everything up to `Event::Shutdown`.
+fn shut_down(net: &mut Net) -> Vec<Event> {
+    let mut events = Vec::new();
+    loop {
+        match next(net) {
+            Event::Shutdown => return events,
+            ev => events.push(ev),
+        }
+    }
+}
+
+#[test]
+fn what_was_written_is_delivered_before_shutting_down() {
+    let mut net = Net::empty().unwrap();
+    let (token, mut client, addr) = accepted(&mut net);
+    net.io(token).unwrap().write(b"goodbye").unwrap();
+    net.shutdown_gracefully(WAIT).unwrap();
+    assert!(TcpStream::connect(addr).is_err(), "no longer listening");
+
+    // the client hangs up once it's heard everything
+    let start = Instant::now();
+    client.set_nonblocking(true).unwrap();
+    let mut got = Vec::new();
+    let mut buf = [0u8; 64];
+    while got.len() < 7 {
+        assert!(start.elapsed() < WAIT, "only got {:?}", got);
+        net.next_timeout(Duration::from_millis(10)).unwrap();
+        if let Ok(n) = client.read(&mut buf) {
+            got.extend_from_slice(&buf[..n]);
+        }
+    }
+    drop(client);
+    assert_eq!(b"goodbye", &got[..]);
+    let events = shut_down(&mut net);
+    assert!(events
+        .iter()
+        .any(|ev| matches!(ev, Event::Closed(t) if *t == token)));
+}
+
+#[test]
+fn what_wont_drain_is_reset_at_the_deadline() {
+    let mut net = Net::empty().unwrap();
+    let (token, _client, _) = accepted(&mut net);
+    // far more than the kernel will hold for a client that isn't reading
+    net.io(token)
+        .unwrap()
+        .write(&vec![0; 32 * 1024 * 1024])
+        .unwrap();
+    let start = Instant::now();
+    net.shutdown_gracefully(Duration::from_millis(200)).unwrap();
+
+    let events = shut_down(&mut net);
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert!(events
+        .iter()
+        .any(|ev| matches!(ev, Event::Closed(t) if *t == token)));
+}
@@ -0,0 +1,48 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::collections::HashSet;
+
+use common::WAIT;
+use nofio::Event;
+use nofio::Net;
+use nofio::Signal;
+
+/// `signal`, for this thread alone, so nothing else in the test binary sees it.
+fn raise(signal: Signal) {
+    assert_eq!(0, unsafe { libc::raise(signal.number()) });
+}
+
+/// The next `count` signals, in whatever order they come.
+fn caught(net: &mut Net, count: usize) -> HashSet<Signal> {
+    let mut signals = HashSet::new();
+    while signals.len() < count {
+        match net.next_timeout(WAIT).unwrap() {
+            Some(Event::Signal(signal)) => signals.insert(signal),
+            other => panic!("expected a Signal, not {:?}", other),
+        };
+    }
+    signals
+}
+
+#[test]
+fn signals_arrive_as_events() {
+    let mut net = Net::empty().unwrap();
+    net.signals(&[Signal::User1, Signal::User2]).unwrap();
+    raise(Signal::User2);
+    raise(Signal::User1);
+    assert_eq!(
+        HashSet::from([Signal::User1, Signal::User2]),
+        caught(&mut net, 2)
+    );
+
+    net.signals(&[Signal::Hangup]).unwrap();
+    raise(Signal::Hangup);
+    raise(Signal::User1);
+    assert_eq!(
+        HashSet::from([Signal::Hangup, Signal::User1]),
+        caught(&mut net, 2),
+        "added to, not replaced"
+    );
+}
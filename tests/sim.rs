@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use nofio::Event;
+use nofio::Net;
+
+/// Everything waiting, without pumping.
+fn events(net: &mut Net) -> Vec<Event> {
+    (0..10).filter_map(|_| net.try_next().unwrap()).collect()
+}
+
+#[test]
+fn timers_wait_for_the_clock() {
+    let (mut net, clock) = Net::builder().build_simulated().unwrap();
+    let start = net.now();
+    let soon = net.timer(Duration::from_secs(1)).unwrap();
+    let later = net.timer(Duration::from_secs(60)).unwrap();
+    assert!(events(&mut net).is_empty());
+
+    clock.advance(Duration::from_millis(999));
+    assert!(events(&mut net).is_empty());
+    clock.advance(Duration::from_millis(1));
+    let timers: Vec<_> = events(&mut net)
+        .into_iter()
+        .filter_map(|ev| match ev {
+            Event::Timer(t) => Some(t),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(vec![soon], timers);
+
+    clock.advance(Duration::from_secs(3600));
+    assert!(events(&mut net)
+        .iter()
+        .any(|ev| matches!(ev, Event::Timer(t) if *t == later)));
+    assert_eq!(start + Duration::from_secs(3601), net.now());
+}
+
+#[test]
+fn idle_connections_time_out_in_simulated_time() {
+    let (mut net, clock) = Net::builder().build_simulated().unwrap();
+    let (a, _b) = net.memory_pair().unwrap();
+    net.io(a)
+        .unwrap()
+        .set_idle_timeout(Duration::from_secs(30))
+        .unwrap();
+
+    clock.advance(Duration::from_secs(29));
+    assert!(!events(&mut net)
+        .iter()
+        .any(|ev| matches!(ev, Event::TimedOut(_))));
+    clock.advance(Duration::from_secs(1));
+    assert!(events(&mut net)
+        .iter()
+        .any(|ev| matches!(ev, Event::TimedOut(t) if *t == a)));
+}
+
+#[test]
+fn waiting_and_sockets_are_refused() {
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    assert!(net.next().is_err());
+    assert!(net.tcp_connect(&"127.0.0.1:1".parse().unwrap()).is_err());
+    assert!(net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).is_err());
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use std::net::TcpStream;
+use std::time::Duration;
+
+use common::next;
+use nofio::Error;
+use nofio::Event;
+use nofio::ListenOptions;
+use nofio::Net;
+use nofio::Operation;
+use nofio::SocketOptions;
+
+/// Away from unix, `into_parts` can't hand the sockets back to be looked at.
+#[cfg(unix)]
+#[test]
+fn options_land_on_the_sockets() {
+    let mut net = Net::empty().unwrap();
+    let options = SocketOptions::default()
+        .nodelay(true)
+        .keepalive(Some(Duration::from_secs(60)))
+        .ttl(42);
+    let addr = "127.0.0.1:0".parse().unwrap();
+    net.tcp_listen_all(&[addr], &ListenOptions::default().socket_options(options))
+        .unwrap();
+    let addr = match next(&mut net) {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    let _by_listener = TcpStream::connect(addr).unwrap();
+    let by_conn = TcpStream::connect(addr).unwrap();
+    for _ in 0..2 {
+        let (token, peer) = match next(&mut net) {
+            Event::NewConnection { token, peer, .. } => (token, peer),
+            other => panic!("expected NewConnection, not {:?}", other),
+        };
+        if peer == by_conn.local_addr().unwrap() {
+            let mut io = net.io(token).unwrap();
+            io.set_nodelay(false).unwrap();
+            io.set_ttl(7).unwrap();
+            io.set_linger(Some(Duration::from_secs(1))).unwrap();
+        }
+    }
+
+    let parts = net.into_parts();
+    let set: Vec<_> = parts
+        .streams
+        .iter()
+        .map(|(_, sock, _)| {
+            let from = sock.peer_addr().unwrap();
+            let is_conns = from == by_conn.local_addr().unwrap();
+            (is_conns, sock.nodelay().unwrap(), sock.ttl().unwrap())
+        })
+        .collect();
+    assert_eq!(2, set.len());
+    assert!(set.contains(&(false, true, 42)), "{:?}", set);
+    assert!(set.contains(&(true, false, 7)), "{:?}", set);
+}
+
+#[test]
+fn a_memory_pair_has_no_socket_to_set() {
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    let (a, _b) = net.memory_pair().unwrap();
+    match net.io(a).unwrap().set_nodelay(true) {
+        Err(e @ Error::Os { .. }) => assert_eq!(Some(Operation::SocketOption), e.operation()),
+        other => panic!("expected a socket option error, not {:?}", other),
+    }
+}
@@ -0,0 +1,144 @@
+mod common;
+
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::time::Duration;
+use std::time::Instant;
+
+use common::wait_for;
+use common::WAIT;
+use nofio::socks5::Target;
+use nofio::ConnToken;
+use nofio::Event;
+use nofio::Net;
+
+/// The proxy's end of a connection made through it.
+fn proxied(proxy: &TcpListener) -> TcpStream {
+    let (stream, _) = proxy.accept().unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(10)))
+        .unwrap();
+    stream
+}
+
+/// `len` bytes from the `Net`, which has to be run for them to be sent; the events before the
+/// proxy replies aren't interesting.
+fn read(net: &mut Net, stream: &mut TcpStream, len: usize) -> Vec<u8> {
+    let mut got = Vec::new();
+    let start = Instant::now();
+    while got.len() < len {
+        assert!(start.elapsed() < WAIT, "only {:?} in time", got);
+        net.next_timeout(Duration::from_millis(10)).unwrap();
+        let mut buf = vec![0; len - got.len()];
+        match stream.read(&mut buf) {
+            Ok(n) => got.extend_from_slice(&buf[..n]),
+            Err(e) if io::ErrorKind::WouldBlock == e.kind() => (),
+            Err(e) => panic!("reading: {:?}", e),
+        }
+    }
+    got
+}
+
+fn connected_or_failed(token: ConnToken) -> impl Fn(&Event) -> bool {
+    move |ev| matches!(ev, Event::Connected(t) | Event::ConnectFailed(t, _) if *t == token)
+}
+
+#[test]
+fn a_name_is_connected_to_after_authenticating() {
+    let proxy = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut net = Net::empty().unwrap();
+    let target = Target::Domain("example.onion".to_string(), 80);
+    let token = net
+        .connect_via_socks5_with_auth(&proxy.local_addr().unwrap(), target, "me", "secret")
+        .unwrap();
+    let mut stream = proxied(&proxy);
+
+    assert_eq!(vec![5, 1, 2], read(&mut net, &mut stream, 3));
+    assert_eq!(
+        b"\x01\x02me\x06secret",
+        &read(&mut net, &mut stream, 11)[..]
+    );
+    let mut request = vec![5, 1, 0, 3, 13];
+    request.extend_from_slice(b"example.onion");
+    request.extend_from_slice(&[0, 80]);
+    assert_eq!(request, read(&mut net, &mut stream, request.len()));
+
+    // the replies in pieces, then the target's first bytes straight after
+    stream.write_all(&[5, 2]).unwrap();
+    stream.write_all(&[1, 0]).unwrap();
+    stream
+        .write_all(&[5, 0, 0, 1, 10, 0, 0, 1, 0x1f, 0x90])
+        .unwrap();
+    stream.write_all(b"HTTP/1.1 200 OK\r\n").unwrap();
+    match wait_for(&mut net, connected_or_failed(token)) {
+        Event::Connected(_) => (),
+        other => panic!("expected Connected, not {:?}", other),
+    }
+    wait_for(&mut net, |ev| matches!(ev, Event::Data(t) if *t == token));
+    assert_eq!(
+        b"HTTP/1.1 200 OK\r\n",
+        net.io(token).unwrap().buf().unwrap()
+    );
+
+    net.io(token).unwrap().write(b"onwards").unwrap();
+    assert_eq!(b"onwards", &read(&mut net, &mut stream, 7)[..]);
+}
+
+#[test]
+fn writes_wait_for_the_tunnel() {
+    let proxy = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut net = Net::empty().unwrap();
+    let target = "192.0.2.1:443".parse::<SocketAddr>().unwrap();
+    let token = net
+        .connect_via_socks5(&proxy.local_addr().unwrap(), target)
+        .unwrap();
+    assert!(net.io(token).unwrap().write(b"too soon").is_err());
+
+    let mut stream = proxied(&proxy);
+    assert_eq!(vec![5, 1, 0], read(&mut net, &mut stream, 3));
+    assert_eq!(
+        vec![5, 1, 0, 1, 192, 0, 2, 1, 1, 187],
+        read(&mut net, &mut stream, 10)
+    );
+}
+
+#[test]
+fn a_refusal_fails_the_connection() {
+    let proxy = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut net = Net::empty().unwrap();
+    let target = "192.0.2.1:443".parse::<SocketAddr>().unwrap();
+    let token = net
+        .connect_via_socks5(&proxy.local_addr().unwrap(), target)
+        .unwrap();
+    let mut stream = proxied(&proxy);
+    read(&mut net, &mut stream, 13);
+
+    stream
+        .write_all(&[5, 0, 5, 5, 0, 1, 0, 0, 0, 0, 0, 0])
+        .unwrap();
+    match wait_for(&mut net, connected_or_failed(token)) {
+        Event::ConnectFailed(_, e) => assert_eq!(io::ErrorKind::ConnectionRefused, e.kind()),
+        other => panic!("expected ConnectFailed, not {:?}", other),
+    }
+    wait_for(&mut net, |ev| matches!(ev, Event::Closed(t) if *t == token));
+}
+
+#[test]
+fn credentials_must_fit() {
+    let mut net = Net::empty().unwrap();
+    let proxy = "127.0.0.1:1080".parse().unwrap();
+    let target = Target::Domain("example.com".to_string(), 80);
+    let long = "x".repeat(256);
+    assert!(net
+        .connect_via_socks5_with_auth(&proxy, target.clone(), "", "secret")
+        .is_err());
+    assert!(net
+        .connect_via_socks5_with_auth(&proxy, target, "me", &long)
+        .is_err());
+    let nameless = Target::Domain(String::new(), 80);
+    assert!(net.connect_via_socks5(&proxy, nameless).is_err());
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use std::time::Duration;
+
+use common::next;
+use mio::Ready;
+use mio::Registration;
+use nofio::reactor::Interest;
+use nofio::Error;
+use nofio::Event;
+use nofio::Net;
+
+#[test]
+fn another_librarys_source_wakes_the_loop() {
+    let mut net = Net::empty().unwrap();
+    let (registration, readiness) = Registration::new2();
+    let token = net
+        .register_source(registration, Interest::READABLE)
+        .unwrap();
+    assert!(net
+        .next_timeout(Duration::from_millis(10))
+        .unwrap()
+        .is_none());
+
+    readiness.set_readiness(Ready::readable()).unwrap();
+    match next(&mut net) {
+        Event::Ready(t) => assert_eq!(token, t),
+        other => panic!("expected Ready, not {:?}", other),
+    }
+
+    net.deregister_source(token).unwrap();
+    readiness.set_readiness(Ready::readable()).unwrap();
+    assert!(net
+        .next_timeout(Duration::from_millis(10))
+        .unwrap()
+        .is_none());
+    assert!(matches!(
+        net.reregister_source(token, Interest::READABLE),
+        Err(Error::UnknownToken(_))
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn a_file_descriptor_wakes_the_loop() {
+    use std::io::Write;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    let mut net = Net::empty().unwrap();
+    let (mut ours, theirs) = UnixStream::pair().unwrap();
+    let token = net
+        .register_fd(theirs.as_raw_fd(), Interest::WRITABLE)
+        .unwrap();
+    match next(&mut net) {
+        Event::Ready(t) => assert_eq!(token, t, "room to write"),
+        other => panic!("expected Ready, not {:?}", other),
+    }
+
+    net.reregister_source(token, Interest::READABLE).unwrap();
+    ours.write_all(b"!").unwrap();
+    match next(&mut net) {
+        Event::Ready(t) => assert_eq!(token, t, "something to read"),
+        other => panic!("expected Ready, not {:?}", other),
+    }
+    net.deregister_source(token).unwrap();
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use common::next;
+use common::settle;
+use common::wait_for;
+use nofio::ConnInfo;
+use nofio::ConnState;
+use nofio::ConnToken;
+use nofio::Event;
+use nofio::MetricsSink;
+use nofio::Net;
+
+#[test]
+fn each_connection_counts_what_it_did_and_when() {
+    let (mut net, clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    let opened = net.now();
+    clock.advance(Duration::from_secs(10));
+    net.io(a).unwrap().write(b"hello").unwrap();
+    settle(&mut net);
+    clock.advance(Duration::from_secs(5));
+
+    let a_stats = net.io(a).unwrap().stats();
+    let b_stats = net.io(b).unwrap().stats();
+    assert_eq!((0, 5), (a_stats.bytes_read, a_stats.bytes_written));
+    assert_eq!((5, 0), (b_stats.bytes_read, b_stats.bytes_written));
+    assert_eq!((5, 0), (b_stats.read_buffered, b_stats.write_buffered));
+    assert_eq!(Duration::from_secs(15), b_stats.age);
+    assert_eq!(opened + Duration::from_secs(10), b_stats.last_active);
+
+    net.io(b).unwrap().consume_all().unwrap();
+    assert_eq!(0, net.io(b).unwrap().stats().read_buffered);
+    assert_eq!(5, net.io(b).unwrap().stats().bytes_read, "still counted");
+}
+
+/// Everything a sink has been told: counters summed, and the latest of each gauge.
+#[derive(Clone, Default)]
+struct Sink(Arc<Mutex<HashMap<&'static str, u64>>>);
+
+impl MetricsSink for Sink {
+    fn counter(&mut self, name: &'static str, delta: u64) {
+        *self.0.lock().unwrap().entry(name).or_default() += delta;
+    }
+
+    fn gauge(&mut self, name: &'static str, value: u64) {
+        self.0.lock().unwrap().insert(name, value);
+    }
+}
+
+#[test]
+fn the_nets_totals_reach_the_sink() {
+    let mut net = Net::empty().unwrap();
+    let sink = Sink::default();
+    net.set_metrics_sink(sink.clone());
+    net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = match next(&mut net) {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    let mut client = TcpStream::connect(addr).unwrap();
+    client.write_all(b"hello").unwrap();
+    let token = match next(&mut net) {
+        Event::NewConnection { token, .. } => token,
+        other => panic!("expected NewConnection, not {:?}", other),
+    };
+    while 5 != net.io(token).unwrap().stats().bytes_read {
+        next(&mut net);
+    }
+    net.io(token).unwrap().write(b"hi").unwrap();
+    while 2 != net.io(token).unwrap().stats().bytes_written {
+        next(&mut net);
+    }
+    // one more pass, to report the last one
+    net.next_timeout(Duration::from_millis(10)).unwrap();
+
+    let stats = net.stats();
+    assert_eq!(
+        (1, 1, 5, 2),
+        (
+            stats.connections,
+            stats.accepted,
+            stats.bytes_read,
+            stats.bytes_written
+        )
+    );
+    assert!(stats.polls > 0 && stats.events >= 3, "{:?}", stats);
+    let told = sink.0.lock().unwrap();
+    assert_eq!(Some(&1), told.get("nofio.accepted"));
+    assert_eq!(Some(&5), told.get("nofio.bytes_read"));
+    assert_eq!(Some(&2), told.get("nofio.bytes_written"));
+    assert_eq!(Some(&1), told.get("nofio.connections"));
+    assert!(!told.contains_key("nofio.blocked"), "nothing to say");
+}
+
+fn state_of(net: &Net, token: ConnToken) -> ConnInfo {
+    net.connections()
+        .find(|info| info.token == token)
+        .expect("listed")
+}
+
+#[test]
+fn the_connection_table_lists_each_connections_state() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut net = Net::empty().unwrap();
+    let token = net.tcp_connect(&addr).unwrap();
+    let info = state_of(&net, token);
+    assert_eq!((ConnState::Connecting, addr), (info.state, info.peer));
+
+    let (mut server, _) = listener.accept().unwrap();
+    wait_for(
+        &mut net,
+        |ev| matches!(ev, Event::Connected(t) if *t == token),
+    );
+    assert_eq!(ConnState::Open, state_of(&net, token).state);
+    server.write_all(b"abc").unwrap();
+    wait_for(&mut net, |ev| matches!(ev, Event::Data(t) if *t == token));
+    assert_eq!(3, state_of(&net, token).read_buffered);
+
+    net.io(token).unwrap().close();
+    assert_eq!(ConnState::Closing, state_of(&net, token).state);
+    let (a, _b) = net.memory_pair().unwrap();
+    assert_eq!(
+        2,
+        net.connections().filter(|info| info.token != token).count()
+    );
+    assert_eq!(ConnState::Open, state_of(&net, a).state);
+}
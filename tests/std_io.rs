@@ -0,0 +1,34 @@
+mod common;
+
+use std::io;
+use std::io::BufRead;
+use std::io::Read;
+use std::io::Write;
+
+use common::settle;
+use nofio::Net;
+
+#[test]
+fn a_connection_is_a_reader_and_a_writer() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    let mut buf = [0u8; 16];
+    let e = net.io(b).unwrap().read(&mut buf).unwrap_err();
+    assert_eq!(io::ErrorKind::WouldBlock, e.kind(), "nothing yet");
+
+    let which = "first";
+    write!(net.io(a).unwrap(), "{} line\nrest", which).unwrap();
+    net.io(a).unwrap().close();
+    settle(&mut net);
+
+    let mut io = net.io(b).unwrap();
+    let mut line = String::new();
+    BufRead::read_line(&mut io, &mut line).unwrap();
+    assert_eq!("first line\n", line);
+    assert_eq!(b"rest", io.fill_buf().unwrap());
+    assert_eq!(2, io.read(&mut buf[..2]).unwrap());
+    assert_eq!(b"re", &buf[..2]);
+    let mut rest = String::new();
+    io.read_to_string(&mut rest).unwrap();
+    assert_eq!("st", rest, "and then the end");
+}
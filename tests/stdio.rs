@@ -0,0 +1,87 @@
+#![cfg(unix)]
+
+use std::env;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::process::Command;
+use std::process::Stdio;
+
+use nofio::Direction;
+use nofio::Event;
+use nofio::Net;
+
+/// Set for the copy of this binary which `child` runs, to say which part to play.
+const ROLE: &str = "NOFIO_STDIO_ROLE";
+
+/// This test binary again, running only `as_a_child`, with `role`.
+fn child(role: &str) -> Command {
+    let mut command = Command::new(env::current_exe().unwrap());
+    command
+        .args(["--exact", "as_a_child", "--test-threads", "1", "--quiet"])
+        .env(ROLE, role);
+    command
+}
+
+/// Does nothing unless it's run by `child`.
+#[test]
+fn as_a_child() {
+    match env::var(ROLE).as_deref() {
+        Ok("shout") => shout(),
+        Ok("nothing") => {
+            let e = Net::empty().unwrap().stdio().unwrap_err();
+            let cause = e.io_error().expect("the os' error");
+            assert_eq!(io::ErrorKind::PermissionDenied, cause.kind());
+        }
+        _ => (),
+    }
+}
+
+/// Echo stdin to stdout, upper-cased, until stdin ends.
+fn shout() {
+    let mut net = Net::empty().unwrap();
+    let token = net.stdio().unwrap();
+    loop {
+        match net.next().unwrap() {
+            Event::Data(t) => {
+                let mut io = net.io(t).unwrap();
+                let loud = io.take_buf().unwrap().to_ascii_uppercase();
+                io.write(&loud).unwrap();
+            }
+            Event::Done(t, Direction::Read) => net.io(t).unwrap().close(),
+            Event::Closed(t) if t == token => return,
+            _ => (),
+        }
+    }
+}
+
+#[test]
+fn stdin_and_stdout_are_a_connection() {
+    let mut shouter = child("shout")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = shouter.stdin.take().unwrap();
+    stdin.write_all(b"hello\nworld\n").unwrap();
+    drop(stdin);
+    let mut out = String::new();
+    shouter
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut out)
+        .unwrap();
+    assert!(shouter.wait().unwrap().success());
+    assert!(out.contains("HELLO\nWORLD\n"), "{:?}", out);
+}
+
+#[test]
+fn nothing_to_wait_on_is_refused() {
+    let status = child("nothing")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use common::settle;
+use nofio::ConnToken;
+use nofio::Direction;
+use nofio::Net;
+use nofio::Tap;
+
+type Chunk = (ConnToken, Direction, Vec<u8>);
+
+/// Everything that went past, in order.
+#[derive(Clone, Default)]
+struct Log(Arc<Mutex<Vec<Chunk>>>);
+
+impl Log {
+    fn take(&self) -> Vec<Chunk> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Tap for Log {
+    fn chunk(&mut self, token: ConnToken, direction: Direction, data: &[u8]) {
+        self.0
+            .lock()
+            .unwrap()
+            .push((token, direction, data.to_vec()));
+    }
+}
+
+#[test]
+fn a_tap_sees_both_directions_of_every_connection() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let log = Log::default();
+    net.set_tap(log.clone());
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a).unwrap().write(b"ping").unwrap();
+    settle(&mut net);
+    net.io(b).unwrap().write(b"pong").unwrap();
+    settle(&mut net);
+
+    assert_eq!(
+        vec![
+            (a, Direction::Write, b"ping".to_vec()),
+            (b, Direction::Read, b"ping".to_vec()),
+            (b, Direction::Write, b"pong".to_vec()),
+            (a, Direction::Read, b"pong".to_vec()),
+        ],
+        log.take()
+    );
+}
+
+#[test]
+fn a_connection_can_have_its_own_tap_or_none() {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (everyone, just_b) = (Log::default(), Log::default());
+    net.set_tap(everyone.clone());
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a).unwrap().remove_tap();
+    net.io(b).unwrap().set_tap(just_b.clone());
+    net.io(a).unwrap().write(b"hello").unwrap();
+    settle(&mut net);
+    assert!(everyone.take().is_empty());
+    assert_eq!(vec![(b, Direction::Read, b"hello".to_vec())], just_b.take());
+
+    net.remove_tap();
+    net.io(a).unwrap().write(b"again").unwrap();
+    settle(&mut net);
+    assert!(just_b.take().is_empty(), "untapped by the net");
+}
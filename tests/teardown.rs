@@ -0,0 +1,198 @@
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::sync::Once;
+use std::time::Duration;
+
+use log::Log;
+use log::Metadata;
+use log::Record;
+use nofio::Net;
+use nofio::MEMORY_PIPE_CAPACITY;
+
+/// Warnings, from every test; hold `serial()` while looking.
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Each test here counts file descriptors, or looks at the warnings, so they can't overlap.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+struct Capture;
+
+impl Log for Capture {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            WARNINGS.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn serial() -> MutexGuard<'static, ()> {
+    static LOGGER: Once = Once::new();
+    LOGGER.call_once(|| {
+        log::set_logger(&Capture).unwrap();
+        log::set_max_level(log::LevelFilter::Warn);
+    });
+    let guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    WARNINGS.lock().unwrap().clear();
+    guard
+}
+
+fn warnings() -> Vec<String> {
+    WARNINGS.lock().unwrap().clone()
+}
+
+#[test]
+fn drop_warns_of_unflushed_connections() {
+    let _serial = serial();
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    let (a, _b) = net.memory_pair().unwrap();
+
+    // more than the pipe can take, and more than `Drop` will try to flush
+    net.io(a)
+        .unwrap()
+        .write(&vec![7; 4 * MEMORY_PIPE_CAPACITY])
+        .unwrap();
+    net.pump();
+    drop(net);
+
+    let warnings = warnings();
+    assert!(
+        warnings
+            .iter()
+            .any(|w| w.starts_with("dropped with unflushed connections: ")
+                && w.contains(&format!("{} (", a.token().0))),
+        "{:?}",
+        warnings
+    );
+}
+
+#[test]
+fn drop_is_quiet_when_everything_is_flushed() {
+    let _serial = serial();
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    let (a, _b) = net.memory_pair().unwrap();
+    net.io(a).unwrap().write(b"small").unwrap();
+    drop(net);
+
+    assert_eq!(Vec::<String>::new(), warnings());
+}
+
+#[test]
+fn into_parts_warns_of_what_it_discards() {
+    let _serial = serial();
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    net.io(a).unwrap().write(b"never sent").unwrap();
+    net.timer(Duration::from_secs(1)).unwrap();
+
+    let parts = net.into_parts();
+    assert!(parts.streams.is_empty(), "memory pairs aren't sockets");
+
+    let warnings = warnings();
+    assert!(
+        warnings.contains(&"into_parts discarded 1 pending timers".to_string()),
+        "{:?}",
+        warnings
+    );
+    assert!(
+        warnings.contains(&format!(
+            "{} into_parts discarded 10 unwritten and 0 unread bytes",
+            a.token().0
+        )),
+        "{:?}",
+        warnings
+    );
+    assert!(
+        !warnings
+            .iter()
+            .any(|w| w.starts_with(&format!("{} ", b.token().0))),
+        "b had nothing to lose: {:?}",
+        warnings
+    );
+}
+
+#[cfg(target_os = "linux")]
+fn open_fds() -> usize {
+    std::fs::read_dir("/proc/self/fd").unwrap().count()
+}
+
+/// A `Net` with a listener, and a connection to it, which has been written to both ways.
+#[cfg(target_os = "linux")]
+fn busy_net() -> (Net, std::net::TcpStream) {
+    use std::io::Write;
+    use std::net::TcpStream;
+
+    use nofio::Event;
+
+    let mut net = Net::empty().unwrap();
+    net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = match net.next().unwrap() {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    let mut peer = TcpStream::connect(addr).unwrap();
+    let token = match net.next().unwrap() {
+        Event::NewConnection { token, .. } => token,
+        other => panic!("expected NewConnection, not {:?}", other),
+    };
+    peer.write_all(b"hello").unwrap();
+    match net.next().unwrap() {
+        Event::Data(t) => assert_eq!(token, t),
+        other => panic!("expected Data, not {:?}", other),
+    }
+    net.io(token).unwrap().write(b"hi").unwrap();
+    (net, peer)
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn drop_leaks_no_fds() {
+    let _serial = serial();
+    let before = open_fds();
+    let (net, peer) = busy_net();
+    drop(net);
+    drop(peer);
+    assert_eq!(before, open_fds());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn into_parts_leaks_no_fds() {
+    let _serial = serial();
+    let before = open_fds();
+    let (net, peer) = busy_net();
+    let parts = net.into_parts();
+    assert_eq!(1, parts.listeners.len());
+    assert_eq!(1, parts.streams.len());
+    drop(parts);
+    drop(peer);
+    assert_eq!(before, open_fds());
+}
+
+#[test]
+fn drop_stops_watching_everything() {
+    use nofio::reactor::MioReactor;
+    use nofio::reactor::TestReactor;
+
+    let _serial = serial();
+    let reactor = TestReactor::new(MioReactor::new().unwrap());
+    let handle = reactor.handle();
+    let mut net: Net = Net::builder().build_with_reactor(reactor).unwrap();
+    let listener = net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let udp = net.udp_bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    net.udp(udp)
+        .unwrap()
+        .send_to(b"unsent", &"127.0.0.1:9".parse().unwrap())
+        .unwrap();
+    assert!(handle.interest(listener.token()).is_some());
+    assert!(handle.interest(udp.token()).is_some());
+
+    drop(net);
+    assert_eq!(None, handle.interest(listener.token()));
+    assert_eq!(None, handle.interest(udp.token()));
+}
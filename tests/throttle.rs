@@ -0,0 +1,83 @@
+mod common;
+
+use std::time::Duration;
+
+use common::settle;
+use nofio::ConnToken;
+use nofio::Event;
+use nofio::Net;
+
+fn closed(events: &[Event], token: ConnToken) -> bool {
+    events
+        .iter()
+        .any(|ev| matches!(ev, Event::Closed(t) if *t == token))
+}
+
+fn pair() -> (Net, ConnToken, ConnToken) {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    (net, a, b)
+}
+
+#[test]
+fn an_empty_read_bucket_does_not_confirm_a_close() {
+    let (mut net, a, b) = pair();
+    // a tenth of a second's worth is one byte, and the clock never moves
+    net.io(a).unwrap().set_rate_limit(Some(10), None).unwrap();
+    net.io(b).unwrap().write(b"hello").unwrap();
+    settle(&mut net);
+    assert_eq!(b"h", net.io(a).unwrap().buf().unwrap());
+
+    net.io(a).unwrap().close();
+    let events = settle(&mut net);
+    assert!(!closed(&events, a), "closed before the peer did");
+
+    net.io(b).unwrap().close();
+    let events = settle(&mut net);
+    assert!(closed(&events, a));
+}
+
+#[test]
+fn writes_go_at_the_rate_the_clock_allows() {
+    let (mut net, clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    // ten bytes at once, then ten every tenth of a second
+    net.io(a).unwrap().set_rate_limit(None, Some(100)).unwrap();
+    net.io(a).unwrap().write(&[b'x'; 25]).unwrap();
+    settle(&mut net);
+    assert_eq!(10, net.io(b).unwrap().take_buf().unwrap().len());
+
+    clock.advance(Duration::from_millis(100));
+    settle(&mut net);
+    assert_eq!(10, net.io(b).unwrap().take_buf().unwrap().len());
+    clock.advance(Duration::from_secs(1));
+    let events = settle(&mut net);
+    assert_eq!(5, net.io(b).unwrap().take_buf().unwrap().len());
+    assert!(events
+        .iter()
+        .any(|ev| matches!(ev, Event::Flushed(t) if *t == a)));
+    assert!(net.io(a).unwrap().set_rate_limit(Some(0), None).is_err());
+}
+
+#[test]
+fn the_egress_limit_is_shared() {
+    let (mut net, clock) = Net::builder()
+        .max_egress_bps(200)
+        .build_simulated()
+        .unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    let (c, d) = net.memory_pair().unwrap();
+    for token in &[a, c] {
+        net.io(*token).unwrap().write(&[b'x'; 100]).unwrap();
+    }
+    let mut got = [0; 2];
+    for _ in 0..5 {
+        clock.advance(Duration::from_millis(100));
+        settle(&mut net);
+        for (got, token) in got.iter_mut().zip(&[b, d]) {
+            *got += net.io(*token).unwrap().take_buf().unwrap().len();
+        }
+    }
+    // twenty bytes each tenth of a second, which can't be saved up, shared evenly
+    assert_eq!([50, 50], got);
+}
@@ -0,0 +1,52 @@
+use std::time::Duration;
+use std::time::Instant;
+
+use nofio::Event;
+use nofio::Net;
+use nofio::TimerToken;
+
+fn fired(ev: Event) -> TimerToken {
+    match ev {
+        Event::Timer(token) => token,
+        other => panic!("expected Timer, not {:?}", other),
+    }
+}
+
+/// With nothing else going on, waiting only lasts until the next timer.
+#[test]
+fn waiting_ends_when_a_timer_is_due() {
+    let mut net = Net::empty().unwrap();
+    let start = Instant::now();
+    let later = net.timer(Duration::from_millis(60)).unwrap();
+    let sooner = net.timer(Duration::from_millis(20)).unwrap();
+
+    assert_eq!(sooner, fired(net.next().unwrap()));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    assert_eq!(later, fired(net.next().unwrap()));
+    assert!(start.elapsed() >= Duration::from_millis(60));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn a_cancelled_timer_never_fires() {
+    let mut net = Net::empty().unwrap();
+    let cancelled = net.timer(Duration::from_millis(10)).unwrap();
+    let kept = net.timer(Duration::from_millis(30)).unwrap();
+    assert!(net.cancel_timer(cancelled));
+    assert!(!net.cancel_timer(cancelled), "only once");
+
+    assert_eq!(kept, fired(net.next().unwrap()));
+    assert!(net
+        .next_timeout(Duration::from_millis(50))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn timers_that_are_too_long_are_refused() {
+    let mut net = Net::empty().unwrap();
+    assert!(net
+        .timer(nofio::MAX_TIMEOUT + Duration::from_secs(1))
+        .is_err());
+    assert!(net.timer(Duration::ZERO).is_ok());
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::time::Duration;
+use std::time::Instant;
+
+use common::next;
+use common::wait_for;
+use common::WAIT;
+use nofio::http1;
+use nofio::ConnToken;
+use nofio::Event;
+use nofio::Net;
+
+fn accepted(listener: &TcpListener) -> TcpStream {
+    let (stream, _) = listener.accept().unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(10)))
+        .unwrap();
+    stream
+}
+
+/// Exactly `len` bytes, running the `Net` while they're on their way.
+fn read(net: &mut Net, stream: &mut TcpStream, len: usize) -> Vec<u8> {
+    let mut got = Vec::new();
+    let start = Instant::now();
+    while got.len() < len {
+        assert!(start.elapsed() < WAIT, "only {:?} in time", got);
+        net.next_timeout(Duration::from_millis(10)).unwrap();
+        let mut buf = vec![0; len - got.len()];
+        match stream.read(&mut buf) {
+            Ok(n) => got.extend_from_slice(&buf[..n]),
+            Err(e) if io::ErrorKind::WouldBlock == e.kind() => (),
+            Err(e) => panic!("reading: {:?}", e),
+        }
+    }
+    got
+}
+
+fn connected_or_failed(token: ConnToken) -> impl Fn(&Event) -> bool {
+    move |ev| matches!(ev, Event::Connected(t) | Event::ConnectFailed(t, _) if *t == token)
+}
+
+const REQUEST: &[u8] =
+    b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\nProxy-Authorization: x\r\n\r\n";
+
+#[test]
+fn a_tunnel_carries_what_follows_the_answer() {
+    let proxy = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut net = Net::empty().unwrap();
+    let token = net
+        .connect_via_http_proxy(
+            &proxy.local_addr().unwrap(),
+            "example.com:443",
+            &[("Proxy-Authorization", "x")],
+        )
+        .unwrap();
+    let mut stream = accepted(&proxy);
+    assert_eq!(REQUEST, &read(&mut net, &mut stream, REQUEST.len())[..]);
+
+    stream
+        .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n\x16\x03\x01")
+        .unwrap();
+    match wait_for(&mut net, connected_or_failed(token)) {
+        Event::Connected(_) => (),
+        other => panic!("expected Connected, not {:?}", other),
+    }
+    wait_for(&mut net, |ev| matches!(ev, Event::Data(t) if *t == token));
+    assert_eq!(b"\x16\x03\x01", net.io(token).unwrap().buf().unwrap());
+}
+
+#[test]
+fn a_refused_tunnel_fails() {
+    let proxy = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut net = Net::empty().unwrap();
+    let token = net
+        .connect_via_http_proxy(&proxy.local_addr().unwrap(), "example.com:443", &[])
+        .unwrap();
+    assert!(net.io(token).unwrap().write(b"too soon").is_err());
+    let mut stream = accepted(&proxy);
+    read(
+        &mut net,
+        &mut stream,
+        REQUEST.len() - "Proxy-Authorization: x\r\n".len(),
+    );
+
+    stream
+        .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\nContent-Length: 0\r\n\r\n")
+        .unwrap();
+    match wait_for(&mut net, connected_or_failed(token)) {
+        Event::ConnectFailed(_, e) => assert_eq!(io::ErrorKind::ConnectionRefused, e.kind()),
+        other => panic!("expected ConnectFailed, not {:?}", other),
+    }
+    assert!(net
+        .connect_via_http_proxy(&proxy.local_addr().unwrap(), "no spaces", &[])
+        .is_err());
+}
+
+#[test]
+fn a_served_connect_is_passed_through() {
+    let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut net = Net::empty().unwrap();
+    net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = match next(&mut net) {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    let mut client = TcpStream::connect(addr).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_millis(10)))
+        .unwrap();
+    let token = match next(&mut net) {
+        Event::NewConnection { token, .. } => token,
+        other => panic!("expected NewConnection, not {:?}", other),
+    };
+    http1::serve(&mut net.io(token).unwrap(), 1024).unwrap();
+
+    client
+        .write_all(b"CONNECT upstream:1 HTTP/1.1\r\nHost: upstream:1\r\n\r\n")
+        .unwrap();
+    wait_for(
+        &mut net,
+        |ev| matches!(ev, Event::HttpRequest(t) if *t == token),
+    );
+    let request = http1::next_request(&mut net.io(token).unwrap())
+        .unwrap()
+        .unwrap();
+    assert_eq!("CONNECT", request.method);
+    http1::accept_connect(&mut net, token, &upstream.local_addr().unwrap()).unwrap();
+
+    let mut server = accepted(&upstream);
+    let answer = b"HTTP/1.1 200 Connection Established\r\n\r\n";
+    assert_eq!(&answer[..], &read(&mut net, &mut client, answer.len())[..]);
+
+    client.write_all(b"up").unwrap();
+    assert_eq!(b"up", &read(&mut net, &mut server, 2)[..]);
+    server.write_all(b"down").unwrap();
+    assert_eq!(b"down", &read(&mut net, &mut client, 4)[..]);
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use std::net::SocketAddr;
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use common::wait_for;
+use common::WAIT;
+use nofio::Error;
+use nofio::Event;
+use nofio::Net;
+use nofio::UdpToken;
+
+fn bind(net: &mut Net) -> (UdpToken, SocketAddr) {
+    let token = net.udp_bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = net.udp(token).unwrap().local_addr().unwrap();
+    (token, addr)
+}
+
+#[test]
+fn datagrams_arrive_with_their_sender() {
+    let mut net = Net::empty().unwrap();
+    let (token, addr) = bind(&mut net);
+    let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+    peer.set_read_timeout(Some(WAIT)).unwrap();
+
+    peer.send_to(b"ping", addr).unwrap();
+    wait_for(
+        &mut net,
+        |ev| matches!(ev, Event::Datagram(t) if *t == token),
+    );
+    let (from, data) = net.udp(token).unwrap().recv_from().unwrap();
+    assert_eq!(peer.local_addr().unwrap(), from);
+    assert_eq!(b"ping", &data[..]);
+    assert!(net.udp(token).unwrap().recv_from().is_none());
+
+    net.udp(token).unwrap().send_to(b"pong", &from).unwrap();
+    // sent on the next pass
+    net.next_timeout(Duration::from_millis(50)).unwrap();
+    let mut got = [0; 4];
+    let (len, from) = peer.recv_from(&mut got).unwrap();
+    assert_eq!(addr, from);
+    assert_eq!(b"pong", &got[..len]);
+}
+
+#[test]
+fn closing_flushes_then_forgets_the_socket() {
+    let mut net = Net::empty().unwrap();
+    let (token, _) = bind(&mut net);
+    let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+    peer.set_read_timeout(Some(WAIT)).unwrap();
+
+    let mut udp = net.udp(token).unwrap();
+    udp.send_to(b"last", &peer.local_addr().unwrap()).unwrap();
+    udp.close();
+    assert!(matches!(
+        net.udp(token)
+            .unwrap()
+            .send_to(b"more", &peer.local_addr().unwrap()),
+        Err(Error::Closed(..))
+    ));
+
+    wait_for(
+        &mut net,
+        |ev| matches!(ev, Event::UdpClosed(t) if *t == token),
+    );
+    let mut got = [0; 4];
+    assert_eq!(4, peer.recv(&mut got).unwrap());
+    assert_eq!(b"last", &got);
+
+    net.try_next().unwrap();
+    assert!(matches!(net.udp(token), Err(Error::UnknownToken(_))));
+}
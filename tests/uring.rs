@@ -0,0 +1,308 @@
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::Shutdown;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use common::next;
+use common::wait_for;
+use common::WAIT;
+use nofio::reactor::Interest;
+use nofio::reactor::Reactor;
+use nofio::reactor::Source;
+use nofio::reactor::Uring;
+use nofio::ConnToken;
+use nofio::Direction;
+use nofio::Event;
+use nofio::Net;
+
+fn uring() -> Net {
+    Net::builder()
+        .build_with_reactor(Uring::new().unwrap())
+        .unwrap()
+}
+
+/// A connection from a plain socket, so what the `Net` writes can be read back.
+fn accepted(net: &mut Net) -> (ConnToken, TcpStream) {
+    net.tcp_listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = match next(net) {
+        Event::Listening { addr, .. } => addr,
+        other => panic!("expected Listening, not {:?}", other),
+    };
+    let peer = TcpStream::connect(addr).unwrap();
+    peer.set_read_timeout(Some(WAIT)).unwrap();
+    match next(net) {
+        Event::NewConnection { token, .. } => (token, peer),
+        other => panic!("expected NewConnection, not {:?}", other),
+    }
+}
+
+#[test]
+fn reads_writes_and_really_closes() {
+    let mut net = uring();
+    let (token, mut peer) = accepted(&mut net);
+
+    peer.write_all(b"ping").unwrap();
+    wait_for(&mut net, |ev| matches!(ev, Event::Data(t) if *t == token));
+    let mut io = net.io(token).unwrap();
+    assert_eq!(b"ping", &io.take_buf().unwrap()[..]);
+    io.write(b"pong").unwrap();
+    io.close();
+    peer.shutdown(Shutdown::Write).unwrap();
+    wait_for(&mut net, |ev| matches!(ev, Event::Closed(t) if *t == token));
+
+    let mut got = Vec::new();
+    peer.read_to_end(&mut got).unwrap();
+    assert_eq!(b"pong", &got[..]);
+}
+
+#[test]
+fn a_watched_socket_really_closes() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    peer.set_read_timeout(Some(WAIT)).unwrap();
+    let (ours, _) = listener.accept().unwrap();
+    let ours = mio::net::TcpStream::from_stream(ours).unwrap();
+
+    let mut reactor = Uring::new().unwrap();
+    let token = mio::Token(7);
+    reactor
+        .register(Source::Stream(&ours), token, Interest::READABLE)
+        .unwrap();
+    // submitted, and nothing to report
+    let mut ready = Vec::new();
+    reactor
+        .poll(&mut ready, 8, Some(Duration::from_millis(10)))
+        .unwrap();
+    assert!(ready.is_empty());
+    reactor.closing(Source::Stream(&ours), token).unwrap();
+    drop(ours);
+
+    // were the poll still holding the socket, the end would never come
+    let mut got = Vec::new();
+    peer.read_to_end(&mut got).unwrap();
+    assert!(got.is_empty());
+}
+
+#[test]
+fn the_peer_going_away_is_seen() {
+    let mut net = uring();
+    let (token, peer) = accepted(&mut net);
+    drop(peer);
+    wait_for(
+        &mut net,
+        |ev| matches!(ev, Event::Done(t, Direction::Read) if *t == token),
+    );
+    net.io(token).unwrap().close();
+    wait_for(&mut net, |ev| matches!(ev, Event::Closed(t) if *t == token));
+}
+
+#[test]
+fn a_remote_wakes_it() {
+    let mut net = uring();
+    let (token, mut peer) = accepted(&mut net);
+
+    let remote = net.remote();
+    let sender = thread::spawn(move || remote.write(token, b"from afar"));
+    wait_for(
+        &mut net,
+        |ev| matches!(ev, Event::Flushed(t) if *t == token),
+    );
+    sender.join().unwrap();
+
+    let mut got = [0; 9];
+    peer.read_exact(&mut got).unwrap();
+    assert_eq!(b"from afar", &got);
+}
+
+#[test]
+fn what_only_mio_can_watch_is_refused() {
+    let mut net = uring();
+    let (registration, _readiness) = mio::Registration::new2();
+    let err = net
+        .register_source(registration, Interest::READABLE)
+        .unwrap_err();
+    let cause = err.io_error().expect("the reactor's error");
+    assert_eq!(io::ErrorKind::Unsupported, cause.kind());
+}
+
+/// One end of a connection for a bare reactor to watch, and the other.
+fn watched_pair() -> (mio::net::TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    peer.set_read_timeout(Some(WAIT)).unwrap();
+    let (ours, _) = listener.accept().unwrap();
+    (mio::net::TcpStream::from_stream(ours).unwrap(), peer)
+}
+
+#[test]
+fn streams_are_read_and_written_on_the_ring() {
+    let (ours, mut peer) = watched_pair();
+    let mut reactor = Uring::new().unwrap();
+    assert!(reactor.completes());
+    let token = mio::Token(7);
+    reactor
+        .register(
+            Source::Stream(&ours),
+            token,
+            Interest::READABLE | Interest::WRITABLE,
+        )
+        .unwrap();
+
+    // each starts, then is collected once it's been reported
+    let mut buf = [0; 16];
+    let mut fill = |out: &mut Vec<u8>| {
+        out.extend_from_slice(b"pong");
+        Ok(())
+    };
+    let err = reactor.read(&ours, token, &mut buf).unwrap_err();
+    assert_eq!(io::ErrorKind::WouldBlock, err.kind());
+    let err = reactor.write(&ours, token, &mut fill).unwrap_err();
+    assert_eq!(io::ErrorKind::WouldBlock, err.kind());
+    peer.write_all(b"ping").unwrap();
+
+    let mut read = None;
+    let mut written = None;
+    let start = Instant::now();
+    while read.is_none() || written.is_none() {
+        assert!(start.elapsed() < WAIT, "{:?} {:?}", read, written);
+        let mut ready = Vec::new();
+        reactor.poll(&mut ready, 8, Some(WAIT)).unwrap();
+        if !ready.contains(&token) {
+            continue;
+        }
+        if read.is_none() {
+            read = reactor.read(&ours, token, &mut buf).ok();
+        }
+        if written.is_none() {
+            written = reactor.write(&ours, token, &mut fill).ok();
+        }
+    }
+    assert_eq!(Some(4), read);
+    assert_eq!(b"ping", &buf[..4]);
+    assert_eq!(Some(4), written);
+    let mut got = [0; 4];
+    peer.read_exact(&mut got).unwrap();
+    assert_eq!(b"pong", &got);
+}
+
+#[test]
+fn a_lot_goes_both_ways() {
+    let mut net = uring();
+    let (token, peer) = accepted(&mut net);
+    let sent: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let expected = sent.clone();
+    let mut writer = peer.try_clone().unwrap();
+    let writing = thread::spawn(move || {
+        writer.write_all(&sent).unwrap();
+        writer.shutdown(Shutdown::Write).unwrap();
+    });
+    let mut reader = peer;
+    let reading = thread::spawn(move || {
+        let mut got = Vec::new();
+        reader.read_to_end(&mut got).unwrap();
+        got
+    });
+
+    // everything is echoed back, then closed once the peer's finished
+    loop {
+        match next(&mut net) {
+            Event::Data(t) if t == token => {
+                let mut io = net.io(t).unwrap();
+                let data = io.take_buf().unwrap();
+                io.write(&data).unwrap();
+            }
+            Event::Done(t, Direction::Read) if t == token => net.io(t).unwrap().close(),
+            Event::Closed(t) if t == token => break,
+            _ => (),
+        }
+    }
+    writing.join().unwrap();
+    assert!(expected == reading.join().unwrap(), "not echoed intact");
+}
+
+#[test]
+fn connecting_is_waited_for() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut net = uring();
+    let token = net.tcp_connect(&listener.local_addr().unwrap()).unwrap();
+    let (mut theirs, _) = listener.accept().unwrap();
+    theirs.set_read_timeout(Some(WAIT)).unwrap();
+    wait_for(
+        &mut net,
+        |ev| matches!(ev, Event::Connected(t) if *t == token),
+    );
+
+    net.io(token).unwrap().write(b"hello").unwrap();
+    theirs.write_all(b"hi").unwrap();
+    wait_for(&mut net, |ev| matches!(ev, Event::Data(t) if *t == token));
+    assert_eq!(b"hi", &net.io(token).unwrap().take_buf().unwrap()[..]);
+    wait_for(
+        &mut net,
+        |ev| matches!(ev, Event::Flushed(t) if *t == token),
+    );
+    let mut got = [0; 5];
+    theirs.read_exact(&mut got).unwrap();
+    assert_eq!(b"hello", &got);
+}
+
+#[test]
+fn a_file_is_sent_through_the_ring() {
+    let contents: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let path = std::env::temp_dir().join(format!("nofio-{}-uring", std::process::id()));
+    fs::write(&path, &contents).unwrap();
+    let mut net = uring();
+    let (token, mut peer) = accepted(&mut net);
+    let mut io = net.io(token).unwrap();
+    io.write(b"before ").unwrap();
+    io.send_file(File::open(&path).unwrap(), 0, contents.len())
+        .unwrap();
+    io.write(b" after").unwrap();
+    io.close();
+
+    let reader = thread::spawn(move || {
+        let mut got = Vec::new();
+        peer.read_to_end(&mut got).unwrap();
+        got
+    });
+    let start = Instant::now();
+    while !reader.is_finished() {
+        assert!(start.elapsed() < WAIT, "still going");
+        net.next_timeout(Duration::from_millis(10)).unwrap();
+    }
+    fs::remove_file(&path).unwrap();
+    let got = reader.join().unwrap();
+    assert_eq!(b"before ", &got[..7]);
+    assert!(
+        contents[..] == got[7..got.len() - 6],
+        "the file arrived intact"
+    );
+    assert_eq!(b" after", &got[got.len() - 6..]);
+}
+
+#[test]
+fn dropping_it_lets_go_of_what_was_being_read() {
+    let mut net = uring();
+    let (token, mut peer) = accepted(&mut net);
+    peer.write_all(b"ping").unwrap();
+    wait_for(&mut net, |ev| matches!(ev, Event::Data(t) if *t == token));
+    net.io(token).unwrap().take_buf().unwrap();
+    // the next recv is on its way to the kernel, and waits there for more
+    net.next_timeout(Duration::from_millis(10)).unwrap();
+    drop(net);
+
+    // were the recv still holding the socket, the end would never come
+    let mut got = Vec::new();
+    peer.read_to_end(&mut got).unwrap();
+    assert!(got.is_empty());
+}
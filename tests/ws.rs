@@ -0,0 +1,151 @@
+mod common;
+
+use common::settle;
+use nofio::http1;
+use nofio::ws;
+use nofio::ws::Message;
+use nofio::ConnToken;
+use nofio::Event;
+use nofio::Net;
+
+/// `a` has accepted a WebSocket from `b`, which has read the answer.
+fn session() -> (Net, ConnToken, ConnToken) {
+    let (mut net, _clock) = Net::builder().build_simulated().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    ws::accept(&mut net.io(a).unwrap(), "key", 1024).unwrap();
+    settle(&mut net);
+    let answer = net.io(b).unwrap().take_buf().unwrap();
+    assert!(answer.starts_with(b"HTTP/1.1 101 "));
+    (net, a, b)
+}
+
+/// A frame as a client sends it, masked.
+fn masked(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [0x37, 0xfa, 0x21, 0x3d];
+    assert!(payload.len() < 126);
+    let mut frame = vec![
+        if fin { 0x80 } else { 0 } | opcode,
+        0x80 | payload.len() as u8,
+    ];
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    frame
+}
+
+fn sent(net: &mut Net, b: ConnToken, frames: &[Vec<u8>]) -> Vec<Event> {
+    net.io(b).unwrap().write(&frames.concat()).unwrap();
+    settle(net)
+}
+
+fn message(events: &[Event], a: ConnToken) -> bool {
+    events
+        .iter()
+        .any(|ev| matches!(ev, Event::WsMessage(t) if *t == a))
+}
+
+#[test]
+fn the_handshake_answers_the_rfc_sample() {
+    let head = b"GET /chat HTTP/1.1\r\n\
+        Host: server.example.com\r\n\
+        Upgrade: websocket\r\n\
+        Connection: keep-alive, Upgrade\r\n\
+        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+        Sec-WebSocket-Version: 13\r\n\r\n";
+    let (request, _) = http1::parse_request(head).unwrap().unwrap();
+    let key = ws::accept_key(&request).expect("a handshake");
+    assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", key);
+
+    let (mut net, _clock) = Net::builder().build_simulated::<()>().unwrap();
+    let (a, b) = net.memory_pair().unwrap();
+    ws::accept(&mut net.io(a).unwrap(), &key, 1024).unwrap();
+    settle(&mut net);
+    let answer = net.io(b).unwrap().take_buf().unwrap();
+    let answer = String::from_utf8(answer).unwrap();
+    assert!(answer.contains("\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+    let plain = b"GET /chat HTTP/1.1\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+    let (request, _) = http1::parse_request(plain).unwrap().unwrap();
+    assert_eq!(None, ws::accept_key(&request));
+}
+
+#[test]
+fn masked_frames_are_unmasked() {
+    let (mut net, a, b) = session();
+    let events = sent(&mut net, b, &[masked(true, 0x1, b"hello")]);
+    assert!(message(&events, a));
+    let got = ws::recv(&mut net.io(a).unwrap()).unwrap();
+    assert_eq!(Some(Message::Text("hello".to_string())), got);
+}
+
+#[test]
+fn an_unmasked_frame_resets_the_connection() {
+    let (mut net, a, b) = session();
+    let events = sent(&mut net, b, &[vec![0x81, 2, b'h', b'i']]);
+    assert!(events
+        .iter()
+        .any(|ev| matches!(ev, Event::Error(t, _) if *t == a)));
+}
+
+#[test]
+fn fragments_are_reassembled_around_a_ping() {
+    let (mut net, a, b) = session();
+    let events = sent(
+        &mut net,
+        b,
+        &[
+            masked(false, 0x2, b"hel"),
+            masked(true, 0x9, b"p"),
+            masked(false, 0x0, b"l"),
+            masked(true, 0x0, b"o"),
+        ],
+    );
+    assert!(message(&events, a));
+    let got = ws::recv(&mut net.io(a).unwrap()).unwrap();
+    assert_eq!(Some(Message::Binary(b"hello".to_vec())), got);
+    assert_eq!(b"\x8a\x01p", &net.io(b).unwrap().take_buf().unwrap()[..]);
+}
+
+#[test]
+fn pings_are_answered_and_not_passed_on() {
+    let (mut net, a, b) = session();
+    let events = sent(&mut net, b, &[masked(true, 0x9, b"abc")]);
+    assert!(!message(&events, a));
+    assert_eq!(None, ws::recv(&mut net.io(a).unwrap()).unwrap());
+    assert_eq!(b"\x8a\x03abc", &net.io(b).unwrap().take_buf().unwrap()[..]);
+}
+
+#[test]
+fn a_close_is_echoed() {
+    let (mut net, a, b) = session();
+    let events = sent(&mut net, b, &[masked(true, 0x8, b"\x03\xe8bye")]);
+    assert!(message(&events, a));
+    let got = ws::recv(&mut net.io(a).unwrap()).unwrap();
+    assert_eq!(Some(Message::Close(Some((1000, "bye".to_string())))), got);
+    // just the status goes back
+    assert_eq!(
+        b"\x88\x02\x03\xe8",
+        &net.io(b).unwrap().take_buf().unwrap()[..]
+    );
+    let sent = ws::send(&mut net.io(a).unwrap(), &Message::Text("late".to_string()));
+    assert!(sent.is_err());
+}
+
+#[test]
+fn messages_wait_undecoded_until_taken() {
+    let (mut net, a, b) = session();
+    let second = masked(true, 0x1, b"second");
+    sent(&mut net, b, &[masked(true, 0x1, b"first"), second.clone()]);
+    // the second is still in the buffer, where it's counted
+    assert_eq!(second.len(), net.io(a).unwrap().buf().unwrap().len());
+
+    let mut io = net.io(a).unwrap();
+    assert_eq!(
+        Some(Message::Text("first".to_string())),
+        ws::recv(&mut io).unwrap()
+    );
+    assert_eq!(
+        Some(Message::Text("second".to_string())),
+        ws::recv(&mut io).unwrap()
+    );
+    assert_eq!(None, ws::recv(&mut io).unwrap());
+}